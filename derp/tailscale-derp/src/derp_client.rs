@@ -57,17 +57,21 @@ impl DerpClient {
         let domain = url.domain().unwrap_or("");
         let mut stream = connector.wrap(domain, stream).await?;
 
-        //发送ws upgrade请求
-        let headers = vec![];
-        let request = crate::derp_utils::build_request(url, &headers);
-        AsyncWriteExt::write_all(&mut stream, request.as_bytes()).await?;
+        // tcp:// 直接收发derp 帧,不需要http 升级
+        let fast_connect = url.scheme() == "tcp";
+        if !fast_connect {
+            //发送ws upgrade请求
+            let headers = vec![];
+            let request = crate::derp_utils::build_request(url, &headers);
+            AsyncWriteExt::write_all(&mut stream, request.as_bytes()).await?;
+        }
         let secret_key = SecretKey::from(self.key);
 
         let public_key_bytes = secret_key.public_key().as_bytes().clone();
         // info!("alice_public_key_bytes:{:?}", public_key_bytes);
         let framed = Framed::new(stream, DerpCodec::new(public_key_bytes,
                                                         secret_key.to_bytes()
-                                                        , false));
+                                                        , fast_connect));
         let (mut sink, mut stream) = framed.split();
 
         let mut rx = self.rx.take().unwrap();
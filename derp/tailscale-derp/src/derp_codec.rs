@@ -10,10 +10,10 @@ use num_enum::{FromPrimitive, IntoPrimitive};
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 
-const MAGIC: &str = "DERP🔑";
+pub const MAGIC: &str = "DERP🔑";
 // 8 bytes: 0x44 45 52 50 f0 9f 94 91
-const PROTOCOL_VERSION: u8 = 2;
-const KEY_LEN: usize = 32;
+pub const PROTOCOL_VERSION: u8 = 2;
+pub const KEY_LEN: usize = 32;
 // const (
 // 	nonceLen       = 24
 // 	frameHeaderLen = 1 + 4 // frameType byte + 4 byte length
@@ -27,6 +27,14 @@ pub struct ServerInfo {
     version: u8,
 }
 
+impl ServerInfo {
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+        }
+    }
+}
+
 /// 兼容ws 响应
 #[derive(Debug)]
 pub enum DerpResponse {
@@ -147,7 +155,7 @@ pub enum CmdType {
 // frameClosePeer = frameType(0x11) // 32B pub key of peer to close.
 
     FramePing = 0x12, // 8 byte ping payload, to be echoed back in framePong
-// framePong = frameType(0x13) // 8 byte payload, the contents of the ping being replied to
+    FramePong = 0x13, // 8 byte payload, the contents of the ping being replied to
 
 // frameHealth is sent from server to client to tell the client
 // if their connection is unhealthy somehow. Currently the only unhealthy state
@@ -343,7 +351,7 @@ impl Encoder<DerpRequest> for DerpCodec {
 }
 
 /// cmd+len+data
-fn write_frame(dst: &mut BytesMut, cmd_type: CmdType, data: &[u8]) {
+pub fn write_frame(dst: &mut BytesMut, cmd_type: CmdType, data: &[u8]) {
     dst.put_u8(cmd_type.into());
     dst.put_u32(data.len() as u32);
     dst.put(data);
@@ -3,6 +3,8 @@ pub mod errors;
 mod derp_codec;
 pub use derp_codec::DerpResponse;
 pub use derp_codec::DerpRequest;
+// 服务端复用帧定义
+pub use derp_codec::{ClientInfo, CmdType, ServerInfo, write_frame, KEY_LEN, MAGIC, PROTOCOL_VERSION};
 
 mod derp_utils;
 mod ssl;
@@ -16,7 +18,7 @@ use crate::errors::Error;
 // MAX_PACKET_SIZE is the maximum size of a packet sent over DERP.
 // (This only includes the data bytes visible to magicsock, not
 // including its on-wire framing overhead)
-const MAX_PACKET_SIZE: usize = 64 << 10;
+pub const MAX_PACKET_SIZE: usize = 64 << 10;
 
 // FAST_START_HEADER is the header (with value "1") that signals to the HTTP
 // server that the DERP HTTP client does not want the HTTP 101 response
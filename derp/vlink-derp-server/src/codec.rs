@@ -0,0 +1,57 @@
+use bytes::{Buf, BytesMut};
+use tailscale_derp::errors::Error;
+use tailscale_derp::{write_frame, CmdType};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// 帧头 frameType(1) + len(4)
+const FRAME_HEADER_LEN: usize = 5;
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+/// derp 帧
+#[derive(Debug)]
+pub struct Frame {
+    pub cmd_type: CmdType,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(cmd_type: CmdType, data: Vec<u8>) -> Self {
+        Self { cmd_type, data }
+    }
+}
+
+/// 服务端 derp 帧编解码,只处理帧头,帧内容由服务端解析
+#[derive(Default)]
+pub struct DerpFrameCodec {}
+
+impl Decoder for DerpFrameCodec {
+    type Item = Frame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([src[1], src[2], src[3], src[4]]) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(Error::MaxFrameLength(len));
+        }
+        if src.len() < FRAME_HEADER_LEN + len {
+            src.reserve(FRAME_HEADER_LEN + len - src.len());
+            return Ok(None);
+        }
+        let cmd_type = CmdType::from(src[0]);
+        src.advance(FRAME_HEADER_LEN);
+        let data = src.split_to(len).to_vec();
+        Ok(Some(Frame { cmd_type, data }))
+    }
+}
+
+impl Encoder<Frame> for DerpFrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        write_frame(dst, item.cmd_type, item.data.as_slice());
+        Ok(())
+    }
+}
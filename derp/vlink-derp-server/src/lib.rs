@@ -0,0 +1,4 @@
+pub mod codec;
+pub mod server;
+
+pub use server::DerpServer;
@@ -1,4 +1,79 @@
+use std::time::Duration;
+
+use clap::Parser;
+use crypto_box::SecretKey;
+use log::{error, info, warn};
+use rand::rngs::OsRng;
+use tokio::net::TcpListener;
+use vlink_derp_server::DerpServer;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// http 升级监听地址, 客户端使用 ws:// 或经 tls 代理后的 wss:// 连接
+    #[arg(short, long)]
+    listen: Option<String>,
+    /// 直接 tcp 监听地址, 客户端使用 tcp:// 连接
+    #[arg(short, long)]
+    tcp_listen: Option<String>,
+    /// 服务器私钥文件,不存在则生成
+    #[arg(short, long)]
+    key_file: Option<String>,
+}
+
 /// 部署在公网服务器上具有udp 转发功能
-fn main() {
-    println!("Hello, world!");
+/// 节点之间无法直连时通过derp 中继转发
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    log4rs::init_file("log4rs.yaml", Default::default()).unwrap();
+    let args = Args::parse();
+    let secret = load_key(args.key_file.unwrap_or("derp.key".to_string()).as_str()).await?;
+    let server = DerpServer::new(secret);
+
+    if let Some(addr) = args.tcp_listen {
+        let tcp = TcpListener::bind(addr.as_str()).await?;
+        info!("derp tcp listening on {}", addr);
+        let server_c = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server_c.serve_tcp(tcp).await {
+                error!("derp tcp server error:{:?}", e);
+            }
+        });
+    }
+    // 队列满的丢包只计数, 定时汇总输出
+    let server_c = server.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        let mut last = 0;
+        loop {
+            interval.tick().await;
+            let dropped = server_c.dropped_packets();
+            if dropped > last {
+                warn!("derp 队列满丢包 {} 个", dropped - last);
+            }
+            last = dropped;
+        }
+    });
+    let addr = args.listen.unwrap_or("0.0.0.0:3340".to_string());
+    let http = TcpListener::bind(addr.as_str()).await?;
+    info!("derp http listening on {}", addr);
+    server.serve_http(http).await?;
+    error!("Server exit");
+    Ok(())
+}
+
+async fn load_key(path: &str) -> anyhow::Result<SecretKey> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => {
+            let key: [u8; 32] = bytes.try_into()
+                .map_err(|_| anyhow::anyhow!("私钥文件格式错误:{}", path))?;
+            Ok(SecretKey::from(key))
+        }
+        Err(_) => {
+            let secret = SecretKey::generate(&mut OsRng);
+            tokio::fs::write(path, secret.to_bytes()).await?;
+            info!("生成服务器私钥:{}", path);
+            Ok(secret)
+        }
+    }
 }
@@ -0,0 +1,318 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use bytes::BytesMut;
+use crypto_box::{PublicKey, SalsaBox, SecretKey};
+use crypto_box::aead::{Aead, AeadCore, Nonce};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use rand::rngs::OsRng;
+use tailscale_derp::{ClientInfo, CmdType, KEY_LEN, MAGIC, MAX_PACKET_SIZE, ServerInfo};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, RwLock};
+use tokio::time::timeout;
+use tokio_util::codec::{Framed, FramedParts};
+
+use crate::codec::{DerpFrameCodec, Frame};
+
+const NONCE_LEN: usize = 24;
+/// 保活间隔
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// 握手超时
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// http 升级请求最大长度
+const MAX_HTTP_REQUEST_LEN: usize = 8 << 10;
+/// 每个客户端发送队列
+const CLIENT_QUEUE: usize = 32;
+
+/// PeerGoneReasonDisconnected
+const PEER_GONE_DISCONNECTED: u8 = 0x00;
+/// PeerGoneReasonNotHere
+const PEER_GONE_NOT_HERE: u8 = 0x01;
+
+/// derp 中继服务器
+/// 按公钥转发客户端之间的数据包,支持 http(ws) 升级和直接 tcp 两种接入方式
+#[derive(Clone)]
+pub struct DerpServer {
+    inner: Arc<DerpServerInner>,
+}
+
+pub struct DerpServerInner {
+    secret: SecretKey,
+    public_key: [u8; KEY_LEN],
+    /// 在线客户端
+    clients: RwLock<HashMap<[u8; KEY_LEN], ClientHandle>>,
+    conn_id: AtomicU64,
+    /// 目标队列满丢弃的包
+    dropped: AtomicU64,
+}
+
+struct ClientHandle {
+    /// 同一个key 重复连接时,只有最新的连接有效
+    conn_id: u64,
+    tx: mpsc::Sender<Frame>,
+    /// 向该连接发送过数据的客户端,断开时通知它们
+    senders: Mutex<HashSet<[u8; KEY_LEN]>>,
+}
+
+impl DerpServer {
+    pub fn new(secret: SecretKey) -> Self {
+        let public_key = *secret.public_key().as_bytes();
+        Self {
+            inner: Arc::new(DerpServerInner {
+                secret,
+                public_key,
+                clients: Default::default(),
+                conn_id: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn public_key(&self) -> &[u8; KEY_LEN] {
+        &self.public_key
+    }
+
+    /// 当前连接数
+    pub async fn client_count(&self) -> usize {
+        self.clients.read().await.len()
+    }
+
+    /// 队列满丢弃的包数
+    pub fn dropped_packets(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 接受 http 升级连接, 即 DerpClient ws:// wss:// 地址
+    /// tls 由前置代理处理
+    pub async fn serve_http(&self, listener: TcpListener) -> anyhow::Result<()> {
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle_http(stream, addr).await {
+                    debug!("derp client {} error:{:?}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// 接受直接 tcp 连接, 即 DerpClient tcp:// 地址
+    pub async fn serve_tcp(&self, listener: TcpListener) -> anyhow::Result<()> {
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle_conn(stream, BytesMut::new(), addr).await {
+                    debug!("derp client {} error:{:?}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// 读取 http 升级请求,响应101 后进入derp 协议
+    pub async fn handle_http<S>(&self, mut stream: S, addr: SocketAddr) -> anyhow::Result<()>
+        where S: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+        let mut buf = BytesMut::with_capacity(1024);
+        let (request_len, upgrade) = loop {
+            if stream.read_buf(&mut buf).await? == 0 {
+                return Err(anyhow!("连接关闭"));
+            }
+            let mut headers = [httparse::EMPTY_HEADER; 32];
+            let mut req = httparse::Request::new(&mut headers);
+            if let httparse::Status::Complete(len) = req.parse(buf.as_ref())? {
+                let upgrade = req.headers.iter()
+                    .any(|h| h.name.eq_ignore_ascii_case("upgrade"));
+                break (len, upgrade);
+            }
+            if buf.len() > MAX_HTTP_REQUEST_LEN {
+                return Err(anyhow!("http 请求过长"));
+            }
+        };
+        if !upgrade {
+            stream.write_all(b"HTTP/1.1 426 Upgrade Required\r\nConnection: close\r\nContent-Length: 31\r\n\r\nDERP requires connection upgrade").await?;
+            return Ok(());
+        }
+        stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n").await?;
+        let _ = buf.split_to(request_len);
+        self.handle_conn(stream, buf, addr).await
+    }
+
+    /// 处理一个derp 连接
+    /// read_buf 为升级请求之后已读到的数据
+    pub async fn handle_conn<S>(&self, stream: S, read_buf: BytesMut, addr: SocketAddr) -> anyhow::Result<()>
+        where S: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+        let mut parts = FramedParts::new::<Frame>(stream, DerpFrameCodec::default());
+        parts.read_buf = read_buf;
+        let mut framed = Framed::from_parts(parts);
+
+        let mut key_frame = MAGIC.as_bytes().to_vec();
+        key_frame.extend_from_slice(self.public_key.as_slice());
+        framed.send(Frame::new(CmdType::FrameServerKey, key_frame)).await?;
+
+        let client_key = timeout(HANDSHAKE_TIMEOUT, self.await_client_info(&mut framed)).await
+            .map_err(|_| anyhow!("握手超时"))??;
+        info!("derp client {} connected:{:?}", addr, client_key);
+
+        let conn_id = self.conn_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, mut rx) = mpsc::channel(CLIENT_QUEUE);
+        let handle = ClientHandle { conn_id, tx: tx.clone(), senders: Default::default() };
+        if let Some(old) = self.clients.write().await.insert(client_key, handle) {
+            warn!("derp client {:?} 重复连接,替换旧连接:{}", client_key, old.conn_id);
+        }
+
+        let (mut sink, mut stream) = framed.split();
+        let write_task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(KEEP_ALIVE);
+            interval.tick().await;
+            loop {
+                let frame = tokio::select! {
+                    frame = rx.recv() => match frame {
+                        Some(f) => f,
+                        None => break,
+                    },
+                    _ = interval.tick() => Frame::new(CmdType::FrameKeepAlive, vec![]),
+                };
+                if let Err(e) = sink.send(frame).await {
+                    debug!("derp send error:{:?}", e);
+                    break;
+                }
+            }
+        });
+
+        // 已登记过的目标连接, 每个连接只登记一次
+        let mut sent_to = HashMap::new();
+        while let Some(frame) = stream.next().await {
+            let frame = match frame {
+                Ok(f) => f,
+                Err(e) => {
+                    debug!("derp read error:{:?}", e);
+                    break;
+                }
+            };
+            match frame.cmd_type {
+                CmdType::FrameSendPacket => {
+                    if frame.data.len() < KEY_LEN || frame.data.len() - KEY_LEN > MAX_PACKET_SIZE {
+                        warn!("derp packet 长度错误:{}", frame.data.len());
+                        continue;
+                    }
+                    let dst: [u8; KEY_LEN] = frame.data[..KEY_LEN].try_into().unwrap();
+                    let mut data = Vec::with_capacity(frame.data.len());
+                    data.extend_from_slice(client_key.as_slice());
+                    data.extend_from_slice(&frame.data[KEY_LEN..]);
+                    if !self.forward(&client_key, &dst, Frame::new(CmdType::FrameRecvPacket, data), &mut sent_to).await {
+                        let mut data = dst.to_vec();
+                        data.push(PEER_GONE_NOT_HERE);
+                        let _ = tx.try_send(Frame::new(CmdType::FramePeerGone, data));
+                    }
+                }
+                CmdType::FramePing => {
+                    let _ = tx.try_send(Frame::new(CmdType::FramePong, frame.data));
+                }
+                CmdType::FrameKeepAlive => {}
+                t => {
+                    debug!("derp ignore frame:{:?}", t);
+                }
+            }
+        }
+
+        write_task.abort();
+        let senders = {
+            let mut clients = self.clients.write().await;
+            match clients.get(&client_key) {
+                Some(c) if c.conn_id == conn_id => clients.remove(&client_key)
+                    .map(|c| c.senders.into_inner().unwrap())
+                    .unwrap_or_default(),
+                _ => HashSet::new(),
+            }
+        };
+        for dst in senders {
+            let mut data = client_key.to_vec();
+            data.push(PEER_GONE_DISCONNECTED);
+            self.send_to(&dst, Frame::new(CmdType::FramePeerGone, data)).await;
+        }
+        info!("derp client {} disconnected", addr);
+        Ok(())
+    }
+
+    /// 等待客户端信息,返回客户端公钥
+    async fn await_client_info<S>(&self, framed: &mut Framed<S, DerpFrameCodec>) -> anyhow::Result<[u8; KEY_LEN]>
+        where S: AsyncRead + AsyncWrite + Unpin {
+        let frame = loop {
+            let frame = framed.next().await
+                .ok_or(anyhow!("连接关闭"))??;
+            match frame.cmd_type {
+                CmdType::FrameClientInfo => break frame,
+                CmdType::FrameKeepAlive => {}
+                t => return Err(anyhow!("握手帧错误:{:?}", t)),
+            }
+        };
+        // client_key + nonce + 加密的 ClientInfo
+        if frame.data.len() < KEY_LEN + NONCE_LEN {
+            return Err(anyhow!("client info 长度错误"));
+        }
+        let client_key: [u8; KEY_LEN] = frame.data[..KEY_LEN].try_into().unwrap();
+        let salsa_box = SalsaBox::new(&PublicKey::from(client_key), &self.secret);
+        let nonce = Nonce::<SalsaBox>::from_slice(&frame.data[KEY_LEN..KEY_LEN + NONCE_LEN]);
+        let info = salsa_box.decrypt(nonce, &frame.data[KEY_LEN + NONCE_LEN..])
+            .map_err(|_| anyhow!("client info 解密失败"))?;
+        let _info: ClientInfo = serde_json::from_slice(info.as_slice())?;
+
+        let info = serde_json::to_vec(&ServerInfo::new())?;
+        let nonce = SalsaBox::generate_nonce(&mut OsRng);
+        let encrypted = salsa_box.encrypt(&nonce, info.as_slice())
+            .map_err(|_| anyhow!("server info 加密失败"))?;
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(encrypted.as_slice());
+        framed.send(Frame::new(CmdType::FrameServerInfo, data)).await?;
+        Ok(client_key)
+    }
+
+    /// 转发src 的数据包,目标不在线返回false
+    /// 首次发送到目标的当前连接时登记src, 目标断开时通知src
+    async fn forward(&self, src: &[u8; KEY_LEN], dst: &[u8; KEY_LEN], frame: Frame,
+                     sent_to: &mut HashMap<[u8; KEY_LEN], u64>) -> bool {
+        let clients = self.clients.read().await;
+        let Some(c) = clients.get(dst) else {
+            return false;
+        };
+        if sent_to.insert(*dst, c.conn_id) != Some(c.conn_id) {
+            c.senders.lock().unwrap().insert(*src);
+        }
+        self.try_send(c, frame);
+        true
+    }
+
+    /// 发送到目标客户端,目标不在线返回false
+    async fn send_to(&self, dst: &[u8; KEY_LEN], frame: Frame) -> bool {
+        match self.clients.read().await.get(dst) {
+            None => false,
+            Some(c) => {
+                self.try_send(c, frame);
+                true
+            }
+        }
+    }
+
+    /// 队列满时丢包, 高负载时会很频繁, 只计数
+    fn try_send(&self, c: &ClientHandle, frame: Frame) {
+        if let Err(e) = c.tx.try_send(frame) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            debug!("derp client {} queue error:{}", c.conn_id, e);
+        }
+    }
+}
+
+impl Deref for DerpServer {
+    type Target = DerpServerInner;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref()
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use crypto_box::SecretKey;
+use futures_util::StreamExt;
+use rand::rngs::OsRng;
+use tailscale_derp::derp_client::DerpClient;
+use tailscale_derp::DerpResponse;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use vlink_derp_server::DerpServer;
+
+fn pub_key(key: [u8; 32]) -> [u8; 32] {
+    *SecretKey::from(key).public_key().as_bytes()
+}
+
+/// 一个节点走 http 升级,一个节点走 tcp,互相转发
+#[tokio::test]
+async fn test_relay_http_and_tcp() {
+    let server = DerpServer::new(SecretKey::generate(&mut OsRng));
+    let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = http.local_addr().unwrap();
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tcp_addr = tcp.local_addr().unwrap();
+    let server_c = server.clone();
+    tokio::spawn(async move { server_c.serve_http(http).await });
+    let server_c = server.clone();
+    tokio::spawn(async move { server_c.serve_tcp(tcp).await });
+
+    let (a_key, b_key) = ([1u8; 32], [2u8; 32]);
+    let mut a = DerpClient::new(a_key, format!("ws://{}/derp", http_addr).as_str()).await.unwrap();
+    let mut a_stream = a.async_connect().await.unwrap();
+    let mut b = DerpClient::new(b_key, format!("tcp://{}", tcp_addr).as_str()).await.unwrap();
+    let mut b_stream = b.async_connect().await.unwrap();
+    assert_eq!(server.client_count().await, 2);
+
+    a.send(pub_key(b_key).as_slice(), b"hello").await.unwrap();
+    match timeout(Duration::from_secs(3), b_stream.next()).await.unwrap() {
+        Some(Ok(DerpResponse::FrameRecvPacket((src, data)))) => {
+            assert_eq!(src, pub_key(a_key));
+            assert_eq!(data, b"hello");
+        }
+        e => panic!("unexpected:{:?}", e),
+    }
+
+    b.send(pub_key(a_key).as_slice(), b"world").await.unwrap();
+    match timeout(Duration::from_secs(3), a_stream.next()).await.unwrap() {
+        Some(Ok(DerpResponse::FrameRecvPacket((src, data)))) => {
+            assert_eq!(src, pub_key(b_key));
+            assert_eq!(data, b"world");
+        }
+        e => panic!("unexpected:{:?}", e),
+    }
+
+    // b 断开, a 收到 PeerGone
+    drop(b);
+    drop(b_stream);
+    match timeout(Duration::from_secs(3), a_stream.next()).await.unwrap() {
+        Some(Ok(DerpResponse::FramePeerGonePacket((key, reason)))) => {
+            assert_eq!(key, pub_key(b_key));
+            assert_eq!(reason, 0);
+        }
+        e => panic!("unexpected:{:?}", e),
+    }
+}
+
+/// 目标不在线返回 PeerGone NotHere
+#[tokio::test]
+async fn test_relay_peer_not_here() {
+    let server = DerpServer::new(SecretKey::generate(&mut OsRng));
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tcp_addr = tcp.local_addr().unwrap();
+    let server_c = server.clone();
+    tokio::spawn(async move { server_c.serve_tcp(tcp).await });
+
+    let mut a = DerpClient::new([3u8; 32], format!("tcp://{}", tcp_addr).as_str()).await.unwrap();
+    let mut a_stream = a.async_connect().await.unwrap();
+    let target = pub_key([4u8; 32]);
+    a.send(target.as_slice(), b"hello").await.unwrap();
+    match timeout(Duration::from_secs(3), a_stream.next()).await.unwrap() {
+        Some(Ok(DerpResponse::FramePeerGonePacket((key, reason)))) => {
+            assert_eq!(key, target);
+            assert_eq!(reason, 1);
+        }
+        e => panic!("unexpected:{:?}", e),
+    }
+}
+
+/// 只通知向断开节点发送过数据的客户端
+#[tokio::test]
+async fn test_peer_gone_only_to_senders() {
+    let server = DerpServer::new(SecretKey::generate(&mut OsRng));
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tcp_addr = tcp.local_addr().unwrap();
+    let server_c = server.clone();
+    tokio::spawn(async move { server_c.serve_tcp(tcp).await });
+
+    let (a_key, b_key) = ([5u8; 32], [6u8; 32]);
+    let mut a = DerpClient::new(a_key, format!("tcp://{}", tcp_addr).as_str()).await.unwrap();
+    let a_stream = a.async_connect().await.unwrap();
+    let mut b = DerpClient::new(b_key, format!("tcp://{}", tcp_addr).as_str()).await.unwrap();
+    let mut b_stream = b.async_connect().await.unwrap();
+
+    a.send(pub_key(b_key).as_slice(), b"hello").await.unwrap();
+    match timeout(Duration::from_secs(3), b_stream.next()).await.unwrap() {
+        Some(Ok(DerpResponse::FrameRecvPacket((src, _)))) => assert_eq!(src, pub_key(a_key)),
+        e => panic!("unexpected:{:?}", e),
+    }
+
+    // b 没有向 a 发送过, a 断开时不通知 b
+    drop(a);
+    drop(a_stream);
+    assert!(timeout(Duration::from_millis(500), b_stream.next()).await.is_err());
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use ip_network::IpNetwork;
+use sea_orm::*;
+use vlink_core::proto::pb::abi::{AclRule, BcAclUpdate};
+use vlink_core::proto::pb::abi::to_client::ToClientData;
+use crate::db::entity::prelude::{AclRuleColumn, AclRuleEntity, AclRuleModel};
+use crate::network::VlinkNetwork;
+use crate::peer::VlinkPeer;
+use crate::server::VlinkServer;
+
+/// 读取网络的访问控制规则, 并把 peer/tag 解析成 cidr
+pub async fn load_acl_rules(server: &VlinkServer, network: &VlinkNetwork) -> anyhow::Result<Vec<AclRule>> {
+    let models = AclRuleEntity::find()
+        .filter(AclRuleColumn::NetworkId.eq(network.network_id)
+            .and(AclRuleColumn::Disabled.eq(false)))
+        .order_by_asc(AclRuleColumn::Priority)
+        .order_by_asc(AclRuleColumn::Id)
+        .all(server.conn())
+        .await?;
+    let peers = network.peers.read_lock().await;
+    Ok(models.iter()
+        .filter_map(|m| resolve_rule(m, &peers))
+        .collect())
+}
+
+/// 规则或节点标签变更后, 向网络中在线节点推送最新规则
+pub async fn broadcast_acl(server: &VlinkServer, network_id: i64) -> anyhow::Result<()> {
+    let Some(network) = server.cached_network(network_id).await else {
+        return Ok(());
+    };
+    let rules = load_acl_rules(server, &network).await?;
+    network.broadcast_by(ToClientData::AclUpdate(BcAclUpdate { rules }), |_, _| true).await;
+    Ok(())
+}
+
+/// 来源或目标解析不出地址的规则不下发, 避免 `tag:x` 没有节点时变成匹配任意地址
+fn resolve_rule(model: &AclRuleModel, peers: &HashMap<String, VlinkPeer>) -> Option<AclRule> {
+    let src = resolve_selectors(model.src.as_str(), peers)?;
+    let dst = resolve_selectors(model.dst.as_str(), peers)?;
+    let (port_start, port_end) = model.port_range().unwrap_or((0, 0));
+    Some(AclRule {
+        id: model.id,
+        src,
+        dst,
+        proto: model.proto.clone(),
+        port_start: port_start as u32,
+        port_end: port_end as u32,
+        allow: model.allow,
+    })
+}
+
+/// 返回空列表表示任意地址, None 表示什么都匹配不到
+fn resolve_selectors(selectors: &str, peers: &HashMap<String, VlinkPeer>) -> Option<Vec<String>> {
+    let mut cidrs = vec![];
+    let mut any = false;
+    for s in selectors.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if s == "*" {
+            any = true;
+        } else if let Some(tag) = s.strip_prefix("tag:") {
+            peers.values()
+                .filter(|p| p.model.has_tag(tag))
+                .for_each(|p| cidrs.extend(peer_cidrs(p)));
+        } else if let Some(pub_key) = s.strip_prefix("peer:") {
+            if let Some(p) = peers.get(pub_key) {
+                cidrs.extend(peer_cidrs(p));
+            }
+        } else if let Some(net) = parse_cidr(s) {
+            cidrs.push(format!("{}/{}", net.network_address(), net.netmask()));
+        }
+    }
+    if any || selectors.trim().is_empty() {
+        return Some(vec![]);
+    }
+    if cidrs.is_empty() {
+        None
+    } else {
+        Some(cidrs)
+    }
+}
+
+/// 校验来源/目标格式
+pub fn check_selectors(selectors: &str) -> Result<(), String> {
+    for s in selectors.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let ok = s == "*"
+            || s.strip_prefix("tag:").map(|t| !t.is_empty()).unwrap_or(false)
+            || s.strip_prefix("peer:").map(|k| !k.is_empty()).unwrap_or(false)
+            || parse_cidr(s).is_some();
+        if !ok {
+            return Err(format!("规则地址格式错误:{}", s));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn parse_cidr(s: &str) -> Option<IpNetwork> {
+    let (ip, mask) = match s.split_once('/') {
+        Some((ip, mask)) => (ip.parse::<IpAddr>().ok()?, Some(mask.parse::<u8>().ok()?)),
+        None => (s.parse::<IpAddr>().ok()?, None),
+    };
+    let mask = mask.unwrap_or(if ip.is_ipv4() { 32 } else { 128 });
+    IpNetwork::new_truncate(ip, mask).ok()
+}
+
+fn peer_cidrs(peer: &VlinkPeer) -> Vec<String> {
+    let mut cidrs = vec![];
+    if let Some(ip) = peer.model.ip.as_ref() {
+        cidrs.push(format!("{}/32", ip));
+    }
+    if let Some(ip) = peer.model.ipv6.as_ref() {
+        cidrs.push(format!("{}/128", ip));
+    }
+    cidrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::entity::prelude::PeerModel;
+
+    fn peer(pub_key: &str, ip: &str, tags: Option<&str>) -> (String, VlinkPeer) {
+        let model = PeerModel {
+            id: 1,
+            pub_key: pub_key.to_string(),
+            name: None,
+            ip: Some(ip.to_string()),
+            ipv6: None,
+            default_proto: None,
+            endpoint_addr: None,
+            port: None,
+            network_id: 1,
+            disabled: false,
+            pending: false,
+            tags: tags.map(|t| t.to_string()),
+            hostname: None,
+            private_key: None,
+            create_at: None,
+            update_at: None,
+        };
+        (pub_key.to_string(), VlinkPeer::from(model))
+    }
+
+    #[test]
+    fn test_resolve_selectors() {
+        let peers: HashMap<String, VlinkPeer> = vec![
+            peer("a", "10.0.0.2", Some("web,db")),
+            peer("b", "10.0.0.3", Some("web")),
+            peer("c", "10.0.0.4", None),
+        ].into_iter().collect();
+
+        assert_eq!(resolve_selectors("*", &peers), Some(vec![]));
+        assert_eq!(resolve_selectors("", &peers), Some(vec![]));
+        assert_eq!(resolve_selectors("peer:c", &peers), Some(vec!["10.0.0.4/32".to_string()]));
+        assert_eq!(resolve_selectors("tag:db, 192.168.1.9/24", &peers),
+                   Some(vec!["10.0.0.2/32".to_string(), "192.168.1.0/24".to_string()]));
+        let mut web = resolve_selectors("tag:web", &peers).unwrap();
+        web.sort();
+        assert_eq!(web, vec!["10.0.0.2/32".to_string(), "10.0.0.3/32".to_string()]);
+        assert_eq!(resolve_selectors("tag:none", &peers), None);
+        assert_eq!(resolve_selectors("peer:x", &peers), None);
+    }
+}
@@ -0,0 +1,28 @@
+use axum::extract::{Path, State};
+use axum::Json;
+use crate::api::error::ApiResult;
+use crate::api::service::acl::{self, CreateAclParam, UpdateAclParam};
+use crate::api::state::AppState;
+use crate::db::entity::prelude::AclRuleModel;
+
+///网络下的访问控制规则
+pub async fn list(State(state): State<AppState>, Path(network_id): Path<i64>) -> ApiResult<Vec<AclRuleModel>> {
+    Ok(Json(acl::list(&state.server, network_id).await?))
+}
+
+///新增规则
+pub async fn create(State(state): State<AppState>, Path(network_id): Path<i64>,
+                    Json(param): Json<CreateAclParam>) -> ApiResult<AclRuleModel> {
+    Ok(Json(acl::create(&state.server, network_id, param).await?))
+}
+
+///修改或禁用规则
+pub async fn update(State(state): State<AppState>, Path(id): Path<i64>,
+                    Json(param): Json<UpdateAclParam>) -> ApiResult<AclRuleModel> {
+    Ok(Json(acl::update(&state.server, id, param).await?))
+}
+
+///删除规则
+pub async fn delete(State(state): State<AppState>, Path(id): Path<i64>) -> ApiResult<()> {
+    Ok(Json(acl::delete(&state.server, id).await?))
+}
@@ -1 +1,5 @@
-pub(crate) mod network;
\ No newline at end of file
+pub(crate) mod network;
+pub(crate) mod token;
+pub(crate) mod peer;
+pub(crate) mod acl;
+pub(crate) mod route;
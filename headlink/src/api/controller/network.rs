@@ -0,0 +1,30 @@
+use axum::extract::{Path, State};
+use axum::Json;
+use crate::api::error::ApiResult;
+use crate::api::service::network::{self, NetworkParam};
+use crate::api::state::AppState;
+use crate::db::entity::prelude::NetworkModel;
+
+///网络列表
+pub async fn list(State(state): State<AppState>) -> ApiResult<Vec<NetworkModel>> {
+    Ok(Json(network::list(&state.server).await?))
+}
+
+pub async fn get(State(state): State<AppState>, Path(network_id): Path<i64>) -> ApiResult<NetworkModel> {
+    Ok(Json(network::get(&state.server, network_id).await?))
+}
+
+///创建网络
+pub async fn create(State(state): State<AppState>, Json(param): Json<NetworkParam>) -> ApiResult<NetworkModel> {
+    Ok(Json(network::create(&state.server, param).await?))
+}
+
+///修改网段和备注
+pub async fn update(State(state): State<AppState>, Path(network_id): Path<i64>,
+                    Json(param): Json<NetworkParam>) -> ApiResult<NetworkModel> {
+    Ok(Json(network::update(&state.server, network_id, param).await?))
+}
+
+pub async fn delete(State(state): State<AppState>, Path(network_id): Path<i64>) -> ApiResult<()> {
+    Ok(Json(network::delete(&state.server, network_id).await?))
+}
@@ -0,0 +1,45 @@
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use serde::Deserialize;
+use crate::api::error::{ApiError, ApiResult};
+use crate::api::service::peer::{self, CreatePeerParam, PeerView, UpdatePeerParam};
+use crate::api::state::AppState;
+use crate::db::entity::prelude::PeerModel;
+
+#[derive(Debug, Deserialize)]
+pub struct PeerQuery {
+    pub pending: Option<bool>,
+}
+
+///网络下的peer, pending=true 查询待审核
+pub async fn list(State(state): State<AppState>, Path(network_id): Path<i64>,
+                  Query(query): Query<PeerQuery>) -> ApiResult<Vec<PeerView>> {
+    Ok(Json(peer::list(&state.server, network_id, query.pending).await?))
+}
+
+///添加由服务器生成密钥的标准 wireguard 节点
+pub async fn create(State(state): State<AppState>, Path(network_id): Path<i64>,
+                    Json(param): Json<CreatePeerParam>) -> ApiResult<PeerModel> {
+    Ok(Json(peer::create(&state.server, network_id, param).await?))
+}
+
+///导出 wg-quick 配置文件
+pub async fn wg_config(State(state): State<AppState>, Path(id): Path<i64>) -> Result<String, ApiError> {
+    peer::wg_config(&state.server, id).await
+}
+
+///审核通过
+pub async fn approve(State(state): State<AppState>, Path(id): Path<i64>) -> ApiResult<PeerModel> {
+    Ok(Json(peer::approve(&state.server, id).await?))
+}
+
+///拒绝
+pub async fn reject(State(state): State<AppState>, Path(id): Path<i64>) -> ApiResult<PeerModel> {
+    Ok(Json(peer::reject(&state.server, id).await?))
+}
+
+///修改名称,ip,默认协议,标签,禁用
+pub async fn update(State(state): State<AppState>, Path(id): Path<i64>,
+                    Json(param): Json<UpdatePeerParam>) -> ApiResult<PeerModel> {
+    Ok(Json(peer::update(&state.server, id, param).await?))
+}
@@ -0,0 +1,21 @@
+use axum::extract::{Path, State};
+use axum::Json;
+use crate::api::error::ApiResult;
+use crate::api::service::route;
+use crate::api::state::AppState;
+use crate::db::entity::prelude::PeerRouteModel;
+
+///节点宣告的子网
+pub async fn list(State(state): State<AppState>, Path(peer_id): Path<i64>) -> ApiResult<Vec<PeerRouteModel>> {
+    Ok(Json(route::list(&state.server, peer_id).await?))
+}
+
+///审核通过
+pub async fn approve(State(state): State<AppState>, Path(id): Path<i64>) -> ApiResult<PeerRouteModel> {
+    Ok(Json(route::approve(&state.server, id).await?))
+}
+
+///拒绝或撤销
+pub async fn delete(State(state): State<AppState>, Path(id): Path<i64>) -> ApiResult<()> {
+    Ok(Json(route::delete(&state.server, id).await?))
+}
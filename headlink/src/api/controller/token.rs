@@ -0,0 +1,23 @@
+use axum::extract::{Path, State};
+use axum::Json;
+use crate::api::error::ApiResult;
+use crate::api::service::token::{self, CreateTokenParam, UpdateTokenParam};
+use crate::api::state::AppState;
+use crate::db::entity::prelude::NetworkTokenModel;
+
+///网络下的token
+pub async fn list(State(state): State<AppState>, Path(network_id): Path<i64>) -> ApiResult<Vec<NetworkTokenModel>> {
+    Ok(Json(token::list(&state.server, network_id).await?))
+}
+
+///生成token
+pub async fn create(State(state): State<AppState>, Path(network_id): Path<i64>,
+                    Json(param): Json<CreateTokenParam>) -> ApiResult<NetworkTokenModel> {
+    Ok(Json(token::create(&state.server, network_id, param).await?))
+}
+
+///禁用或设置过期
+pub async fn update(State(state): State<AppState>, Path(id): Path<i64>,
+                    Json(param): Json<UpdateTokenParam>) -> ApiResult<NetworkTokenModel> {
+    Ok(Json(token::update(&state.server, id, param).await?))
+}
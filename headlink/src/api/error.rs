@@ -0,0 +1,42 @@
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use log::error;
+use sea_orm::DbErr;
+use serde_json::json;
+use thiserror::Error;
+use crate::client::error::ExecuteError;
+
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("未授权")]
+    Unauthorized,
+    #[error("{0}不存在")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    DbError(#[from] DbErr),
+    #[error("{0}")]
+    Execute(#[from] ExecuteError),
+    #[error("{0}")]
+    Internal(#[from] anyhow::Error),
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::DbError(e) => {
+                error!("DbErr: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ApiError::Execute(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(json!({ "msg": self.to_string() }))).into_response()
+    }
+}
+
+pub type ApiResult<T> = Result<Json<T>, ApiError>;
@@ -1,5 +1,34 @@
+use std::sync::Arc;
+use axum::Router;
+use log::{error, info};
+use crate::api::state::{AppState, AppStateInner};
+use crate::server::VlinkServer;
 
 mod controller;
 mod router;
 mod state;
-mod service;
\ No newline at end of file
+mod service;
+mod error;
+mod auth;
+
+const API_PORT: u16 = 9798;
+
+/// 启动管理接口
+pub async fn start_http_server(listen_addr: Option<String>, token: String, server: VlinkServer) -> anyhow::Result<()> {
+    let addr = listen_addr.unwrap_or(format!("0.0.0.0:{API_PORT}"));
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    info!("Api listening on {}", addr.as_str());
+    let state = AppState::new(Arc::new(AppStateInner::new(server, token)));
+
+    let app = Router::new()
+        .nest("/api", router::api(state.clone()))
+        .with_state(state);
+    let api_server = axum::serve(listener, app.into_make_service());
+
+    tokio::spawn(async move {
+        if let Err(e) = api_server.await {
+            error!("api_server stop error:{:?}", e);
+        }
+    });
+    Ok(())
+}
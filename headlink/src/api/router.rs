@@ -1,7 +1,26 @@
-use axum::Router;
+use axum::{middleware, Router};
+use axum::routing::{delete, get, post, put};
+use crate::api::auth::require_token;
+use crate::api::controller::{acl, network, peer, route, token};
 use crate::api::state::AppState;
 
-pub fn api() -> Router<AppState> {
+pub fn api(state: AppState) -> Router<AppState> {
     Router::new()
-        .nest("/network", Router::new())
-}
\ No newline at end of file
+        .nest("/network", Router::new()
+            .route("/", get(network::list).post(network::create))
+            .route("/:network_id", get(network::get).put(network::update).delete(network::delete))
+            .route("/:network_id/token", get(token::list).post(token::create))
+            .route("/:network_id/peer", get(peer::list).post(peer::create))
+            .route("/:network_id/acl", get(acl::list).post(acl::create)),
+        )
+        .route("/token/:id", put(token::update))
+        .route("/acl/:id", put(acl::update).delete(acl::delete))
+        .route("/peer/:id", put(peer::update))
+        .route("/peer/:id/approve", post(peer::approve))
+        .route("/peer/:id/reject", post(peer::reject))
+        .route("/peer/:id/route", get(route::list))
+        .route("/peer/:id/wg-config", get(peer::wg_config))
+        .route("/route/:id", delete(route::delete))
+        .route("/route/:id/approve", post(route::approve))
+        .route_layer(middleware::from_fn_with_state(state, require_token))
+}
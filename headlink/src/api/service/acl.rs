@@ -0,0 +1,144 @@
+use chrono::Local;
+use sea_orm::*;
+use sea_orm::ActiveValue::Set;
+use serde::Deserialize;
+use crate::acl::{broadcast_acl, check_selectors};
+use crate::api::error::ApiError;
+use crate::api::service::network;
+use crate::db::entity::prelude::{AclRuleActiveModel, AclRuleColumn, AclRuleEntity, AclRuleModel};
+use crate::server::VlinkServer;
+use crate::SNOWFLAKE;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAclParam {
+    #[serde(default)]
+    pub priority: i32,
+    /// 逗号分隔, `*`, `peer:<pub_key>`, `tag:<tag>` 或 cidr
+    pub src: String,
+    pub dst: String,
+    #[serde(default)]
+    pub proto: String,
+    /// `22` 或 `8000-8100`
+    pub ports: Option<String>,
+    pub allow: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateAclParam {
+    pub priority: Option<i32>,
+    pub src: Option<String>,
+    pub dst: Option<String>,
+    pub proto: Option<String>,
+    pub ports: Option<String>,
+    pub allow: Option<bool>,
+    pub disabled: Option<bool>,
+}
+
+pub async fn list(server: &VlinkServer, network_id: i64) -> Result<Vec<AclRuleModel>, ApiError> {
+    Ok(AclRuleEntity::find()
+        .filter(AclRuleColumn::NetworkId.eq(network_id))
+        .order_by_asc(AclRuleColumn::Priority)
+        .order_by_asc(AclRuleColumn::Id)
+        .all(server.conn())
+        .await?)
+}
+
+pub async fn create(server: &VlinkServer, network_id: i64, param: CreateAclParam) -> Result<AclRuleModel, ApiError> {
+    network::get(server, network_id).await?;
+    let model = AclRuleModel {
+        id: SNOWFLAKE.next_id(),
+        network_id,
+        priority: param.priority,
+        src: param.src,
+        dst: param.dst,
+        proto: param.proto,
+        ports: param.ports.filter(|p| !p.trim().is_empty()),
+        allow: param.allow,
+        disabled: false,
+        create_at: Some(Local::now().naive_local()),
+    };
+    check_rule(&model)?;
+    let model = AclRuleActiveModel {
+        id: Set(model.id),
+        network_id: Set(model.network_id),
+        priority: Set(model.priority),
+        src: Set(model.src),
+        dst: Set(model.dst),
+        proto: Set(model.proto),
+        ports: Set(model.ports),
+        allow: Set(model.allow),
+        disabled: Set(model.disabled),
+        create_at: Set(model.create_at),
+    }.insert(server.conn()).await?;
+    broadcast_acl(server, network_id).await?;
+    Ok(model)
+}
+
+/// 修改后立即推送给网络中在线的节点
+pub async fn update(server: &VlinkServer, id: i64, param: UpdateAclParam) -> Result<AclRuleModel, ApiError> {
+    let old = AclRuleEntity::find_by_id(id)
+        .one(server.conn())
+        .await?
+        .ok_or(ApiError::NotFound("acl"))?;
+    let mut rule = old.clone();
+    if let Some(priority) = param.priority {
+        rule.priority = priority;
+    }
+    if let Some(src) = param.src {
+        rule.src = src;
+    }
+    if let Some(dst) = param.dst {
+        rule.dst = dst;
+    }
+    if let Some(proto) = param.proto {
+        rule.proto = proto;
+    }
+    if let Some(ports) = param.ports {
+        rule.ports = Some(ports).filter(|p| !p.trim().is_empty());
+    }
+    if let Some(allow) = param.allow {
+        rule.allow = allow;
+    }
+    if let Some(disabled) = param.disabled {
+        rule.disabled = disabled;
+    }
+    check_rule(&rule)?;
+    let mut model: AclRuleActiveModel = old.into();
+    model.priority = Set(rule.priority);
+    model.src = Set(rule.src);
+    model.dst = Set(rule.dst);
+    model.proto = Set(rule.proto);
+    model.ports = Set(rule.ports);
+    model.allow = Set(rule.allow);
+    model.disabled = Set(rule.disabled);
+    let model = model.update(server.conn()).await?;
+    broadcast_acl(server, model.network_id).await?;
+    Ok(model)
+}
+
+pub async fn delete(server: &VlinkServer, id: i64) -> Result<(), ApiError> {
+    let rule = AclRuleEntity::find_by_id(id)
+        .one(server.conn())
+        .await?
+        .ok_or(ApiError::NotFound("acl"))?;
+    AclRuleEntity::delete_by_id(id)
+        .exec(server.conn())
+        .await?;
+    broadcast_acl(server, rule.network_id).await?;
+    Ok(())
+}
+
+fn check_rule(rule: &AclRuleModel) -> Result<(), ApiError> {
+    check_selectors(rule.src.as_str()).map_err(ApiError::BadRequest)?;
+    check_selectors(rule.dst.as_str()).map_err(ApiError::BadRequest)?;
+    if !matches!(rule.proto.to_ascii_lowercase().as_str(), "" | "*" | "any" | "tcp" | "udp" | "icmp") {
+        return Err(ApiError::BadRequest(format!("协议错误:{}", rule.proto)));
+    }
+    if rule.ports.is_some() {
+        match rule.port_range() {
+            Some((start, end)) if start <= end => {}
+            _ => return Err(ApiError::BadRequest(format!("端口错误:{}", rule.ports.clone().unwrap_or_default()))),
+        }
+    }
+    Ok(())
+}
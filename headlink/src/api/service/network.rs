@@ -0,0 +1,130 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+use ip_network::{Ipv4Network, Ipv6Network};
+use sea_orm::*;
+use sea_orm::ActiveValue::Set;
+use serde::Deserialize;
+use crate::api::error::ApiError;
+use crate::db::entity::prelude::{AclRuleColumn, AclRuleEntity, NetworkActiveModel, NetworkEntity, NetworkModel, NetworkTokenColumn, NetworkTokenEntity, PeerColumn, PeerEntity, PeerModel};
+use crate::server::VlinkServer;
+use crate::SNOWFLAKE;
+
+#[derive(Debug, Deserialize)]
+pub struct NetworkParam {
+    pub cidr: String,
+    pub cidr6: Option<String>,
+    pub remark: Option<String>,
+    /// token 注册的节点需要审核
+    #[serde(default)]
+    pub require_approval: bool,
+}
+
+impl NetworkParam {
+    fn parse(&self) -> Result<(Ipv4Network, Option<Ipv6Network>), ApiError> {
+        let cidr = self.cidr.parse::<Ipv4Network>()
+            .map_err(|e| ApiError::BadRequest(format!("cidr错误:{}", e)))?;
+        let cidr6 = match self.cidr6.as_ref() {
+            None => None,
+            Some(c) => Some(c.parse::<Ipv6Network>()
+                .map_err(|e| ApiError::BadRequest(format!("cidr6错误:{}", e)))?),
+        };
+        Ok((cidr, cidr6))
+    }
+}
+
+pub async fn list(server: &VlinkServer) -> Result<Vec<NetworkModel>, ApiError> {
+    Ok(NetworkEntity::find().all(server.conn()).await?)
+}
+
+pub async fn get(server: &VlinkServer, network_id: i64) -> Result<NetworkModel, ApiError> {
+    NetworkEntity::find_by_id(network_id)
+        .one(server.conn())
+        .await?
+        .ok_or(ApiError::NotFound("网络"))
+}
+
+pub async fn create(server: &VlinkServer, param: NetworkParam) -> Result<NetworkModel, ApiError> {
+    let (cidr, cidr6) = param.parse()?;
+    let model = NetworkActiveModel {
+        network_id: Set(SNOWFLAKE.next_id()),
+        cidr: Set(cidr.to_string()),
+        cidr6: Set(cidr6.map(|c| c.to_string())),
+        remark: Set(param.remark),
+        require_approval: Set(param.require_approval),
+    }.insert(server.conn()).await?;
+    Ok(model)
+}
+
+/// 修改网段时已分配的ip 必须还在网段内
+pub async fn update(server: &VlinkServer, network_id: i64, param: NetworkParam) -> Result<NetworkModel, ApiError> {
+    let old = get(server, network_id).await?;
+    let (cidr, cidr6) = param.parse()?;
+    let peers = PeerEntity::find()
+        .filter(PeerColumn::NetworkId.eq(network_id))
+        .all(server.conn())
+        .await?;
+    check_peers_in_cidr(&peers, cidr, cidr6)?;
+
+    let cidr = cidr.to_string();
+    let cidr6 = cidr6.map(|c| c.to_string());
+    let cidr_changed = old.cidr != cidr || old.cidr6 != cidr6;
+    let mut model: NetworkActiveModel = old.into();
+    model.cidr = Set(cidr);
+    model.cidr6 = Set(cidr6);
+    model.remark = Set(param.remark);
+    model.require_approval = Set(param.require_approval);
+    let model = model.update(server.conn()).await?;
+
+    server.refresh_network(model.clone()).await?;
+    if cidr_changed {
+        // 网段变化,在线的peer 需要重新获取配置
+        if let Some(network) = server.cached_network(network_id).await {
+            for peer in network.peers.read_lock().await.values() {
+                if let Some(info) = peer.online_info.as_ref() {
+                    info.connect.kick();
+                }
+            }
+        }
+    }
+    Ok(model)
+}
+
+/// 网络下没有peer 才能删除
+pub async fn delete(server: &VlinkServer, network_id: i64) -> Result<(), ApiError> {
+    get(server, network_id).await?;
+    let count = PeerEntity::find()
+        .filter(PeerColumn::NetworkId.eq(network_id))
+        .count(server.conn())
+        .await?;
+    if count > 0 {
+        return Err(ApiError::BadRequest(format!("网络下还有{}个peer", count)));
+    }
+    NetworkTokenEntity::delete_many()
+        .filter(NetworkTokenColumn::NetworkId.eq(network_id))
+        .exec(server.conn())
+        .await?;
+    AclRuleEntity::delete_many()
+        .filter(AclRuleColumn::NetworkId.eq(network_id))
+        .exec(server.conn())
+        .await?;
+    NetworkEntity::delete_by_id(network_id)
+        .exec(server.conn())
+        .await?;
+    server.networks.remove(&network_id).await;
+    Ok(())
+}
+
+fn check_peers_in_cidr(peers: &[PeerModel], cidr: Ipv4Network, cidr6: Option<Ipv6Network>) -> Result<(), ApiError> {
+    for p in peers {
+        if let Some(ip) = p.ip.as_ref().and_then(|ip| ip.parse::<Ipv4Addr>().ok()) {
+            if !cidr.contains(ip) {
+                return Err(ApiError::BadRequest(format!("peer({}) ip {} 不在网段内", p.id, ip)));
+            }
+        }
+        if let (Some(cidr6), Some(ip)) = (cidr6, p.ipv6.as_ref().and_then(|ip| ip.parse::<Ipv6Addr>().ok())) {
+            if !cidr6.contains(ip) {
+                return Err(ApiError::BadRequest(format!("peer({}) ipv6 {} 不在网段内", p.id, ip)));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use chrono::Local;
+use ip_network::{Ipv4Network, Ipv6Network};
+use sea_orm::*;
+use sea_orm::ActiveValue::Set;
+use serde::{Deserialize, Serialize};
+use crate::api::error::ApiError;
+use log::warn;
+use vlink_core::proto::pb::abi::{BcPeerEnter, ConnectionMode};
+use vlink_core::proto::pb::abi::to_client::ToClientData;
+use vlink_core::base64::encode_base64;
+use vlink_core::secret::VlinkStaticSecret;
+use crate::{acl, dns, route, SNOWFLAKE};
+use crate::api::service::network;
+use crate::client::handler::req_config::{build_resp_config, generate_ip, generate_ipv6};
+use crate::db::entity::prelude::{PeerActiveModel, PeerColumn, PeerEntity, PeerModel};
+use crate::network::VlinkNetwork;
+use crate::peer::VlinkPeer;
+use crate::server::VlinkServer;
+
+/// 标准 wireguard 客户端在nat 后面时的保活间隔
+const WG_KEEPALIVE: u32 = 25;
+
+#[derive(Debug, Deserialize)]
+pub struct UpdatePeerParam {
+    pub name: Option<String>,
+    /// 固定ip
+    pub ip: Option<String>,
+    pub default_proto: Option<String>,
+    pub disabled: Option<bool>,
+    /// 标签, 逗号分隔
+    pub tags: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePeerParam {
+    pub name: Option<String>,
+    /// 标签, 逗号分隔
+    pub tags: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeerView {
+    #[serde(flatten)]
+    pub model: PeerModel,
+    pub online: bool,
+}
+
+pub async fn list(server: &VlinkServer, network_id: i64, pending: Option<bool>) -> Result<Vec<PeerView>, ApiError> {
+    let mut query = PeerEntity::find()
+        .filter(PeerColumn::NetworkId.eq(network_id));
+    if let Some(pending) = pending {
+        query = query.filter(PeerColumn::Pending.eq(pending));
+    }
+    let peers = query.all(server.conn()).await?;
+    let network = server.cached_network(network_id).await;
+    let mut views = vec![];
+    for model in peers {
+        let online = match network.as_ref() {
+            None => false,
+            Some(n) => n.peers.read_lock().await
+                .get(model.pub_key.as_str())
+                .map(|p| p.is_online())
+                .unwrap_or(false),
+        };
+        views.push(PeerView { model, online });
+    }
+    Ok(views)
+}
+
+/// 修改ip 时在线的peer 会被断开, 重连后获取新的配置
+/// 禁用时同时通知其他节点移除该peer
+pub async fn update(server: &VlinkServer, id: i64, param: UpdatePeerParam) -> Result<PeerModel, ApiError> {
+    let old = PeerEntity::find_by_id(id)
+        .one(server.conn())
+        .await?
+        .ok_or(ApiError::NotFound("peer"))?;
+    let mut kick = false;
+    let mut remove = false;
+    let mut tags_changed = false;
+    let mut model: PeerActiveModel = old.clone().into();
+    if let Some(name) = param.name {
+        model.name = Set(Some(name));
+    }
+    if let Some(default_proto) = param.default_proto {
+        model.default_proto = Set(Some(default_proto));
+    }
+    if let Some(disabled) = param.disabled {
+        remove = disabled && !old.disabled;
+        model.disabled = Set(disabled);
+    }
+    if let Some(tags) = param.tags {
+        let tags = Some(tags).filter(|t| !t.trim().is_empty());
+        tags_changed = tags != old.tags;
+        model.tags = Set(tags);
+    }
+    if let Some(ip) = param.ip {
+        let ip = check_ip(server, &old, ip.as_str()).await?;
+        kick |= old.ip.as_deref() != Some(ip.as_str());
+        model.ip = Set(Some(ip));
+    }
+    model.update_at = Set(Some(Local::now().naive_local()));
+    let model = model.update(server.conn()).await?;
+
+    if let Some(network) = server.cached_network(model.network_id).await {
+        network.peers.refresh_model(model.clone()).await;
+        if remove {
+            network.remove_peer(model.pub_key.as_str()).await;
+        } else if kick {
+            if let Some(p) = network.peers.read_lock().await.get(model.pub_key.as_str()) {
+                if let Some(info) = p.online_info.as_ref() {
+                    info.connect.kick();
+                }
+            }
+        }
+    }
+    // 标签, 地址变化或节点删除后, 规则解析出的地址会变化
+    if tags_changed || kick || remove {
+        acl::broadcast_acl(server, model.network_id).await?;
+    }
+    Ok(model)
+}
+
+/// 审核通过, 下发等待中的配置并通知其他节点
+pub async fn approve(server: &VlinkServer, id: i64) -> Result<PeerModel, ApiError> {
+    let old = find_pending(server, id).await?;
+    let mut model: PeerActiveModel = old.into();
+    model.pending = Set(false);
+    model.update_at = Set(Some(Local::now().naive_local()));
+    let model = model.update(server.conn()).await?;
+
+    let network = server.get_network(model.network_id).await?;
+    network.peers.refresh_model(model.clone()).await;
+    if let Some((connect, req_id)) = network.pending_configs.remove(&model.pub_key).await {
+        // 分配ip 后再通知
+        let resp = build_resp_config(server, model.network_id, model.pub_key.as_str()).await?;
+        if let Err(e) = connect.send(Some(req_id), ToClientData::RespConfig(resp)).await {
+            warn!("下发配置失败:{}", e);
+        }
+    }
+    let model = network.peers.read_lock().await
+        .get(model.pub_key.as_str())
+        .map(|p| p.model.clone())
+        .unwrap_or(model);
+    broadcast_enter(server, &network, &model).await?;
+    Ok(model)
+}
+
+/// 通知其他节点新增了离线节点, 节点上线后再更新端点
+async fn broadcast_enter(server: &VlinkServer, network: &VlinkNetwork, model: &PeerModel) -> Result<(), ApiError> {
+    if let Some(ip) = model.ip.clone() {
+        let routes = route::peer_approved_routes(server, model.id).await?;
+        network.broadcast(ToClientData::PeerEnter(BcPeerEnter {
+            pub_key: model.pub_key.clone(),
+            ip,
+            endpoint_addr: None,
+            port: 0,
+            last_con_type: None,
+            mode: i32::from(ConnectionMode::Bidirectional),
+            is_online: false,
+            ipv6: model.ipv6.clone(),
+            routes,
+            hostname: model.hostname.clone(),
+            observed_ip: None,
+        }), model.pub_key.as_str()).await;
+    }
+    Ok(())
+}
+
+/// 由服务器生成密钥的节点, 用于手机,路由器等只支持udp 的标准 wireguard 客户端
+/// 创建时分配ip, 导出配置后直接使用
+pub async fn create(server: &VlinkServer, network_id: i64, param: CreatePeerParam) -> Result<PeerModel, ApiError> {
+    network::get(server, network_id).await?;
+    let network = server.get_network(network_id).await?;
+    let secret = VlinkStaticSecret::generate();
+    let ip = generate_ip(network.cidr, &network.peers).await?;
+    let ipv6 = match network.cidr6 {
+        Some(cidr6) => Some(generate_ipv6(cidr6, &network.peers).await?.to_string()),
+        None => None,
+    };
+    let model = new_peer(network_id, &secret, ip, ipv6, &param)
+        .insert(server.conn()).await?;
+    network.peers.insert(model.pub_key.clone(), VlinkPeer::from(model.clone())).await;
+    if let Some(name) = param.name.as_deref() {
+        dns::assign_hostname(server, &network, model.pub_key.as_str(), name).await?;
+    }
+    let model = network.peers.read_lock().await
+        .get(model.pub_key.as_str())
+        .map(|p| p.model.clone())
+        .unwrap_or(model);
+    broadcast_enter(server, &network, &model).await?;
+    // 新节点可能被 peer:/tag: 规则引用
+    acl::broadcast_acl(server, network_id).await?;
+    Ok(model)
+}
+
+/// 已审核, 不需要等待节点连接
+fn new_peer(network_id: i64, secret: &VlinkStaticSecret, ip: Ipv4Addr, ipv6: Option<String>, param: &CreatePeerParam) -> PeerActiveModel {
+    let now = Local::now().naive_local();
+    PeerActiveModel {
+        id: Set(SNOWFLAKE.next_id()),
+        pub_key: Set(secret.base64_pub()),
+        name: Set(param.name.clone()),
+        ip: Set(Some(ip.to_string())),
+        ipv6: Set(ipv6),
+        default_proto: Set(None),
+        endpoint_addr: Set(None),
+        port: Set(None),
+        network_id: Set(network_id),
+        disabled: Set(false),
+        pending: Set(false),
+        tags: Set(param.tags.clone().filter(|t| !t.trim().is_empty())),
+        hostname: Set(None),
+        private_key: Set(Some(encode_base64(secret.private_key.as_bytes()))),
+        create_at: Set(Some(now)),
+        update_at: Set(Some(now)),
+    }
+}
+
+/// 导出节点的 wg-quick 配置
+/// 私钥由节点自己保存时留空, 只有配置了公网地址的在线节点填写 Endpoint
+pub async fn wg_config(server: &VlinkServer, id: i64) -> Result<String, ApiError> {
+    let model = PeerEntity::find_by_id(id)
+        .one(server.conn())
+        .await?
+        .ok_or(ApiError::NotFound("peer"))?;
+    let network = server.get_network(model.network_id).await?;
+    let routes = route::approved_routes(server, model.network_id).await?;
+    let peers: Vec<VlinkPeer> = network.peers.read_lock().await
+        .values()
+        .cloned()
+        .collect();
+    render_wg_config(&model, network.cidr, network.cidr6, peers, routes)
+}
+
+/// peers 为网络内全部节点, 跳过自己, 禁用, 待审核和未分配ip 的
+fn render_wg_config(model: &PeerModel,
+                    cidr: Ipv4Network,
+                    cidr6: Option<Ipv6Network>,
+                    peers: Vec<VlinkPeer>,
+                    mut routes: HashMap<i64, Vec<String>>) -> Result<String, ApiError> {
+    let ip = model.ip.clone()
+        .ok_or(ApiError::BadRequest("节点未分配ip".to_string()))?;
+    let mut out = String::new();
+    let _ = writeln!(out, "[Interface]");
+    match model.private_key.as_ref() {
+        Some(key) => { let _ = writeln!(out, "PrivateKey = {key}"); }
+        None => { let _ = writeln!(out, "# PrivateKey = <节点私钥>"); }
+    }
+    let mut address = vec![format!("{}/{}", ip, cidr.netmask())];
+    if let (Some(ipv6), Some(cidr6)) = (model.ipv6.as_ref(), cidr6) {
+        address.push(format!("{}/{}", ipv6, cidr6.netmask()));
+    }
+    let _ = writeln!(out, "Address = {}", address.join(", "));
+
+    let mut peers: Vec<VlinkPeer> = peers.into_iter()
+        .filter(|p| p.pub_key != model.pub_key && !p.model.disabled && !p.model.pending && p.model.ip.is_some())
+        .collect();
+    peers.sort_by_key(|p| p.model.ip.as_ref().and_then(|ip| ip.parse::<Ipv4Addr>().ok()));
+    for p in peers {
+        let _ = writeln!(out, "\n[Peer]");
+        if let Some(name) = p.model.hostname.as_ref().or(p.model.name.as_ref()) {
+            let _ = writeln!(out, "# {name}");
+        }
+        let _ = writeln!(out, "PublicKey = {}", p.pub_key);
+        let mut allowed_ips = vec![format!("{}/32", p.model.ip.as_deref().unwrap_or_default())];
+        if let Some(ipv6) = p.model.ipv6.as_ref() {
+            allowed_ips.push(format!("{ipv6}/128"));
+        }
+        // 出口节点的默认路由需要客户端自己选择
+        allowed_ips.extend(routes.remove(&p.model.id).unwrap_or_default()
+            .into_iter()
+            .filter(|r| !r.ends_with("/0")));
+        let _ = writeln!(out, "AllowedIPs = {}", allowed_ips.join(", "));
+        let endpoint = p.online_info.as_ref()
+            .and_then(|info| udp_endpoint(info.endpoint_addr.as_deref(), info.port));
+        if let Some(endpoint) = endpoint {
+            let _ = writeln!(out, "Endpoint = {endpoint}");
+            let _ = writeln!(out, "PersistentKeepalive = {WG_KEEPALIVE}");
+        }
+    }
+    Ok(out)
+}
+
+/// 只使用节点配置的公网地址
+/// 服务器看到的是控制连接的地址, 不知道 nat 给 udp 端口映射的外部端口, 不能拼成端点
+fn udp_endpoint(endpoint_addr: Option<&str>, port: u32) -> Option<String> {
+    let host = endpoint_addr.filter(|h| !h.is_empty())?;
+    if port == 0 || port > u16::MAX as u32 {
+        return None;
+    }
+    Some(match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port as u16).to_string(),
+        // 域名
+        Err(_) => format!("{}:{}", host, port),
+    })
+}
+
+/// 拒绝, 节点禁用并断开
+pub async fn reject(server: &VlinkServer, id: i64) -> Result<PeerModel, ApiError> {
+    let old = find_pending(server, id).await?;
+    let mut model: PeerActiveModel = old.into();
+    model.pending = Set(false);
+    model.disabled = Set(true);
+    model.update_at = Set(Some(Local::now().naive_local()));
+    let model = model.update(server.conn()).await?;
+
+    if let Some(network) = server.cached_network(model.network_id).await {
+        network.peers.refresh_model(model.clone()).await;
+        if let Some((connect, _)) = network.pending_configs.remove(&model.pub_key).await {
+            connect.kick();
+        }
+    }
+    Ok(model)
+}
+
+async fn find_pending(server: &VlinkServer, id: i64) -> Result<PeerModel, ApiError> {
+    let peer = PeerEntity::find_by_id(id)
+        .one(server.conn())
+        .await?
+        .ok_or(ApiError::NotFound("peer"))?;
+    if !peer.pending {
+        return Err(ApiError::BadRequest("peer不在审核中".to_string()));
+    }
+    Ok(peer)
+}
+
+/// ip 必须在网段内, 并且没有被其他peer 使用
+async fn check_ip(server: &VlinkServer, peer: &PeerModel, ip: &str) -> Result<String, ApiError> {
+    let addr: Ipv4Addr = ip.parse()
+        .map_err(|_| ApiError::BadRequest(format!("ip错误:{}", ip)))?;
+    let network = network::get(server, peer.network_id).await?;
+    let cidr: Ipv4Network = network.cidr.parse()
+        .map_err(|e| ApiError::BadRequest(format!("cidr错误:{}", e)))?;
+    if !cidr.contains(addr) || addr == cidr.network_address() || addr == cidr.broadcast_address() {
+        return Err(ApiError::BadRequest(format!("ip {} 不在网段 {} 内", addr, cidr)));
+    }
+    let used = PeerEntity::find()
+        .filter(PeerColumn::NetworkId.eq(peer.network_id)
+            .and(PeerColumn::Ip.eq(addr.to_string()))
+            .and(PeerColumn::Id.ne(peer.id)))
+        .count(server.conn())
+        .await?;
+    if used > 0 {
+        return Err(ApiError::BadRequest(format!("ip {} 已被使用", addr)));
+    }
+    Ok(addr.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vlink_core::base64::decode_base64;
+    use crate::server::Peers;
+
+    fn peer(id: i64, ip: &str) -> VlinkPeer {
+        let secret = VlinkStaticSecret::generate();
+        VlinkPeer::from(PeerModel {
+            id,
+            pub_key: secret.base64_pub(),
+            name: None,
+            ip: Some(ip.to_string()),
+            ipv6: None,
+            default_proto: None,
+            endpoint_addr: None,
+            port: None,
+            network_id: 1,
+            disabled: false,
+            pending: false,
+            tags: None,
+            hostname: None,
+            private_key: None,
+            create_at: None,
+            update_at: None,
+        })
+    }
+
+    #[test]
+    fn test_udp_endpoint() {
+        assert_eq!(udp_endpoint(Some("1.2.3.4"), 51820), Some("1.2.3.4:51820".to_string()));
+        assert_eq!(udp_endpoint(Some("2001:db8::1"), 51820), Some("[2001:db8::1]:51820".to_string()));
+        assert_eq!(udp_endpoint(Some("vpn.example.com"), 51820), Some("vpn.example.com:51820".to_string()));
+        // 没有配置公网地址时不用服务器看到的ip 猜测
+        assert_eq!(udp_endpoint(None, 51820), None);
+        assert_eq!(udp_endpoint(Some(""), 51820), None);
+        assert_eq!(udp_endpoint(Some("1.2.3.4"), 0), None);
+        assert_eq!(udp_endpoint(Some("1.2.3.4"), 70000), None);
+    }
+
+    #[tokio::test]
+    async fn test_create() {
+        let cidr: Ipv4Network = "10.0.0.0/24".parse().unwrap();
+        let peers = Peers::new(vec![peer(1, "10.0.0.2")]);
+        let secret = VlinkStaticSecret::generate();
+        let ip = generate_ip(cidr, &peers).await.unwrap();
+        let param = CreatePeerParam {
+            name: Some("phone".to_string()),
+            tags: Some(" ".to_string()),
+        };
+        let model = new_peer(1, &secret, ip, None, &param).try_into_model().unwrap();
+        assert_eq!(model.ip.as_deref(), Some("10.0.0.3"));
+        assert_eq!(model.name.as_deref(), Some("phone"));
+        assert_eq!(model.tags, None);
+        assert!(!model.pending && !model.disabled);
+        // 导出的私钥和公钥配对
+        let private_key = decode_base64(model.private_key.as_deref().unwrap()).unwrap();
+        let hex: String = private_key.iter().map(|b| format!("{b:02x}")).collect();
+        let restored: VlinkStaticSecret = serde_json::from_value(serde_json::Value::String(hex)).unwrap();
+        assert_eq!(restored.base64_pub(), model.pub_key);
+
+        // 加入后不会再分配同一个ip
+        peers.insert(model.pub_key.clone(), VlinkPeer::from(model)).await;
+        assert_eq!(generate_ip(cidr, &peers).await.unwrap(), "10.0.0.4".parse::<Ipv4Addr>().unwrap());
+    }
+
+    #[test]
+    fn test_wg_config() {
+        let mut me = peer(1, "10.0.0.2");
+        me.model.ipv6 = Some("fd00::2".to_string());
+        me.model.private_key = Some("cHJpdmF0ZQ==".to_string());
+        let b = peer(2, "10.0.0.10");
+        let mut c = peer(3, "10.0.0.3");
+        c.model.hostname = Some("nas".to_string());
+        let mut disabled = peer(4, "10.0.0.4");
+        disabled.model.disabled = true;
+        let mut pending = peer(5, "10.0.0.5");
+        pending.model.pending = true;
+        let routes: HashMap<i64, Vec<String>> = [
+            (2, vec!["192.168.1.0/24".to_string(), "0.0.0.0/0".to_string()]),
+        ].into_iter().collect();
+
+        let peers = vec![me.clone(), b.clone(), c.clone(), disabled, pending];
+        let out = render_wg_config(&me.model,
+                                   "10.0.0.0/24".parse().unwrap(),
+                                   Some("fd00::/64".parse().unwrap()),
+                                   peers,
+                                   routes).unwrap();
+        let expected = format!("[Interface]
+PrivateKey = cHJpdmF0ZQ==
+Address = 10.0.0.2/24, fd00::2/64
+
+[Peer]
+# nas
+PublicKey = {}
+AllowedIPs = 10.0.0.3/32
+
+[Peer]
+PublicKey = {}
+AllowedIPs = 10.0.0.10/32, 192.168.1.0/24
+", c.pub_key, b.pub_key);
+        assert_eq!(out, expected);
+
+        // 节点自己保存私钥
+        let mut other = peer(6, "10.0.0.6");
+        other.model.private_key = None;
+        let out = render_wg_config(&other.model, "10.0.0.0/24".parse().unwrap(), None, vec![], HashMap::new()).unwrap();
+        assert!(out.contains("# PrivateKey = "));
+        other.model.ip = None;
+        assert!(render_wg_config(&other.model, "10.0.0.0/24".parse().unwrap(), None, vec![], HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use sea_orm::*;
+use sea_orm::ActiveValue::Set;
+use crate::acl::parse_cidr;
+use crate::api::error::ApiError;
+use crate::db::entity::prelude::{PeerEntity, PeerRouteActiveModel, PeerRouteColumn, PeerRouteEntity, PeerRouteModel};
+use crate::route::broadcast_peer_routes;
+use crate::server::VlinkServer;
+
+pub async fn list(server: &VlinkServer, peer_id: i64) -> Result<Vec<PeerRouteModel>, ApiError> {
+    Ok(PeerRouteEntity::find()
+        .filter(PeerRouteColumn::PeerId.eq(peer_id))
+        .all(server.conn())
+        .await?)
+}
+
+/// 同一网络中已审核的路由不能重叠, 否则无法确定下一跳
+/// 默认路由除外, 可以有多个出口节点由客户端选择
+pub async fn approve(server: &VlinkServer, id: i64) -> Result<PeerRouteModel, ApiError> {
+    let route = find(server, id).await?;
+    let net = parse_cidr(route.cidr.as_str())
+        .ok_or(ApiError::BadRequest(format!("子网格式错误:{}", route.cidr)))?;
+    let approved = PeerRouteEntity::find()
+        .filter(PeerRouteColumn::NetworkId.eq(route.network_id)
+            .and(PeerRouteColumn::Approved.eq(true))
+            .and(PeerRouteColumn::PeerId.ne(route.peer_id)))
+        .all(server.conn())
+        .await?;
+    for other in approved {
+        if let Some(o) = parse_cidr(other.cidr.as_str()) {
+            if net.netmask() == 0 || o.netmask() == 0 {
+                continue;
+            }
+            if o.contains(net.network_address()) || net.contains(o.network_address()) {
+                return Err(ApiError::BadRequest(format!("子网{} 与节点{} 的{} 重叠", route.cidr, other.peer_id, other.cidr)));
+            }
+        }
+    }
+    let mut model: PeerRouteActiveModel = route.into();
+    model.approved = Set(true);
+    let model = model.update(server.conn()).await?;
+    notify(server, &model).await?;
+    Ok(model)
+}
+
+/// 删除后节点重连时再次宣告会重新进入审核
+pub async fn delete(server: &VlinkServer, id: i64) -> Result<(), ApiError> {
+    let route = find(server, id).await?;
+    PeerRouteEntity::delete_by_id(id)
+        .exec(server.conn())
+        .await?;
+    if route.approved {
+        notify(server, &route).await?;
+    }
+    Ok(())
+}
+
+async fn find(server: &VlinkServer, id: i64) -> Result<PeerRouteModel, ApiError> {
+    PeerRouteEntity::find_by_id(id)
+        .one(server.conn())
+        .await?
+        .ok_or(ApiError::NotFound("route"))
+}
+
+async fn notify(server: &VlinkServer, route: &PeerRouteModel) -> Result<(), ApiError> {
+    if let Some(peer) = PeerEntity::find_by_id(route.peer_id).one(server.conn()).await? {
+        broadcast_peer_routes(server, route.network_id, peer.pub_key.as_str()).await?;
+    }
+    Ok(())
+}
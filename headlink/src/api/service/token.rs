@@ -0,0 +1,73 @@
+use chrono::{Local, NaiveDateTime};
+use rand::RngCore;
+use sea_orm::*;
+use sea_orm::ActiveValue::Set;
+use serde::Deserialize;
+use crate::api::error::ApiError;
+use crate::api::service::network;
+use crate::db::entity::prelude::{NetworkTokenActiveModel, NetworkTokenColumn, NetworkTokenEntity, NetworkTokenModel};
+use crate::server::VlinkServer;
+use crate::SNOWFLAKE;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenParam {
+    pub expire_at: Option<NaiveDateTime>,
+    pub max_uses: Option<i32>,
+    #[serde(default)]
+    pub single_use: bool,
+    /// 注册的节点需要审核, 即使网络不要求审核
+    #[serde(default)]
+    pub require_approval: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateTokenParam {
+    pub disabled: Option<bool>,
+    pub expire_at: Option<NaiveDateTime>,
+    pub max_uses: Option<i32>,
+}
+
+pub async fn list(server: &VlinkServer, network_id: i64) -> Result<Vec<NetworkTokenModel>, ApiError> {
+    Ok(NetworkTokenEntity::find()
+        .filter(NetworkTokenColumn::NetworkId.eq(network_id))
+        .all(server.conn())
+        .await?)
+}
+
+pub async fn create(server: &VlinkServer, network_id: i64, param: CreateTokenParam) -> Result<NetworkTokenModel, ApiError> {
+    network::get(server, network_id).await?;
+    let mut token = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut token);
+    let model = NetworkTokenActiveModel {
+        token: Set(hex::encode(token)),
+        network_id: Set(network_id),
+        create_at: Set(Some(Local::now().naive_local())),
+        disabled: Set(false),
+        expire_at: Set(param.expire_at),
+        max_uses: Set(param.max_uses),
+        used_count: Set(0),
+        single_use: Set(param.single_use),
+        require_approval: Set(param.require_approval),
+        id: Set(SNOWFLAKE.next_id()),
+    }.insert(server.conn()).await?;
+    Ok(model)
+}
+
+/// 禁用,设置过期时间或使用次数, token 只在握手时校验, 不影响已连接的peer
+pub async fn update(server: &VlinkServer, id: i64, param: UpdateTokenParam) -> Result<NetworkTokenModel, ApiError> {
+    let old = NetworkTokenEntity::find_by_id(id)
+        .one(server.conn())
+        .await?
+        .ok_or(ApiError::NotFound("token"))?;
+    let mut model: NetworkTokenActiveModel = old.into();
+    if let Some(disabled) = param.disabled {
+        model.disabled = Set(disabled);
+    }
+    if let Some(expire_at) = param.expire_at {
+        model.expire_at = Set(Some(expire_at));
+    }
+    if let Some(max_uses) = param.max_uses {
+        model.max_uses = Set(Some(max_uses));
+    }
+    Ok(model.update(server.conn()).await?)
+}
@@ -1,4 +1,24 @@
-#[derive(Clone)]
-pub struct AppState{
+use std::ops::Deref;
+use std::sync::Arc;
+use derive_new::new;
+use crate::server::VlinkServer;
 
-}
\ No newline at end of file
+#[derive(Clone, new)]
+pub struct AppState {
+    inner: Arc<AppStateInner>,
+}
+
+#[derive(new)]
+pub struct AppStateInner {
+    pub server: VlinkServer,
+    /// 管理接口的访问token
+    pub token: String,
+}
+
+impl Deref for AppState {
+    type Target = AppStateInner;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref()
+    }
+}
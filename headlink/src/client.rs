@@ -10,9 +10,11 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use anyhow::{anyhow, Error};
 use bytes::{Bytes, BytesMut};
+use chrono::Local;
+use vlink_core::base64::decode_base64;
 use vlink_core::proto::pb::abi::*;
 use futures::{SinkExt, Stream, StreamExt};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use vlink_core::proto::pb::abi::RespConfig;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::select;
@@ -27,11 +29,16 @@ use vlink_core::proto::bind_transport;
 use futures::FutureExt;
 use futures_util::future::join_all;
 use prost::Message;
-use sea_orm::{EntityTrait, QueryFilter};
+use sea_orm::{ActiveModelTrait, EntityTrait, QueryFilter, TransactionTrait};
+use sea_orm::ActiveValue::Set;
+use sea_orm::sea_query::Expr;
 use tokio::time::timeout;
-use crate::db::entity::prelude::{NetworkEntity, NetworkTokenColumn, NetworkTokenEntity, PeerColumn, PeerEntity};
+use tokio_util::sync::CancellationToken;
+use crate::db::entity::prelude::{NetworkEntity, NetworkTokenColumn, NetworkTokenEntity, PeerActiveModel, PeerColumn, PeerEntity, PeerModel};
 use crate::client::dispatcher::{Dispatcher, ClientRequest, RequestContext};
 use crate::peer::VlinkPeer;
+use crate::SNOWFLAKE;
+use crate::dns;
 
 pub type ToClientParam = (Option<u64>, ToClientData, oneshot::Sender<Result<u64, std::io::Error>>);
 
@@ -47,6 +54,8 @@ pub struct ClientConnect {
     pub addr: SocketAddr,
     pub client_id: Arc<OnceLock<ClientId>>,
     sender: mpsc::Sender<ToClientParam>,
+    /// 服务端主动断开
+    kicker: CancellationToken,
 }
 
 impl ClientConnect {
@@ -59,6 +68,10 @@ impl ClientConnect {
     pub async fn close(&self) {
         self.sender.closed().await
     }
+    /// 主动断开连接,客户端重连后重新获取配置
+    pub fn kick(&self) {
+        self.kicker.cancel();
+    }
     pub fn client_id(&self) -> Option<ClientId> {
         self.client_id.get().cloned()
     }
@@ -91,7 +104,9 @@ impl ClientStream {
             addr,
             client_id: Arc::new(Default::default()),
             sender: tx,
+            kicker: CancellationToken::new(),
         };
+        let kicker = client.kicker.clone();
         let (mut sink, mut stream) = bind_transport(stream).split();
 
         //开启数据交换
@@ -133,6 +148,7 @@ impl ClientStream {
                     resp = recv_handler => {resp}
                     resp = to_client_handler => {resp}
                     resp = process => {resp}
+                    _ = kicker.cancelled() => {Err(anyhow!("服务端断开连接"))}
                 };
                 error!("process error:{:?}",resp);
                 is_connected_c.store(false, Ordering::SeqCst);
@@ -188,6 +204,58 @@ async fn process_client(server: VlinkServer, client: ClientConnect, tx: broadcas
 
 
 //<T: AsyncRead + AsyncWrite>(stream: T) where <T as Stream>::Item: Vec<u8>
+/// 校验token 并注册新节点
+async fn enroll_peer(server: &VlinkServer, token: &str, pub_key: &str, hostname: Option<String>) -> anyhow::Result<PeerModel> {
+    let token = NetworkTokenEntity::find()
+        .filter(NetworkTokenColumn::Token.eq(token))
+        .one(server.conn())
+        .await?
+        .ok_or(anyhow!("token不存在"))?;
+    token.check_enroll(Local::now().naive_local()).map_err(|e| anyhow!(e))?;
+    let network = NetworkEntity::find_by_id(token.network_id)
+        .one(server.conn())
+        .await?
+        .ok_or(anyhow!("网络id不存在"))?;
+    // 占用次数和插入节点在同一个事务中, 插入失败时回滚, 并发注册时不会超过上限
+    let txn = server.conn().begin().await?;
+    let mut update = NetworkTokenEntity::update_many()
+        .col_expr(NetworkTokenColumn::UsedCount, Expr::col(NetworkTokenColumn::UsedCount).add(1))
+        .filter(NetworkTokenColumn::Id.eq(token.id));
+    if let Some(limit) = token.use_limit() {
+        update = update.filter(NetworkTokenColumn::UsedCount.lt(limit));
+    }
+    if update.exec(&txn).await?.rows_affected == 0 {
+        return Err(anyhow!("token使用次数已达上限"));
+    }
+    let now = Local::now().naive_local();
+    let model = PeerActiveModel {
+        id: Set(SNOWFLAKE.next_id()),
+        pub_key: Set(pub_key.to_string()),
+        name: Set(hostname),
+        ip: Set(None),
+        ipv6: Set(None),
+        default_proto: Set(None),
+        endpoint_addr: Set(None),
+        port: Set(None),
+        network_id: Set(token.network_id),
+        // 审核只用 pending 一个状态, 禁用留给管理员禁用和拒绝
+        disabled: Set(false),
+        pending: Set(network.require_approval || token.require_approval),
+        tags: Set(None),
+        hostname: Set(None),
+        private_key: Set(None),
+        create_at: Set(Some(now)),
+        update_at: Set(Some(now)),
+    }.insert(&txn).await?;
+    txn.commit().await?;
+    info!("token注册节点:{} network:{}", pub_key, model.network_id);
+    // 已加载的网络需要加入新节点
+    if let Some(network) = server.cached_network(model.network_id).await {
+        network.peers.insert(model.pub_key.clone(), VlinkPeer::from(model.clone())).await;
+    }
+    Ok(model)
+}
+
 /// 握手成功返回pub_key
 async fn await_handshake(server: VlinkServer, secs: u64, client: ClientConnect, mut rx: broadcast::Receiver<ToServer>) -> anyhow::Result<ClientId> {
     let info = server.info.clone();
@@ -200,39 +268,45 @@ async fn await_handshake(server: VlinkServer, secs: u64, client: ClientConnect,
         if let Ok(data) = rx.recv().await {
             let id = data.id;
             let result = handshake0(&server, &client, data).await;
-            client.send(Some(id), ToClientData::RespHandshake(RespHandshake { success: result.is_ok(), msg: result.as_ref().err().map(|e| e.to_string()) })).await?;
-            return result;
+            client.send(Some(id), ToClientData::RespHandshake(RespHandshake {
+                success: result.is_ok(),
+                msg: result.as_ref().err().map(|e| e.to_string()),
+                pending: result.as_ref().map(|r| r.1).unwrap_or(false),
+                observed_ip: Some(client.addr.ip().to_string()),
+            })).await?;
+            return result.map(|r| r.0);
         }
         Err(anyhow!("握手包处理错误"))
     }).await.map_err(|_| anyhow!("握手超时"))?
 }
 
 
-async fn handshake0(server: &VlinkServer, client: &ClientConnect, data: ToServer) -> anyhow::Result<ClientId> {
+/// 返回节点id 和是否待审核
+async fn handshake0(server: &VlinkServer, client: &ClientConnect, data: ToServer) -> anyhow::Result<(ClientId, bool)> {
     if let Some(ToServerData::Handshake(data)) = data.to_server_data {
         debug!("握手包数据:{:?}",data);
         let pub_key = data.pub_key.clone();
+        // 先证明持有私钥, 再查询或注册节点
+        let key: [u8; 32] = decode_base64(pub_key.as_str())?.try_into()
+            .map_err(|_| anyhow!("pub_key 长度错误"))?;
+        if !server.info.secret.verify_hello(key, data.sign.as_str()) {
+            return Err(anyhow!("握手签名错误"));
+        }
 
-        let network_id = if let Some(token) = data.token.clone() {
-            let token = NetworkTokenEntity::find()
-                .filter(NetworkTokenColumn::Token.eq(data.token.unwrap().as_str()))
-                .one(server.conn())
-                .await?
-                .ok_or(anyhow!("token不存在"))?;
-            if token.disabled {
-                return Err(anyhow!("token已禁用"));
-            }
-            let network = server.get_network(token.network_id).await?;
-            network.network_id
-        } else {
-            //peer取
-            let peer = PeerEntity::find()
-                .filter(PeerColumn::PubKey.eq(pub_key.as_str()))
-                .one(server.conn())
-                .await?
-                .ok_or(anyhow!("peer 未注册"))?;
-            peer.network_id
+        let peer = PeerEntity::find()
+            .filter(PeerColumn::PubKey.eq(pub_key.as_str()))
+            .one(server.conn())
+            .await?;
+        let peer = match (peer, data.token.as_ref()) {
+            (Some(peer), _) => peer,
+            //新节点通过token 注册
+            (None, Some(token)) => enroll_peer(server, token, pub_key.as_str(), data.hostname.clone()).await?,
+            (None, None) => return Err(anyhow!("peer 未注册")),
         };
+        if peer.disabled {
+            return Err(anyhow!("peer已禁用"));
+        }
+        let network_id = peer.network_id;
         let pub_key_c = pub_key.clone();
         // 从server中 取网络
         let client_id = ClientId {
@@ -249,8 +323,14 @@ async fn handshake0(server: &VlinkServer, client: &ClientConnect, data: ToServer
                 return Err(anyhow!(err));
             }
         }
+        if let Some(hostname) = data.hostname.as_deref() {
+            // 主机名只影响 dns, 失败不影响握手
+            if let Err(e) = dns::assign_hostname(server, &network, pub_key_c.as_str(), hostname).await {
+                warn!("节点{} 主机名设置失败:{:?}", pub_key_c, e);
+            }
+        }
         // client.send(Some(id), ToClientData::RespHandshake(RespHandshake { success: true, msg: None })).await?;
-        return Ok(client_id);
+        return Ok((client_id, peer.pending));
     } else {
         Err(anyhow!("握手包数据错误"))
     }
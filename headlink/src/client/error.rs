@@ -22,6 +22,8 @@ pub enum ExecuteError {
     IpNotMatch,
     #[error("IpNotFound")]
     IpNotFound,
+    #[error("PeerPending")]
+    PeerPending,
 }
 
 impl ExecuteError {
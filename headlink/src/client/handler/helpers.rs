@@ -1,4 +1,15 @@
+use crate::client::error::ExecuteError;
+use crate::network::VlinkNetwork;
 
+/// 待审核的节点不能参与组网, 不能发送或接收打洞和端点信息
+pub async fn check_pending(network: &VlinkNetwork, pub_key: &str) -> Result<(), ExecuteError> {
+    let peers = network.peers.read_lock().await;
+    let peer = peers.get(pub_key).ok_or(ExecuteError::PeerNotFound)?;
+    if peer.model.pending {
+        return Err(ExecuteError::PeerPending);
+    }
+    Ok(())
+}
 
 pub fn union_pub_key(a: &str, b: &str) -> (String, bool) {
     match a < b {
@@ -1,7 +1,7 @@
 use crate::client::dispatcher::{ClientRequest, RequestContext};
 use crate::client::error::ExecuteError;
 
-mod req_config;
+pub(crate) mod req_config;
 mod peer_enter;
 mod update_extra_endpoint;
 mod dev_handshake_complete;
@@ -7,6 +7,7 @@ use crate::client::dispatcher::{ClientRequest, RequestContext};
 use crate::client::error::ExecuteError;
 use crate::client::handler::{ExecuteResult, ToServerDataHandler};
 use crate::peer::OnlineInfo;
+use crate::route::{peer_approved_routes, sync_advertised};
 
 /// 处理客户端首次连接或者恢复连接
 impl ToServerDataHandler for PeerEnter {
@@ -16,6 +17,9 @@ impl ToServerDataHandler for PeerEnter {
         let mut lock = network.peers.write_lock().await;
         let mut peer = lock.get_mut(ctx.client_id.pub_key.as_str())
             .ok_or(ExecuteError::PeerNotFound)?;
+        if peer.model.pending {
+            return Err(ExecuteError::PeerPending);
+        }
         let mut extra_endpoints = vec![];
         for x in self.extra_endpoints.iter() {
             extra_endpoints.push((x.proto.clone(), x.endpoint.clone()));
@@ -26,13 +30,17 @@ impl ToServerDataHandler for PeerEnter {
             port: self.port,
             endpoint_addr: self.endpoint_addr.clone(),
             extra_endpoints: RwMap::from(extra_endpoints),
+            observed_addr: ctx.client.addr,
         };
         if self.ip.as_str() != peer.model.ip.clone().unwrap_or("".to_string()).as_str() {
             return Err(ExecuteError::IpNotMatch);
         };
         peer.online_info = Some(online_info);
+        let model = peer.model.clone();
         //客户端进入->enter
         drop(lock);
+        sync_advertised(&ctx.server, &network, &model, &self.advertise_routes).await?;
+        let routes = peer_approved_routes(&ctx.server, model.id).await?;
         network.broadcast(ToClientData::PeerEnter(BcPeerEnter {
             pub_key: pub_key.clone(),
             ip: self.ip.clone(),
@@ -41,6 +49,10 @@ impl ToServerDataHandler for PeerEnter {
             last_con_type: None,
             mode: i32::from(ConnectionMode::Bidirectional),
             is_online: true,
+            ipv6: model.ipv6.clone(),
+            routes,
+            hostname: model.hostname.clone(),
+            observed_ip: Some(ctx.client.addr.ip().to_string()),
         }), pub_key.as_str()).await;
         Ok(())
     }
@@ -1,17 +1,30 @@
-use vlink_core::proto::pb::abi::{PeerForward};
+use vlink_core::proto::pb::abi::{PeerForward, Punch};
 use vlink_core::proto::pb::abi::peer_forward::Data;
 use vlink_core::proto::pb::abi::to_client::ToClientData;
 use crate::client::dispatcher::ClientRequest;
 use crate::client::handler::{ExecuteResult, ToServerDataHandler};
+use crate::client::handler::helpers::check_pending;
 
 impl ToServerDataHandler for PeerForward {
     async fn execute(&self, ctx: ClientRequest) -> ExecuteResult {
         let net = ctx.network.clone();
+        check_pending(&net, ctx.pub_key().as_str()).await?;
+        if check_pending(&net, self.target_pub_key.as_str()).await.is_err() {
+            return Ok(());
+        }
         if let Some(e) = self.data.as_ref() {
             match e {
                 Data::RequireReply(r) => {
                     net.broadcast_to(ToClientData::RequireReply(r.clone()), vec![self.target_pub_key.clone()]).await;
                 }
+                Data::Punch(p) => {
+                    // 来源以连接为准,避免冒充其他节点
+                    let data = ToClientData::Punch(Punch {
+                        src: ctx.pub_key(),
+                        ..p.clone()
+                    });
+                    net.broadcast_to(data, vec![self.target_pub_key.clone()]).await;
+                }
             }
         }
         Ok(())
@@ -1,111 +1,195 @@
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use futures_util::StreamExt;
-use ip_network::{IpNetwork, Ipv4Network};
+use ip_network::{IpNetwork, Ipv4Network, Ipv6Network};
 use sea_orm::*;
 use sea_orm::ActiveValue::Set;
+use log::warn;
+use crate::acl::{broadcast_acl, load_acl_rules};
+use crate::route::approved_routes;
 use crate::client::dispatcher::{ClientRequest, RequestContext};
 use crate::client::handler::{ExecuteResult, ToServerDataHandler};
-use vlink_core::proto::pb::abi::{BcPeerEnter, ExtraTransport, PeerExtraTransport, ReqConfig, RespConfig};
+use vlink_core::proto::pb::abi::{BcPeerEnter, ExtraTransport, PeerExtraTransport, RelayServer, ReqConfig, RespConfig};
 use vlink_core::proto::pb::abi::to_client::ToClientData;
 use crate::client::error::ExecuteError;
-use crate::db::entity::prelude::{PeerActiveModel, PeerColumn, PeerEntity, PeerExtraTransportColumn, PeerExtraTransportEntity, PeerModel};
-use crate::server::Peers;
+use crate::db::entity::prelude::{PeerActiveModel, PeerColumn, PeerEntity, PeerExtraTransportColumn, PeerExtraTransportEntity, PeerModel, RelayServerColumn, RelayServerEntity};
+use crate::server::{Peers, VlinkServer};
 
 impl ToServerDataHandler for ReqConfig {
     /// 发送配置
+    /// 待审核的节点先记录请求, 审核通过后再发送
     async fn execute(&self, ctx: ClientRequest) -> ExecuteResult {
-        //ctx.pubkey;, 查找peer
         let network = ctx.server.get_network(ctx.client_id.network_id).await?;
-        let self_peer = network.peers
+        let pending = network.peers
             .read_lock().await
-            .get(ctx.client_id.pub_key.as_str()).cloned()
-            .ok_or(ExecuteError::PeerNotFound)?;
-        let mut peers = vec![];
-        for (k, p) in network.peers.read_lock().await.iter() {
-            if let Some(ip) = p.model.ip.as_ref() {
-                //额外的连接信息
-                peers.push(BcPeerEnter {
-                    pub_key: k.to_string(),
-                    ip: ip.to_string(),
-                    endpoint_addr: p.online_info.as_ref().map(|e| e.endpoint_addr.clone()).unwrap_or(None),
-                    port: p.online_info.as_ref().map(|e| e.port).unwrap_or(0),
-                    last_con_type: None,
-                    mode: 3,
-                    is_online: p.online_info.is_some(),
-                })
-            }
+            .get(ctx.client_id.pub_key.as_str())
+            .ok_or(ExecuteError::PeerNotFound)?
+            .model.pending;
+        if pending {
+            network.pending_configs.insert(ctx.pub_key(), (ctx.client.clone(), ctx.id)).await;
+            return Ok(());
         }
+        let resp = build_resp_config(&ctx.server, ctx.client_id.network_id, ctx.client_id.pub_key.as_str()).await?;
+        ctx.send_resp(ToClientData::RespConfig(resp)).await?;
+        Ok(())
+    }
+}
 
-        // 获取ip
-        let addr = match self_peer.model.ip.clone() {
-            None => {
-                //生成ip
-                let gen_ip = generate_ip(network.cidr, &network.peers).await?;
-                //更新ip
-                let mut model = self_peer.model.clone();
-                model.ip = Some(gen_ip.to_string());
-                PeerEntity::update(PeerActiveModel {
-                    id: Set(self_peer.model.id),
-                    ip: Set(Some(gen_ip.to_string())),
-                    // pub_key: Set(self_peer.model.pub_key.clone()),
-                    ..Default::default()
-                })
-                    .exec(ctx.conn())
-                    .await?;
-
-                network.peers.refresh_model(model).await;
-                gen_ip
-            }
-            Some(e) => e.as_str().parse()?
-        };
-        //查询额外的传输层协议
-        let transports = PeerExtraTransportEntity::find()
-            .filter(PeerExtraTransportColumn::PeerId.eq(self_peer.model.id)
-                .and(PeerExtraTransportColumn::Disabled.eq(false)))
-            .all(ctx.conn())
-            .await?;
-
-        let extra_transports = transports.into_iter().map(|m| {
-            ExtraTransport {
-                proto: m.proto,
-                params: m.params,
-            }
-        }).collect();
+/// 生成节点配置, 未分配ip 时分配ip
+pub async fn build_resp_config(server: &VlinkServer, network_id: i64, pub_key: &str) -> Result<RespConfig, ExecuteError> {
+    let conn = server.conn();
+    let network = server.get_network(network_id).await?;
+    let self_peer = network.peers
+        .read_lock().await
+        .get(pub_key).cloned()
+        .ok_or(ExecuteError::PeerNotFound)?;
+    let mut routes = approved_routes(server, network_id).await?;
+    let mut peers = vec![];
+    for (k, p) in network.peers.read_lock().await.iter() {
+        if p.model.disabled || p.model.pending {
+            continue;
+        }
+        if let Some(ip) = p.model.ip.as_ref() {
+            //额外的连接信息
+            peers.push(BcPeerEnter {
+                pub_key: k.to_string(),
+                ip: ip.to_string(),
+                endpoint_addr: p.online_info.as_ref().map(|e| e.endpoint_addr.clone()).unwrap_or(None),
+                port: p.online_info.as_ref().map(|e| e.port).unwrap_or(0),
+                last_con_type: None,
+                mode: 3,
+                is_online: p.online_info.is_some(),
+                ipv6: p.model.ipv6.clone(),
+                routes: routes.remove(&p.model.id).unwrap_or_default(),
+                hostname: p.model.hostname.clone(),
+                observed_ip: p.online_info.as_ref().map(|e| e.observed_ip()),
+            })
+        }
+    }
+
+    // 新分配地址后, 引用该节点的访问规则需要重新解析
+    let mut assigned = false;
+    // 获取ip
+    let addr = match self_peer.model.ip.clone() {
+        None => {
+            assigned = true;
+            //生成ip
+            let gen_ip = generate_ip(network.cidr, &network.peers).await?;
+            //更新ip
+            let mut model = self_peer.model.clone();
+            model.ip = Some(gen_ip.to_string());
+            PeerEntity::update(PeerActiveModel {
+                id: Set(self_peer.model.id),
+                ip: Set(Some(gen_ip.to_string())),
+                // pub_key: Set(self_peer.model.pub_key.clone()),
+                ..Default::default()
+            })
+                .exec(conn)
+                .await?;
 
-        let mut extra_endpoints_map = HashMap::new();
-        for (k, v) in network.peers.read_lock().await.iter() {
-            if let Some(e) = v.online_info.clone() {
-                for (proto, end) in e.extra_endpoints.read_lock().await.iter() {
-                    extra_endpoints_map.insert(k.clone(), (proto.to_string(), end.to_string()));
+            network.peers.refresh_model(model).await;
+            gen_ip
+        }
+        Some(e) => e.as_str().parse()?
+    };
+    // 双栈网络分配ipv6
+    let ipv6_addr = match network.cidr6 {
+        None => None,
+        Some(cidr6) => {
+            let self_peer = network.peers
+                .read_lock().await
+                .get(pub_key).cloned()
+                .ok_or(ExecuteError::PeerNotFound)?;
+            let addr6 = match self_peer.model.ipv6.clone() {
+                None => {
+                    assigned = true;
+                    let gen_ip = generate_ipv6(cidr6, &network.peers).await?;
+                    let mut model = self_peer.model.clone();
+                    model.ipv6 = Some(gen_ip.to_string());
+                    PeerEntity::update(PeerActiveModel {
+                        id: Set(self_peer.model.id),
+                        ipv6: Set(Some(gen_ip.to_string())),
+                        ..Default::default()
+                    })
+                        .exec(conn)
+                        .await?;
+                    network.peers.refresh_model(model).await;
+                    gen_ip
                 }
+                Some(e) => e.as_str().parse()?
+            };
+            Some(format!("{}/{}", addr6, cidr6.netmask()))
+        }
+    };
+    //查询额外的传输层协议
+    let transports = PeerExtraTransportEntity::find()
+        .filter(PeerExtraTransportColumn::PeerId.eq(self_peer.model.id)
+            .and(PeerExtraTransportColumn::Disabled.eq(false)))
+        .all(conn)
+        .await?;
+
+    let extra_transports = transports.into_iter().map(|m| {
+        ExtraTransport {
+            proto: m.proto,
+            params: m.params,
+        }
+    }).collect();
+
+    let mut extra_endpoints_map = HashMap::new();
+    for (k, v) in network.peers.read_lock().await.iter() {
+        if let Some(e) = v.online_info.clone() {
+            for (proto, end) in e.extra_endpoints.read_lock().await.iter() {
+                extra_endpoints_map.insert(k.clone(), (proto.to_string(), end.to_string()));
             }
-        };
-        let mut peer_extra_transports = vec![];
-        for (k, v) in extra_endpoints_map {
-            //todo 校验协议是否对该peer 可用
-            peer_extra_transports.push(PeerExtraTransport {
-                target_pub_key: k.to_string(),
-                proto: v.0,
-                endpoint: v.1,
-                index: 0,
-            });
         }
+    };
+    let mut peer_extra_transports = vec![];
+    for (k, v) in extra_endpoints_map {
+        //todo 校验协议是否对该peer 可用
+        peer_extra_transports.push(PeerExtraTransport {
+            target_pub_key: k.to_string(),
+            proto: v.0,
+            endpoint: v.1,
+            index: 0,
+        });
+    }
 
-        let resp = RespConfig {
-            network_id: network.network_id,
-            address: addr.into(),
-            mask: network.cidr.netmask() as u32,
-            network: network.cidr.network_address().into(),
-            port: self_peer.model.port.unwrap_or(0) as u32,
-            ipv6_addr: None,
-            peers,
-            extra_transports,
-            peer_extra_transports,
-        };
-        ctx.send_resp(ToClientData::RespConfig(resp)).await?;
-        Ok(())
+    //中继服务器
+    let relay_servers = RelayServerEntity::find()
+        .filter(RelayServerColumn::Disabled.eq(false))
+        .order_by_asc(RelayServerColumn::Priority)
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|m| RelayServer {
+            region: m.region,
+            url: m.url,
+            priority: m.priority,
+        })
+        .collect();
+
+    let acl_rules = load_acl_rules(server, &network).await?;
+    if assigned {
+        if let Err(e) = broadcast_acl(server, network_id).await {
+            warn!("推送访问规则失败:{}", e);
+        }
     }
+
+    let resp = RespConfig {
+        network_id: network.network_id,
+        address: addr.into(),
+        mask: network.cidr.netmask() as u32,
+        network: network.cidr.network_address().into(),
+        port: self_peer.model.port.unwrap_or(0) as u32,
+        ipv6_addr,
+        peers,
+        extra_transports,
+        peer_extra_transports,
+        relay_servers,
+        acl_rules,
+        hostname: self_peer.model.hostname.clone(),
+    };
+    Ok(resp)
 }
 
 pub async fn generate_ip(network: Ipv4Network, peers: &Peers) -> anyhow::Result<Ipv4Addr> {
@@ -120,10 +204,23 @@ pub async fn generate_ip(network: Ipv4Network, peers: &Peers) -> anyhow::Result<
         }).ok_or(anyhow::anyhow!("ip地址不够"))?)
 }
 
+/// 在网段中找一个未使用的ipv6,跳过网络地址和网关
+pub async fn generate_ipv6(network: Ipv6Network, peers: &Peers) -> anyhow::Result<Ipv6Addr> {
+    let peer_ips: Vec<Ipv6Addr> = peers.read_lock().await.iter()
+        .filter_map(|p| p.1.model.ipv6.as_ref().and_then(|ip| ip.parse().ok()))
+        .collect();
+    let start = u128::from(network.network_address());
+    // 只在前面一段地址中分配
+    (2..u16::MAX as u128)
+        .map(|i| Ipv6Addr::from(start + i))
+        .take_while(|ip| network.contains(*ip))
+        .find(|ip| !peer_ips.contains(ip))
+        .ok_or(anyhow::anyhow!("ipv6地址不够"))
+}
 
 #[cfg(test)]
 pub mod test {
-    use std::net::Ipv4Addr;
+    use std::net::{Ipv4Addr, Ipv6Addr};
     use ip_network::IpNetwork;
 
     #[test]
@@ -148,4 +245,12 @@ pub mod test {
             }
         }
     }
+
+    #[tokio::test]
+    pub async fn test_generate_ipv6() {
+        let network = "fd00:1::/64".parse().unwrap();
+        let peers = crate::server::Peers::new(vec![]);
+        let ip = super::generate_ipv6(network, &peers).await.unwrap();
+        assert_eq!(ip, "fd00:1::2".parse::<Ipv6Addr>().unwrap());
+    }
 }
\ No newline at end of file
@@ -5,6 +5,9 @@ use crate::client::error::ExecuteError;
 use crate::client::handler::{ExecuteResult, ToServerDataHandler};
 use crate::client::handler::helpers::union_pub_key;
 
+/// 动态公网ip 变化后旧地址失效, 已连接的节点也要通知
+const DIP_PROTO: &str = "Dip";
+
 
 ///扩展协议启动成功时,客户端向服务器上报该扩展解析的接入端点,每一个客户端只能有一种协议的接入方式
 /// 服务器判断是否是默认协议，如果是默认协议则广播给所有节点,
@@ -20,6 +23,9 @@ impl ToServerDataHandler for ExtraEndpoint {
             .read_lock().await
             .get(ctx.client_id.pub_key.as_str()).cloned()
             .ok_or(ExecuteError::PeerNotFound)?;
+        if self_peer.model.pending {
+            return Err(ExecuteError::PeerPending);
+        }
         if let Some(e) = self_peer.online_info {
             e.extra_endpoints.insert(self.proto.clone(), self.endpoint.clone()).await;
         };
@@ -29,6 +35,7 @@ impl ToServerDataHandler for ExtraEndpoint {
             endpoint: self.endpoint.clone(),
         });
         let is_default = self_peer.model.default_proto.as_ref().map(|e| e.as_str() == self.proto.as_str()).unwrap_or(false);
+        let is_dip = self.proto.as_str() == DIP_PROTO;
         let mut broad_to = vec![];
 
         for (k, p) in network.peers.read_lock().await.iter() {
@@ -43,12 +50,15 @@ impl ToServerDataHandler for ExtraEndpoint {
             match conn {
                 None => {
                     //未连接
-                    if is_default {
+                    if is_default || is_dip {
                         broad_to.push(k.clone());
                     }
                 }
-                Some(e) => {
+                Some(_) => {
                     //已连接
+                    if is_dip {
+                        broad_to.push(k.clone());
+                    }
                 }
             }
         }
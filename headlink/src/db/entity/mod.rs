@@ -4,4 +4,7 @@ pub mod network;
 pub mod prelude;
 pub mod config;
 
-pub mod peer_extra_transport;
\ No newline at end of file
+pub mod peer_extra_transport;
+pub mod relay_server;
+pub mod acl_rule;
+pub mod peer_route;
@@ -23,14 +23,20 @@ impl EntityName for Entity {
 pub struct Model {
     pub network_id: i64,
     pub cidr: String,
+    /// ipv6 网段,为空不分配ipv6
+    pub cidr6: Option<String>,
     pub remark: Option<String>,
+    /// token 注册的节点需要管理员审核
+    pub require_approval: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
 pub enum Column {
     NetworkId,
     Cidr,
+    Cidr6,
     Remark,
+    RequireApproval,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -56,7 +62,9 @@ impl ColumnTrait for Column {
         match self {
             Self::NetworkId => ColumnType::BigInteger.def(),
             Self::Cidr => ColumnType::Cidr.def(),
+            Self::Cidr6 => ColumnType::Cidr.def().null(),
             Self::Remark => ColumnType::Text.def().null(),
+            Self::RequireApproval => ColumnType::Boolean.def(),
         }
     }
 }
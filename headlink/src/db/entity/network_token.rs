@@ -18,6 +18,16 @@ pub struct Model {
     pub network_id: i64,
     pub create_at: Option<DateTime>,
     pub disabled: bool,
+    /// 过期时间,为空不过期
+    pub expire_at: Option<DateTime>,
+    /// 最大使用次数,为空不限制
+    pub max_uses: Option<i32>,
+    /// 已注册的节点数
+    pub used_count: i32,
+    /// 只能使用一次
+    pub single_use: bool,
+    /// 注册的节点进入待审核,与网络的 require_approval 作用相同
+    pub require_approval: bool,
     pub id: i64,
 }
 
@@ -27,6 +37,11 @@ pub enum Column {
     NetworkId,
     CreateAt,
     Disabled,
+    ExpireAt,
+    MaxUses,
+    UsedCount,
+    SingleUse,
+    RequireApproval,
     Id,
 }
 
@@ -55,6 +70,11 @@ impl ColumnTrait for Column {
             Self::NetworkId => ColumnType::BigInteger.def(),
             Self::CreateAt => ColumnType::DateTime.def().null(),
             Self::Disabled => ColumnType::Boolean.def(),
+            Self::ExpireAt => ColumnType::DateTime.def().null(),
+            Self::MaxUses => ColumnType::Integer.def().null(),
+            Self::UsedCount => ColumnType::Integer.def(),
+            Self::SingleUse => ColumnType::Boolean.def(),
+            Self::RequireApproval => ColumnType::Boolean.def(),
             Self::Id => ColumnType::BigInteger.def(),
         }
     }
@@ -73,6 +93,31 @@ impl RelationTrait for Relation {
     }
 }
 
+impl Model {
+    /// 可注册的节点数上限
+    pub fn use_limit(&self) -> Option<i32> {
+        if self.single_use {
+            Some(1)
+        } else {
+            self.max_uses
+        }
+    }
+
+    /// 校验token 是否还能注册新节点
+    pub fn check_enroll(&self, now: DateTime) -> Result<(), &'static str> {
+        if self.disabled {
+            return Err("token已禁用");
+        }
+        if self.expire_at.map(|e| e <= now).unwrap_or(false) {
+            return Err("token已过期");
+        }
+        if self.use_limit().map(|limit| self.used_count >= limit).unwrap_or(false) {
+            return Err("token使用次数已达上限");
+        }
+        Ok(())
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
 
 impl Related<super::network::Entity> for Entity {
@@ -81,3 +126,54 @@ impl Related<super::network::Entity> for Entity {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Local};
+    use super::*;
+
+    fn token() -> Model {
+        Model {
+            token: "t".to_string(),
+            network_id: 1,
+            create_at: None,
+            disabled: false,
+            expire_at: None,
+            max_uses: None,
+            used_count: 0,
+            single_use: false,
+            require_approval: false,
+            id: 1,
+        }
+    }
+
+    #[test]
+    fn test_check_enroll() {
+        let now = Local::now().naive_local();
+        let mut t = token();
+        t.used_count = 100;
+        assert!(t.check_enroll(now).is_ok());
+
+        t.max_uses = Some(3);
+        t.used_count = 2;
+        assert!(t.check_enroll(now).is_ok());
+        t.used_count = 3;
+        assert_eq!(t.check_enroll(now), Err("token使用次数已达上限"));
+
+        // 单次使用优先于 max_uses
+        let mut t = token();
+        t.single_use = true;
+        t.max_uses = Some(10);
+        assert!(t.check_enroll(now).is_ok());
+        t.used_count = 1;
+        assert!(t.check_enroll(now).is_err());
+
+        let mut t = token();
+        t.expire_at = Some(now - Duration::seconds(1));
+        assert_eq!(t.check_enroll(now), Err("token已过期"));
+        t.expire_at = Some(now + Duration::hours(1));
+        assert!(t.check_enroll(now).is_ok());
+        t.disabled = true;
+        assert_eq!(t.check_enroll(now), Err("token已禁用"));
+    }
+}
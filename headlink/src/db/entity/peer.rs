@@ -16,12 +16,24 @@ impl EntityName for Entity {
 pub struct Model {
     pub id: i64,
     pub pub_key: String,
+    /// 备注名称
+    pub name: Option<String>,
     pub ip: Option<String>,
+    pub ipv6: Option<String>,
     pub default_proto: Option<String>,
     pub endpoint_addr: Option<String>,
     pub port: Option<i32>,
     pub network_id: i64,
     pub disabled: bool,
+    /// 等待审核,审核前不下发配置
+    pub pending: bool,
+    /// 标签, 逗号分隔, 用于访问控制规则
+    pub tags: Option<String>,
+    /// dns 主机名, 网络内唯一, 数据库需建唯一索引 (network_id, hostname)
+    pub hostname: Option<String>,
+    /// 服务器生成的私钥, base64, 用于导出标准 wireguard 配置
+    #[serde(skip_serializing)]
+    pub private_key: Option<String>,
     pub create_at: Option<DateTime>,
     pub update_at: Option<DateTime>,
 }
@@ -30,7 +42,9 @@ pub struct Model {
 pub enum Column {
     Id,
     PubKey,
+    Name,
     Ip,
+    Ipv6,
     EndpointAddr,
     Port,
     DefaultProto,
@@ -38,6 +52,10 @@ pub enum Column {
     CreateAt,
     UpdateAt,
     Disabled,
+    Pending,
+    Tags,
+    Hostname,
+    PrivateKey,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -61,12 +79,18 @@ impl ColumnTrait for Column {
         match self {
             Self::PubKey => ColumnType::Text.def().unique(),
             Self::Id => ColumnType::BigInteger.def().unique(),
+            Self::Name => ColumnType::Text.def().null(),
             Self::Ip => ColumnType::Text.def().null(),
+            Self::Ipv6 => ColumnType::Text.def().null(),
             Self::EndpointAddr => ColumnType::Text.def().null(),
             Self::DefaultProto => ColumnType::Text.def().null(),
             Self::Port => ColumnType::Integer.def().null(),
             Self::NetworkId => ColumnType::BigInteger.def(),
             Self::Disabled => ColumnType::Boolean.def(),
+            Self::Pending => ColumnType::Boolean.def(),
+            Self::Tags => ColumnType::Text.def().null(),
+            Self::Hostname => ColumnType::Text.def().null(),
+            Self::PrivateKey => ColumnType::Text.def().null(),
             Self::CreateAt => ColumnType::DateTime.def().null(),
             Self::UpdateAt => ColumnType::DateTime.def().null(),
         }
@@ -80,3 +104,11 @@ impl RelationTrait for Relation {
 }
 
 impl ActiveModelBehavior for ActiveModel {}
+
+impl Model {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.as_deref()
+            .map(|tags| tags.split(',').any(|t| t.trim() == tag))
+            .unwrap_or(false)
+    }
+}
@@ -8,9 +8,13 @@ pub use super::peer::ActiveModel as PeerActiveModel;
 
 pub use super::network_token::Entity as NetworkTokenEntity;
 pub use super::network_token::Column as NetworkTokenColumn;
+pub use super::network_token::Model as NetworkTokenModel;
+pub use super::network_token::ActiveModel as NetworkTokenActiveModel;
 
 pub use super::network::Entity as NetworkEntity;
 pub use super::network::ActiveModel as NetworkActiveModel;
+pub use super::network::Model as NetworkModel;
+pub use super::network::Column as NetworkColumn;
 
 
 
@@ -20,4 +24,17 @@ pub use super::config::ActiveModel as ConfigActiveModel;
 
 pub use super::peer_extra_transport::Entity as PeerExtraTransportEntity;
 pub use super::peer_extra_transport::Model as PeerExtraTransportModel;
-pub use super::peer_extra_transport::Column as PeerExtraTransportColumn;
\ No newline at end of file
+pub use super::peer_extra_transport::Column as PeerExtraTransportColumn;
+
+pub use super::relay_server::Entity as RelayServerEntity;
+pub use super::relay_server::Column as RelayServerColumn;
+
+pub use super::acl_rule::Entity as AclRuleEntity;
+pub use super::acl_rule::Column as AclRuleColumn;
+pub use super::acl_rule::Model as AclRuleModel;
+pub use super::acl_rule::ActiveModel as AclRuleActiveModel;
+
+pub use super::peer_route::Entity as PeerRouteEntity;
+pub use super::peer_route::Column as PeerRouteColumn;
+pub use super::peer_route::Model as PeerRouteModel;
+pub use super::peer_route::ActiveModel as PeerRouteActiveModel;
@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use log::info;
+use sea_orm::*;
+use sea_orm::ActiveValue::Set;
+use crate::db::entity::prelude::{PeerActiveModel, PeerEntity};
+use crate::network::VlinkNetwork;
+use crate::server::VlinkServer;
+
+/// 节点上报的主机名转成网络内唯一的 dns 名称, 重名时加数字后缀
+/// 查重到写库期间持有写锁, 同时上线的节点不会分到同一个名称
+pub async fn assign_hostname(server: &VlinkServer, network: &VlinkNetwork, pub_key: &str, reported: &str) -> anyhow::Result<()> {
+    let Some(base) = sanitize_hostname(reported) else {
+        return Ok(());
+    };
+    let mut peers = network.peers.write_lock().await;
+    let Some(peer) = peers.get(pub_key) else {
+        return Ok(());
+    };
+    let taken: HashSet<&str> = peers.iter()
+        .filter(|(k, _)| k.as_str() != pub_key)
+        .filter_map(|(_, p)| p.model.hostname.as_deref())
+        .collect();
+    let hostname = unique_hostname(base.as_str(), &taken, peer.model.hostname.as_deref());
+    if peer.model.hostname.as_deref() == Some(hostname.as_str()) {
+        return Ok(());
+    }
+    PeerEntity::update(PeerActiveModel {
+        id: Set(peer.model.id),
+        hostname: Set(Some(hostname.clone())),
+        ..Default::default()
+    })
+        .exec(server.conn())
+        .await?;
+    info!("节点{} 主机名:{}", pub_key, hostname);
+    if let Some(peer) = peers.get_mut(pub_key) {
+        peer.model.hostname = Some(hostname);
+    }
+    Ok(())
+}
+
+/// 只保留小写字母,数字和-, 最长63
+pub fn sanitize_hostname(name: &str) -> Option<String> {
+    let label: String = name.trim()
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .take(63)
+        .collect();
+    let label = label.trim_matches('-');
+    if label.is_empty() {
+        None
+    } else {
+        Some(label.to_string())
+    }
+}
+
+/// 当前名称是 base 或 base-n 时保持不变, 避免节点重连后改名
+fn unique_hostname(base: &str, taken: &HashSet<&str>, current: Option<&str>) -> String {
+    if let Some(current) = current {
+        let same_base = current == base || current.strip_prefix(base)
+            .and_then(|s| s.strip_prefix('-'))
+            .map(|n| n.parse::<u32>().is_ok())
+            .unwrap_or(false);
+        if same_base && !taken.contains(current) {
+            return current.to_string();
+        }
+    }
+    if !taken.contains(base) {
+        return base.to_string();
+    }
+    (2..)
+        .map(|i| format!("{}-{}", base, i))
+        .find(|name| !taken.contains(name.as_str()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hostname() {
+        assert_eq!(sanitize_hostname("DB1.local"), Some("db1".to_string()));
+        assert_eq!(sanitize_hostname("my laptop_01"), Some("my-laptop-01".to_string()));
+        assert_eq!(sanitize_hostname("--"), None);
+
+        let taken: HashSet<&str> = ["db1", "db1-2"].into_iter().collect();
+        assert_eq!(unique_hostname("db1", &taken, None), "db1-3");
+        assert_eq!(unique_hostname("db1", &taken, Some("db1-5")), "db1-5");
+        assert_eq!(unique_hostname("web", &taken, Some("db1-5")), "web");
+    }
+}
@@ -5,6 +5,9 @@ pub mod server;
 pub mod client;
 
 pub mod peer;
+pub mod acl;
+pub mod route;
+pub mod dns;
 
 use once_cell::sync::Lazy;
 use crate::db::snowflake::MySnowflakeGenerator;
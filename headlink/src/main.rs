@@ -1,8 +1,9 @@
 use clap::Parser;
-use log::{error, info};
+use log::{error, info, warn};
 use tokio::net::TcpListener;
 use headlink::db::init::open_db;
 use headlink::server;
+use headlink::api::start_http_server;
 use headlink::client::ClientStream;
 
 #[derive(Parser, Debug)]
@@ -14,6 +15,12 @@ struct Args {
     /// 数据库连接
     #[arg(short, long)]
     db_schema: Option<String>,
+    /// 管理接口地址
+    #[arg(long)]
+    api_listen: Option<String>,
+    /// 管理接口token,不设置不启动管理接口
+    #[arg(long)]
+    api_token: Option<String>,
 }
 
 /// 流程,客户端连接
@@ -39,6 +46,10 @@ async fn main() -> anyhow::Result<()> {
     //广播器
     // let (mut tx, mut rx) = broadcast::channel(16);
     let server = server::VlinkServer::new(conn).await?;
+    match args.api_token {
+        None => warn!("未设置api token,管理接口不启动"),
+        Some(token) => start_http_server(args.api_listen, token, server.clone()).await?,
+    }
     let server_c = server.clone();
     loop {
         info!("start accept");
@@ -3,11 +3,12 @@ use std::ops::Deref;
 use std::sync::Arc;
 use futures_util::future::join_all;
 use futures_util::SinkExt;
-use ip_network::{IpNetwork, Ipv4Network};
+use ip_network::{IpNetwork, Ipv4Network, Ipv6Network};
 use sea_orm::ColIdx;
 use vlink_core::proto::pb::abi::to_client::ToClientData;
-use vlink_core::proto::pb::abi::{BcPeerLevel, ToClient};
+use vlink_core::proto::pb::abi::{BcPeerLevel, BcPeerRemoved, ToClient};
 use vlink_core::rw_map::RwMap;
+use crate::client::ClientConnect;
 use crate::peer::VlinkPeer;
 use crate::server::{Peers, VlinkServer};
 
@@ -37,9 +38,13 @@ pub struct PeerConnect {
 pub struct VlinkNetworkInner {
     pub network_id: i64,
     pub cidr: Ipv4Network,
+    /// ipv6 网段,双栈时有值
+    pub cidr6: Option<Ipv6Network>,
     // pub online_peers: HashSet<String>,
     pub peers: Peers,
     pub connects: RwMap<String, PeerConnect>,
+    /// 待审核节点的配置请求,pub_key -> (连接,请求id)
+    pub pending_configs: RwMap<String, (ClientConnect, u64)>,
 }
 
 impl VlinkNetworkInner {
@@ -47,12 +52,25 @@ impl VlinkNetworkInner {
     ///下线设备
     pub async fn offline(&self, pub_key: &str) {
         self.peers.offline(pub_key).await;
+        self.pending_configs.remove(&pub_key.to_string()).await;
         self.broadcast(ToClientData::PeerLeave(BcPeerLevel {
             pub_key: pub_key.to_string(),
         }), pub_key).await;
     }
 
 
+    /// 移除节点, 断开它的连接并通知其他节点删除
+    pub async fn remove_peer(&self, pub_key: &str) {
+        if let Some(p) = self.peers.read_lock().await.get(pub_key) {
+            if let Some(info) = p.online_info.as_ref() {
+                info.connect.kick();
+            }
+        }
+        self.broadcast(ToClientData::PeerRemoved(BcPeerRemoved {
+            pub_key: pub_key.to_string(),
+        }), pub_key).await;
+    }
+
     pub async fn broadcast_by<F>(&self, data: ToClientData, predict: F)
         where F: Fn(&String, &VlinkPeer) -> bool {
         let mut task = vec![];
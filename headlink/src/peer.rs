@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use vlink_core::rw_map::RwMap;
 use crate::client::ClientConnect;
 use crate::db::entity::prelude::PeerModel;
@@ -15,6 +15,14 @@ pub struct OnlineInfo {
     pub endpoint_addr: Option<String>,
     /// 扩展协议的接入端点,proto,endpoint
     pub extra_endpoints: RwMap<String, String>,
+    /// 服务器看到的连接地址,节点在nat 后面时是公网地址
+    pub observed_addr: SocketAddr,
+}
+
+impl OnlineInfo {
+    pub fn observed_ip(&self) -> String {
+        self.observed_addr.ip().to_string()
+    }
 }
 
 #[derive(Clone)]
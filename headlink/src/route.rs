@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use chrono::Local;
+use ip_network::IpNetwork;
+use log::{info, warn};
+use sea_orm::*;
+use sea_orm::ActiveValue::Set;
+use vlink_core::proto::pb::abi::{BcPeerEnter, ConnectionMode};
+use vlink_core::proto::pb::abi::to_client::ToClientData;
+use crate::acl::parse_cidr;
+use crate::db::entity::prelude::{PeerModel, PeerRouteActiveModel, PeerRouteColumn, PeerRouteEntity};
+use crate::network::VlinkNetwork;
+use crate::server::VlinkServer;
+use crate::SNOWFLAKE;
+
+/// 节点上报的子网入库, 新宣告的等待审核
+/// 不再宣告的未审核路由删除, 已审核的只标记为不活跃, 保留审核结果
+pub async fn sync_advertised(server: &VlinkServer, network: &VlinkNetwork, peer: &PeerModel, advertised: &[String]) -> anyhow::Result<()> {
+    let mut wanted = vec![];
+    for r in advertised {
+        match parse_cidr(r.as_str()) {
+            Some(net) if overlaps_overlay(network, &net) => warn!("节点{} 子网{} 与虚拟网段冲突", peer.pub_key, r),
+            Some(net) => wanted.push(format!("{}/{}", net.network_address(), net.netmask())),
+            None => warn!("节点{} 子网格式错误:{}", peer.pub_key, r),
+        }
+    }
+    let old = PeerRouteEntity::find()
+        .filter(PeerRouteColumn::PeerId.eq(peer.id))
+        .all(server.conn())
+        .await?;
+    for m in old.iter() {
+        let active = wanted.contains(&m.cidr);
+        if !active && !m.approved {
+            PeerRouteEntity::delete_by_id(m.id).exec(server.conn()).await?;
+        } else if active != m.active {
+            let mut model: PeerRouteActiveModel = m.clone().into();
+            model.active = Set(active);
+            model.update(server.conn()).await?;
+        }
+    }
+    for cidr in wanted {
+        if old.iter().any(|m| m.cidr == cidr) {
+            continue;
+        }
+        info!("节点{} 宣告子网{}, 等待审核", peer.pub_key, cidr);
+        PeerRouteActiveModel {
+            id: Set(SNOWFLAKE.next_id()),
+            network_id: Set(peer.network_id),
+            peer_id: Set(peer.id),
+            cidr: Set(cidr),
+            approved: Set(false),
+            active: Set(true),
+            create_at: Set(Some(Local::now().naive_local())),
+        }.insert(server.conn()).await?;
+    }
+    Ok(())
+}
+
+/// 网络中所有已审核且仍在宣告的路由, peer_id -> cidr
+pub async fn approved_routes(server: &VlinkServer, network_id: i64) -> anyhow::Result<HashMap<i64, Vec<String>>> {
+    let mut map: HashMap<i64, Vec<String>> = HashMap::new();
+    PeerRouteEntity::find()
+        .filter(PeerRouteColumn::NetworkId.eq(network_id)
+            .and(PeerRouteColumn::Approved.eq(true))
+            .and(PeerRouteColumn::Active.eq(true)))
+        .all(server.conn())
+        .await?
+        .into_iter()
+        .for_each(|m| map.entry(m.peer_id).or_default().push(m.cidr));
+    Ok(map)
+}
+
+pub async fn peer_approved_routes(server: &VlinkServer, peer_id: i64) -> anyhow::Result<Vec<String>> {
+    Ok(PeerRouteEntity::find()
+        .filter(PeerRouteColumn::PeerId.eq(peer_id)
+            .and(PeerRouteColumn::Approved.eq(true))
+            .and(PeerRouteColumn::Active.eq(true)))
+        .all(server.conn())
+        .await?
+        .into_iter()
+        .map(|m| m.cidr)
+        .collect())
+}
+
+/// 审核结果变更后重新广播节点信息, 其他节点据此更新路由
+pub async fn broadcast_peer_routes(server: &VlinkServer, network_id: i64, pub_key: &str) -> anyhow::Result<()> {
+    let Some(network) = server.cached_network(network_id).await else {
+        return Ok(());
+    };
+    let Some(peer) = network.peers.read_lock().await.get(pub_key).cloned() else {
+        return Ok(());
+    };
+    let Some(ip) = peer.model.ip.clone() else {
+        return Ok(());
+    };
+    if peer.model.disabled || peer.model.pending {
+        return Ok(());
+    }
+    let routes = peer_approved_routes(server, peer.model.id).await?;
+    network.broadcast(ToClientData::PeerEnter(BcPeerEnter {
+        pub_key: pub_key.to_string(),
+        ip,
+        endpoint_addr: peer.online_info.as_ref().and_then(|e| e.endpoint_addr.clone()),
+        port: peer.online_info.as_ref().map(|e| e.port).unwrap_or(0),
+        last_con_type: None,
+        mode: i32::from(ConnectionMode::Bidirectional),
+        is_online: peer.is_online(),
+        ipv6: peer.model.ipv6.clone(),
+        routes,
+        hostname: peer.model.hostname.clone(),
+        observed_ip: peer.online_info.as_ref().map(|e| e.observed_ip()),
+    }), pub_key).await;
+    Ok(())
+}
+
+/// 出口节点的默认路由不算冲突
+fn overlaps_overlay(network: &VlinkNetwork, net: &IpNetwork) -> bool {
+    if net.netmask() == 0 {
+        return false;
+    }
+    let overlaps = |a: &IpNetwork, b: &IpNetwork| a.contains(b.network_address()) || b.contains(a.network_address());
+    overlaps(&IpNetwork::V4(network.cidr), net)
+        || network.cidr6.map(|c| overlaps(&IpNetwork::V6(c), net)).unwrap_or(false)
+}
@@ -5,7 +5,7 @@ use std::sync::Arc;
 use anyhow::anyhow;
 use log::{debug, info};
 use sea_orm::{DatabaseConnection, EntityTrait};
-use crate::db::entity::prelude::{ConfigActiveModel, ConfigEntity, NetworkEntity, PeerColumn, PeerEntity, PeerModel};
+use crate::db::entity::prelude::{ConfigActiveModel, ConfigEntity, NetworkEntity, NetworkModel, PeerColumn, PeerEntity, PeerModel};
 use crate::network::{VlinkNetwork, VlinkNetworkInner};
 use crate::client::ClientConnect;
 use crate::peer::VlinkPeer;
@@ -87,6 +87,32 @@ impl ServerInner {
     }
 
 
+    /// 网络配置修改后刷新缓存, 保留原有的peer 和连接
+    pub async fn refresh_network(&self, model: NetworkModel) -> anyhow::Result<()> {
+        let mut networks = self.networks.write_lock().await;
+        if let Some(old) = networks.get(&model.network_id).cloned() {
+            networks.insert(model.network_id, VlinkNetwork {
+                inner: Arc::new(VlinkNetworkInner {
+                    network_id: model.network_id,
+                    cidr: model.cidr.parse()?,
+                    cidr6: match model.cidr6.as_ref() {
+                        None => None,
+                        Some(c) => Some(c.parse()?),
+                    },
+                    peers: old.peers.clone(),
+                    connects: old.connects.clone(),
+                    pending_configs: old.pending_configs.clone(),
+                }),
+            });
+        }
+        Ok(())
+    }
+
+    /// 缓存中的网络,未加载返回None
+    pub async fn cached_network(&self, network_id: i64) -> Option<VlinkNetwork> {
+        self.networks.read_lock().await.get(&network_id).cloned()
+    }
+
     pub async fn get_network(&self, network_id: i64) -> anyhow::Result<VlinkNetwork> {
         Ok(match self.networks.write_lock().await.entry(network_id) {
             Entry::Occupied(e) => { e.get().clone() }
@@ -109,8 +135,13 @@ impl ServerInner {
                     inner: Arc::new(VlinkNetworkInner {
                         network_id: network.network_id,
                         cidr: network.cidr.parse()?,
+                        cidr6: match network.cidr6.as_ref() {
+                            None => None,
+                            Some(c) => Some(c.parse()?),
+                        },
                         peers: Peers::new(peers),
                         connects: Default::default(),
+                        pending_configs: Default::default(),
                     }),
                 };
                 // 查询peers
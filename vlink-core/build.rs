@@ -19,6 +19,9 @@ fn main() {
     let mut config = prost_build::Config::new();
     config.bytes(&["."]);
     // config.type_attribute(".", "#[derive(PartialOrd)]");
+    // 需要缓存到本地的配置
+    config.type_attribute("PeerExtraTransport", "#[derive(serde::Serialize, serde::Deserialize)]");
+    config.type_attribute("RelayServer", "#[derive(serde::Serialize, serde::Deserialize)]");
     // config.type_attribute("ServiceResponse.response_data", "#[repr(u8)]");
     config
         .out_dir("src/proto/pb")
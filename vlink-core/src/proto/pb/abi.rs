@@ -6,6 +6,24 @@ pub struct RequireReply {
     pub proto: ::prost::alloc::string::String,
     #[prost(string, tag="3")]
     pub server: ::prost::alloc::string::String,
+    /// 发起方可达的中继服务器,按延迟排序,接收方选第一个自己也能连上的
+    #[prost(string, repeated, tag="4")]
+    pub servers: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// udp 打洞,交换双方wireguard 端口的候选地址
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Punch {
+    #[prost(string, tag="1")]
+    pub src: ::prost::alloc::string::String,
+    /// 发起方生成,探测包携带,用于校验
+    #[prost(uint64, tag="2")]
+    pub session: u64,
+    /// 候选地址 ip:port
+    #[prost(string, repeated, tag="3")]
+    pub candidates: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 是否是对请求的回复
+    #[prost(bool, tag="4")]
+    pub reply: bool,
 }
 ///客户端->服务端
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -48,7 +66,7 @@ pub mod to_server {
 pub struct PeerForward {
     #[prost(string, tag="1")]
     pub target_pub_key: ::prost::alloc::string::String,
-    #[prost(oneof="peer_forward::Data", tags="2")]
+    #[prost(oneof="peer_forward::Data", tags="2, 3")]
     pub data: ::core::option::Option<peer_forward::Data>,
 }
 /// Nested message and enum types in `PeerForward`.
@@ -57,6 +75,8 @@ pub mod peer_forward {
     pub enum Data {
         #[prost(message, tag="2")]
         RequireReply(super::RequireReply),
+        #[prost(message, tag="3")]
+        Punch(super::Punch),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -84,6 +104,9 @@ pub struct ReqHandshake {
     pub token: ::core::option::Option<::prost::alloc::string::String>,
     #[prost(string, tag="4")]
     pub sign: ::prost::alloc::string::String,
+    ///主机名,token 注册新节点时作为节点名称
+    #[prost(string, optional, tag="5")]
+    pub hostname: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ReqConfig {
@@ -100,6 +123,9 @@ pub struct PeerEnter {
     pub port: u32,
     #[prost(message, repeated, tag="4")]
     pub extra_endpoints: ::prost::alloc::vec::Vec<ExtraEndpoint>,
+    //// 本机后面的子网,审核后其他节点才会路由过来
+    #[prost(string, repeated, tag="5")]
+    pub advertise_routes: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct PeerLeave {
@@ -119,7 +145,7 @@ pub struct ToClient {
     /// 通信id
     #[prost(uint64, tag="1")]
     pub id: u64,
-    #[prost(oneof="to_client::ToClientData", tags="2, 3, 4, 5, 6, 7, 8, 10")]
+    #[prost(oneof="to_client::ToClientData", tags="2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13")]
     pub to_client_data: ::core::option::Option<to_client::ToClientData>,
 }
 /// Nested message and enum types in `ToClient`.
@@ -142,6 +168,12 @@ pub mod to_client {
         RequireReply(super::RequireReply),
         #[prost(message, tag="10")]
         UpdateExtraEndpoint(super::BcUpdateExtraEndpoint),
+        #[prost(message, tag="11")]
+        PeerRemoved(super::BcPeerRemoved),
+        #[prost(message, tag="12")]
+        AclUpdate(super::BcAclUpdate),
+        #[prost(message, tag="13")]
+        Punch(super::Punch),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -149,6 +181,18 @@ pub struct BcPeerLevel {
     #[prost(string, tag="1")]
     pub pub_key: ::prost::alloc::string::String,
 }
+/// 节点被禁用或删除,收到后移除节点
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BcPeerRemoved {
+    #[prost(string, tag="1")]
+    pub pub_key: ::prost::alloc::string::String,
+}
+/// 访问控制规则变更,整体替换
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BcAclUpdate {
+    #[prost(message, repeated, tag="1")]
+    pub rules: ::prost::alloc::vec::Vec<AclRule>,
+}
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct BcUpdateExtraEndpoint {
     #[prost(string, tag="1")]
@@ -182,6 +226,12 @@ pub struct RespHandshake {
     pub success: bool,
     #[prost(string, optional, tag="4")]
     pub msg: ::core::option::Option<::prost::alloc::string::String>,
+    /// 节点等待管理员审核,审核通过后下发配置
+    #[prost(bool, tag="5")]
+    pub pending: bool,
+    /// 服务器看到的客户端地址,用于获取公网ip
+    #[prost(string, optional, tag="6")]
+    pub observed_ip: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ExtraTransport {
@@ -206,6 +256,7 @@ pub struct RespConfig {
     ///udp 端口
     #[prost(uint32, tag="5")]
     pub port: u32,
+    /// 分配的ipv6 地址,带前缀长度,如 fd00::2/64
     #[prost(string, optional, tag="6")]
     pub ipv6_addr: ::core::option::Option<::prost::alloc::string::String>,
     #[prost(message, repeated, tag="10")]
@@ -214,7 +265,49 @@ pub struct RespConfig {
     pub extra_transports: ::prost::alloc::vec::Vec<ExtraTransport>,
     #[prost(message, repeated, tag="12")]
     pub peer_extra_transports: ::prost::alloc::vec::Vec<PeerExtraTransport>,
+    /// 中继服务器列表
+    #[prost(message, repeated, tag="13")]
+    pub relay_servers: ::prost::alloc::vec::Vec<RelayServer>,
+    /// 访问控制规则,按顺序匹配
+    #[prost(message, repeated, tag="14")]
+    pub acl_rules: ::prost::alloc::vec::Vec<AclRule>,
+    /// 本节点的主机名,用于内置dns
+    #[prost(string, optional, tag="15")]
+    pub hostname: ::core::option::Option<::prost::alloc::string::String>,
+}
+//// 访问控制规则,src/dst 已解析为cidr,为空表示任意
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AclRule {
+    #[prost(int64, tag="1")]
+    pub id: i64,
+    #[prost(string, repeated, tag="2")]
+    pub src: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag="3")]
+    pub dst: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// any/tcp/udp/icmp
+    #[prost(string, tag="4")]
+    pub proto: ::prost::alloc::string::String,
+    /// 目标端口范围,都为0 表示全部端口
+    #[prost(uint32, tag="5")]
+    pub port_start: u32,
+    #[prost(uint32, tag="6")]
+    pub port_end: u32,
+    #[prost(bool, tag="7")]
+    pub allow: bool,
+}
+//// 中继服务器
+#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RelayServer {
+    #[prost(string, tag="1")]
+    pub region: ::prost::alloc::string::String,
+    #[prost(string, tag="2")]
+    pub url: ::prost::alloc::string::String,
+    /// 延迟相同时优先级小的优先
+    #[prost(int32, tag="3")]
+    pub priority: i32,
 }
+#[derive(serde::Serialize, serde::Deserialize)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct PeerExtraTransport {
     #[prost(string, tag="1")]
@@ -246,6 +339,18 @@ pub struct BcPeerEnter {
     pub mode: i32,
     #[prost(bool, tag="8")]
     pub is_online: bool,
+    //// 网络中的ipv6,双栈时有值
+    #[prost(string, optional, tag="9")]
+    pub ipv6: ::core::option::Option<::prost::alloc::string::String>,
+    //// 已审核的子网路由
+    #[prost(string, repeated, tag="10")]
+    pub routes: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    //// 主机名,网络内唯一
+    #[prost(string, optional, tag="11")]
+    pub hostname: ::core::option::Option<::prost::alloc::string::String>,
+    //// 服务器看到的公网ip,没有endpoint_addr 时作为直连候选
+    #[prost(string, optional, tag="12")]
+    pub observed_ip: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
@@ -43,6 +43,21 @@ impl VlinkStaticSecret {
             .map_err(|e| anyhow::anyhow!("加密错误:{}",e))?;
         Ok(base64Encoding.encode(ciphertext))
     }
+
+    /// 校验对方 hello_sign 的签名, 证明对方持有 peer_pub 的私钥
+    pub fn verify_hello(&self, peer_pub: [u8; 32], sign: &str) -> bool {
+        let Ok(ciphertext) = base64Encoding.decode(sign) else {
+            return false;
+        };
+        let peer_public_key = crypto_box::PublicKey::from(peer_pub);
+        let secret_key = SecretKey::from(*self.private_key.as_bytes());
+        let own_pub = self.public_key.to_bytes();
+        let no = Nonce::from_slice(&own_pub[..24]);
+        let salsa_box = SalsaBox::new(&peer_public_key, &secret_key);
+        salsa_box.decrypt(no, ciphertext.as_slice())
+            .map(|msg| msg == HELLO_STR.as_bytes())
+            .unwrap_or(false)
+    }
 }
 
 impl Debug for VlinkStaticSecret {
@@ -74,3 +89,21 @@ impl<'de> Deserialize<'de> for VlinkStaticSecret {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_hello() {
+        let server = VlinkStaticSecret::generate();
+        let client = VlinkStaticSecret::generate();
+        let other = VlinkStaticSecret::generate();
+        let sign = client.hello_sign(server.public_key.to_bytes()).unwrap();
+        assert!(server.verify_hello(client.public_key.to_bytes(), sign.as_str()));
+        // 用别人的公钥冒充
+        assert!(!server.verify_hello(other.public_key.to_bytes(), sign.as_str()));
+        assert!(!server.verify_hello(client.public_key.to_bytes(), "aGVsbG8="));
+        assert!(!server.verify_hello(client.public_key.to_bytes(), "not base64"));
+    }
+}
@@ -68,6 +68,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
         peers: Default::default(),
         address: Ipv4Addr::new(192, 168, 10, 5),
         network: IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 10, 0), 24).unwrap()),
+        address6: None,
+        network6: None,
+        acl: Default::default(),
+        bypass: vec![],
+        route: Default::default(),
+        rekey: Default::default(),
     };
     let cidr = config.allowed_ips.parse::<Cidr>().unwrap();
     let allowed_ips = HashSet::from([cidr]);
@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::device::peer::cidr::Cidr;
+
+const PROTO_ICMP: u8 = 1;
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+const PROTO_ICMPV6: u8 = 58;
+const TCP_FLAG_RST: u8 = 0x04;
+/// 连接空闲超时, tcp 长一些
+const TCP_FLOW_TIMEOUT: Duration = Duration::from_secs(300);
+const FLOW_TIMEOUT: Duration = Duration::from_secs(60);
+/// 超过后不再记录新连接, 新连接的回包按规则检查
+const MAX_FLOWS: usize = 65536;
+const FLOW_GC_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclProto {
+    #[default]
+    Any,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+impl AclProto {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "" | "*" | "any" => Some(Self::Any),
+            "tcp" => Some(Self::Tcp),
+            "udp" => Some(Self::Udp),
+            "icmp" => Some(Self::Icmp),
+            _ => None,
+        }
+    }
+}
+
+/// 单条访问规则, src/dst 为空表示任意地址
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AclRule {
+    pub id: i64,
+    pub src: Vec<Cidr>,
+    pub dst: Vec<Cidr>,
+    #[serde(default)]
+    pub proto: AclProto,
+    /// 目标端口范围(闭区间),只对tcp/udp 生效
+    #[serde(default)]
+    pub ports: Option<(u16, u16)>,
+    pub allow: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AclConfig {
+    pub rules: Vec<AclRule>,
+    /// 没有规则命中时的动作
+    pub default_allow: bool,
+}
+
+impl Default for AclConfig {
+    fn default() -> Self {
+        Self {
+            rules: vec![],
+            default_allow: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AclRuleMetrics {
+    pub id: i64,
+    pub hits: u64,
+}
+
+struct RuleEntry {
+    rule: AclRule,
+    hits: AtomicU64,
+}
+
+/// 连接五元组, 非首个分片等没有端口时为0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    src: IpAddr,
+    dst: IpAddr,
+    proto: u8,
+    src_port: u16,
+    dst_port: u16,
+}
+
+impl FlowKey {
+    fn reverse(&self) -> Self {
+        Self {
+            src: self.dst,
+            dst: self.src,
+            proto: self.proto,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+        }
+    }
+}
+
+/// 规则放行的连接, 同方向和反方向的后续包不再检查规则
+struct Flows {
+    flows: HashMap<FlowKey, Instant>,
+    gc_at: Instant,
+}
+
+impl Flows {
+    fn timeout(proto: u8) -> Duration {
+        if proto == PROTO_TCP {
+            TCP_FLOW_TIMEOUT
+        } else {
+            FLOW_TIMEOUT
+        }
+    }
+
+    /// 属于已放行的连接时刷新时间
+    fn touch(&mut self, key: &FlowKey) -> bool {
+        let timeout = Self::timeout(key.proto);
+        for k in [*key, key.reverse()] {
+            if let Some(at) = self.flows.get_mut(&k) {
+                if at.elapsed() < timeout {
+                    *at = Instant::now();
+                    return true;
+                }
+                self.flows.remove(&k);
+            }
+        }
+        false
+    }
+
+    fn remove(&mut self, key: &FlowKey) {
+        self.flows.remove(key);
+        self.flows.remove(&key.reverse());
+    }
+
+    fn insert(&mut self, key: FlowKey) {
+        if self.gc_at.elapsed() > FLOW_GC_INTERVAL {
+            self.flows.retain(|k, at| at.elapsed() < Self::timeout(k.proto));
+            self.gc_at = Instant::now();
+        }
+        if self.flows.len() < MAX_FLOWS {
+            self.flows.insert(key, Instant::now());
+        }
+    }
+}
+
+/// 设备级访问控制,出入口共用
+/// 规则按顺序匹配,第一条命中的规则生效
+/// 有状态过滤: 规则放行的包记录连接, 同一连接两个方向的后续包直接放行,
+/// 不属于任何连接的包(包括 tcp 非SYN 包)都按规则检查
+pub struct Acl {
+    rules: RwLock<Vec<RuleEntry>>,
+    default_allow: AtomicBool,
+    flows: Mutex<Flows>,
+}
+
+impl Acl {
+    pub fn new(cfg: AclConfig) -> Self {
+        let acl = Self {
+            rules: RwLock::new(vec![]),
+            default_allow: AtomicBool::new(true),
+            flows: Mutex::new(Flows {
+                flows: HashMap::new(),
+                gc_at: Instant::now(),
+            }),
+        };
+        acl.set(cfg);
+        acl
+    }
+
+    /// 替换全部规则,命中计数清零
+    /// 已记录的连接按新规则重新检查
+    pub fn set(&self, cfg: AclConfig) {
+        let rules = cfg.rules.into_iter()
+            .map(|rule| RuleEntry { rule, hits: AtomicU64::new(0) })
+            .collect();
+        *self.rules.write().unwrap() = rules;
+        self.default_allow.store(cfg.default_allow, Ordering::Relaxed);
+        self.flows.lock().unwrap().flows.clear();
+    }
+
+    pub fn config(&self) -> AclConfig {
+        AclConfig {
+            rules: self.rules.read().unwrap().iter().map(|e| e.rule.clone()).collect(),
+            default_allow: self.default_allow.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn metrics(&self) -> Vec<AclRuleMetrics> {
+        self.rules.read().unwrap().iter()
+            .map(|e| AclRuleMetrics { id: e.rule.id, hits: e.hits.load(Ordering::Relaxed) })
+            .collect()
+    }
+
+    /// 检查ip 包是否放行
+    pub fn check(&self, buf: &[u8]) -> bool {
+        let rules = self.rules.read().unwrap();
+        if rules.is_empty() {
+            return self.default_allow.load(Ordering::Relaxed);
+        }
+        let Some(pkt) = PacketInfo::parse(buf) else {
+            return self.default_allow.load(Ordering::Relaxed);
+        };
+        let key = pkt.flow_key();
+        let mut flows = self.flows.lock().unwrap();
+        if flows.touch(&key) {
+            // 连接重置后不再放行回包
+            if pkt.proto == PROTO_TCP && pkt.tcp_flags & TCP_FLAG_RST != 0 {
+                flows.remove(&key);
+            }
+            return true;
+        }
+        let allow = rules.iter()
+            .find(|entry| entry.rule.matches(&pkt))
+            .map(|entry| {
+                entry.hits.fetch_add(1, Ordering::Relaxed);
+                entry.rule.allow
+            })
+            .unwrap_or(self.default_allow.load(Ordering::Relaxed));
+        if allow {
+            flows.insert(key);
+        }
+        allow
+    }
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Self::new(AclConfig::default())
+    }
+}
+
+impl AclRule {
+    fn matches(&self, pkt: &PacketInfo) -> bool {
+        if !self.src.is_empty() && !self.src.iter().any(|c| c.contains(pkt.src)) {
+            return false;
+        }
+        if !self.dst.is_empty() && !self.dst.iter().any(|c| c.contains(pkt.dst)) {
+            return false;
+        }
+        let proto_ok = match self.proto {
+            AclProto::Any => true,
+            AclProto::Tcp => pkt.proto == PROTO_TCP,
+            AclProto::Udp => pkt.proto == PROTO_UDP,
+            AclProto::Icmp => pkt.proto == PROTO_ICMP || pkt.proto == PROTO_ICMPV6,
+        };
+        if !proto_ok {
+            return false;
+        }
+        match (self.ports, pkt.dst_port) {
+            (None, _) => true,
+            (Some((start, end)), Some(port)) => start <= port && port <= end,
+            // 带端口的规则不匹配分片/无端口协议
+            (Some(_), None) => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct PacketInfo {
+    src: IpAddr,
+    dst: IpAddr,
+    proto: u8,
+    src_port: Option<u16>,
+    dst_port: Option<u16>,
+    tcp_flags: u8,
+}
+
+impl PacketInfo {
+    fn flow_key(&self) -> FlowKey {
+        FlowKey {
+            src: self.src,
+            dst: self.dst,
+            proto: self.proto,
+            src_port: self.src_port.unwrap_or(0),
+            dst_port: self.dst_port.unwrap_or(0),
+        }
+    }
+
+    fn parse(buf: &[u8]) -> Option<Self> {
+        match buf.first()? >> 4 {
+            4 => Self::parse_v4(buf),
+            6 => Self::parse_v6(buf),
+            _ => None,
+        }
+    }
+
+    fn parse_v4(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 20 {
+            return None;
+        }
+        let ihl = ((buf[0] & 0x0F) as usize) * 4;
+        if ihl < 20 || buf.len() < ihl {
+            return None;
+        }
+        let src: [u8; 4] = buf[12..16].try_into().unwrap();
+        let dst: [u8; 4] = buf[16..20].try_into().unwrap();
+        // 非首个分片没有四层头
+        let frag_offset = u16::from_be_bytes([buf[6] & 0x1F, buf[7]]);
+        let l4 = if frag_offset == 0 { &buf[ihl..] } else { &[][..] };
+        let (ports, tcp_flags) = parse_l4(buf[9], l4);
+        Some(Self {
+            src: IpAddr::V4(Ipv4Addr::from(src)),
+            dst: IpAddr::V4(Ipv4Addr::from(dst)),
+            proto: buf[9],
+            src_port: ports.map(|p| p.0),
+            dst_port: ports.map(|p| p.1),
+            tcp_flags,
+        })
+    }
+
+    fn parse_v6(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 40 {
+            return None;
+        }
+        let src: [u8; 16] = buf[8..24].try_into().unwrap();
+        let dst: [u8; 16] = buf[24..40].try_into().unwrap();
+        let mut next = buf[6];
+        let mut offset = 40;
+        let mut fragmented = false;
+        // 跳过扩展头
+        loop {
+            match next {
+                0 | 43 | 60 => {
+                    if buf.len() < offset + 2 {
+                        return None;
+                    }
+                    next = buf[offset];
+                    offset += (buf[offset + 1] as usize + 1) * 8;
+                }
+                44 => {
+                    if buf.len() < offset + 8 {
+                        return None;
+                    }
+                    next = buf[offset];
+                    let frag_offset = u16::from_be_bytes([buf[offset + 2], buf[offset + 3]]) >> 3;
+                    fragmented = frag_offset != 0;
+                    offset += 8;
+                }
+                _ => break,
+            }
+        }
+        let l4 = if fragmented || buf.len() < offset { &[][..] } else { &buf[offset..] };
+        let (ports, tcp_flags) = parse_l4(next, l4);
+        Some(Self {
+            src: IpAddr::V6(Ipv6Addr::from(src)),
+            dst: IpAddr::V6(Ipv6Addr::from(dst)),
+            proto: next,
+            src_port: ports.map(|p| p.0),
+            dst_port: ports.map(|p| p.1),
+            tcp_flags,
+        })
+    }
+}
+
+/// 返回 (源端口, 目标端口) 和 tcp 标志
+fn parse_l4(proto: u8, l4: &[u8]) -> (Option<(u16, u16)>, u8) {
+    let ports = |l4: &[u8]| (u16::from_be_bytes([l4[0], l4[1]]), u16::from_be_bytes([l4[2], l4[3]]));
+    match proto {
+        PROTO_TCP if l4.len() >= 14 => (Some(ports(l4)), l4[13]),
+        PROTO_UDP if l4.len() >= 4 => (Some(ports(l4)), 0),
+        _ => (None, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCP_FLAG_SYN: u8 = 0x02;
+    const TCP_FLAG_ACK: u8 = 0x10;
+
+    fn ipv4_packet(src: [u8; 4], dst: [u8; 4], proto: u8, dst_port: u16, flags: u8) -> Vec<u8> {
+        ipv4_flow((src, 40000), (dst, dst_port), proto, flags)
+    }
+
+    fn ipv4_flow(src: ([u8; 4], u16), dst: ([u8; 4], u16), proto: u8, flags: u8) -> Vec<u8> {
+        let mut buf = vec![0u8; 40];
+        buf[0] = 0x45;
+        buf[9] = proto;
+        buf[12..16].copy_from_slice(&src.0);
+        buf[16..20].copy_from_slice(&dst.0);
+        buf[20..22].copy_from_slice(&src.1.to_be_bytes());
+        buf[22..24].copy_from_slice(&dst.1.to_be_bytes());
+        buf[33] = flags;
+        buf
+    }
+
+    #[test]
+    fn test_parse_packet() {
+        let buf = ipv4_packet([10, 0, 0, 1], [10, 0, 0, 2], PROTO_TCP, 22, TCP_FLAG_SYN);
+        let pkt = PacketInfo::parse(&buf).unwrap();
+        assert_eq!(pkt.src, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(pkt.dst, "10.0.0.2".parse::<IpAddr>().unwrap());
+        assert_eq!(pkt.src_port, Some(40000));
+        assert_eq!(pkt.dst_port, Some(22));
+        assert_eq!(pkt.tcp_flags, TCP_FLAG_SYN);
+
+        // ipv6 + hop-by-hop + udp
+        let mut buf = vec![0u8; 56];
+        buf[0] = 0x60;
+        buf[6] = 0;
+        buf[8..24].copy_from_slice(&"fd00::1".parse::<Ipv6Addr>().unwrap().octets());
+        buf[24..40].copy_from_slice(&"fd00::2".parse::<Ipv6Addr>().unwrap().octets());
+        buf[40] = PROTO_UDP;
+        buf[50..52].copy_from_slice(&53u16.to_be_bytes());
+        let pkt = PacketInfo::parse(&buf).unwrap();
+        assert_eq!(pkt.proto, PROTO_UDP);
+        assert_eq!(pkt.dst_port, Some(53));
+
+        assert!(PacketInfo::parse(&[0x45, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn test_acl_check() {
+        let acl = Acl::new(AclConfig {
+            rules: vec![
+                AclRule {
+                    id: 1,
+                    src: vec![],
+                    dst: vec!["10.0.0.2".parse().unwrap()],
+                    proto: AclProto::Tcp,
+                    ports: Some((22, 22)),
+                    allow: true,
+                },
+                AclRule {
+                    id: 2,
+                    src: vec![],
+                    dst: vec!["10.0.0.2".parse().unwrap()],
+                    proto: AclProto::Any,
+                    ports: None,
+                    allow: false,
+                },
+            ],
+            default_allow: true,
+        });
+        assert!(acl.check(&ipv4_packet([10, 0, 0, 1], [10, 0, 0, 2], PROTO_TCP, 22, TCP_FLAG_SYN)));
+        assert!(!acl.check(&ipv4_packet([10, 0, 0, 1], [10, 0, 0, 2], PROTO_TCP, 80, TCP_FLAG_SYN)));
+        assert!(!acl.check(&ipv4_packet([10, 0, 0, 1], [10, 0, 0, 2], PROTO_UDP, 22, 0)));
+        // 不属于任何连接的 tcp 包也按规则检查, 不能绕过
+        assert!(!acl.check(&ipv4_packet([10, 0, 0, 1], [10, 0, 0, 2], PROTO_TCP, 80, TCP_FLAG_ACK)));
+        // 已放行连接的回包
+        assert!(acl.check(&ipv4_flow(([10, 0, 0, 2], 22), ([10, 0, 0, 1], 40000), PROTO_TCP, TCP_FLAG_SYN | TCP_FLAG_ACK)));
+        assert!(acl.check(&ipv4_packet([10, 0, 0, 1], [10, 0, 0, 3], PROTO_UDP, 53, 0)));
+
+        let hits: Vec<u64> = acl.metrics().iter().map(|m| m.hits).collect();
+        assert_eq!(hits, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_acl_flows() {
+        // 10.0.0.2 不能主动连接其他节点
+        let cfg = AclConfig {
+            rules: vec![AclRule {
+                id: 1,
+                src: vec!["10.0.0.2".parse().unwrap()],
+                dst: vec![],
+                proto: AclProto::Any,
+                ports: None,
+                allow: false,
+            }],
+            default_allow: true,
+        };
+        let acl = Acl::new(cfg.clone());
+        let (a, b) = ([10, 0, 0, 1], [10, 0, 0, 2]);
+        assert!(acl.check(&ipv4_flow((a, 40000), (b, 80), PROTO_TCP, TCP_FLAG_SYN)));
+        assert!(acl.check(&ipv4_flow((b, 80), (a, 40000), PROTO_TCP, TCP_FLAG_SYN | TCP_FLAG_ACK)));
+        assert!(acl.check(&ipv4_flow((b, 80), (a, 40000), PROTO_TCP, TCP_FLAG_ACK)));
+        // 其他端口不是同一个连接
+        assert!(!acl.check(&ipv4_flow((b, 80), (a, 40001), PROTO_TCP, TCP_FLAG_ACK)));
+        assert!(!acl.check(&ipv4_flow((b, 40000), (a, 22), PROTO_TCP, TCP_FLAG_SYN)));
+
+        assert!(acl.check(&ipv4_flow((a, 5353), (b, 53), PROTO_UDP, 0)));
+        assert!(acl.check(&ipv4_flow((b, 53), (a, 5353), PROTO_UDP, 0)));
+
+        // 重置后回包按规则检查
+        assert!(acl.check(&ipv4_flow((b, 80), (a, 40000), PROTO_TCP, TCP_FLAG_RST)));
+        assert!(!acl.check(&ipv4_flow((b, 80), (a, 40000), PROTO_TCP, TCP_FLAG_ACK)));
+
+        // 替换规则后清空连接
+        acl.set(cfg);
+        assert!(!acl.check(&ipv4_flow((b, 53), (a, 5353), PROTO_UDP, 0)));
+    }
+}
@@ -1,9 +1,11 @@
 use std::collections::{HashMap, HashSet};
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::time::Duration;
-use ip_network::IpNetwork;
-use serde::{Deserialize, Serialize};
+use ip_network::{IpNetwork, Ipv6Network};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use crate::{LocalStaticSecret};
+use crate::device::acl::AclConfig;
+use crate::router::RouteConfig;
 use crate::device::peer::cidr::Cidr;
 
 // #[derive(Clone, Debug)]
@@ -11,7 +13,7 @@ use crate::device::peer::cidr::Cidr;
 //     Udp(SocketAddr)
 // }
 
-#[derive(Default, Clone, Debug, )]
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct PeerConfig {
     pub public_key: [u8; 32],
     ///节点接受的ip
@@ -35,21 +37,79 @@ pub struct PeerConfig {
 impl PeerConfig {}
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceConfig {
+    /// 私钥不参与序列化,由使用方自行填充
+    #[serde(skip)]
     pub private_key: [u8; 32],
     pub fwmark: u32,
     pub port: u16,
+    #[serde(with = "serde_peers")]
     pub peers: HashMap<[u8; 32], PeerConfig>,
     pub address: Ipv4Addr,
     //网络
     pub network: IpNetwork,
+    /// ipv6 地址,双栈时有值
+    #[serde(default)]
+    pub address6: Option<Ipv6Addr>,
+    #[serde(default)]
+    pub network6: Option<Ipv6Network>,
+    /// 节点间访问控制
+    #[serde(default)]
+    pub acl: AclConfig,
+    /// 使用出口节点时,这些地址仍走原来的网关,如服务器和对端的传输层地址
+    #[serde(default)]
+    pub bypass: Vec<IpAddr>,
+    /// 系统路由的优先级和路由表
+    #[serde(default)]
+    pub route: RouteConfig,
+    /// 会话重新握手的时间和包数, 默认与 wireguard 相同
+    #[serde(default)]
+    pub rekey: RekeyConfig,
 }
 
-#[derive(Debug, Clone)]
+/// 会话建立后超过时间或发送包数时重新握手, 一般不需要修改
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RekeyConfig {
+    /// 默认120s
+    #[serde(default)]
+    pub after_time: Option<Duration>,
+    /// 默认 2^60
+    #[serde(default)]
+    pub after_messages: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ArgConfig {
     pub endpoint_addr: Option<String>,
     pub port: Option<u16>,
+    /// 宣告的子网路由
+    #[serde(default)]
+    pub advertise_routes: Vec<String>,
+    /// 作为出口节点
+    #[serde(default)]
+    pub exit_node: bool,
+    /// 使用的出口节点,公钥或虚拟ip
+    #[serde(default)]
+    pub use_exit_node: Option<String>,
+    /// 添加系统路由时的优先级
+    #[serde(default)]
+    pub route_metric: Option<u32>,
+    /// 添加系统路由时使用的路由表
+    #[serde(default)]
+    pub route_table: Option<u32>,
+    /// 开启内置dns
+    #[serde(default)]
+    pub magic_dns: bool,
+    /// 内置dns 解析的域名,默认 vlink
+    #[serde(default)]
+    pub dns_domain: Option<String>,
+    /// 其他域名转发的上游dns,默认取系统配置
+    #[serde(default)]
+    pub dns_upstream: Option<String>,
+    /// 打洞时获取wireguard 端口映射地址的stun 服务器
+    #[serde(default)]
+    pub stun_servers: Vec<String>,
 }
 
 /// 传输层配置
@@ -106,3 +166,18 @@ impl DeviceConfig {
 }
 
 
+/// peers 按列表序列化,json 的key 不支持数组
+mod serde_peers {
+    use super::*;
+
+    pub fn serialize<S>(peers: &HashMap<[u8; 32], PeerConfig>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer {
+        serializer.collect_seq(peers.values())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<HashMap<[u8; 32], PeerConfig>, D::Error>
+        where D: Deserializer<'de> {
+        let peers = Vec::<PeerConfig>::deserialize(deserializer)?;
+        Ok(peers.into_iter().map(|p| (p.public_key, p)).collect())
+    }
+}
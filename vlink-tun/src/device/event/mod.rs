@@ -1,6 +1,4 @@
-use std::sync::Arc;
 use tokio::sync::broadcast;
-use crate::device::peer::Peer;
 use crate::noise::crypto::PublicKey;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -23,12 +21,14 @@ pub enum DeviceEvent {
     ExtraEndpointSuccess(ExtraEndpoint),
     NoEndpoint((PublicKey, String)),
     /// 节点未握手
-    SessionFailed(Arc<Peer>),
+    SessionFailed((PublicKey, String)),
 
     /// 传输层协议失败
     TransportFailed(ExtraEndpoint),
     /// endpoint 连接失败
     PeerEndpointFailed(PublicKey),
+    /// 监听端口被修改, 需要重新上报
+    ListenPortChanged(u16),
     // HandshakeTimeout,
 }
 
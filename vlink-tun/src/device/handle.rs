@@ -1,14 +1,17 @@
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio_util::sync::CancellationToken;
 use log::{debug, error, warn};
+use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use crate::{LocalStaticSecret, Tun};
 use crate::noise::handshake::{Cookie, IncomingInitiation};
 use crate::noise::{Message, protocol};
 use crate::device::DeviceInner;
 use crate::device::peer::InboundEvent;
-use crate::device::inbound::OutboundSender;
+use crate::device::inbound::{InboundResult, OutboundSender};
+use crate::device::transport::udp;
 
 pub struct DeviceHandle {
     inbound_loop: JoinHandle<()>,
@@ -17,7 +20,7 @@ pub struct DeviceHandle {
 
 impl DeviceHandle {
     /// 新建并挂起
-    pub fn spawn(token: CancellationToken, inner: Arc<DeviceInner>) -> Self {
+    pub fn spawn<T: Tun>(token: CancellationToken, inner: Arc<DeviceInner<T>>) -> Self {
         //入口数据
 
         //出口数据
@@ -38,7 +41,7 @@ impl Drop for DeviceHandle {
     }
 }
 /// 循环处理outbound
-async fn loop_outbound(token: CancellationToken, inner: Arc<DeviceInner>)
+async fn loop_outbound<T: Tun>(token: CancellationToken, inner: Arc<DeviceInner<T>>)
 
 {
     debug!("Device outbound loop is UP");
@@ -54,7 +57,7 @@ async fn loop_outbound(token: CancellationToken, inner: Arc<DeviceInner>)
 }
 
 
-async fn tick_outbound(inner: Arc<DeviceInner>)
+async fn tick_outbound<T: Tun>(inner: Arc<DeviceInner<T>>)
 {
     const IPV4_HEADER_LEN: usize = 20;
     const IPV6_HEADER_LEN: usize = 40;
@@ -89,6 +92,10 @@ async fn tick_outbound(inner: Arc<DeviceInner>)
                 return;
             }
 
+            if !inner.acl_check(&buf) {
+                debug!("packet to {dst} denied by acl");
+                return;
+            }
             debug!("trying to send packet to {}", dst);
             let peer = inner.peers.read().unwrap().get_by_ip(dst);
 
@@ -106,11 +113,35 @@ async fn tick_outbound(inner: Arc<DeviceInner>)
 }
 
 
+/// 负载高后至少保持这么久, 避免队列刚降下来又被打满
+const UNDER_LOAD_AFTER: Duration = Duration::from_secs(1);
+
+/// 按入口队列深度判断负载, 超过容量的 1/8 时认为负载高
+struct LoadMonitor {
+    tx: mpsc::Sender<InboundResult>,
+    until: Option<Instant>,
+}
+
+impl LoadMonitor {
+    fn under_load(&mut self) -> bool {
+        let depth = self.tx.max_capacity() - self.tx.capacity();
+        if depth >= self.tx.max_capacity() / 8 {
+            self.until = Some(Instant::now() + UNDER_LOAD_AFTER);
+            return true;
+        }
+        self.until.map(|t| Instant::now() < t).unwrap_or(false)
+    }
+}
+
 /// 处理设备endpoint入口数据
 ///
-async fn loop_inbound(token: CancellationToken, inner: Arc<DeviceInner>)
+async fn loop_inbound<T: Tun>(token: CancellationToken, inner: Arc<DeviceInner<T>>)
 {
     let mut transport = inner.settings.lock().unwrap().inbound.take_rx().expect("inbound transport is none");
+    let mut load = LoadMonitor {
+        tx: inner.settings.lock().unwrap().inbound.tx(),
+        until: None,
+    };
     debug!("Device Inbound loop is UP");
 
 
@@ -129,7 +160,8 @@ async fn loop_inbound(token: CancellationToken, inner: Arc<DeviceInner>)
             //处理传输层数据
             data = transport.recv() => {
                 if let Some((data, sender)) = data {
-                    tick_inbound(Arc::clone(&inner), &secret, Arc::clone(&cookie), sender, data).await;
+                    let under_load = load.under_load();
+                    tick_inbound(Arc::clone(&inner), &secret, Arc::clone(&cookie), sender, data, under_load).await;
                 }
             }
         }
@@ -137,36 +169,54 @@ async fn loop_inbound(token: CancellationToken, inner: Arc<DeviceInner>)
 }
 
 
-async fn tick_inbound(
-    inner: Arc<DeviceInner>,
+async fn tick_inbound<T: Tun>(
+    inner: Arc<DeviceInner<T>>,
     secret: &LocalStaticSecret,
     cookie: Arc<Cookie>,
     endpoint: Box<dyn OutboundSender>,
     payload: Vec<u8>,
+    under_load: bool,
 )
 {
+    if endpoint.protocol() == udp::PROTO_NAME && !Message::is_wireguard(&payload) {
+        inner.dispatch_udp(endpoint.dst(), payload);
+        return;
+    }
     if Message::is_handshake(&payload) {
         if !cookie.validate_mac1(&payload) {
             debug!("invalid mac1");
+            inner.handshake_counter.invalid_mac1();
             return;
         }
-
-        if !inner.rate_limiter.fetch_token() {
-            debug!("rate limited");
-            if !cookie.validate_mac2(&payload) {
-                debug!("invalid mac2");
+        // 其他传输层已经建立连接, 来源地址无法伪造, 只防护udp
+        if under_load && endpoint.protocol() == udp::PROTO_NAME {
+            let src = endpoint.dst();
+            if !cookie.validate_mac2(&payload, src) {
+                debug!("under load, send cookie reply to {src}");
+                inner.handshake_counter.cookie_replied();
+                let reply = cookie.generate_cookie_reply(&payload, src);
+                if let Err(e) = endpoint.send(&reply).await {
+                    debug!("send cookie reply to {src} error: {e}");
+                }
+                return;
+            }
+            if !inner.rate_limiter.fetch_token(src.ip()) {
+                debug!("handshake from {src} rate limited");
+                inner.handshake_counter.throttled();
                 return;
             }
-            debug!("try to send cookie reply");
-            let reply = cookie.generate_cookie_reply(&payload, endpoint.dst());
-            endpoint.send(&reply).await.unwrap();
-            return;
         }
     }
 
     match Message::parse(&payload) {
         Ok(Message::HandshakeInitiation(p)) => {
-            let initiation = IncomingInitiation::parse(secret, &p).unwrap_or_else(|_| todo!());
+            let initiation = match IncomingInitiation::parse(secret, &p) {
+                Ok(initiation) => initiation,
+                Err(e) => {
+                    debug!("invalid handshake initiation: {e}");
+                    return;
+                }
+            };
             if let Some(peer) = inner.get_peer_by_key(initiation.static_public_key.as_bytes()) {
                 peer.stage_inbound(InboundEvent::HanshakeInitiation {
                     endpoint,
@@ -4,6 +4,7 @@ use std::net::SocketAddr;
 use std::sync::Mutex;
 use async_trait::async_trait;
 use tokio::sync::{mpsc};
+use tokio_util::sync::CancellationToken;
 use crate::device::transport::udp::{UdpOutboundSender, UdpSocketInfo, UdpTransport};
 
 
@@ -12,7 +13,7 @@ pub trait OutboundSender: BoxCloneOutboundSender + Send + Sync + Debug + Display
     async fn send(&self, data: &[u8]) -> Result<(), io::Error>;
     fn dst(&self) -> SocketAddr;
 
-    fn protocol(&self) -> String;
+    fn protocol(&self) -> &'static str;
 
     fn writeable(&self) -> bool {
         true
@@ -37,16 +38,20 @@ pub(super) struct Inbound {
     pub(crate) tx: mpsc::Sender<InboundResult>,
     rx: Mutex<Option<mpsc::Receiver<InboundResult>>>,
     socket_info: UdpSocketInfo,
+    /// 取消后关闭当前udp 端口
+    udp_token: CancellationToken,
 }
 
 impl Inbound {
     pub fn new(tx: mpsc::Sender<InboundResult>,
                rx: mpsc::Receiver<InboundResult>,
-               socket_info: UdpSocketInfo) -> Self {
+               socket_info: UdpSocketInfo,
+               udp_token: CancellationToken) -> Self {
         Self {
             tx,
             rx: Mutex::new(Some(rx)),
             socket_info,
+            udp_token,
         }
     }
     pub fn tx(&self) -> mpsc::Sender<InboundResult> {
@@ -55,6 +60,14 @@ impl Inbound {
     pub fn take_rx(&self) -> Option<mpsc::Receiver<InboundResult>> {
         self.rx.lock().unwrap().take()
     }
+    pub fn port(&self) -> u16 {
+        self.socket_info.ipv4.local_addr().map(|a| a.port()).unwrap_or(0)
+    }
+    /// 换成新绑定的udp 端口, 关闭旧端口
+    pub fn replace_socket(&mut self, socket_info: UdpSocketInfo, udp_token: CancellationToken) {
+        self.socket_info = socket_info;
+        std::mem::replace(&mut self.udp_token, udp_token).cancel();
+    }
     pub fn endpoint_for(&self, addr: SocketAddr) -> Box<dyn OutboundSender> {
         Box::new(UdpOutboundSender {
             dst: addr,
@@ -1,6 +1,42 @@
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 
+/// 握手防护统计
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeMetrics {
+    /// mac1 错误丢弃
+    pub invalid_mac1: u64,
+    /// 负载高时 mac2 无效, 回复cookie 后丢弃
+    pub cookie_replies: u64,
+    /// 负载高时来源ip 超过限速丢弃
+    pub throttled: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct HandshakeCounter {
+    invalid_mac1: AtomicU64,
+    cookie_replies: AtomicU64,
+    throttled: AtomicU64,
+}
+
+impl HandshakeCounter {
+    #[inline]
+    pub fn invalid_mac1(&self) {
+        self.invalid_mac1.fetch_add(1, Ordering::Relaxed);
+    }
+    #[inline]
+    pub fn cookie_replied(&self) {
+        self.cookie_replies.fetch_add(1, Ordering::Relaxed);
+    }
+    #[inline]
+    pub fn throttled(&self) {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+    }
 
-pub struct DeviceMetrics {
-    // pub peers: HashMap<[u8; 32], PeerMetrics>, // index by public key
+    pub fn metrics(&self) -> HandshakeMetrics {
+        HandshakeMetrics {
+            invalid_mac1: self.invalid_mac1.load(Ordering::Relaxed),
+            cookie_replies: self.cookie_replies.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+        }
+    }
 }
@@ -1,22 +1,28 @@
-use std::net::{IpAddr, Ipv4Addr};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::ops::Deref;
+use std::path::Path;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use ip_network::IpNetwork;
-use log::debug;
-use tokio::sync::{broadcast, mpsc, Mutex as AsyncMutex};
+use log::{debug, info, warn};
+use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
 
-use crate::{LocalStaticSecret, Tun};
+use crate::{LocalStaticSecret, NativeTun, Tun};
+use crate::device::acl::{Acl, AclConfig, AclRuleMetrics};
 use crate::device::config::{DeviceConfig, PeerConfig};
 use crate::device::handle::DeviceHandle;
 use crate::device::inbound::{Inbound, InboundResult};
 use crate::device::peer::Peer;
+use crate::device::peer::cidr::Cidr;
 use crate::device::peer::peers::PeerList;
 use crate::device::peer::session::Session;
-use crate::device::rate_limiter::RateLimiter;
+use crate::device::metrics::HandshakeCounter;
+use crate::device::rate_limiter::IpRateLimiter;
 // use crate::device::transport::{Transport, TransportDispatcher, TransportWrapper};
-use crate::device::transport::udp::UdpTransport;
+use crate::device::transport::udp::{self, UdpTransport};
 use crate::errors::Error;
 use crate::noise::handshake::Cookie;
 use crate::router::Router;
@@ -26,6 +32,7 @@ pub mod peer;
 mod handle;
 
 mod metrics;
+pub use metrics::HandshakeMetrics;
 mod rate_limiter;
 mod time;
 pub mod config;
@@ -35,6 +42,11 @@ pub mod endpoint;
 mod crypto;
 mod cipher;
 pub mod event;
+pub mod acl;
+pub(crate) mod wg_quick;
+
+/// 负载高时每个来源ip 每秒允许的握手数
+const HANDSHAKES_PER_SECOND: u16 = 20;
 
 struct Settings
 {
@@ -63,15 +75,17 @@ impl Settings
 
     #[inline(always)]
     pub fn listen_port(&self) -> u16 {
-        // self.inbound.port()
-        todo!();
+        self.inbound.port()
     }
 }
 
 
-pub struct Device {
+/// 来源地址和数据
+pub type UdpPacket = (SocketAddr, Vec<u8>);
+
+pub struct Device<T = NativeTun> {
     token: CancellationToken,
-    inner: Arc<DeviceInner>,
+    inner: Arc<DeviceInner<T>>,
     handler: DeviceHandle,
     pub port: u16,
 }
@@ -84,10 +98,7 @@ impl Device {
         let tun = crate::NativeTun::new(name, false)?;
         tun.enabled(true)?;
         //设置ip,network
-        let mask = match cfg.network {
-            IpNetwork::V4(n) => n.full_netmask(),
-            IpNetwork::V6(n) => todo!("未实现ipv6"),
-        };
+        let mask = netmask(&cfg)?;
         debug!("set ip :{};{}",cfg.address,mask);
 
         // let mask = Ipv4Addr::from(helpers::bite_mask(cfg.netmask));
@@ -95,25 +106,72 @@ impl Device {
         //let network = Ipv4Network::new(cfg.address, cfg.netmask);
         tun.set_ip(cfg.address, mask)?;
         //设置网络路由
-        let router = Router::new(tun.name().to_string());
+        let router = Router::new(tun.name().to_string(), cfg.route.clone());
         //Cidr
         router.add_route(cfg.network.network_address(), IpAddr::V4(mask))?;
+        //双栈
+        if let (Some(addr6), Some(net6)) = (cfg.address6, cfg.network6) {
+            debug!("set ipv6 :{}/{}", addr6, net6.netmask());
+            tun.set_ipv6(addr6, net6.netmask())?;
+            let mask6 = u128::MAX.checked_shl(128 - net6.netmask() as u32).unwrap_or(0);
+            router.add_route(IpAddr::V6(net6.network_address()), IpAddr::V6(Ipv6Addr::from(mask6)))?;
+        }
+        Self::spawn(tun, Some(router), cfg).await
+    }
+
+    /// 使用 wg-quick 配置文件启动, 网卡名取文件名, 如 wg0.conf -> wg0
+    pub async fn from_wg_quick(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let cfg = DeviceConfig::from_wg_quick(&std::fs::read_to_string(path)?)?;
+        let name = path.file_stem().map(|s| s.to_string_lossy().to_string());
+        Self::new(name, cfg).await
+    }
+}
+
+impl<T: Tun> Device<T> {
+    /// 使用指定的tun, 如用户态协议栈, 不修改系统路由
+    pub async fn with_tun(tun: T, cfg: DeviceConfig) -> Result<Self, Error> {
+        tun.enabled(true)?;
+        tun.set_ip(cfg.address, netmask(&cfg)?)?;
+        if let (Some(addr6), Some(net6)) = (cfg.address6, cfg.network6) {
+            tun.set_ipv6(addr6, net6.netmask())?;
+        }
+        Self::spawn(tun, None, cfg).await
+    }
 
+    async fn spawn(tun: T, router: Option<Router>, cfg: DeviceConfig) -> Result<Self, Error> {
         let token = CancellationToken::new();
         let (tx, rx) = mpsc::channel::<InboundResult>(1024);
-        let (port, socket_info) = UdpTransport::spawn(token.child_token(), cfg.port, tx.clone()).await?;
-        let inbound = Inbound::new(tx.clone(), rx, socket_info);
+        let udp_token = token.child_token();
+        let (port, socket_info) = UdpTransport::spawn(udp_token.clone(), cfg.port, tx.clone()).await?;
+        let inbound = Inbound::new(tx.clone(), rx, socket_info, udp_token);
         let settings = Mutex::new(Settings::new(inbound, cfg.private_key, cfg.fwmark));
         let (tx, _) = broadcast::channel(32);
-        let peers = Arc::new(RwLock::new(PeerList::new(token.child_token(), tun.clone(), tx.clone())));
+        let acl = Arc::new(Acl::new(cfg.acl));
+        let peers = Arc::new(RwLock::new(PeerList::new(token.child_token(), tun.clone(), acl.clone(), cfg.rekey, tx.clone())));
+        let mut overlay = vec![Cidr::new(cfg.network.network_address(), cfg.network.netmask())];
+        if let Some(net6) = cfg.network6 {
+            overlay.push(Cidr::new(IpAddr::V6(net6.network_address()), net6.netmask()));
+        }
         let inner = Arc::new(DeviceInner {
             tun_addr: tun.address()?,
+            tun_addr6: cfg.address6,
             tun,
             peers,
             settings,
-            rate_limiter: RateLimiter::new(u16::MAX),
+            acl,
+            router,
+            overlay,
+            routes: Mutex::new(HashSet::new()),
+            bypass: Mutex::new(HashSet::new()),
+            rate_limiter: IpRateLimiter::new(HANDSHAKES_PER_SECOND),
+            handshake_counter: HandshakeCounter::default(),
             event_bus: tx,
+            udp_raw: broadcast::channel(64).0,
+            token: token.clone(),
         });
+        // 先固定旁路, 再添加可能包含默认路由的peer
+        inner.set_bypass(cfg.bypass.into_iter().collect());
         inner.reset_peers(cfg.peers.into_values().collect());
 
         let handler = DeviceHandle::spawn(token.clone(), inner.clone());
@@ -125,6 +183,12 @@ impl Device {
         })
     }
 
+    pub fn control(&self) -> DeviceControl<T> {
+        DeviceControl {
+            inner: self.inner.clone(),
+        }
+    }
+
     /// 用于向设备输入数据
     /// 扩展协议可以将数据直接输入到设备
     pub fn inbound_tx(&self) -> mpsc::Sender<InboundResult> {
@@ -132,34 +196,56 @@ impl Device {
     }
 }
 
-impl Deref for Device {
-    type Target = DeviceInner;
+impl<T> Drop for Device<T> {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+impl<T> Deref for Device<T> {
+    type Target = DeviceInner<T>;
 
     fn deref(&self) -> &Self::Target {
         self.inner.as_ref()
     }
 }
 
-pub struct DeviceInner {
-    pub tun: crate::NativeTun,
+pub struct DeviceInner<T = NativeTun> {
+    pub tun: T,
     pub tun_addr: Ipv4Addr,
-    pub peers: Arc<RwLock<PeerList>>,
+    pub tun_addr6: Option<Ipv6Addr>,
+    pub peers: Arc<RwLock<PeerList<T>>>,
     settings: Mutex<Settings>,
-    /// 对入口数据限流
-    rate_limiter: RateLimiter,
+    /// 出入口共用的访问控制
+    acl: Arc<Acl>,
+    /// 用户态协议栈没有系统路由
+    router: Option<Router>,
+    /// 虚拟网段,网段内的路由已在启动时添加
+    overlay: Vec<Cidr>,
+    /// 已添加的子网路由
+    routes: Mutex<HashSet<Cidr>>,
+    /// 不走tun 的主机路由
+    bypass: Mutex<HashSet<IpAddr>>,
+    /// 负载高时按来源ip 限制握手
+    rate_limiter: IpRateLimiter,
+    handshake_counter: HandshakeCounter,
     /// 设备事件总线
     pub event_bus: event::DevicePublisher,
+    /// udp 端口收到的非 wireguard 数据
+    udp_raw: broadcast::Sender<UdpPacket>,
+    /// 设备的token, 重新绑定端口时使用
+    token: CancellationToken,
 }
 
-impl DeviceInner {
+impl<T: Tun> DeviceInner<T> {
     #[inline]
-    pub fn get_peer_by_key(&self, public_key: &[u8; 32]) -> Option<Arc<Peer>> {
+    pub fn get_peer_by_key(&self, public_key: &[u8; 32]) -> Option<Arc<Peer<T>>> {
         let index = self.peers.read().unwrap();
         index.get_by_key(public_key)
     }
 
     #[inline]
-    pub fn get_session_by_index(&self, i: u32) -> Option<(Session, Arc<Peer>)> {
+    pub fn get_session_by_index(&self, i: u32) -> Option<(Session, Arc<Peer<T>>)> {
         let index = self.peers.read().unwrap();
         index.get_session_by_index(i)
     }
@@ -178,6 +264,9 @@ impl DeviceInner {
             let endpoint = p.endpoint.map(|addr| settings.inbound.endpoint_for(addr));
             index.insert(secret, p.allowed_ips, endpoint, p.persistent_keepalive, p.is_online, p.ip_addr);
         }
+        drop(index);
+        drop(settings);
+        self.sync_routes();
     }
     /// 插入peer 需要确认传输层协议
     #[inline]
@@ -190,13 +279,240 @@ impl DeviceInner {
         }
         let endpoint = cfg.endpoint.map(|addr| settings.inbound.endpoint_for(addr));
         index.insert(secret, cfg.allowed_ips, endpoint, cfg.persistent_keepalive, cfg.is_online, cfg.ip_addr);
+        drop(index);
+        drop(settings);
+        self.sync_routes();
+    }
+    /// 删除单个peer, 不影响其他peer 的会话
+    #[inline]
+    pub fn remove_peer(&self, public_key: &[u8; 32]) -> Option<Arc<Peer<T>>> {
+        let peer = self.peers.write().unwrap().remove_by_key(public_key);
+        self.sync_routes();
+        peer
+    }
+    #[inline]
+    pub fn update_peer_allowed_ips(&self, public_key: &[u8; 32], allowed_ips: HashSet<Cidr>) -> bool {
+        let changed = self.peers.write().unwrap().update_allowed_ips_by_key(public_key, allowed_ips);
+        if changed {
+            self.sync_routes();
+        }
+        changed
+    }
+    #[inline]
+    pub fn update_peer_keepalive(&self, public_key: &[u8; 32], interval: Option<Duration>) -> bool {
+        let index = self.peers.read().unwrap();
+        index.update_keepalive_by_key(public_key, interval)
+    }
+    #[inline]
+    pub fn update_peer_psk(&self, public_key: &[u8; 32], psk: [u8; 32]) -> bool {
+        let index = self.peers.read().unwrap();
+        index.update_psk_by_key(public_key, psk)
+    }
+    /// 当前监听的udp 端口, 可能已被 set_listen_port 修改
+    pub fn listen_port(&self) -> u16 {
+        self.settings.lock().unwrap().listen_port()
+    }
+    pub fn private_key(&self) -> [u8; 32] {
+        self.settings.lock().unwrap().secret.private_key().to_bytes()
+    }
+    pub fn fwmark(&self) -> u32 {
+        self.settings.lock().unwrap().fwmark
+    }
+    /// 设备关闭时取消
+    pub(crate) fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+    /// 绑定新的udp 端口, 已有的udp 端点改用新端口发送
+    pub async fn set_listen_port(&self, port: u16) -> Result<u16, Error> {
+        if port != 0 && port == self.listen_port() {
+            return Ok(port);
+        }
+        let tx = self.settings.lock().unwrap().inbound.tx();
+        let udp_token = self.token.child_token();
+        let (port, socket_info) = UdpTransport::spawn(udp_token.clone(), port, tx).await?;
+        let peers = self.peers.read().unwrap().all();
+        let settings = &mut self.settings.lock().unwrap();
+        settings.inbound.replace_socket(socket_info, udp_token);
+        for peer in peers {
+            let dst = peer.endpoint.read().unwrap().as_ref()
+                .filter(|e| e.protocol() == udp::PROTO_NAME)
+                .map(|e| e.dst());
+            if let Some(dst) = dst {
+                peer.update_endpoint(settings.inbound.endpoint_for(dst));
+            }
+        }
+        info!("监听端口改为 {}", port);
+        let _ = self.event_bus.send(event::DeviceEvent::ListenPortChanged(port));
+        Ok(port)
+    }
+    /// 对端udp 地址变化时直接替换端点, 不等握手超时
+    #[inline]
+    pub fn update_peer_endpoint(&self, public_key: &[u8; 32], addr: SocketAddr) -> bool {
+        let Some(peer) = self.get_peer_by_key(public_key) else {
+            return false;
+        };
+        let endpoint = self.settings.lock().unwrap().inbound.endpoint_for(addr);
+        peer.update_endpoint(endpoint);
+        true
+    }
+    /// peer 路由中虚拟网段以外的子网,添加到系统路由表,不再使用的删除
+    /// 默认路由拆成两条 /1, 不覆盖系统原有的默认路由
+    fn sync_routes(&self) {
+        let Some(router) = self.router.as_ref() else {
+            return;
+        };
+        let wanted: HashSet<Cidr> = self.peers.read().unwrap()
+            .allowed_ips()
+            .into_iter()
+            .filter(|c| !self.overlay.iter().any(|o| o.contains_cidr(c)))
+            .flat_map(split_default)
+            .collect();
+        let mut routes = self.routes.lock().unwrap();
+        for cidr in routes.difference(&wanted) {
+            let (addr, mask) = cidr.addr_mask();
+            if let Err(e) = router.del_route(addr, mask) {
+                warn!("删除路由失败 {}: {}", cidr.to_string(), e);
+            }
+        }
+        routes.retain(|c| wanted.contains(c));
+        for cidr in wanted {
+            if routes.contains(&cidr) {
+                continue;
+            }
+            let (addr, mask) = cidr.addr_mask();
+            match router.add_route(addr, mask) {
+                Ok(_) => {
+                    info!("添加子网路由 {}", cidr.to_string());
+                    routes.insert(cidr);
+                }
+                Err(e) => warn!("添加路由失败 {}: {}", cidr.to_string(), e),
+            }
+        }
+    }
+    /// 设备udp 端口收到的stun 响应,打洞探测等数据
+    pub fn subscribe_udp(&self) -> broadcast::Receiver<UdpPacket> {
+        self.udp_raw.subscribe()
+    }
+    /// 从设备udp 端口发送, nat 映射与 wireguard 数据相同
+    pub async fn send_udp(&self, dst: SocketAddr, data: &[u8]) -> Result<(), Error> {
+        let endpoint = self.settings.lock().unwrap().inbound.endpoint_for(dst);
+        endpoint.send(data).await?;
+        Ok(())
+    }
+    pub(crate) fn dispatch_udp(&self, src: SocketAddr, data: Vec<u8>) {
+        // 没有订阅时直接丢弃
+        let _ = self.udp_raw.send((src, data));
+    }
+    /// 替换旁路地址, 需要在添加默认路由之前设置
+    pub fn set_bypass(&self, ips: HashSet<IpAddr>) {
+        let mut bypass = self.bypass.lock().unwrap();
+        let Some(router) = self.router.as_ref() else {
+            *bypass = ips;
+            return;
+        };
+        for ip in bypass.difference(&ips) {
+            if let Err(e) = router.del_bypass_route(*ip) {
+                warn!("删除旁路失败 {}: {}", ip, e);
+            }
+        }
+        bypass.retain(|ip| ips.contains(ip));
+        for ip in ips {
+            if bypass.contains(&ip) {
+                continue;
+            }
+            match router.add_bypass_route(ip) {
+                Ok(_) => {
+                    info!("添加旁路 {}", ip);
+                    bypass.insert(ip);
+                }
+                Err(e) => warn!("添加旁路失败 {}: {}", ip, e),
+            }
+        }
+    }
+    pub fn bypass(&self) -> HashSet<IpAddr> {
+        self.bypass.lock().unwrap().clone()
+    }
+    /// 替换访问控制规则,立即对所有peer 生效
+    #[inline]
+    pub fn set_acl(&self, cfg: AclConfig) {
+        self.acl.set(cfg);
+    }
+    #[inline]
+    pub fn acl_config(&self) -> AclConfig {
+        self.acl.config()
+    }
+    /// 握手被丢弃或回复cookie 的次数
+    #[inline]
+    pub fn handshake_metrics(&self) -> HandshakeMetrics {
+        self.handshake_counter.metrics()
+    }
+    /// 每条规则的命中次数
+    #[inline]
+    pub fn acl_metrics(&self) -> Vec<AclRuleMetrics> {
+        self.acl.metrics()
+    }
+    #[inline]
+    pub(crate) fn acl_check(&self, buf: &[u8]) -> bool {
+        self.acl.check(buf)
+    }
+}
+
+impl<T> DeviceInner<T> {
+    /// 删除设备添加的子网路由和旁路, 退出前调用
+    pub fn clear_routes(&self) {
+        let Some(router) = self.router.as_ref() else {
+            return;
+        };
+        for cidr in self.routes.lock().unwrap().drain() {
+            let (addr, mask) = cidr.addr_mask();
+            if let Err(e) = router.del_route(addr, mask) {
+                warn!("删除路由失败 {}: {}", cidr.to_string(), e);
+            }
+        }
+        for ip in self.bypass.lock().unwrap().drain() {
+            if let Err(e) = router.del_bypass_route(ip) {
+                warn!("删除旁路失败 {}: {}", ip, e);
+            }
+        }
     }
 }
 
+impl<T> Drop for DeviceInner<T> {
+    fn drop(&mut self) {
+        self.clear_routes();
+    }
+}
 
+fn netmask(cfg: &DeviceConfig) -> Result<Ipv4Addr, Error> {
+    match cfg.network {
+        IpNetwork::V4(n) => Ok(n.full_netmask()),
+        IpNetwork::V6(_) => Err(Error::InvalidConfig("network 需为ipv4 网段,ipv6 使用 network6".to_string())),
+    }
+}
+
+fn split_default(cidr: Cidr) -> Vec<Cidr> {
+    if !cidr.is_default() {
+        return vec![cidr];
+    }
+    let (addr, _) = cidr.addr_mask();
+    let high = match addr {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(128, 0, 0, 0)),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(0x8000, 0, 0, 0, 0, 0, 0, 0)),
+    };
+    vec![Cidr::new(addr, 1), Cidr::new(high, 1)]
+}
+
+/// 设备控制句柄, 可以在其他任务中修改设备, 如 uapi
 #[derive(Clone)]
-pub struct DeviceControl
+pub struct DeviceControl<T = NativeTun>
 {
-    inner: Arc<DeviceInner>,
-    handle: Arc<AsyncMutex<DeviceHandle>>,
+    inner: Arc<DeviceInner<T>>,
+}
+
+impl<T> Deref for DeviceControl<T> {
+    type Target = DeviceInner<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref()
+    }
 }
\ No newline at end of file
@@ -1,9 +1,11 @@
 use std::hash::{Hash, Hasher};
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 use std::str::FromStr;
 
 use ip_network::IpNetwork;
 use ip_network_table::IpNetworkTable;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
 
 const fn max_mask_for_ip(ip: &IpAddr) -> u8 {
     match ip {
@@ -21,6 +23,34 @@ impl Cidr {
     pub fn new(ip: IpAddr, mask: u8) -> Self {
         Self(IpNetwork::new_truncate(ip, mask).unwrap())
     }
+
+    #[inline]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+
+    /// 0.0.0.0/0 或 ::/0
+    #[inline]
+    pub fn is_default(&self) -> bool {
+        self.0.netmask() == 0
+    }
+
+    /// other 是否是当前网段的子网
+    pub fn contains_cidr(&self, other: &Cidr) -> bool {
+        self.0.netmask() <= other.0.netmask() && self.0.contains(other.0.network_address())
+    }
+
+    /// 网络地址和子网掩码,用于添加系统路由
+    pub fn addr_mask(&self) -> (IpAddr, IpAddr) {
+        let mask = match self.0 {
+            IpNetwork::V4(n) => IpAddr::V4(n.full_netmask()),
+            IpNetwork::V6(n) => {
+                let mask = u128::MAX.checked_shl(128 - n.netmask() as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(mask))
+            }
+        };
+        (self.0.network_address(), mask)
+    }
 }
 
 impl ToString for Cidr {
@@ -69,6 +99,20 @@ impl FromStr for Cidr {
     }
 }
 
+/// 以字符串形式序列化,如 10.2.3.0/24
+impl Serialize for Cidr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(self.to_string().as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let s = String::deserialize(deserializer)?;
+        Cidr::from_str(s.as_str()).map_err(D::Error::custom)
+    }
+}
+
 #[derive(thiserror::Error, Debug, PartialEq)]
 pub enum ParseCidrError {
     #[error("invalid ip address")]
@@ -96,6 +140,10 @@ impl<T> CidrTable<T> {
         self.table.longest_match(ip).map(|(_, v)| v)
     }
 
+    pub fn get_exact(&self, cidr: &Cidr) -> Option<&T> {
+        self.table.exact_match(cidr.0)
+    }
+
     pub fn remove(&mut self, cidr: &Cidr) {
         self.table.remove(cidr.0);
     }
@@ -116,6 +164,8 @@ mod tests {
             ("10.2.3.4/32", "10.2.3.4/32"),
             ("10.2.3.4/16", "10.2.0.0/16"),
             ("10.2.3.4/24", "10.2.3.0/24"),
+            ("fd00::2", "fd00::2/128"),
+            ("fd00::2/64", "fd00::/64"),
         ];
 
         for (input, expected) in valid_cases {
@@ -130,6 +180,7 @@ mod tests {
             ("10.2.3.256", ParseCidrError::InvalidIp),
             ("10.0.0.1/33", ParseCidrError::InvalidMask),
             ("10.0.0.1/32/", ParseCidrError::InvalidMask),
+            ("fd00::1/129", ParseCidrError::InvalidMask),
         ];
 
         for (input, expected) in invalid_cases {
@@ -157,4 +208,42 @@ mod tests {
         assert_eq!(table.get_by_ip("10.2.1.0".parse().unwrap()), Some(&1));
         assert_eq!(table.get_by_ip("10.2.255.0".parse().unwrap()), Some(&1));
     }
+
+    #[test]
+    fn test_cidr_table_ipv6() {
+        let mut table = CidrTable::new();
+        table.insert("10.2.3.4/32".parse().unwrap(), 1);
+        table.insert("fd00::2/128".parse().unwrap(), 1);
+        table.insert("fd00:1::/64".parse().unwrap(), 2);
+        assert_eq!(table.get_by_ip("fd00::2".parse().unwrap()), Some(&1));
+        assert_eq!(table.get_by_ip("fd00::3".parse().unwrap()), None);
+        assert_eq!(table.get_by_ip("fd00:1::abcd".parse().unwrap()), Some(&2));
+        assert_eq!(table.get_by_ip("10.2.3.4".parse().unwrap()), Some(&1));
+    }
+
+    #[test]
+    fn test_cidr_table_remove() {
+        let mut table = CidrTable::new();
+        let a: Cidr = "10.2.3.4/32".parse().unwrap();
+        table.insert(a, 1);
+        table.insert("10.2.0.0/16".parse().unwrap(), 2);
+        assert_eq!(table.get_exact(&a), Some(&1));
+        assert_eq!(table.get_exact(&"10.2.3.0/24".parse().unwrap()), None);
+        table.remove(&a);
+        assert_eq!(table.get_exact(&a), None);
+        assert_eq!(table.get_by_ip("10.2.3.4".parse().unwrap()), Some(&2));
+    }
+
+    #[test]
+    fn test_cidr_contains_cidr() {
+        let net: Cidr = "10.2.0.0/16".parse().unwrap();
+        assert!(net.contains_cidr(&"10.2.3.0/24".parse().unwrap()));
+        assert!(net.contains_cidr(&net));
+        assert!(!net.contains_cidr(&"10.0.0.0/8".parse().unwrap()));
+        assert!(!net.contains_cidr(&"192.168.1.0/24".parse().unwrap()));
+        assert!(!net.contains_cidr(&"fd00::/64".parse().unwrap()));
+        let (addr, mask) = "fd00:1::/64".parse::<Cidr>().unwrap().addr_mask();
+        assert_eq!(addr, "fd00:1::".parse::<IpAddr>().unwrap());
+        assert_eq!(mask, "ffff:ffff:ffff:ffff::".parse::<IpAddr>().unwrap());
+    }
 }
@@ -1,5 +1,5 @@
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use futures::future::join_all;
 use tokio::task::JoinHandle;
 use tokio::time;
@@ -9,6 +9,8 @@ use crate::device::event::DeviceEvent;
 use crate::Tun;
 use crate::device::peer::{inbound, InboundEvent, InboundRx, OutboundEvent, OutboundRx, Peer};
 
+const KEEPALIVE_RECHECK: Duration = Duration::from_secs(1);
+
 pub struct PeerHandle {
     token: CancellationToken,
     handles: Vec<JoinHandle<()>>,
@@ -16,8 +18,8 @@ pub struct PeerHandle {
 
 impl PeerHandle {
     /// peer 任务, 定时握手, 处理入口数据, 发送数据
-    pub fn spawn(token: CancellationToken,
-                 peer: Arc<Peer>,
+    pub fn spawn<T: Tun>(token: CancellationToken,
+                 peer: Arc<Peer<T>>,
                  inbound: InboundRx,
                  outbound: OutboundRx, ) -> Self {
         let handshake_loop = tokio::spawn(loop_handshake(token.child_token(), Arc::clone(&peer)));
@@ -62,7 +64,7 @@ impl PeerHandle {
 
 
 // Send to tun if we have a valid session
-async fn loop_inbound(token: CancellationToken, peer: Arc<Peer>, mut rx: InboundRx)
+async fn loop_inbound<T: Tun>(token: CancellationToken, peer: Arc<Peer<T>>, mut rx: InboundRx)
 
 {
     debug!("Inbound loop for {peer} is UP");
@@ -84,7 +86,7 @@ async fn loop_inbound(token: CancellationToken, peer: Arc<Peer>, mut rx: Inbound
 
 /// 处理peer 的入口数据
 
-async fn tick_inbound(peer: Arc<Peer>, event: InboundEvent)
+async fn tick_inbound<T: Tun>(peer: Arc<Peer<T>>, event: InboundEvent)
 {
     match event {
         InboundEvent::HanshakeInitiation {
@@ -110,7 +112,7 @@ async fn tick_inbound(peer: Arc<Peer>, event: InboundEvent)
 }
 
 /// 循环与peer 握手
-async fn loop_handshake(token: CancellationToken, peer: Arc<Peer>)
+async fn loop_handshake<T: Tun>(token: CancellationToken, peer: Arc<Peer<T>>)
 {
     debug!("Handshake loop for {peer} is UP");
     while !token.is_cancelled() {
@@ -139,7 +141,7 @@ async fn loop_handshake(token: CancellationToken, peer: Arc<Peer>)
 
 
 // Send to endpoint if connected, otherwise queue for later
-async fn loop_outbound(token: CancellationToken, peer: Arc<Peer>, mut rx: OutboundRx)
+async fn loop_outbound<T: Tun>(token: CancellationToken, peer: Arc<Peer<T>>, mut rx: OutboundRx)
 
 {
     debug!("Outbound loop for {peer} is UP");
@@ -147,8 +149,11 @@ async fn loop_outbound(token: CancellationToken, peer: Arc<Peer>, mut rx: Outbou
     loop {
         tokio::select! {
             _ = token.cancelled() => break,
-            _ = time::sleep_until(peer.monitor.keepalive().next_attempt_in(peer.monitor.traffic()).into()) => {
-                peer.keepalive().await;
+            // 收包不会唤醒这里, 定期重新计算被动保活时间
+            _ = time::sleep_until(next_keepalive(&peer).into()) => {
+                if peer.monitor.keepalive().can(peer.monitor.traffic()) {
+                    peer.keepalive().await;
+                }
             }
             event = rx.recv() => {
                 match event {
@@ -167,11 +172,17 @@ async fn loop_outbound(token: CancellationToken, peer: Arc<Peer>, mut rx: Outbou
 }
 
 #[inline]
-async fn tick_outbound(peer: Arc<Peer>, data: Vec<u8>)
+fn next_keepalive<T: Tun>(peer: &Peer<T>) -> Instant {
+    peer.monitor.keepalive().next_attempt_in(peer.monitor.traffic())
+        .min(Instant::now() + KEEPALIVE_RECHECK)
+}
+
+#[inline]
+async fn tick_outbound<T: Tun>(peer: Arc<Peer<T>>, data: Vec<u8>)
 {
     let session = { peer.sessions.read().unwrap().current().clone() };
     let session = if let Some(s) = session { s } else {
-        peer.pub_event(DeviceEvent::SessionFailed(peer.clone()));
+        peer.pub_event(DeviceEvent::SessionFailed((peer.pub_key, peer.ip_addr().to_string())));
         return;
     };
 
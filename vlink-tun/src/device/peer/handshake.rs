@@ -1,5 +1,5 @@
 use super::session::{Session, SessionIndex};
-use crate::noise::protocol::HandshakeResponse;
+use crate::noise::protocol::{CookieReply, HandshakeResponse};
 use crate::noise::{
     crypto::{kdf2, PeerStaticSecret},
     handshake::{
@@ -31,6 +31,21 @@ impl Handshake {
         }
     }
 
+    /// 更新psk, 未完成的握手作废
+    pub fn set_psk(&mut self, psk: [u8; 32]) {
+        self.secret.set_psk(psk);
+        self.state = State::Uninit;
+    }
+
+    pub fn psk(&self) -> [u8; 32] {
+        *self.secret.psk()
+    }
+
+    /// 保存cookie, 重试握手时生成 mac2
+    pub fn consume_cookie_reply(&mut self, packet: &CookieReply) -> Result<(), Error> {
+        self.macs.consume_cookie_reply(packet)
+    }
+
     // Prepare HandshakeInitiation packet.
     pub fn initiate(&mut self) -> (Session, Vec<u8>) {
         let sender_index = self.session_index.next_index();
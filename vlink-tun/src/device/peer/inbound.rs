@@ -10,8 +10,8 @@ use crate::device::inbound::OutboundSender;
 use crate::device::peer::Peer;
 use crate::device::peer::session::Session;
 
-pub(super) async fn handle_handshake_initiation(
-    peer: Arc<Peer>,
+pub(super) async fn handle_handshake_initiation<T: Tun>(
+    peer: Arc<Peer<T>>,
     endpoint: Box<dyn OutboundSender>,
     initiation: IncomingInitiation,
 ) {
@@ -36,8 +36,8 @@ pub(super) async fn handle_handshake_initiation(
     }
 }
 
-pub(super) async fn handle_handshake_response(
-    peer: Arc<Peer>,
+pub(super) async fn handle_handshake_response<T: Tun>(
+    peer: Arc<Peer<T>>,
     endpoint: Box<dyn OutboundSender>,
     packet: HandshakeResponse,
     _session: Session,
@@ -59,11 +59,11 @@ pub(super) async fn handle_handshake_response(
                 debug!("failed to complete handshake, session not found");
                 return;
             }
-            peer.monitor.handshake().completed();
+            peer.monitor.completed();
             info!("handshake completed for {endpoint}");
             peer.update_endpoint(endpoint.box_clone());
 
-            let proto = endpoint.protocol();
+            let proto = endpoint.protocol().to_string();
             peer.pub_event(DeviceEvent::HandshakeComplete(HandshakeComplete {
                 pub_key: peer.pub_key,
                 proto,
@@ -75,18 +75,23 @@ pub(super) async fn handle_handshake_response(
     }
 }
 
-pub(super) async fn handle_cookie_reply(
-    peer: Arc<Peer>,
+pub(super) async fn handle_cookie_reply<T: Tun>(
+    peer: Arc<Peer<T>>,
     _endpoint: Box<dyn OutboundSender>,
-    _packet: CookieReply,
+    packet: CookieReply,
     _session: Session,
 ) {
     peer.monitor.traffic().inbound(COOKIE_REPLY_PACKET_SIZE);
+    // 不立即重发, 等握手重试
+    match peer.handshake.write().unwrap().consume_cookie_reply(&packet) {
+        Ok(()) => debug!("{peer} 负载高, 收到cookie"),
+        Err(e) => debug!("invalid cookie reply: {e}"),
+    }
 }
 
 /// 传输数据
-pub(super) async fn handle_transport_data(
-    peer: Arc<Peer>,
+pub(super) async fn handle_transport_data<T: Tun>(
+    peer: Arc<Peer<T>>,
     endpoint: Box<dyn OutboundSender>,
     packet: TransportData,
     session: Session,
@@ -96,7 +101,7 @@ pub(super) async fn handle_transport_data(
         let mut sessions = peer.sessions.write().unwrap();
         if sessions.complete_next(session.clone()) {
             info!("handshake completed");
-            peer.monitor.handshake().completed();
+            peer.monitor.completed();
         }
     }
     if !session.can_accept(packet.counter) {
@@ -104,14 +109,21 @@ pub(super) async fn handle_transport_data(
         return;
     }
 
+    let proto = endpoint.protocol();
     peer.update_endpoint(endpoint);
     match session.decrypt_data(&packet) {
         Ok(data) => {
+            peer.received(proto);
             if data.is_empty() {
                 // keepalive
                 return;
             }
 
+            if !peer.acl.check(&data) {
+                debug!("{peer} packet denied by acl");
+                session.aceept(packet.counter);
+                return;
+            }
             debug!("recv data from peer and try to send it to TUN");
             if let Err(e) = peer.tun.send(&data).await {
                 error!("{peer} failed to send data to tun: {e}");
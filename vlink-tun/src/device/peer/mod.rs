@@ -2,24 +2,28 @@ pub mod cidr;
 pub(crate) mod peers;
 pub(crate) mod session;
 mod monitor;
+pub use monitor::PeerMetrics;
 pub(crate) mod handler;
 mod handshake;
 mod inbound;
 
 use std::fmt::{Debug, Display, Formatter};
-use std::sync::RwLock;
-use std::time::Duration;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, watch};
 use log::{debug, warn};
 use tokio_util::sync::CancellationToken;
 use crate::{NativeTun, PeerStaticSecret, Tun};
+use crate::device::acl::Acl;
+use crate::device::config::RekeyConfig;
 use crate::device::event;
 use crate::device::event::DeviceEvent;
 use crate::device::inbound::OutboundSender;
 use crate::noise::handshake::IncomingInitiation;
 use crate::noise::{crypto, protocol};
 use crate::device::peer::handshake::Handshake;
-use crate::device::peer::monitor::{PeerMetrics, PeerMonitor};
+use crate::device::peer::monitor::PeerMonitor;
+use crate::device::time::AtomicInstant;
 use crate::device::peer::session::{ActiveSession, Session, SessionIndex};
 use crate::noise::crypto::PublicKey;
 
@@ -80,9 +84,10 @@ impl WatchOnline {
 
 /// 通过endpoint 发送数据
 /// udp-> peer
-pub struct Peer {
+pub struct Peer<T = NativeTun> {
     pub pub_key: PublicKey,
-    tun: NativeTun,
+    tun: T,
+    acl: Arc<Acl>,
     online: WatchOnline,
     monitor: PeerMonitor,
     handshake: RwLock<Handshake>,
@@ -90,6 +95,8 @@ pub struct Peer {
     /// 连接端点, 用于发送数据
     // pub endpoint: RwLock<Option<Box<dyn OutboundSender>>>,
     pub endpoint: RwLock<Option<Box<dyn OutboundSender>>>,
+    /// 各传输协议最后收到有效数据的时间, 用于判断路径是否可用
+    recv_at: [RecvSlot; RECV_SLOTS],
     inbound: InboundTx,
     outbound: OutboundTx,
     ip_addr: String,
@@ -98,44 +105,66 @@ pub struct Peer {
     token: CancellationToken,
 }
 
-impl Debug for Peer {
+/// 记录收包时间的协议数, 一个节点同时使用的传输协议很少
+const RECV_SLOTS: usize = 8;
+
+/// 协议第一次收到数据时占用, 之后只更新时间
+struct RecvSlot {
+    proto: OnceLock<&'static str>,
+    at: AtomicInstant,
+}
+
+impl Default for RecvSlot {
+    fn default() -> Self {
+        Self {
+            proto: OnceLock::new(),
+            at: AtomicInstant::now(),
+        }
+    }
+}
+
+impl<T> Debug for Peer<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Peer({})", self.ip_addr.as_str())
     }
 }
 
 
-impl Drop for Peer {
+impl<T> Drop for Peer<T> {
     fn drop(&mut self) {
         self.token.cancel();
     }
 }
 
-impl Peer {
+impl<T: Tun> Peer<T> {
     pub(super) fn new(
-        tun: NativeTun,
+        tun: T,
+        acl: Arc<Acl>,
         secret: PeerStaticSecret,
         session_index: SessionIndex,
         endpoint: Option<Box<dyn OutboundSender>>,
         inbound: InboundTx,
         outbound: OutboundTx,
         persitent_keepalive_interval: Option<Duration>,
+        rekey: &RekeyConfig,
         is_online: bool,
         ip_addr: String,
         event_pub: event::DevicePublisher,
     ) -> Self {
         let handshake = RwLock::new(Handshake::new(secret.clone(), session_index.clone()));
         let sessions = RwLock::new(ActiveSession::new(session_index));
-        let monitor = PeerMonitor::new(persitent_keepalive_interval);
+        let monitor = PeerMonitor::new(persitent_keepalive_interval, rekey);
         let endpoint = RwLock::new(endpoint);
         Self {
             pub_key: secret.public_key().clone(),
             tun,
+            acl,
             handshake,
             sessions,
             inbound,
             outbound,
             endpoint,
+            recv_at: std::array::from_fn(|_| RecvSlot::default()),
             monitor,
             online: WatchOnline::new(is_online),
             ip_addr,
@@ -143,6 +172,10 @@ impl Peer {
             token: Default::default(),
         }
     }
+    /// 节点在网络中的ip
+    pub fn ip_addr(&self) -> &str {
+        self.ip_addr.as_str()
+    }
     pub fn child_token(&self) -> CancellationToken {
         self.token.child_token()
     }
@@ -228,9 +261,44 @@ impl Peer {
         let mut guard = self.endpoint.write().unwrap();
         let _ = guard.take();
     }
+    /// 每个包都会调用, 按协议名找到或占用一个槽位, 不加锁
+    #[inline]
+    fn received(&self, proto: &'static str) {
+        self.monitor.traffic().received();
+        for slot in self.recv_at.iter() {
+            if *slot.proto.get_or_init(|| proto) == proto {
+                slot.at.set_now();
+                return;
+            }
+        }
+    }
+    /// 通过该协议最后一次收到数据的时间
+    pub fn last_recv_at(&self, proto: &str) -> Option<Instant> {
+        self.recv_at.iter()
+            .find(|s| s.proto.get() == Some(&proto))
+            .map(|s| s.at.to_std())
+    }
+    /// 最后一次发送数据的时间, 不包括握手
+    pub fn last_sent_at(&self) -> Instant {
+        self.monitor.traffic().last_sent_at()
+    }
+    /// 修改保活间隔, 下一次发送时生效
+    pub fn update_keepalive(&self, interval: Option<Duration>) {
+        self.monitor.keepalive().set_interval(interval);
+    }
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        self.monitor.keepalive().interval()
+    }
+    pub fn psk(&self) -> [u8; 32] {
+        self.handshake.read().unwrap().psk()
+    }
+    /// 修改psk, 当前会话继续有效, 下一次握手使用新的psk
+    pub fn update_psk(&self, psk: [u8; 32]) {
+        self.handshake.write().unwrap().set_psk(psk);
+    }
 }
 
-impl Display for Peer {
+impl<T> Display for Peer<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Peer({}),endpoint:{:?}", self.ip_addr, self.endpoint.read())
     }
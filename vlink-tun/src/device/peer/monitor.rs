@@ -2,6 +2,7 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant, SystemTime};
 use log::info;
 
+use crate::device::config::RekeyConfig;
 use crate::device::time::{AtomicInstant, AtomicTimestamp};
 
 const REKEY_AFTER_MESSAGES: u64 = 1 << 60;
@@ -17,16 +18,19 @@ pub(super) struct HandshakeMonitor {
     last_complete_at: AtomicInstant,
     last_complete_ts: AtomicTimestamp,
     attempt_before: AtomicInstant,
+    /// 默认 REKEY_AFTER_TIME
+    rekey_after_time: Duration,
 }
 
 impl HandshakeMonitor {
     #[inline]
-    pub fn new() -> Self {
+    pub fn new(rekey_after_time: Duration) -> Self {
         Self {
             last_attempt_at: AtomicInstant::from_std(Instant::now() - REKEY_TIMEOUT),
             last_complete_at: AtomicInstant::from_std(Instant::now() - REJECT_AFTER_TIME),
             attempt_before: AtomicInstant::now() + REKEY_ATTEMPT_TIME,
             last_complete_ts: AtomicTimestamp::zeroed(),
+            rekey_after_time,
         }
     }
 
@@ -37,7 +41,7 @@ impl HandshakeMonitor {
 
     #[inline]
     pub fn will_initiate_in(&self) -> Instant {
-        if self.is_max_attempt() || self.last_complete_at.elapsed() < REKEY_AFTER_TIME {
+        if self.is_max_attempt() || self.last_complete_at.elapsed() < self.rekey_after_time {
             return Instant::now() + REKEY_TIMEOUT;
         }
 
@@ -98,36 +102,61 @@ impl TrafficMonitor {
         self.rx_messages.fetch_add(1, Ordering::Relaxed);
         self.rx_bytes.fetch_add(n, Ordering::Relaxed);
     }
+
+    /// 解密成功才算收到, 被动保活依赖这个时间
+    #[inline]
+    pub fn received(&self) {
+        self.last_recv_at.set_now();
+    }
+
+    #[inline]
+    pub fn last_sent_at(&self) -> Instant {
+        self.last_sent_at.to_std()
+    }
 }
 
 pub(super) struct KeepAliveMonitor {
     last_attempt_at: AtomicInstant,
-    perisistent_keepalive_interval: Option<Duration>,
+    /// 毫秒, 0 表示不开启
+    perisistent_keepalive_interval: AtomicU64,
 }
 
 impl KeepAliveMonitor {
     pub fn new(persistent_keepalive_interval: Option<Duration>) -> Self {
-        Self {
+        let monitor = Self {
             last_attempt_at: AtomicInstant::now(),
-            perisistent_keepalive_interval: persistent_keepalive_interval,
-        }
+            perisistent_keepalive_interval: AtomicU64::new(0),
+        };
+        monitor.set_interval(persistent_keepalive_interval);
+        monitor
     }
 
     #[inline]
-    pub fn next_attempt_in(&self, traffic: &TrafficMonitor) -> Instant {
-        if self.last_attempt_at.elapsed() >= KEEPALIVE_TIMEOUT
-            && traffic.last_recv_at.to_std() > traffic.last_sent_at.to_std()
-        {
-            if traffic.last_recv_at.elapsed() > KEEPALIVE_TIMEOUT {
-                return Instant::now();
-            } else {
-                return Instant::now() + KEEPALIVE_TIMEOUT - traffic.last_recv_at.elapsed();
-            }
+    pub fn interval(&self) -> Option<Duration> {
+        match self.perisistent_keepalive_interval.load(Ordering::Relaxed) {
+            0 => None,
+            v => Some(Duration::from_millis(v)),
         }
+    }
+
+    #[inline]
+    pub fn set_interval(&self, interval: Option<Duration>) {
+        let v = interval.map(|d| d.as_millis() as u64).unwrap_or(0);
+        self.perisistent_keepalive_interval.store(v, Ordering::Relaxed);
+    }
 
-        self.perisistent_keepalive_interval
+    /// 收到数据后 KEEPALIVE_TIMEOUT 内没有发送过, 回一个被动保活
+    /// 从上次发送开始计时, 单向持续收包时也会定期回复, 对端才能确认路径可用
+    #[inline]
+    pub fn next_attempt_in(&self, traffic: &TrafficMonitor) -> Instant {
+        let persistent = self.interval()
             .map(|v| self.last_attempt_at.to_std() + v)
-            .unwrap_or_else(|| Instant::now() + REKEY_AFTER_TIME)
+            .unwrap_or_else(|| Instant::now() + REKEY_AFTER_TIME);
+        if traffic.last_recv_at.to_std() > traffic.last_sent_at.to_std() {
+            let passive = traffic.last_sent_at.to_std().max(self.last_attempt_at.to_std()) + KEEPALIVE_TIMEOUT;
+            return passive.min(persistent);
+        }
+        persistent
     }
 
     #[inline]
@@ -145,29 +174,43 @@ pub(super) struct PeerMonitor {
     handshake: HandshakeMonitor,
     traffic: TrafficMonitor,
     keepalive: KeepAliveMonitor,
+    /// 当前会话建立时已发送的包数, 按会话计算 rekey_after_messages
+    session_tx_base: AtomicU64,
+    rekey_after_messages: u64,
 }
 
 impl PeerMonitor {
-    pub fn new(persistent_keepalive_interval: Option<Duration>) -> Self {
+    pub fn new(persistent_keepalive_interval: Option<Duration>, rekey: &RekeyConfig) -> Self {
         Self {
-            handshake: HandshakeMonitor::new(),
+            handshake: HandshakeMonitor::new(rekey.after_time.unwrap_or(REKEY_AFTER_TIME)),
             traffic: TrafficMonitor::new(),
             keepalive: KeepAliveMonitor::new(persistent_keepalive_interval),
+            session_tx_base: AtomicU64::new(0),
+            rekey_after_messages: rekey.after_messages.unwrap_or(REKEY_AFTER_MESSAGES),
         }
     }
 
+    /// 握手完成, 新会话重新计数
+    #[inline]
+    pub fn completed(&self) {
+        self.handshake.completed();
+        self.session_tx_base.store(self.traffic.tx_messages.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
     #[inline]
     pub fn can_handshake(&self) -> bool {
-        if self.traffic.tx_messages.load(Ordering::Relaxed) >= REKEY_AFTER_MESSAGES {
+        let tx = self.traffic.tx_messages.load(Ordering::Relaxed) - self.session_tx_base.load(Ordering::Relaxed);
+        if tx >= self.rekey_after_messages {
             return true;
         }
 
-        if self.handshake.last_complete_at.elapsed() < REKEY_AFTER_TIME {
+        let rekey_after_time = self.handshake.rekey_after_time;
+        if self.handshake.last_complete_at.elapsed() < rekey_after_time {
             // An active session exists
             return false;
         }
 
-        if self.handshake.attempt_before.to_std() < self.handshake.last_complete_at.to_std() + REKEY_AFTER_TIME
+        if self.handshake.attempt_before.to_std() < self.handshake.last_complete_at.to_std() + rekey_after_time
         {
             self.handshake.reset_attempt();
         }
@@ -201,6 +244,7 @@ impl PeerMonitor {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct PeerMetrics {
     pub tx_messages: u64,
     pub rx_messages: u64,
@@ -208,3 +252,41 @@ pub struct PeerMetrics {
     pub rx_bytes: u64,
     pub last_handshake_at: SystemTime,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 只收不发时每 KEEPALIVE_TIMEOUT 回复一次, 不会因为一直在收包而推迟
+    #[test]
+    fn test_passive_keepalive() {
+        let mut traffic = TrafficMonitor::new();
+        let mut keepalive = KeepAliveMonitor::new(None);
+        assert!(!keepalive.can(&traffic));
+
+        let before = Instant::now() - KEEPALIVE_TIMEOUT - Duration::from_secs(1);
+        traffic.last_sent_at = AtomicInstant::from_std(before);
+        traffic.last_recv_at = AtomicInstant::from_std(before);
+        keepalive.last_attempt_at = AtomicInstant::from_std(before);
+        assert!(!keepalive.can(&traffic));
+        traffic.received();
+        assert!(keepalive.can(&traffic));
+
+        keepalive.attempt();
+        traffic.outbound(32);
+        assert!(!keepalive.can(&traffic));
+        // 毫秒精度, 同一毫秒内的收发分不出先后
+        std::thread::sleep(Duration::from_millis(5));
+        traffic.received();
+        let next = keepalive.next_attempt_in(&traffic);
+        assert!(next > Instant::now() + KEEPALIVE_TIMEOUT - Duration::from_secs(1));
+        assert!(next <= Instant::now() + KEEPALIVE_TIMEOUT);
+        // 持续收包不推迟
+        traffic.received();
+        assert_eq!(keepalive.next_attempt_in(&traffic), next);
+
+        // 固定保活更早时使用固定保活
+        keepalive.set_interval(Some(Duration::from_secs(5)));
+        assert!(keepalive.next_attempt_in(&traffic) < next);
+    }
+}
@@ -10,34 +10,41 @@ use crate::device::peer::handler::PeerHandle;
 use crate::device::peer::monitor::PeerMetrics;
 use crate::device::peer::Peer;
 use crate::device::peer::session::{Session, SessionIndex};
-use crate::{NativeTun, PeerStaticSecret};
+use crate::{NativeTun, PeerStaticSecret, Tun};
+use crate::device::acl::Acl;
 use crate::device::event;
 use crate::device::inbound::OutboundSender;
+use crate::device::config::{PeerConfig, RekeyConfig};
+use crate::device::transport::udp;
 
-struct PeerEntry {
-    peer: Arc<Peer>,
+struct PeerEntry<T> {
+    peer: Arc<Peer<T>>,
     allowed_ips: HashSet<Cidr>,
     #[allow(unused)]
     handle: PeerHandle,
 }
 
-pub struct PeerList {
+pub struct PeerList<T = NativeTun> {
     token: CancellationToken,
-    tun: NativeTun,
+    tun: T,
+    acl: Arc<Acl>,
+    rekey: RekeyConfig,
     sessions: SessionIndex,
-    ips: CidrTable<Arc<Peer>>,
-    peers: HashMap<[u8; 32], PeerEntry>,
+    ips: CidrTable<Arc<Peer<T>>>,
+    peers: HashMap<[u8; 32], PeerEntry<T>>,
     event_pub: event::DevicePublisher,
 }
 
-impl PeerList {
-    pub fn new(token: CancellationToken, tun: NativeTun, event_pub: event::DevicePublisher) -> Self {
+impl<T: Tun> PeerList<T> {
+    pub fn new(token: CancellationToken, tun: T, acl: Arc<Acl>, rekey: RekeyConfig, event_pub: event::DevicePublisher) -> Self {
         Self {
             token,
             peers: HashMap::new(),
             sessions: SessionIndex::new(),
             ips: CidrTable::new(),
             tun,
+            acl,
+            rekey,
             event_pub,
         }
     }
@@ -50,17 +57,17 @@ impl PeerList {
     }
 
     /// Returns the peer that matches the given public key.
-    pub fn get_by_key(&self, public_key: &[u8; 32]) -> Option<Arc<Peer>> {
+    pub fn get_by_key(&self, public_key: &[u8; 32]) -> Option<Arc<Peer<T>>> {
         self.peers.get(public_key).map(|e| Arc::clone(&e.peer))
     }
 
     /// Returns the peer that matches the given IP address.
-    pub fn get_by_ip(&self, ip: IpAddr) -> Option<Arc<Peer>> {
+    pub fn get_by_ip(&self, ip: IpAddr) -> Option<Arc<Peer<T>>> {
         self.ips.get_by_ip(ip).cloned()
     }
 
     /// Returns the peer that matches the index of the session.
-    pub fn get_session_by_index(&self, i: u32) -> Option<(Session, Arc<Peer>)> {
+    pub fn get_session_by_index(&self, i: u32) -> Option<(Session, Arc<Peer<T>>)> {
         match self.sessions.get_by_index(i) {
             Some(session) => self
                 .get_by_key(session.secret().public_key().as_bytes())
@@ -69,8 +76,21 @@ impl PeerList {
         }
     }
 
+    /// 所有peer 的路由
+    pub fn allowed_ips(&self) -> HashSet<Cidr> {
+        self.peers
+            .values()
+            .flat_map(|entry| entry.allowed_ips.iter().cloned())
+            .collect()
+    }
+
+    /// 单个peer 的路由
+    pub fn allowed_ips_by_key(&self, public_key: &[u8; 32]) -> Option<HashSet<Cidr>> {
+        self.peers.get(public_key).map(|entry| entry.allowed_ips.clone())
+    }
+
     #[inline]
-    pub fn all(&self) -> Vec<Arc<Peer>> {
+    pub fn all(&self) -> Vec<Arc<Peer<T>>> {
         self.peers
             .values()
             .map(|entry| Arc::clone(&entry.peer))
@@ -92,7 +112,7 @@ impl PeerList {
         persistent_keepalive_interval: Option<Duration>,
         is_online: bool,
         ip_addr: String,
-    ) -> Arc<Peer> {
+    ) -> Arc<Peer<T>> {
         debug!("新增节点:{:?}",allowed_ips);
         let entry = self
             .peers
@@ -102,12 +122,14 @@ impl PeerList {
                 let (outbound_tx, outbound_rx) = mpsc::channel(32);
                 let peer = Arc::new(Peer::new(
                     self.tun.clone(),
+                    self.acl.clone(),
                     secret,
                     self.sessions.clone(),
                     endpoint,
                     inbound_tx,
                     outbound_tx,
                     persistent_keepalive_interval,
+                    &self.rekey,
                     is_online,
                     ip_addr,
                     self.event_pub.clone(),
@@ -131,6 +153,71 @@ impl PeerList {
         Arc::clone(&entry.peer)
     }
 
+    /// 删除peer, 同时删除它的路由和会话
+    pub fn remove_by_key(&mut self, public_key: &[u8; 32]) -> Option<Arc<Peer<T>>> {
+        let entry = self.peers.remove(public_key)?;
+        tokio::spawn(entry.handle.cancel(Duration::from_secs(5)));
+        // 外部可能还持有peer, 置为离线让相关任务停下
+        entry.peer.set_online(false);
+        for cidr in &entry.allowed_ips {
+            self.remove_ip(cidr, &entry.peer);
+        }
+        self.sessions.remove_by_key(public_key);
+        Some(entry.peer)
+    }
+
+    /// 更新peer 的路由, 没有变化返回false
+    pub fn update_allowed_ips_by_key(
+        &mut self,
+        public_key: &[u8; 32],
+        allowed_ips: HashSet<Cidr>,
+    ) -> bool {
+        let (peer, old) = match self.peers.get_mut(public_key) {
+            Some(entry) if entry.allowed_ips != allowed_ips => {
+                let old = std::mem::replace(&mut entry.allowed_ips, allowed_ips.clone());
+                (Arc::clone(&entry.peer), old)
+            }
+            _ => return false,
+        };
+        debug!("更新节点路由:{:?} -> {:?}", old, allowed_ips);
+        for cidr in old.difference(&allowed_ips) {
+            self.remove_ip(cidr, &peer);
+        }
+        for &cidr in &allowed_ips {
+            self.ips.insert(cidr, Arc::clone(&peer));
+        }
+        true
+    }
+
+    /// 更新保活间隔
+    pub fn update_keepalive_by_key(&self, public_key: &[u8; 32], interval: Option<Duration>) -> bool {
+        match self.peers.get(public_key) {
+            Some(entry) => {
+                entry.peer.update_keepalive(interval);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 更新psk
+    pub fn update_psk_by_key(&self, public_key: &[u8; 32], psk: [u8; 32]) -> bool {
+        match self.peers.get(public_key) {
+            Some(entry) => {
+                entry.peer.update_psk(psk);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 路由已经被其他peer 占用时不删除
+    fn remove_ip(&mut self, cidr: &Cidr, peer: &Arc<Peer<T>>) {
+        if self.ips.get_exact(cidr).map(|p| Arc::ptr_eq(p, peer)).unwrap_or(false) {
+            self.ips.remove(cidr);
+        }
+    }
+
     /*   pub fn insert(
            &mut self,
            secret: PeerStaticSecret,
@@ -172,58 +259,112 @@ impl PeerList {
 
            Arc::clone(&entry.peer)
        }
+    */
 
-       pub fn update_allowed_ips_by_key(
-           &mut self,
-           public_key: &[u8; 32],
-           allowed_ips: HashSet<Cidr>,
-       ) -> bool {
-           if let Some(entry) = self.peers.get_mut(public_key) {
-               if entry.allowed_ips == allowed_ips {
-                   return false;
-               }
-               for cidr in &entry.allowed_ips {
-                   self.ips.remove(cidr);
-               }
-               for cidr in allowed_ips.clone() {
-                   self.ips.insert(cidr, Arc::clone(&entry.peer));
-               }
-               entry.allowed_ips = allowed_ips;
-               true
-           } else {
-               false
-           }
-       }
-
-       pub fn remove_by_key(&mut self, public_key: &[u8; 32]) {
-           if let Some(entry) = self.peers.remove(public_key) {
-               tokio::spawn(entry.handle.cancel(Duration::from_secs(5)));
-               for cidr in entry.allowed_ips {
-                   self.ips.remove(&cidr);
-               }
-               self.sessions.remove_by_key(public_key);
-           }
-       }
-
-       */
-
-    /*    pub fn to_config(&self) -> Vec<PeerConfig> {
-            self.peers
-                .values()
-                .map(|entry| PeerConfig {
-                    public_key: entry.peer.secret().public_key().to_bytes(),
+    /// 导出当前peer 配置, 端点只保留udp 地址
+    pub fn to_config(&self) -> Vec<PeerConfig> {
+        self.peers
+            .values()
+            .map(|entry| {
+                let endpoint = entry.peer.endpoint.read().unwrap().as_ref()
+                    .filter(|e| e.protocol() == udp::PROTO_NAME)
+                    .map(|e| e.dst());
+                let psk = entry.peer.psk();
+                PeerConfig {
+                    public_key: entry.peer.pub_key.to_bytes(),
                     allowed_ips: entry.allowed_ips.clone(),
-                    endpoint: entry.peer.endpoint().map(|endpoint| endpoint.dst()),
-                    preshared_key: Some(*entry.peer.secret().psk()),
-                    persistent_keepalive: None,
-                })
-                .collect()
-        }*/
+                    endpoint,
+                    preshared_key: (psk != [0u8; 32]).then_some(psk),
+                    is_online: entry.peer.is_online(),
+                    persistent_keepalive: entry.peer.keepalive_interval(),
+                    ip_addr: entry.peer.ip_addr().to_string(),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
 }
 
-impl Drop for PeerList {
+impl<T> Drop for PeerList<T> {
     fn drop(&mut self) {
         self.token.cancel()
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast;
+    use crate::device::acl::AclConfig;
+    use crate::device::peer::session::Session;
+    use crate::noise::crypto::LocalStaticSecret;
+    use crate::MemoryTun;
+    use super::*;
+
+    fn cidrs(s: &[&str]) -> HashSet<Cidr> {
+        s.iter().map(|c| c.parse().unwrap()).collect()
+    }
+
+    fn add(list: &mut PeerList<MemoryTun>, local: &LocalStaticSecret, ips: &[&str]) -> (Arc<Peer<MemoryTun>>, u32) {
+        let remote = LocalStaticSecret::random();
+        let secret = PeerStaticSecret::new(local.clone(), remote.public_key().to_bytes());
+        let peer = list.insert(secret.clone(), cidrs(ips), None, None, true, String::new());
+        let index = list.sessions.next_index();
+        list.sessions.insert(Session::new(secret, index, [1u8; 32], 0, [2u8; 32]));
+        (peer, index)
+    }
+
+    /// 删除和更新路由后, 路由表和会话索引与peer 一致
+    #[tokio::test]
+    async fn test_remove_update_consistent() {
+        let (tx, _) = broadcast::channel(16);
+        let acl = Arc::new(Acl::new(AclConfig::default()));
+        let mut list = PeerList::new(CancellationToken::new(), MemoryTun::new("t"), acl, RekeyConfig::default(), tx);
+        let local = LocalStaticSecret::random();
+        let (a, a_index) = add(&mut list, &local, &["10.0.0.1/32", "192.168.1.0/24"]);
+        let (b, b_index) = add(&mut list, &local, &["10.0.0.2/32"]);
+        let a_key = a.pub_key.to_bytes();
+        let b_key = b.pub_key.to_bytes();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert!(list.update_allowed_ips_by_key(&a_key, cidrs(&["10.0.0.1/32", "192.168.2.0/24"])));
+        assert!(!list.update_allowed_ips_by_key(&a_key, cidrs(&["10.0.0.1/32", "192.168.2.0/24"])));
+        assert!(list.get_by_ip(ip("192.168.1.5")).is_none());
+        assert!(Arc::ptr_eq(&list.get_by_ip(ip("192.168.2.5")).unwrap(), &a));
+
+        // b 接管 a 的子网, 删除 a 时不影响 b
+        assert!(list.update_allowed_ips_by_key(&b_key, cidrs(&["10.0.0.2/32", "192.168.2.0/24"])));
+        assert!(list.remove_by_key(&a_key).is_some());
+        assert!(list.remove_by_key(&a_key).is_none());
+        assert!(list.get_by_key(&a_key).is_none());
+        assert!(list.get_by_ip(ip("10.0.0.1")).is_none());
+        assert!(list.get_session_by_index(a_index).is_none());
+        assert!(!a.is_online());
+        assert!(Arc::ptr_eq(&list.get_by_ip(ip("192.168.2.5")).unwrap(), &b));
+        assert!(Arc::ptr_eq(&list.get_session_by_index(b_index).unwrap().1, &b));
+        assert_eq!(list.allowed_ips(), cidrs(&["10.0.0.2/32", "192.168.2.0/24"]));
+    }
+
+    /// 每个协议单独记录收包时间, 槽位用完后忽略新的协议
+    #[tokio::test]
+    async fn test_last_recv_at() {
+        let (tx, _) = broadcast::channel(16);
+        let acl = Arc::new(Acl::new(AclConfig::default()));
+        let mut list = PeerList::new(CancellationToken::new(), MemoryTun::new("t"), acl, RekeyConfig::default(), tx);
+        let (peer, _) = add(&mut list, &LocalStaticSecret::random(), &["10.0.0.1/32"]);
+        assert!(peer.last_recv_at("Udp").is_none());
+        std::thread::sleep(Duration::from_millis(50));
+        peer.received("Udp");
+        peer.received("Ws");
+        // 毫秒精度
+        assert!(peer.last_recv_at("Udp").unwrap().elapsed() < Duration::from_millis(50));
+        assert!(peer.last_recv_at("Ws").is_some());
+        assert!(peer.last_recv_at("Reply").is_none());
+        const PROTOS: [&str; 10] = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+        for proto in PROTOS {
+            peer.received(proto);
+        }
+        assert!(peer.last_recv_at("f").is_some());
+        assert!(peer.last_recv_at("g").is_none());
+    }
+}
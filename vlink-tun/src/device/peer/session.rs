@@ -209,6 +209,8 @@ impl fmt::Debug for NonceFilter {
 pub(super) struct ActiveSession {
     index: SessionIndex,
     uninit: Option<Session>,
+    /// 比 previous 更早的会话, 双方同时握手时对端可能仍在用它发送
+    stale: Option<Session>,
     previous: Option<Session>,
     current: Option<Session>,
     next: Option<Session>,
@@ -219,6 +221,7 @@ impl ActiveSession {
         Self {
             index,
             uninit: None,
+            stale: None,
             previous: None,
             current: None,
             next: None,
@@ -246,9 +249,7 @@ impl ActiveSession {
                 self.deactivate_uninit();
 
                 self.activate(&session);
-                self.deactivate_previous();
-                self.previous = self.current.take();
-                self.current = Some(session);
+                self.rotate(session);
                 true
             }
             _ => false,
@@ -271,17 +272,28 @@ impl ActiveSession {
         match self.next.as_ref() {
             Some(next) if next.sender_index == session.sender_index => {
                 self.deactivate_next();
-                self.deactivate_previous();
 
                 self.activate(&session);
-                self.previous = self.current.take();
-                self.current = Some(session);
+                self.rotate(session);
                 true
             }
             _ => false,
         }
     }
 
+    /// 新会话生效, 旧会话保留两代用于接收
+    /// 初次握手交叉时, 双方的 current 不是同一对会话, 对端的 current 对应本端的 previous,
+    /// 本端先完成重新握手时直接删除 previous 会丢掉对端切换前发来的包
+    #[inline]
+    fn rotate(&mut self, session: Session) {
+        if let Some(stale) = self.stale.take() {
+            self.deactivate(&stale);
+        }
+        self.stale = self.previous.take();
+        self.previous = self.current.take();
+        self.current = Some(session);
+    }
+
     #[inline]
     fn deactivate_uninit(&mut self) {
         if let Some(unint) = self.uninit.take() {
@@ -1,34 +1,96 @@
-use std::sync::atomic::{AtomicI16, Ordering};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use super::time::AtomicInstant;
+/// 空闲超过该时间的桶已经装满, 可以删除
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 令牌桶, 每秒补充 tokens 个, 最多存 tokens 个
+/// 令牌按纳秒计, 避免整数补充时的误差
+struct Bucket {
+    nanos: u64,
+    last_at: Instant,
+}
+
+impl Bucket {
+    fn new(tokens: u16) -> Self {
+        Self {
+            nanos: cost(tokens) * tokens as u64,
+            last_at: Instant::now(),
+        }
+    }
+
+    fn take(&mut self, tokens: u16) -> bool {
+        let cost = cost(tokens);
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_at).as_nanos().min(u64::MAX as u128) as u64;
+        self.last_at = now;
+        self.nanos = self.nanos.saturating_add(elapsed).min(cost * tokens as u64);
+        if self.nanos >= cost {
+            self.nanos -= cost;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// 每个令牌需要的纳秒
+fn cost(tokens: u16) -> u64 {
+    1_000_000_000 / tokens.max(1) as u64
+}
 
 pub(crate) struct RateLimiter {
     tokens: u16,
-    bucket: AtomicI16,
-    last_at: AtomicInstant,
+    bucket: Mutex<Bucket>,
 }
 
 impl RateLimiter {
     pub fn new(tokens: u16) -> Self {
         Self {
             tokens,
-            bucket: AtomicI16::new(tokens as _),
-            last_at: AtomicInstant::now(),
+            bucket: Mutex::new(Bucket::new(tokens)),
         }
     }
 
     pub fn fetch_token(&self) -> bool {
-        true
-        /*if self.last_at.elapsed() > Duration::from_secs(1) {
-            self.bucket.store(self.tokens as i16 - 1, Ordering::Relaxed);
-            self.last_at.set_now();
-            true
-        } else if self.bucket.load(Ordering::Relaxed) > 0 {
-            self.bucket.fetch_sub(1, Ordering::Relaxed) > 0
-        } else {
-            false
-        }*/
+        self.bucket.lock().unwrap().take(self.tokens)
+    }
+
+    fn idle(&self) -> Duration {
+        self.bucket.lock().unwrap().last_at.elapsed()
+    }
+}
+
+/// 按来源ip 限流, ipv6 按 /64 计算
+pub(crate) struct IpRateLimiter {
+    tokens: u16,
+    buckets: Mutex<(HashMap<IpAddr, RateLimiter>, Instant)>,
+}
+
+impl IpRateLimiter {
+    pub fn new(tokens: u16) -> Self {
+        Self {
+            tokens,
+            buckets: Mutex::new((HashMap::new(), Instant::now())),
+        }
+    }
+
+    pub fn fetch_token(&self, ip: IpAddr) -> bool {
+        let key = match ip {
+            IpAddr::V4(_) => ip,
+            IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & !(u64::MAX as u128)).into()),
+        };
+        let mut guard = self.buckets.lock().unwrap();
+        let (buckets, gc_at) = &mut *guard;
+        if gc_at.elapsed() > IDLE_TIMEOUT {
+            buckets.retain(|_, b| b.idle() < IDLE_TIMEOUT);
+            *gc_at = Instant::now();
+        }
+        buckets.entry(key)
+            .or_insert_with(|| RateLimiter::new(self.tokens))
+            .fetch_token()
     }
 }
 
@@ -55,4 +117,19 @@ mod tests {
         assert!(rl.fetch_token());
         assert!(!rl.fetch_token());
     }
+
+    #[test]
+    fn test_ip_ratelimiter() {
+        let rl = IpRateLimiter::new(2);
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(rl.fetch_token(a));
+        assert!(rl.fetch_token(a));
+        assert!(!rl.fetch_token(a));
+        assert!(rl.fetch_token(b));
+        // 同一个 /64
+        assert!(rl.fetch_token("fd00::1".parse().unwrap()));
+        assert!(rl.fetch_token("fd00::2".parse().unwrap()));
+        assert!(!rl.fetch_token("fd00::3".parse().unwrap()));
+    }
 }
@@ -70,8 +70,8 @@ impl OutboundSender for UdpOutboundSender {
         self.dst.clone()
     }
 
-    fn protocol(&self) -> String {
-        PROTO_NAME.to_string()
+    fn protocol(&self) -> &'static str {
+        PROTO_NAME
     }
 }
 
@@ -80,6 +80,8 @@ impl UdpTransport {
     pub(crate) async fn spawn(token: CancellationToken, port: u16, sender: Sender<InboundResult>) -> Result<(u16, UdpSocketInfo), io::Error> {
         // tokio::spawn()
         let mut udp = Self::bind(Ipv4Addr::UNSPECIFIED, Ipv6Addr::UNSPECIFIED, port).await?;
+        // port 为0 时返回实际绑定的端口
+        let port = udp.port();
         let info = UdpSocketInfo {
             ipv4: udp.ipv4.clone(),
             ipv6: udp.ipv6.clone(),
@@ -192,7 +194,7 @@ impl UdpTransport {
             self.ipv4_buf = vec![0u8; 2048];
         }
         if self.ipv6_buf.is_empty() {
-            self.ipv4_buf = vec![0u8; 2048];
+            self.ipv6_buf = vec![0u8; 2048];
         }
 
         let (data, addr) = tokio::select! {
@@ -0,0 +1,208 @@
+//! 解析 wg-quick 配置文件, 可以用标准 wireguard 的配置启动设备
+//! DNS/MTU/Table/PostUp 等由 wg-quick 脚本处理的字段忽略
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ip_network::{IpNetwork, Ipv6Network};
+use log::debug;
+
+use crate::device::config::{DeviceConfig, PeerConfig};
+use crate::device::peer::cidr::Cidr;
+use crate::errors::Error;
+
+enum Section {
+    None,
+    Interface,
+    Peer,
+}
+
+impl DeviceConfig {
+    /// 地址取 Address 中的第一个ipv4/ipv6, 网段为地址的前缀
+    pub fn from_wg_quick(s: &str) -> Result<Self, Error> {
+        let mut private_key = None;
+        let mut port = 0;
+        let mut fwmark = 0;
+        let mut address = None;
+        let mut address6 = None;
+        let mut peers: Vec<PeerConfig> = vec![];
+        let mut section = Section::None;
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: &str| Error::InvalidConfig(format!("第{}行 {}: {}", n + 1, msg, line));
+            if line.starts_with('[') {
+                section = match line.to_ascii_lowercase().as_str() {
+                    "[interface]" => Section::Interface,
+                    "[peer]" => {
+                        peers.push(PeerConfig {
+                            is_online: true,
+                            ..Default::default()
+                        });
+                        Section::Peer
+                    }
+                    _ => return Err(err("未知的段")),
+                };
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| err("缺少 ="))?;
+            let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+            match section {
+                Section::None => return Err(err("不在任何段中")),
+                Section::Interface => match key.as_str() {
+                    "privatekey" => private_key = Some(parse_key(value).map_err(|_| err("私钥错误"))?),
+                    "listenport" => port = value.parse().map_err(|_| err("端口错误"))?,
+                    "fwmark" => fwmark = parse_fwmark(value).ok_or_else(|| err("fwmark错误"))?,
+                    "address" => {
+                        for v in value.split(',').map(str::trim) {
+                            let cidr: IpNetwork = parse_network(v).ok_or_else(|| err("地址错误"))?;
+                            let ip: IpAddr = v.split('/').next().unwrap_or_default().parse()
+                                .map_err(|_| err("地址错误"))?;
+                            match ip {
+                                IpAddr::V4(ip) if address.is_none() => address = Some((ip, cidr)),
+                                IpAddr::V6(ip) if address6.is_none() => address6 = Some((ip, cidr)),
+                                _ => debug!("忽略多余的地址 {}", v),
+                            }
+                        }
+                    }
+                    _ => debug!("忽略 wg-quick 字段 {}", key),
+                },
+                Section::Peer => {
+                    let peer = peers.last_mut().unwrap();
+                    match key.as_str() {
+                        "publickey" => peer.public_key = parse_key(value).map_err(|_| err("公钥错误"))?,
+                        "presharedkey" => peer.preshared_key = Some(parse_key(value).map_err(|_| err("psk错误"))?),
+                        "allowedips" => {
+                            for v in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+                                peer.allowed_ips.insert(v.parse::<Cidr>().map_err(|_| err("AllowedIPs错误"))?);
+                            }
+                        }
+                        "endpoint" => peer.endpoint = Some(parse_endpoint(value).ok_or_else(|| err("Endpoint错误"))?),
+                        "persistentkeepalive" => {
+                            peer.persistent_keepalive = match value {
+                                "off" | "0" => None,
+                                v => Some(Duration::from_secs(v.parse().map_err(|_| err("PersistentKeepalive错误"))?)),
+                            }
+                        }
+                        _ => debug!("忽略 wg-quick 字段 {}", key),
+                    }
+                }
+            }
+        }
+
+        let private_key = private_key.ok_or(Error::InvalidConfig("缺少 PrivateKey".to_string()))?;
+        let (address, network) = address.ok_or(Error::InvalidConfig("缺少ipv4 Address".to_string()))?;
+        let (address6, network6) = match address6 {
+            Some((ip, IpNetwork::V6(n))) => (Some(ip), Some(n)),
+            _ => (None, None::<Ipv6Network>),
+        };
+        let mut map = HashMap::new();
+        for mut p in peers {
+            if p.public_key == [0u8; 32] {
+                return Err(Error::InvalidConfig("Peer 缺少 PublicKey".to_string()));
+            }
+            // 第一条 ipv4 路由的地址作为节点ip
+            p.ip_addr = first_ipv4(&p.allowed_ips).map(|ip| ip.to_string()).unwrap_or_default();
+            map.insert(p.public_key, p);
+        }
+        Ok(Self {
+            private_key,
+            fwmark,
+            port,
+            peers: map,
+            address,
+            network,
+            address6,
+            network6,
+            acl: Default::default(),
+            bypass: vec![],
+            route: Default::default(),
+            rekey: Default::default(),
+        })
+    }
+}
+
+fn parse_key(s: &str) -> Result<[u8; 32], ()> {
+    STANDARD.decode(s).map_err(|_| ())?.try_into().map_err(|_| ())
+}
+
+fn parse_fwmark(s: &str) -> Option<u32> {
+    match s {
+        "off" => Some(0),
+        s if s.starts_with("0x") => u32::from_str_radix(&s[2..], 16).ok(),
+        s => s.parse().ok(),
+    }
+}
+
+/// 没有前缀时为主机地址
+fn parse_network(s: &str) -> Option<IpNetwork> {
+    let (ip, mask) = match s.split_once('/') {
+        Some((ip, mask)) => (ip.parse::<IpAddr>().ok()?, mask.parse().ok()?),
+        None => {
+            let ip = s.parse::<IpAddr>().ok()?;
+            (ip, if ip.is_ipv4() { 32 } else { 128 })
+        }
+    };
+    IpNetwork::new_truncate(ip, mask).ok()
+}
+
+/// 域名端点启动时解析一次
+fn parse_endpoint(s: &str) -> Option<SocketAddr> {
+    s.parse().ok().or_else(|| s.to_socket_addrs().ok()?.next())
+}
+
+pub(crate) fn first_ipv4(ips: &HashSet<Cidr>) -> Option<IpAddr> {
+    let mut v4: Vec<IpAddr> = ips.iter()
+        .map(|c| c.addr_mask().0)
+        .filter(|ip| ip.is_ipv4())
+        .collect();
+    v4.sort();
+    v4.into_iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_wg_quick() {
+        let s = r#"
+# 手机客户端
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+Address = 10.99.0.5/24, fd00:99::5/64
+ListenPort = 51820
+DNS = 10.99.0.1
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+AllowedIPs = 10.99.0.1/32, 192.168.1.0/24
+Endpoint = 1.2.3.4:51820
+PersistentKeepalive = 25
+
+[Peer]
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=
+AllowedIPs = 10.99.0.2/32
+"#;
+        let cfg = DeviceConfig::from_wg_quick(s).unwrap();
+        assert_eq!(cfg.port, 51820);
+        assert_eq!(cfg.address.to_string(), "10.99.0.5");
+        assert_eq!(cfg.network, "10.99.0.0/24".parse::<IpNetwork>().unwrap());
+        assert_eq!(cfg.address6, Some("fd00:99::5".parse().unwrap()));
+        assert_eq!(cfg.peers.len(), 2);
+        let key: [u8; 32] = parse_key("xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=").unwrap();
+        let peer = &cfg.peers[&key];
+        assert_eq!(peer.ip_addr, "10.99.0.1");
+        assert_eq!(peer.allowed_ips.len(), 2);
+        assert_eq!(peer.endpoint, Some("1.2.3.4:51820".parse().unwrap()));
+        assert_eq!(peer.persistent_keepalive, Some(Duration::from_secs(25)));
+
+        assert!(DeviceConfig::from_wg_quick("[Interface]\nAddress = 10.0.0.1/24\n").is_err());
+    }
+}
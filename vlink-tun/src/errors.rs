@@ -6,4 +6,8 @@ pub enum Error {
     Noise(#[from] crate::noise::Error),
     #[error("Tun error: {0}")]
     Tun(#[from] crate::tun::Error),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("Route error: {0}")]
+    Route(String),
 }
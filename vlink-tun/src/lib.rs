@@ -3,6 +3,8 @@ mod errors;
 pub mod tun;
 pub mod noise;
 pub mod router;
+#[cfg(unix)]
+pub mod uapi;
 
 pub use crate::device::peer::peers::PeerList;
 
@@ -13,4 +15,8 @@ pub use device::{
 pub use noise::crypto::{LocalStaticSecret, PeerStaticSecret};
 pub use tun::{Error as TunError, Tun};
 
-pub use tun::NativeTun;
\ No newline at end of file
+pub use tun::NativeTun;
+pub use tun::memory::MemoryTun;
+
+#[cfg(feature = "netstack")]
+pub use tun::netstack::NetstackTun;
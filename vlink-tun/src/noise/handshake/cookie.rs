@@ -6,7 +6,9 @@ use bytes::{BufMut, BytesMut};
 use rand_core::{OsRng, RngCore};
 
 use super::{LABEL_COOKIE, LABEL_MAC1};
-use crate::noise::crypto::{hash, mac, xaead_encrypt, LocalStaticSecret, PeerStaticSecret};
+use crate::noise::crypto::{hash, mac, xaead_decrypt, xaead_encrypt, LocalStaticSecret, PeerStaticSecret};
+use crate::noise::protocol::CookieReply;
+use crate::noise::Error;
 
 const MESSAGE_TYPE_COOKIE_REPLY: u8 = 3u8;
 const PACKET_SIZE: usize = 64;
@@ -14,8 +16,10 @@ const COOKIE_LIFETIME: Duration = Duration::from_secs(120);
 
 pub struct MacGenerator {
     peer_mac1_hash: [u8; 32],   // pre-compute hash for generating mac1
-    peer_cookie_hash: [u8; 32], // pre-compute hash for generating mac2
+    peer_cookie_hash: [u8; 32], // pre-compute hash for decrypting cookie reply
     last_cookie: Option<([u8; 16], Instant)>,
+    /// 最后发送的mac1, 解密 cookie reply 时作为 aad
+    last_mac1: Option<[u8; 16]>,
 }
 
 impl MacGenerator {
@@ -26,24 +30,36 @@ impl MacGenerator {
             peer_mac1_hash: hash(&LABEL_MAC1, peer_pub),
             peer_cookie_hash: hash(&LABEL_COOKIE, peer_pub),
             last_cookie: None,
+            last_mac1: None,
         }
     }
 
     /// Generate mac1 for handshake initiation and response.
     #[inline]
-    pub fn generate_mac1(&self, payload: &[u8]) -> [u8; 16] {
-        mac(&self.peer_mac1_hash, payload)
+    pub fn generate_mac1(&mut self, payload: &[u8]) -> [u8; 16] {
+        let mac1 = mac(&self.peer_mac1_hash, payload);
+        self.last_mac1 = Some(mac1);
+        mac1
     }
 
     /// Generate mac2 for handshake initiation and response.
+    /// payload 包含 mac1, 没有有效的cookie 时为0
     #[inline]
     pub fn generate_mac2(&self, payload: &[u8]) -> [u8; 16] {
-        if self.last_cookie.is_none() || self.last_cookie.unwrap().1.elapsed() >= COOKIE_LIFETIME {
-            [0u8; 16]
-        } else {
-            mac(&self.peer_cookie_hash, payload)
+        match self.last_cookie {
+            Some((cookie, at)) if at.elapsed() < COOKIE_LIFETIME => mac(&cookie, payload),
+            _ => [0u8; 16],
         }
     }
+
+    /// 对端负载高时回复cookie, 下一次握手带上 mac2
+    pub fn consume_cookie_reply(&mut self, reply: &CookieReply) -> Result<(), Error> {
+        let mac1 = self.last_mac1.ok_or(Error::InvalidPacket)?;
+        let cookie = xaead_decrypt(&self.peer_cookie_hash, &reply.nonce, &reply.cookie, &mac1)?;
+        let cookie: [u8; 16] = cookie.try_into().map_err(|_| Error::InvalidPacket)?;
+        self.last_cookie = Some((cookie, Instant::now()));
+        Ok(())
+    }
 }
 
 pub struct Cookie {
@@ -72,11 +88,15 @@ impl Cookie {
     }
 
     /// Validate mac2 of the payload.
-    pub fn validate_mac2(&self, payload: &[u8]) -> bool {
-        let (msg, macs) = payload.split_at(payload.len() - 32);
-        let (_mac1, mac2) = macs.split_at(16);
-
-        mac2 == mac(&self.cookie_hash, msg)
+    /// cookie 由来源地址生成, 秘密过期后之前发出的cookie 都失效
+    pub fn validate_mac2(&self, payload: &[u8], src: SocketAddr) -> bool {
+        let secret = match self.secret.lock().unwrap().as_ref() {
+            Some((secret, at)) if at.elapsed() < COOKIE_LIFETIME => *secret,
+            _ => return false,
+        };
+        let (msg, mac2) = payload.split_at(payload.len() - 16);
+        let cookie = mac(&secret, &Self::encode_dst_addr(src));
+        mac2 == mac(&cookie, msg)
     }
 
     pub fn generate_cookie_reply(&self, payload: &[u8], dst: SocketAddr) -> Vec<u8> {
@@ -15,7 +15,8 @@ pub use response::{IncomingResponse, OutgoingResponse};
 mod tests {
     use super::*;
     use crate::noise::crypto::{LocalStaticSecret, PeerStaticSecret};
-    use crate::noise::protocol::{HandshakeInitiation, HandshakeResponse};
+    use std::net::SocketAddr;
+    use crate::noise::protocol::{CookieReply, HandshakeInitiation, HandshakeResponse};
 
     #[inline]
     fn gen_2_static_key() -> (PeerStaticSecret, PeerStaticSecret) {
@@ -67,4 +68,25 @@ mod tests {
         assert_eq!(resp_out.chaining_key, resp_in.chaining_key);
         assert_eq!(resp_out.hash, resp_in.hash);
     }
+
+    #[test]
+    fn cookie_reply() {
+        let (p1_key, p2_key) = gen_2_static_key();
+        let responder = Cookie::new(p2_key.local());
+        let mut p1_cookie = MacGenerator::new(&p1_key);
+        let src: SocketAddr = "1.2.3.4:51820".parse().unwrap();
+
+        let (_, payload) = OutgoingInitiation::new(1, &p1_key, &mut p1_cookie);
+        assert!(responder.validate_mac1(&payload));
+        assert!(!responder.validate_mac2(&payload, src));
+
+        let reply = responder.generate_cookie_reply(&payload, src);
+        let reply = CookieReply::try_from(reply.as_slice()).unwrap();
+        p1_cookie.consume_cookie_reply(&reply).unwrap();
+
+        let (_, payload) = OutgoingInitiation::new(2, &p1_key, &mut p1_cookie);
+        assert!(responder.validate_mac1(&payload));
+        assert!(responder.validate_mac2(&payload, src));
+        assert!(!responder.validate_mac2(&payload, "1.2.3.5:51820".parse().unwrap()));
+    }
 }
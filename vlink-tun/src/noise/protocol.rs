@@ -200,6 +200,13 @@ impl Message {
         Ok(message)
     }
 
+    /// 类型和保留字段符合 wireguard 格式
+    pub fn is_wireguard(payload: &[u8]) -> bool {
+        payload.len() >= 4
+            && (MESSAGE_TYPE_HANDSHAKE_INITIATION..=MESSAGE_TYPE_TRANSPORT_DATA).contains(&payload[0])
+            && payload[1..4] == [0, 0, 0]
+    }
+
     pub fn is_handshake(payload: &[u8]) -> bool {
         match payload[0] {
             MESSAGE_TYPE_HANDSHAKE_INITIATION
@@ -2,21 +2,27 @@ use std::net::IpAddr;
 use std::process::Command;
 use log::info;
 use crate::device::peer::cidr::Cidr;
-use crate::router::IRouter;
+use crate::router::{IRouter, RouteConfig};
 
 pub struct Router {
     tun_name: String,
 }
 
 impl Router {
-    pub fn new(tun_name: String) -> Self {
+    pub fn new(tun_name: String, _cfg: RouteConfig) -> Self {
         Router { tun_name }
     }
     pub fn add_route(&self, addr: IpAddr, mask: IpAddr) ->Result<(),crate::errors::Error> {
-        let route_add_str: String = format!(
-            "route -n add {} -netmask {} -interface {}",
-            addr, mask, self.tun_name
-        );
+        let route_add_str: String = match mask {
+            IpAddr::V6(m) => format!(
+                "route -n add -inet6 {} -prefixlen {} -interface {}",
+                addr, u128::from(m).leading_ones(), self.tun_name
+            ),
+            IpAddr::V4(_) => format!(
+                "route -n add {} -netmask {} -interface {}",
+                addr, mask, self.tun_name
+            ),
+        };
         info!("route_add_str:{}", route_add_str);
         let route_add_out = Command::new("sh")
             .arg("-c")
@@ -27,6 +33,51 @@ impl Router {
         info!("route_add_out:{}", String::from_utf8_lossy(str.as_slice()));
         Ok(())
     }
+    pub fn del_route(&self, addr: IpAddr, mask: IpAddr) -> Result<(), crate::errors::Error> {
+        let route_del_str: String = match mask {
+            IpAddr::V6(m) => format!(
+                "route -n delete -inet6 {} -prefixlen {} -interface {}",
+                addr, u128::from(m).leading_ones(), self.tun_name
+            ),
+            IpAddr::V4(_) => format!(
+                "route -n delete {} -netmask {} -interface {}",
+                addr, mask, self.tun_name
+            ),
+        };
+        info!("route_del_str:{}", route_del_str);
+        let out = Command::new("sh")
+            .arg("-c")
+            .arg(&route_del_str)
+            .output()?;
+        info!("route_del_out:{}", String::from_utf8_lossy(out.stdout.as_slice()));
+        Ok(())
+    }
+    /// 按当前默认网关固定一条到dst 的主机路由
+    pub fn add_bypass_route(&self, dst: IpAddr) -> Result<(), crate::errors::Error> {
+        let family = if dst.is_ipv6() { "-inet6" } else { "-inet" };
+        let out = Command::new("route").args(["-n", "get", family, &dst.to_string()]).output()?;
+        let out = String::from_utf8_lossy(&out.stdout).to_string();
+        let field = |name: &str| out.lines()
+            .filter_map(|l| l.trim().strip_prefix(name))
+            .map(|v| v.trim().to_string())
+            .next();
+        if field("interface:").as_deref() == Some(self.tun_name.as_str()) {
+            return Err(crate::errors::Error::InvalidConfig(format!("{} 已经路由到 {}", dst, self.tun_name)));
+        }
+        let route_add_str = match (field("gateway:"), field("interface:")) {
+            (Some(gw), _) => format!("route -n add {} -host {} {}", family, dst, gw),
+            (None, Some(iface)) => format!("route -n add {} -host {} -interface {}", family, dst, iface),
+            (None, None) => return Err(crate::errors::Error::InvalidConfig(format!("{} 没有可用路由", dst))),
+        };
+        info!("route_add_str:{}", route_add_str);
+        Command::new("sh").arg("-c").arg(&route_add_str).output()?;
+        Ok(())
+    }
+    pub fn del_bypass_route(&self, dst: IpAddr) -> Result<(), crate::errors::Error> {
+        let family = if dst.is_ipv6() { "-inet6" } else { "-inet" };
+        Command::new("route").args(["-n", "delete", family, "-host", &dst.to_string()]).output()?;
+        Ok(())
+    }
 }
 
-impl IRouter for Router {}
\ No newline at end of file
+impl IRouter for Router {}
@@ -1,19 +1,382 @@
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::future::Future;
 use std::net::IpAddr;
-use crate::router::IRouter;
+use std::process::Command;
+use std::sync::Mutex;
 
-pub struct Router{
+use futures::TryStreamExt;
+use log::warn;
+use netlink_packet_route::{RouteMessage, RT_TABLE_MAIN};
+use netlink_packet_route::route::Nla;
+use rtnetlink::{Handle, IpVersion};
 
+use crate::device::peer::cidr::Cidr;
+use crate::errors::Error;
+use crate::router::{IRouter, RouteConfig};
+
+/// vlink 添加的路由使用的协议号,用于区分系统和其他程序的路由
+/// 旁路路由不在 tun 网卡上, 同样使用该协议号
+const RTPROT_VLINK: u8 = 0x76;
+
+/// 通过 rtnetlink 管理 tun 网卡的路由, 不记录添加过的路由, 由设备维护
+/// 同一主机可能有多个设备, drop 时只删除本网卡上的 vlink 路由和自己添加的旁路
+pub struct Router {
+    name: String,
+    cfg: RouteConfig,
+    /// 本设备添加的旁路主机路由, 不在 tun 网卡上, 无法按出口网卡区分
+    bypass: Mutex<HashSet<IpAddr>>,
 }
-impl Router{
-    pub fn new(name:String)->Self {
-        Self{
 
+impl Router {
+    pub fn new(name: String, cfg: RouteConfig) -> Self {
+        let router = Self {
+            name,
+            cfg,
+            bypass: Mutex::new(HashSet::new()),
+        };
+        // 只能清理同名网卡上残留的路由, 上次异常退出残留的旁路分不清属于哪个设备, 不处理
+        if let Err(e) = router.flush() {
+            warn!("清理残留路由失败:{}", e);
         }
+        router
     }
-    pub fn add_route(&self, addr: IpAddr, mask: IpAddr) ->Result<(),crate::errors::Error> {
-        Ok(())
+
+    fn table(&self) -> u32 {
+        self.cfg.table.unwrap_or(RT_TABLE_MAIN as u32)
+    }
+
+    fn index(&self) -> Result<u32, Error> {
+        if_index(&self.name)
+    }
+
+    pub fn add_route(&self, addr: IpAddr, mask: IpAddr) -> Result<(), Error> {
+        let (addr, prefix) = (addr, prefix_len(mask));
+        let index = self.index()?;
+        let (table, metric) = (self.table(), self.cfg.metric);
+        netlink(move |handle| async move {
+            let req = handle.route().add()
+                .output_interface(index)
+                .table_id(table)
+                .protocol(RTPROT_VLINK)
+                .replace();
+            match addr {
+                IpAddr::V4(a) => {
+                    let mut req = req.v4().destination_prefix(a, prefix);
+                    if let Some(metric) = metric {
+                        req.message_mut().nlas.push(Nla::Priority(metric));
+                    }
+                    req.execute().await
+                }
+                IpAddr::V6(a) => {
+                    let mut req = req.v6().destination_prefix(a, prefix);
+                    if let Some(metric) = metric {
+                        req.message_mut().nlas.push(Nla::Priority(metric));
+                    }
+                    req.execute().await
+                }
+            }
+        })
+    }
+
+    pub fn del_route(&self, addr: IpAddr, mask: IpAddr) -> Result<(), Error> {
+        let (addr, prefix) = (addr, prefix_len(mask));
+        let index = self.index()?;
+        let table = self.table();
+        netlink(move |handle| async move {
+            for msg in dump(&handle, addr.is_ipv4()).await? {
+                if route_table(&msg) == table
+                    && msg.output_interface() == Some(index)
+                    && destination(&msg) == (addr, prefix) {
+                    handle.route().del(msg).execute().await?;
+                }
+            }
+            Ok(())
+        })
     }
+
+    /// tun 网卡在路由表中的全部路由,包括内核根据地址生成的
+    pub fn routes(&self) -> Result<Vec<Cidr>, Error> {
+        let index = self.index()?;
+        let table = self.table();
+        netlink(move |handle| async move {
+            let mut routes = dump(&handle, true).await?;
+            routes.extend(dump(&handle, false).await?);
+            Ok(routes.iter()
+                .filter(|m| route_table(m) == table && m.output_interface() == Some(index))
+                .map(|m| {
+                    let (addr, prefix) = destination(m);
+                    Cidr::new(addr, prefix)
+                })
+                .collect())
+        })
+    }
+
+    /// 删除本网卡上的 vlink 路由和本设备添加的旁路, 不影响其他设备
+    pub fn flush(&self) -> Result<(), Error> {
+        // drop 时网卡可能已删除, 上面的路由随网卡一起删除了
+        let index = self.index().ok();
+        let table = self.table();
+        let bypass: Vec<IpAddr> = self.bypass.lock().unwrap().drain().collect();
+        netlink(move |handle| async move {
+            for v4 in [true, false] {
+                for msg in dump(&handle, v4).await? {
+                    if msg.header.protocol != RTPROT_VLINK {
+                        continue;
+                    }
+                    let own = index.is_some() && msg.output_interface() == index;
+                    let is_bypass = route_table(&msg) == table
+                        && bypass.iter().any(|dst| destination(&msg) == (*dst, host_prefix(*dst)));
+                    if own || is_bypass {
+                        handle.route().del(msg).execute().await?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// 按当前路由表固定一条到dst 的主机路由,不受默认路由切换到tun 的影响
+    pub fn add_bypass_route(&self, dst: IpAddr) -> Result<(), Error> {
+        let out = run_ip(&["route", "get", &dst.to_string()])?;
+        let (gateway, dev) = parse_route_get(&out);
+        let Some(dev) = dev else {
+            return Err(Error::Route(format!("{} 没有可用路由", dst)));
+        };
+        if dev == self.name {
+            return Err(Error::InvalidConfig(format!("{} 已经路由到 {}", dst, dev)));
+        }
+        let index = if_index(&dev)?;
+        let table = self.table();
+        // 先记录, 添加失败时 flush 多删一次不存在的路由也无影响
+        self.bypass.lock().unwrap().insert(dst);
+        netlink(move |handle| async move {
+            let req = handle.route().add()
+                .output_interface(index)
+                .table_id(table)
+                .protocol(RTPROT_VLINK)
+                .replace();
+            match (dst, gateway) {
+                (IpAddr::V4(a), gateway) => {
+                    let mut req = req.v4().destination_prefix(a, 32);
+                    if let Some(IpAddr::V4(g)) = gateway {
+                        req = req.gateway(g);
+                    }
+                    req.execute().await
+                }
+                (IpAddr::V6(a), gateway) => {
+                    let mut req = req.v6().destination_prefix(a, 128);
+                    if let Some(IpAddr::V6(g)) = gateway {
+                        req = req.gateway(g);
+                    }
+                    req.execute().await
+                }
+            }
+        })
+    }
+
+    /// 只删除本设备添加的旁路, 其他设备可能旁路了同一个地址
+    pub fn del_bypass_route(&self, dst: IpAddr) -> Result<(), Error> {
+        if !self.bypass.lock().unwrap().remove(&dst) {
+            return Ok(());
+        }
+        let prefix = host_prefix(dst);
+        let table = self.table();
+        netlink(move |handle| async move {
+            for msg in dump(&handle, dst.is_ipv4()).await? {
+                if msg.header.protocol == RTPROT_VLINK
+                    && route_table(&msg) == table
+                    && destination(&msg) == (dst, prefix) {
+                    handle.route().del(msg).execute().await?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("删除路由失败: {}", e);
+        }
+    }
+}
+
+/// 调用方可能在 tokio 运行时内, 也可能持有同步锁, 在独立线程中执行 netlink 请求
+fn netlink<T, F, Fut>(f: F) -> Result<T, Error>
+    where T: Send,
+          F: FnOnce(Handle) -> Fut + Send,
+          Fut: Future<Output=Result<T, rtnetlink::Error>> {
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .build()?;
+            rt.block_on(async {
+                let (conn, handle, _) = rtnetlink::new_connection()?;
+                tokio::spawn(conn);
+                f(handle).await.map_err(|e| Error::Route(e.to_string()))
+            })
+        }).join().unwrap_or_else(|_| Err(Error::Route("netlink 线程异常".to_string())))
+    })
+}
+
+async fn dump(handle: &Handle, v4: bool) -> Result<Vec<RouteMessage>, rtnetlink::Error> {
+    let version = if v4 { IpVersion::V4 } else { IpVersion::V6 };
+    handle.route().get(version).execute().try_collect().await
+}
+
+/// 表id 大于255 时放在 RTA_TABLE 中
+fn route_table(msg: &RouteMessage) -> u32 {
+    msg.nlas.iter()
+        .find_map(|nla| if let Nla::Table(t) = nla { Some(*t) } else { None })
+        .unwrap_or(msg.header.table as u32)
+}
+
+/// 默认路由没有 RTA_DST
+fn destination(msg: &RouteMessage) -> (IpAddr, u8) {
+    msg.destination_prefix().unwrap_or_else(|| {
+        let addr = if msg.header.address_family == libc::AF_INET as u8 {
+            IpAddr::from([0u8; 4])
+        } else {
+            IpAddr::from([0u8; 16])
+        };
+        (addr, 0)
+    })
+}
+
+fn host_prefix(addr: IpAddr) -> u8 {
+    if addr.is_ipv4() { 32 } else { 128 }
 }
-impl IRouter for Router{
 
-}
\ No newline at end of file
+fn prefix_len(mask: IpAddr) -> u8 {
+    match mask {
+        IpAddr::V4(m) => u32::from(m).leading_ones() as u8,
+        IpAddr::V6(m) => u128::from(m).leading_ones() as u8,
+    }
+}
+
+fn if_index(name: &str) -> Result<u32, Error> {
+    let c_name = CString::new(name)
+        .map_err(|_| Error::InvalidConfig(format!("网卡名错误:{}", name)))?;
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(std::io::Error::last_os_error().into()),
+        index => Ok(index),
+    }
+}
+
+/// 解析 ip route get 输出中的网关和出口网卡
+fn parse_route_get(out: &str) -> (Option<IpAddr>, Option<String>) {
+    let (mut gateway, mut dev) = (None, None);
+    let mut words = out.split_whitespace();
+    while let Some(w) = words.next() {
+        match w {
+            "via" => gateway = words.next().and_then(|v| v.parse().ok()),
+            "dev" => dev = words.next().map(|v| v.to_string()),
+            _ => {}
+        }
+    }
+    (gateway, dev)
+}
+
+fn run_ip(args: &[&str]) -> Result<String, Error> {
+    let out = Command::new("ip").args(args).output()?;
+    if !out.status.success() {
+        return Err(std::io::Error::other(format!("ip {}: {}", args.join(" "), String::from_utf8_lossy(&out.stderr).trim())).into());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+impl IRouter for Router {}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use netlink_packet_route::{AF_INET, AF_INET6};
+
+    use super::*;
+
+    #[test]
+    fn test_prefix_len() {
+        assert_eq!(prefix_len(IpAddr::V4(Ipv4Addr::new(255, 255, 255, 0))), 24);
+        assert_eq!(prefix_len(IpAddr::V4(Ipv4Addr::UNSPECIFIED)), 0);
+        assert_eq!(prefix_len(IpAddr::V4(Ipv4Addr::BROADCAST)), 32);
+        assert_eq!(prefix_len(IpAddr::V6(Ipv6Addr::from(u128::MAX << 64))), 64);
+    }
+
+    #[test]
+    fn test_destination_table() {
+        let mut msg = RouteMessage::default();
+        msg.header.address_family = AF_INET as u8;
+        msg.header.table = RT_TABLE_MAIN;
+        // 默认路由
+        assert_eq!(destination(&msg), (IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0));
+        assert_eq!(route_table(&msg), RT_TABLE_MAIN as u32);
+
+        msg.header.destination_prefix_length = 24;
+        msg.nlas.push(Nla::Destination(vec![10, 0, 1, 0]));
+        msg.nlas.push(Nla::Table(1000));
+        assert_eq!(destination(&msg), (IpAddr::V4(Ipv4Addr::new(10, 0, 1, 0)), 24));
+        assert_eq!(route_table(&msg), 1000);
+
+        let mut msg = RouteMessage::default();
+        msg.header.address_family = AF_INET6 as u8;
+        assert_eq!(destination(&msg), (IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0));
+    }
+
+    #[test]
+    fn test_parse_route_get() {
+        let out = "1.1.1.1 via 192.168.1.1 dev eth0 src 192.168.1.10 uid 0 \n    cache";
+        assert_eq!(parse_route_get(out), (Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))), Some("eth0".to_string())));
+        let out = "192.168.1.2 dev eth0 src 192.168.1.10 uid 0";
+        assert_eq!(parse_route_get(out), (None, Some("eth0".to_string())));
+    }
+
+    /// 需要 root 创建 tun 网卡, 没有权限时跳过
+    #[test]
+    fn test_two_routers() {
+        let names = ["vlt-r0", "vlt-r1"];
+        for name in names {
+            let _ = run_ip(&["tuntap", "del", name, "mode", "tun"]);
+            if run_ip(&["tuntap", "add", name, "mode", "tun"]).is_err()
+                || run_ip(&["link", "set", name, "up"]).is_err() {
+                let _ = run_ip(&["tuntap", "del", names[0], "mode", "tun"]);
+                println!("跳过: 无法创建 tun 网卡");
+                return;
+            }
+        }
+        let cfg = RouteConfig { metric: None, table: Some(1076) };
+        let mask = IpAddr::V4(Ipv4Addr::new(255, 255, 255, 0));
+        let net0 = Cidr::new(IpAddr::V4(Ipv4Addr::new(10, 123, 1, 0)), 24);
+        let net1 = Cidr::new(IpAddr::V4(Ipv4Addr::new(10, 123, 2, 0)), 24);
+        let bypass = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+        let has_bypass = || {
+            run_ip(&["route", "show", "table", "1076", "proto", "118"]).unwrap()
+                .contains(&bypass.to_string())
+        };
+        let r0 = Router::new(names[0].to_string(), cfg.clone());
+        r0.add_route(IpAddr::V4(Ipv4Addr::new(10, 123, 1, 0)), mask).unwrap();
+        r0.add_bypass_route(bypass).unwrap();
+        assert!(has_bypass());
+
+        // 新设备启动时不清理已有设备的路由
+        let r1 = Router::new(names[1].to_string(), cfg);
+        r1.add_route(IpAddr::V4(Ipv4Addr::new(10, 123, 2, 0)), mask).unwrap();
+        assert!(r0.routes().unwrap().contains(&net0));
+        assert!(has_bypass());
+
+        // 不是自己添加的旁路不删除
+        r1.del_bypass_route(bypass).unwrap();
+        assert!(has_bypass());
+
+        drop(r0);
+        assert!(!has_bypass());
+        assert!(r1.routes().unwrap().contains(&net1));
+        drop(r1);
+        for name in names {
+            let _ = run_ip(&["tuntap", "del", name, "mode", "tun"]);
+        }
+    }
+}
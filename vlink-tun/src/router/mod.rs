@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 #[cfg(target_os = "linux")]
 mod linux;
 
@@ -18,4 +20,15 @@ mod windows;
 pub use windows::Router;
 
 
-pub trait IRouter {}
\ No newline at end of file
+pub trait IRouter {}
+
+/// 添加系统路由的参数,目前只有linux 使用
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteConfig {
+    /// 路由优先级,越小越优先
+    #[serde(default)]
+    pub metric: Option<u32>,
+    /// 路由表id,默认main,自定义表需要自行配置 ip rule
+    #[serde(default)]
+    pub table: Option<u32>,
+}
\ No newline at end of file
@@ -1,11 +1,11 @@
 use std::net::IpAddr;
-use crate::router::IRouter;
+use crate::router::{IRouter, RouteConfig};
 
 pub struct Router{
 
 }
 impl Router{
-    pub fn new(name:String)->Self {
+    pub fn new(name:String, _cfg: RouteConfig)->Self {
         Self{
 
         }
@@ -13,6 +13,15 @@ impl Router{
     pub fn add_route(&self, addr: IpAddr, mask: IpAddr) ->Result<(),crate::errors::Error> {
         Ok(())
     }
+    pub fn del_route(&self, addr: IpAddr, mask: IpAddr) ->Result<(),crate::errors::Error> {
+        Ok(())
+    }
+    pub fn add_bypass_route(&self, dst: IpAddr) -> Result<(), crate::errors::Error> {
+        Ok(())
+    }
+    pub fn del_bypass_route(&self, dst: IpAddr) -> Result<(), crate::errors::Error> {
+        Ok(())
+    }
 }
 impl IRouter for Router{
 
@@ -19,6 +19,16 @@ ioctl_write_ptr_bad!(siocsifaddr,0x8916, ifreq);
 ioctl_read_bad!(siocgifnetmask,0x891b,ifreq);
 ioctl_write_ptr_bad!(siocsifnetmask,0x891c,ifreq);
 
+/// linux/ipv6.h in6_ifreq
+#[repr(C)]
+pub struct in6_ifreq {
+    pub ifr6_addr: libc::in6_addr,
+    pub ifr6_prefixlen: u32,
+    pub ifr6_ifindex: libc::c_int,
+}
+
+ioctl_write_ptr_bad!(siocsifaddr6,0x8916, in6_ifreq);
+
 ioctl_read_bad!(siocgifflags , 0x8913, ifreq);
 ioctl_write_ptr_bad!( siocsifflags , 0x8914, ifreq);
 pub fn new_ifreq(name: &str) -> ifreq {
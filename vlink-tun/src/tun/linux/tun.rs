@@ -1,6 +1,6 @@
 use std::{io, mem, ptr};
-use std::ffi::CStr;
-use std::net::Ipv4Addr;
+use std::ffi::{CStr, CString};
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::sync::Arc;
 
@@ -144,6 +144,28 @@ impl Tun for NativeTun {
         }
     }
 
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        let name = CString::new(self.name.as_str())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // ipv6 地址需要通过 AF_INET6 socket 设置
+        let ctrl = unsafe { libc::socket(libc::AF_INET6, libc::SOCK_DGRAM, 0) };
+        if ctrl < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ctrl = unsafe { OwnedFd::from_raw_fd(ctrl) };
+        let req = sys::in6_ifreq {
+            ifr6_addr: libc::in6_addr { s6_addr: address.octets() },
+            ifr6_prefixlen: prefix as u32,
+            ifr6_ifindex: index as _,
+        };
+        unsafe { sys::siocsifaddr6(ctrl.as_raw_fd(), &req) }?;
+        Ok(())
+    }
+
     async fn recv(&self) -> Result<Vec<u8>, Error> {
         let mut buf = BytesMut::zeroed(1500);
 
@@ -1,6 +1,6 @@
 use std::io;
 use std::mem::{size_of, size_of_val};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::sync::Arc;
 use std::time::Duration;
@@ -171,6 +171,21 @@ impl Tun for NativeTun {
         Ok(())
     }
 
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        let out = std::process::Command::new("ifconfig")
+            .arg(self.name.as_str())
+            .arg("inet6")
+            .arg(address.to_string())
+            .arg("prefixlen")
+            .arg(prefix.to_string())
+            .arg("alias")
+            .output()?;
+        if !out.status.success() {
+            return Err(io::Error::new(io::ErrorKind::Other, String::from_utf8_lossy(&out.stderr).to_string()));
+        }
+        Ok(())
+    }
+
     async fn recv(&self) -> Result<Vec<u8>, Error> {
         let mut buf = BytesMut::zeroed(1500);
 
@@ -0,0 +1,125 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use crate::tun::Error;
+use crate::Tun;
+
+const QUEUE_SIZE: usize = 1024;
+
+/// 内存tun, 用于测试
+/// write_packet 模拟应用发出的包, 由 Device 读取发给peer
+/// read_packet 读取 Device 写入tun 的包
+#[derive(Clone)]
+pub struct MemoryTun {
+    inner: Arc<MemoryTunInner>,
+}
+
+struct MemoryTunInner {
+    name: String,
+    state: Mutex<State>,
+    /// 应用 -> 设备
+    outbound_tx: mpsc::Sender<Vec<u8>>,
+    outbound_rx: tokio::sync::Mutex<mpsc::Receiver<Vec<u8>>>,
+    /// 设备 -> 应用
+    inbound_tx: mpsc::Sender<Vec<u8>>,
+    inbound_rx: tokio::sync::Mutex<mpsc::Receiver<Vec<u8>>>,
+}
+
+struct State {
+    mtu: u16,
+    address: Ipv4Addr,
+    netmask: Ipv4Addr,
+    address6: Option<(Ipv6Addr, u8)>,
+}
+
+impl MemoryTun {
+    pub fn new(name: &str) -> Self {
+        let (outbound_tx, outbound_rx) = mpsc::channel(QUEUE_SIZE);
+        let (inbound_tx, inbound_rx) = mpsc::channel(QUEUE_SIZE);
+        Self {
+            inner: Arc::new(MemoryTunInner {
+                name: name.to_string(),
+                state: Mutex::new(State {
+                    mtu: 1420,
+                    address: Ipv4Addr::UNSPECIFIED,
+                    netmask: Ipv4Addr::UNSPECIFIED,
+                    address6: None,
+                }),
+                outbound_tx,
+                outbound_rx: tokio::sync::Mutex::new(outbound_rx),
+                inbound_tx,
+                inbound_rx: tokio::sync::Mutex::new(inbound_rx),
+            }),
+        }
+    }
+
+    /// 模拟应用发出的包
+    pub async fn write_packet(&self, buf: &[u8]) -> Result<(), Error> {
+        self.inner.outbound_tx.send(buf.to_vec()).await
+            .map_err(|_| Error::Closed)
+    }
+
+    /// 读取设备写入tun 的包
+    pub async fn read_packet(&self) -> Option<Vec<u8>> {
+        self.inner.inbound_rx.lock().await.recv().await
+    }
+}
+
+#[async_trait]
+impl Tun for MemoryTun {
+    fn enabled(&self, _value: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name.as_str()
+    }
+
+    fn mtu(&self) -> Result<u16, Error> {
+        Ok(self.inner.state.lock().unwrap().mtu)
+    }
+
+    fn set_mtu(&self, mtu: u16) -> Result<(), Error> {
+        self.inner.state.lock().unwrap().mtu = mtu;
+        Ok(())
+    }
+
+    fn address(&self) -> io::Result<Ipv4Addr> {
+        Ok(self.inner.state.lock().unwrap().address)
+    }
+
+    fn set_address(&self, value: Ipv4Addr) -> io::Result<()> {
+        self.inner.state.lock().unwrap().address = value;
+        Ok(())
+    }
+
+    fn netmask(&self) -> io::Result<Ipv4Addr> {
+        Ok(self.inner.state.lock().unwrap().netmask)
+    }
+
+    fn set_netmask(&self, value: Ipv4Addr) -> io::Result<()> {
+        self.inner.state.lock().unwrap().netmask = value;
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Vec<u8>, Error> {
+        self.inner.outbound_rx.lock().await
+            .recv()
+            .await
+            .ok_or(Error::Closed)
+    }
+
+    async fn send(&self, buf: &[u8]) -> Result<(), Error> {
+        self.inner.inbound_tx.send(buf.to_vec()).await
+            .map_err(|_| Error::Closed)
+    }
+
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        self.inner.state.lock().unwrap().address6 = Some((address, prefix));
+        Ok(())
+    }
+}
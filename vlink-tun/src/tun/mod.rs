@@ -1,7 +1,7 @@
 mod error;
 
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 pub use error::Error;
 
 #[cfg(target_os = "macos")]
@@ -25,10 +25,15 @@ pub use windows::NativeTun;
 #[cfg(unix)]
 mod unix;
 
+pub mod memory;
+
+#[cfg(feature = "netstack")]
+pub mod netstack;
+
 use async_trait::async_trait;
 
 #[async_trait]
-pub trait Tun: Send + Sync + Clone {
+pub trait Tun: Send + Sync + Clone + 'static {
     fn enabled(&self, value: bool) -> io::Result<()>;
 
     fn name(&self) -> &str;
@@ -45,5 +50,7 @@ pub trait Tun: Send + Sync + Clone {
         self.set_address(address)?;
         self.set_netmask(mask)
     }
+    /// 添加ipv6 地址,prefix 为前缀长度
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()>;
 }
 
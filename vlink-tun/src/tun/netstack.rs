@@ -0,0 +1,548 @@
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::debug;
+use rand_core::{OsRng, RngCore};
+use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
+use smoltcp::socket::AnySocket;
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::socket::tcp;
+use smoltcp::time::Instant as SmolInstant;
+use smoltcp::wire::{HardwareAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address, Ipv6Address};
+use tokio::sync::{mpsc, Notify};
+
+use crate::tun::Error;
+use crate::Tun;
+
+const SOCKET_BUFFER: usize = 64 * 1024;
+/// 入口队列上限, 协议栈处理不过来时丢包
+const RX_QUEUE: usize = 1024;
+/// 没有定时任务时也定期poll, 同时检查协议栈是否已释放
+const MAX_POLL_DELAY: Duration = Duration::from_secs(1);
+const EPHEMERAL_PORT_START: u16 = 49152;
+/// 每个监听端口同时等待握手的socket 数
+const LISTEN_BACKLOG: usize = 16;
+
+/// 用户态 tcp/ip 协议栈
+/// 不需要 CAP_NET_ADMIN 和内核tun, 应用通过 connect/listen 直接加入虚拟网络
+/// 作为 Tun 交给 Device 时, Device 发给tun 的包进入协议栈, 协议栈发出的包由 Device 加密发送
+#[derive(Clone)]
+pub struct NetstackTun {
+    inner: Arc<NetstackInner>,
+}
+
+struct NetstackInner {
+    name: String,
+    stack: Mutex<Stack>,
+    /// 有输入或socket 操作时唤醒poll
+    poll_notify: Arc<Notify>,
+    /// 每次poll 后唤醒等待中的socket
+    socket_notify: Notify,
+    outbound: tokio::sync::Mutex<mpsc::Receiver<Vec<u8>>>,
+}
+
+struct Stack {
+    iface: Interface,
+    device: VirtualDevice,
+    sockets: SocketSet<'static>,
+    mtu: u16,
+    address: Ipv4Addr,
+    netmask: Ipv4Addr,
+    address6: Option<(Ipv6Addr, u8)>,
+    next_port: u16,
+    /// 已监听的端口
+    listen_ports: HashSet<u16>,
+    /// 已关闭等待挥手结束的socket
+    closing: Vec<SocketHandle>,
+}
+
+impl NetstackTun {
+    pub fn new(name: &str, mtu: u16) -> Self {
+        let mut device = VirtualDevice {
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+            mtu: mtu as usize,
+        };
+        let mut config = Config::new(HardwareAddress::Ip);
+        config.random_seed = OsRng.next_u64();
+        let iface = Interface::new(config, &mut device, SmolInstant::now());
+        let (tx, rx) = mpsc::channel(RX_QUEUE);
+        let poll_notify = Arc::new(Notify::new());
+        let inner = Arc::new(NetstackInner {
+            name: name.to_string(),
+            stack: Mutex::new(Stack {
+                iface,
+                device,
+                sockets: SocketSet::new(vec![]),
+                mtu,
+                address: Ipv4Addr::UNSPECIFIED,
+                netmask: Ipv4Addr::UNSPECIFIED,
+                address6: None,
+                next_port: EPHEMERAL_PORT_START,
+                listen_ports: HashSet::new(),
+                closing: vec![],
+            }),
+            poll_notify: poll_notify.clone(),
+            socket_notify: Notify::new(),
+            outbound: tokio::sync::Mutex::new(rx),
+        });
+        spawn_poll(Arc::downgrade(&inner), poll_notify, tx);
+        Self { inner }
+    }
+
+    /// 连接虚拟网络中的地址, 握手完成后返回
+    pub async fn connect(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let handle = {
+            let mut stack = self.inner.stack.lock().unwrap();
+            let port = stack.ephemeral_port()?;
+            let handle = stack.sockets.add(new_tcp_socket());
+            let Stack { iface, sockets, .. } = &mut *stack;
+            let socket = sockets.get_mut::<tcp::Socket>(handle);
+            if let Err(e) = socket.connect(iface.context(), IpEndpoint::from(addr), port) {
+                sockets.remove(handle);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, e.to_string()));
+            }
+            handle
+        };
+        let stream = TcpStream {
+            stack: self.clone(),
+            handle,
+        };
+        self.wait(handle, |s| match s.state() {
+            tcp::State::Established => Some(Ok(())),
+            tcp::State::Closed => Some(Err(io::Error::from(io::ErrorKind::ConnectionRefused))),
+            _ => None,
+        }).await?;
+        Ok(stream)
+    }
+
+    pub fn listen(&self, port: u16) -> io::Result<TcpListener> {
+        let mut stack = self.inner.stack.lock().unwrap();
+        if !stack.listen_ports.insert(port) {
+            return Err(io::Error::from(io::ErrorKind::AddrInUse));
+        }
+        let mut handles = Vec::with_capacity(LISTEN_BACKLOG);
+        for _ in 0..LISTEN_BACKLOG {
+            match stack.listen_socket(port) {
+                Ok(h) => handles.push(h),
+                Err(e) => {
+                    handles.into_iter().for_each(|h| { stack.sockets.remove(h); });
+                    stack.listen_ports.remove(&port);
+                    return Err(e);
+                }
+            }
+        }
+        drop(stack);
+        Ok(TcpListener {
+            stack: self.clone(),
+            port,
+            handles,
+        })
+    }
+
+    /// 等待socket 满足条件, 每次poll 后重新检查
+    async fn wait<R>(&self, handle: SocketHandle, mut f: impl FnMut(&mut tcp::Socket<'static>) -> Option<R>) -> R {
+        self.wait_stack(|stack| f(stack.sockets.get_mut::<tcp::Socket>(handle))).await
+    }
+
+    async fn wait_stack<R>(&self, mut f: impl FnMut(&mut Stack) -> Option<R>) -> R {
+        loop {
+            let notified = self.inner.socket_notify.notified();
+            let ret = f(&mut self.inner.stack.lock().unwrap());
+            if let Some(r) = ret {
+                // 读写后需要poll 发送ack 和数据
+                self.inner.poll_notify.notify_one();
+                return r;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl NetstackInner {
+    /// 处理输入并收集输出, 返回下一次poll 的等待时间
+    fn poll(&self) -> (Vec<Vec<u8>>, Duration) {
+        let mut stack = self.stack.lock().unwrap();
+        let now = SmolInstant::now();
+        let Stack { iface, device, sockets, closing, .. } = &mut *stack;
+        iface.poll(now, device, sockets);
+        closing.retain(|h| {
+            if sockets.get::<tcp::Socket>(*h).is_open() {
+                return true;
+            }
+            sockets.remove(*h);
+            false
+        });
+        let delay = iface.poll_delay(now, sockets)
+            .map(|d| Duration::from_millis(d.total_millis()))
+            .unwrap_or(MAX_POLL_DELAY)
+            .min(MAX_POLL_DELAY);
+        let packets = device.tx.drain(..).collect();
+        drop(stack);
+        self.socket_notify.notify_waiters();
+        (packets, delay)
+    }
+}
+
+/// 只持有弱引用, 协议栈释放后退出
+fn spawn_poll(inner: Weak<NetstackInner>, notify: Arc<Notify>, tx: mpsc::Sender<Vec<u8>>) {
+    tokio::spawn(async move {
+        loop {
+            let (packets, delay) = match inner.upgrade() {
+                Some(inner) => inner.poll(),
+                None => break,
+            };
+            for packet in packets {
+                if tx.send(packet).await.is_err() {
+                    return;
+                }
+            }
+            tokio::select! {
+                _ = notify.notified() => {}
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+        debug!("netstack poll loop is DOWN");
+    });
+}
+
+impl Stack {
+    /// 跳过已监听和已连接的端口, 全部占用时返回错误
+    fn ephemeral_port(&mut self) -> io::Result<u16> {
+        let used: HashSet<u16> = self.sockets.iter()
+            .filter_map(|(_, s)| tcp::Socket::downcast(s)?.local_endpoint())
+            .map(|e| e.port)
+            .chain(self.listen_ports.iter().copied())
+            .collect();
+        for _ in EPHEMERAL_PORT_START..=u16::MAX {
+            let port = self.next_port;
+            self.next_port = self.next_port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START);
+            if !used.contains(&port) {
+                return Ok(port);
+            }
+        }
+        Err(io::Error::from(io::ErrorKind::AddrInUse))
+    }
+
+    fn listen_socket(&mut self, port: u16) -> io::Result<SocketHandle> {
+        let mut socket = new_tcp_socket();
+        socket.listen(port)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        Ok(self.sockets.add(socket))
+    }
+
+    /// 地址变化后重新设置接口地址和默认路由
+    fn update_addrs(&mut self) {
+        let prefix = u32::from(self.netmask).count_ones() as u8;
+        let address = self.address;
+        let address6 = self.address6;
+        self.iface.update_ip_addrs(|addrs| {
+            addrs.clear();
+            if !address.is_unspecified() {
+                let _ = addrs.push(IpCidr::new(IpAddress::from(IpAddr::V4(address)), prefix));
+            }
+            if let Some((ip, prefix)) = address6 {
+                let _ = addrs.push(IpCidr::new(IpAddress::from(IpAddr::V6(ip)), prefix));
+            }
+        });
+        // peer 的子网路由也交给协议栈, 由 Device 按 allowed_ips 转发
+        if !address.is_unspecified() {
+            let _ = self.iface.routes_mut().add_default_ipv4_route(Ipv4Address::from(address));
+        }
+        match address6 {
+            Some((ip, _)) => {
+                let _ = self.iface.routes_mut().add_default_ipv6_route(Ipv6Address::from(ip));
+            }
+            None => {
+                self.iface.routes_mut().remove_default_ipv6_route();
+            }
+        }
+    }
+}
+
+fn new_tcp_socket() -> tcp::Socket<'static> {
+    tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; SOCKET_BUFFER]),
+        tcp::SocketBuffer::new(vec![0; SOCKET_BUFFER]),
+    )
+}
+
+#[async_trait]
+impl Tun for NetstackTun {
+    fn enabled(&self, _value: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name.as_str()
+    }
+
+    fn mtu(&self) -> Result<u16, Error> {
+        Ok(self.inner.stack.lock().unwrap().mtu)
+    }
+
+    fn set_mtu(&self, mtu: u16) -> Result<(), Error> {
+        let mut stack = self.inner.stack.lock().unwrap();
+        stack.mtu = mtu;
+        stack.device.mtu = mtu as usize;
+        Ok(())
+    }
+
+    fn address(&self) -> io::Result<Ipv4Addr> {
+        Ok(self.inner.stack.lock().unwrap().address)
+    }
+
+    fn set_address(&self, value: Ipv4Addr) -> io::Result<()> {
+        let mut stack = self.inner.stack.lock().unwrap();
+        stack.address = value;
+        stack.update_addrs();
+        Ok(())
+    }
+
+    fn netmask(&self) -> io::Result<Ipv4Addr> {
+        Ok(self.inner.stack.lock().unwrap().netmask)
+    }
+
+    fn set_netmask(&self, value: Ipv4Addr) -> io::Result<()> {
+        let mut stack = self.inner.stack.lock().unwrap();
+        stack.netmask = value;
+        stack.update_addrs();
+        Ok(())
+    }
+
+    /// 协议栈发出的包
+    async fn recv(&self) -> Result<Vec<u8>, Error> {
+        self.inner.outbound.lock().await
+            .recv()
+            .await
+            .ok_or(Error::Closed)
+    }
+
+    /// 输入协议栈
+    async fn send(&self, buf: &[u8]) -> Result<(), Error> {
+        {
+            let mut stack = self.inner.stack.lock().unwrap();
+            if stack.device.rx.len() >= RX_QUEUE {
+                debug!("netstack rx queue full, drop packet");
+                return Ok(());
+            }
+            stack.device.rx.push_back(buf.to_vec());
+        }
+        self.inner.poll_notify.notify_one();
+        Ok(())
+    }
+
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        let mut stack = self.inner.stack.lock().unwrap();
+        stack.address6 = Some((address, prefix));
+        stack.update_addrs();
+        Ok(())
+    }
+}
+
+/// 协议栈连接
+pub struct TcpStream {
+    stack: NetstackTun,
+    handle: SocketHandle,
+}
+
+impl TcpStream {
+    /// 返回0 表示对端已关闭
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stack.wait(self.handle, |s| {
+            if s.can_recv() {
+                Some(s.recv_slice(buf).map_err(|e| io::Error::other(e.to_string())))
+            } else if !s.may_recv() {
+                Some(Ok(0))
+            } else {
+                None
+            }
+        }).await
+    }
+
+    pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.stack.wait(self.handle, |s| {
+            if s.can_send() {
+                Some(s.send_slice(buf).map_err(|e| io::Error::other(e.to_string())))
+            } else if !s.may_send() {
+                Some(Err(io::Error::from(io::ErrorKind::BrokenPipe)))
+            } else {
+                None
+            }
+        }).await
+    }
+
+    pub async fn write_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let n = self.write(buf).await?;
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        let stack = self.stack.inner.stack.lock().unwrap();
+        stack.sockets.get::<tcp::Socket>(self.handle)
+            .remote_endpoint()
+            .map(|e| SocketAddr::new(e.addr.into(), e.port))
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let mut stack = self.stack.inner.stack.lock().unwrap();
+        stack.sockets.get_mut::<tcp::Socket>(self.handle).close();
+        stack.closing.push(self.handle);
+        drop(stack);
+        self.stack.inner.poll_notify.notify_one();
+    }
+}
+
+/// 监听端口, 保持 LISTEN_BACKLOG 个监听socket, 每接受一个连接补一个新的
+pub struct TcpListener {
+    stack: NetstackTun,
+    port: u16,
+    handles: Vec<SocketHandle>,
+}
+
+impl TcpListener {
+    pub async fn accept(&mut self) -> io::Result<(TcpStream, SocketAddr)> {
+        let handles = &mut self.handles;
+        let port = self.port;
+        let handle = self.stack.wait_stack(|stack| {
+            let i = handles.iter().position(|h| !matches!(
+                stack.sockets.get::<tcp::Socket>(*h).state(),
+                tcp::State::Listen | tcp::State::SynReceived
+            ))?;
+            Some(stack.listen_socket(port).map(|h| std::mem::replace(&mut handles[i], h)))
+        }).await?;
+        let stream = TcpStream {
+            stack: self.stack.clone(),
+            handle,
+        };
+        let addr = stream.peer_addr()
+            .ok_or(io::Error::from(io::ErrorKind::NotConnected))?;
+        Ok((stream, addr))
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let mut stack = self.stack.inner.stack.lock().unwrap();
+        for h in self.handles.drain(..) {
+            stack.sockets.remove(h);
+        }
+        stack.listen_ports.remove(&self.port);
+    }
+}
+
+/// 内存收发的ip 层设备
+struct VirtualDevice {
+    rx: VecDeque<Vec<u8>>,
+    tx: VecDeque<Vec<u8>>,
+    mtu: usize,
+}
+
+impl phy::Device for VirtualDevice {
+    type RxToken<'a> = RxToken where Self: 'a;
+    type TxToken<'a> = TxToken<'a> where Self: 'a;
+
+    fn receive(&mut self, _timestamp: SmolInstant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let buf = self.rx.pop_front()?;
+        Some((RxToken(buf), TxToken(&mut self.tx)))
+    }
+
+    fn transmit(&mut self, _timestamp: SmolInstant) -> Option<Self::TxToken<'_>> {
+        Some(TxToken(&mut self.tx))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ip;
+        caps.max_transmission_unit = self.mtu;
+        caps
+    }
+}
+
+struct RxToken(Vec<u8>);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+        where F: FnOnce(&mut [u8]) -> R
+    {
+        f(&mut self.0)
+    }
+}
+
+struct TxToken<'a>(&'a mut VecDeque<Vec<u8>>);
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+        where F: FnOnce(&mut [u8]) -> R
+    {
+        let mut buf = vec![0; len];
+        let r = f(&mut buf);
+        self.0.push_back(buf);
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 两个协议栈直接互相转发
+    fn wire(a: NetstackTun, b: NetstackTun) {
+        tokio::spawn(async move {
+            while let Ok(packet) = a.recv().await {
+                let _ = b.send(&packet).await;
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_tcp_echo() {
+        let a = NetstackTun::new("ns0", 1420);
+        a.set_ip(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(255, 255, 255, 0)).unwrap();
+        let b = NetstackTun::new("ns1", 1420);
+        b.set_ip(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(255, 255, 255, 0)).unwrap();
+        wire(a.clone(), b.clone());
+        wire(b.clone(), a.clone());
+
+        let mut listener = b.listen(80).unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, addr) = listener.accept().await.unwrap();
+            assert_eq!(addr.ip(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+            let mut buf = [0u8; 16];
+            let n = stream.read(&mut buf).await.unwrap();
+            stream.write_all(&buf[..n]).await.unwrap();
+        });
+
+        let stream = a.connect("10.0.0.2:80".parse().unwrap()).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 16];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_port() {
+        let a = NetstackTun::new("ns0", 1420);
+        let _listener = a.listen(EPHEMERAL_PORT_START).unwrap();
+        assert_eq!(a.listen(EPHEMERAL_PORT_START).err().map(|e| e.kind()), Some(io::ErrorKind::AddrInUse));
+        let mut stack = a.inner.stack.lock().unwrap();
+        assert_eq!(stack.ephemeral_port().unwrap(), EPHEMERAL_PORT_START + 1);
+        stack.next_port = u16::MAX;
+        assert_eq!(stack.ephemeral_port().unwrap(), u16::MAX);
+        assert_eq!(stack.ephemeral_port().unwrap(), EPHEMERAL_PORT_START + 1);
+    }
+}
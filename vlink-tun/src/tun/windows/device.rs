@@ -1,5 +1,5 @@
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
 use async_trait::async_trait;
 use crate::Tun;
@@ -100,4 +100,10 @@ impl Tun for NativeTun {
             NativeTun::Tun(t) => t.set_ip(address, mask)
         }
     }
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        match self {
+            NativeTun::Tap(t) => t.set_ipv6(address, prefix),
+            NativeTun::Tun(t) => t.set_ipv6(address, prefix)
+        }
+    }
 }
\ No newline at end of file
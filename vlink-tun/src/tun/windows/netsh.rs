@@ -2,7 +2,7 @@
 use crate::tun::windows::exe_cmd;
 
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 /// 设置网卡名称
 pub fn set_interface_name(old_name: &str, new_name: &str) -> io::Result<()> {
@@ -28,6 +28,15 @@ pub fn set_interface_ip(index: u32, address: &Ipv4Addr, netmask: &Ipv4Addr) -> i
     exe_cmd(&cmd)
 }
 
+/// 添加网卡ipv6
+pub fn add_interface_ipv6(index: u32, address: &Ipv6Addr, prefix: u8) -> io::Result<()> {
+    let cmd = format!(
+        "netsh interface ipv6 add address {} {}/{}",
+        index, address, prefix,
+    );
+    exe_cmd(&cmd)
+}
+
 pub fn set_interface_mtu(index: u32, mtu: u32) -> io::Result<()> {
     let cmd = format!(
         "netsh interface ipv4 set subinterface {}  mtu={} store=persistent",
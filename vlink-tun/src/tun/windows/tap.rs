@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use async_trait::async_trait;
 use winapi::shared::ifdef::NET_LUID;
 use winapi::shared::minwindef::DWORD;
@@ -177,6 +177,9 @@ impl Tun for Device {
     fn set_ip(&self, address: Ipv4Addr, mask: Ipv4Addr) -> io::Result<()> {
         netsh::set_interface_ip(self.index, &address, &mask)
     }
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        netsh::add_interface_ipv6(self.index, &address, prefix)
+    }
     async fn recv(&self) -> Result<Vec<u8>, Error> {
         todo!()
     }
@@ -2,7 +2,7 @@
 
 use libloading::Library;
 use std::io;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::RwLock;
 use async_trait::async_trait;
 use log::error;
@@ -242,6 +242,9 @@ impl Tun for Device {
         });
         Ok(())
     }
+    fn set_ipv6(&self, address: Ipv6Addr, prefix: u8) -> io::Result<()> {
+        netsh::add_interface_ipv6(self.index, &address, prefix)
+    }
 
     async fn send(&self, buf: &[u8]) -> Result<(), Error> {
         let mut packet = self.allocate_send_packet(buf.len() as u16)?;
@@ -0,0 +1,436 @@
+//! 标准 wireguard 跨平台 UAPI, 监听 /var/run/wireguard/<iface>.sock
+//! 可以直接使用 wg show / wg set 查看和修改设备
+//! 协议说明 https://www.wireguard.com/xplatform/
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::{debug, info, warn};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::device::peer::cidr::Cidr;
+use crate::device::wg_quick::first_ipv4;
+use crate::noise::crypto::encode_to_hex;
+use crate::{DeviceControl, PeerConfig, Tun};
+
+const SOCKET_DIR: &str = "/var/run/wireguard";
+
+#[derive(thiserror::Error, Debug)]
+pub enum UapiError {
+    #[error("invalid line: {0}")]
+    InvalidLine(String),
+    #[error("invalid value for {0}: {1}")]
+    InvalidValue(&'static str, String),
+    #[error("unknown key: {0}")]
+    UnknownKey(String),
+    #[error("{0} can not be changed")]
+    Unsupported(&'static str),
+    #[error("bind listen port failed: {0}")]
+    ListenPort(String),
+}
+
+impl UapiError {
+    /// 返回给客户端的 errno, 为正数
+    pub fn errno(&self) -> i32 {
+        match self {
+            UapiError::ListenPort(_) => libc::EADDRINUSE,
+            UapiError::InvalidLine(_) => libc::EPROTO,
+            _ => libc::EINVAL,
+        }
+    }
+}
+
+pub fn socket_path(name: &str) -> PathBuf {
+    Path::new(SOCKET_DIR).join(format!("{name}.sock"))
+}
+
+/// 监听设备的 uapi socket, 设备关闭时删除 socket 文件
+pub async fn bind_and_handle<T: Tun>(ctrl: DeviceControl<T>) -> io::Result<()> {
+    let path = socket_path(ctrl.tun.name());
+    std::fs::create_dir_all(SOCKET_DIR)?;
+    if path.exists() {
+        // 能连上说明同名设备还在运行, 不能删除
+        if UnixStream::connect(&path).await.is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} 正在使用", path.display())));
+        }
+        // 上次异常退出遗留的文件
+        std::fs::remove_file(&path)?;
+    }
+    // 创建时就只有 owner 可以访问, 不在 bind 之后再 chmod
+    let umask = unsafe { libc::umask(0o077) };
+    let listener = UnixListener::bind(&path);
+    unsafe { libc::umask(umask) };
+    let listener = listener?;
+    info!("uapi 监听 {}", path.display());
+
+    let token = ctrl.token();
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            rs = listener.accept() => {
+                match rs {
+                    Ok((stream, _)) => {
+                        let ctrl = ctrl.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_conn(ctrl, stream).await {
+                                debug!("uapi 连接断开: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        warn!("uapi accept error: {e}");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+/// 一个连接可以连续执行多次 get/set, 每次以空行结束
+async fn handle_conn<T: Tun>(ctrl: DeviceControl<T>, stream: UnixStream) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(op) = lines.next_line().await? {
+        let mut body = vec![];
+        while let Some(line) = lines.next_line().await? {
+            if line.is_empty() {
+                break;
+            }
+            body.push(line);
+        }
+        let rs = match op.as_str() {
+            "get=1" => {
+                let out = get(&ctrl);
+                writer.write_all(out.as_bytes()).await?;
+                Ok(())
+            }
+            "set=1" => set(&ctrl, &body).await,
+            _ => Err(UapiError::InvalidLine(op)),
+        };
+        let errno = match rs {
+            Ok(()) => 0,
+            Err(e) => {
+                warn!("uapi: {e}");
+                e.errno()
+            }
+        };
+        writer.write_all(format!("errno={errno}\n\n").as_bytes()).await?;
+        if errno == libc::EPROTO {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// 输出设备和所有peer 的状态, 不包括结尾的 errno
+pub fn get<T: Tun>(ctrl: &DeviceControl<T>) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "private_key={}", encode_to_hex(&ctrl.private_key()));
+    let _ = writeln!(out, "listen_port={}", ctrl.listen_port());
+    let fwmark = ctrl.fwmark();
+    if fwmark != 0 {
+        let _ = writeln!(out, "fwmark={fwmark}");
+    }
+    let configs = ctrl.peers.read().unwrap().to_config();
+    for cfg in configs {
+        let Some(peer) = ctrl.get_peer_by_key(&cfg.public_key) else {
+            continue;
+        };
+        let metrics = peer.metrics();
+        let _ = writeln!(out, "public_key={}", encode_to_hex(&cfg.public_key));
+        let _ = writeln!(out, "preshared_key={}", encode_to_hex(&cfg.preshared_key.unwrap_or_default()));
+        let _ = writeln!(out, "protocol_version=1");
+        if let Some(endpoint) = cfg.endpoint {
+            let _ = writeln!(out, "endpoint={endpoint}");
+        }
+        let handshake = metrics.last_handshake_at.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        let _ = writeln!(out, "last_handshake_time_sec={}", handshake.as_secs());
+        let _ = writeln!(out, "last_handshake_time_nsec={}", handshake.subsec_nanos());
+        let _ = writeln!(out, "tx_bytes={}", metrics.tx_bytes);
+        let _ = writeln!(out, "rx_bytes={}", metrics.rx_bytes);
+        let keepalive = cfg.persistent_keepalive.map(|d| d.as_secs()).unwrap_or(0);
+        let _ = writeln!(out, "persistent_keepalive_interval={keepalive}");
+        for cidr in cfg.allowed_ips {
+            let _ = writeln!(out, "allowed_ip={}", cidr.to_string());
+        }
+    }
+    out
+}
+
+/// set 中的一个peer
+#[derive(Default)]
+struct PeerSet {
+    public_key: [u8; 32],
+    remove: bool,
+    update_only: bool,
+    preshared_key: Option<[u8; 32]>,
+    endpoint: Option<SocketAddr>,
+    keepalive: Option<u16>,
+    replace_allowed_ips: bool,
+    allowed_ips: HashSet<Cidr>,
+}
+
+/// 先解析全部内容, 出错时不修改设备
+pub async fn set<T: Tun>(ctrl: &DeviceControl<T>, lines: &[String]) -> Result<(), UapiError> {
+    let mut listen_port = None;
+    let mut peers: Vec<PeerSet> = vec![];
+    for line in lines {
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| UapiError::InvalidLine(line.clone()))?;
+        // public_key 之后的都属于该peer
+        if let Some(peer) = peers.last_mut() {
+            match key {
+                "public_key" => {}
+                "remove" => peer.remove = parse_true("remove", value)?,
+                "update_only" => peer.update_only = parse_true("update_only", value)?,
+                "preshared_key" => peer.preshared_key = Some(parse_key("preshared_key", value)?),
+                "endpoint" => peer.endpoint = Some(value.parse()
+                    .map_err(|_| UapiError::InvalidValue("endpoint", value.to_string()))?),
+                "persistent_keepalive_interval" => peer.keepalive = Some(value.parse()
+                    .map_err(|_| UapiError::InvalidValue("persistent_keepalive_interval", value.to_string()))?),
+                "replace_allowed_ips" => peer.replace_allowed_ips = parse_true("replace_allowed_ips", value)?,
+                "allowed_ip" => {
+                    peer.allowed_ips.insert(value.parse()
+                        .map_err(|_| UapiError::InvalidValue("allowed_ip", value.to_string()))?);
+                }
+                "protocol_version" if value == "1" => {}
+                "protocol_version" => return Err(UapiError::InvalidValue("protocol_version", value.to_string())),
+                _ => return Err(UapiError::UnknownKey(key.to_string())),
+            }
+            if key != "public_key" {
+                continue;
+            }
+        }
+        match key {
+            "private_key" => {
+                if parse_key("private_key", value)? != ctrl.private_key() {
+                    return Err(UapiError::Unsupported("private_key"));
+                }
+            }
+            "listen_port" => listen_port = Some(value.parse::<u16>()
+                .map_err(|_| UapiError::InvalidValue("listen_port", value.to_string()))?),
+            "fwmark" => {
+                let fwmark = value.parse::<u32>()
+                    .map_err(|_| UapiError::InvalidValue("fwmark", value.to_string()))?;
+                if fwmark != ctrl.fwmark() {
+                    return Err(UapiError::Unsupported("fwmark"));
+                }
+            }
+            // peer 由 headlink 管理, 不允许通过 uapi 清空
+            "replace_peers" => {
+                parse_true("replace_peers", value)?;
+                return Err(UapiError::Unsupported("replace_peers"));
+            }
+            "public_key" => peers.push(PeerSet {
+                public_key: parse_key("public_key", value)?,
+                ..Default::default()
+            }),
+            _ => return Err(UapiError::UnknownKey(key.to_string())),
+        }
+    }
+
+    if let Some(port) = listen_port {
+        ctrl.set_listen_port(port).await
+            .map_err(|e| UapiError::ListenPort(e.to_string()))?;
+    }
+    for peer in peers {
+        apply_peer(ctrl, peer);
+    }
+    Ok(())
+}
+
+fn apply_peer<T: Tun>(ctrl: &DeviceControl<T>, p: PeerSet) {
+    let key = p.public_key;
+    if p.remove {
+        ctrl.remove_peer(&key);
+        return;
+    }
+    let keepalive = p.keepalive.map(|v| match v {
+        0 => None,
+        v => Some(Duration::from_secs(v as u64)),
+    });
+    let current = ctrl.peers.read().unwrap().allowed_ips_by_key(&key);
+    let Some(current) = current else {
+        if p.update_only {
+            return;
+        }
+        // 和 wg-quick 配置一样, 最小的ipv4 作为节点ip
+        let ip_addr = first_ipv4(&p.allowed_ips)
+            .map(|ip| ip.to_string())
+            .unwrap_or_default();
+        ctrl.insert_peer(PeerConfig {
+            public_key: key,
+            allowed_ips: p.allowed_ips,
+            endpoint: p.endpoint,
+            preshared_key: p.preshared_key,
+            is_online: true,
+            persistent_keepalive: keepalive.flatten(),
+            ip_addr,
+            ..Default::default()
+        });
+        return;
+    };
+    if let Some(psk) = p.preshared_key {
+        ctrl.update_peer_psk(&key, psk);
+    }
+    if let Some(addr) = p.endpoint {
+        ctrl.update_peer_endpoint(&key, addr);
+    }
+    if let Some(interval) = keepalive {
+        ctrl.update_peer_keepalive(&key, interval);
+    }
+    if p.replace_allowed_ips || !p.allowed_ips.is_empty() {
+        let mut allowed_ips = if p.replace_allowed_ips { HashSet::new() } else { current };
+        allowed_ips.extend(p.allowed_ips);
+        ctrl.update_peer_allowed_ips(&key, allowed_ips);
+    }
+}
+
+fn parse_true(key: &'static str, value: &str) -> Result<bool, UapiError> {
+    match value {
+        "true" => Ok(true),
+        _ => Err(UapiError::InvalidValue(key, value.to_string())),
+    }
+}
+
+fn parse_key(key: &'static str, value: &str) -> Result<[u8; 32], UapiError> {
+    let invalid = || UapiError::InvalidValue(key, value.to_string());
+    if value.len() != 64 || !value.is_ascii() {
+        return Err(invalid());
+    }
+    let mut out = [0u8; 32];
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::event::DeviceEvent;
+    use crate::{Device, DeviceConfig, LocalStaticSecret, MemoryTun};
+
+    async fn device(name: &str, private_key: [u8; 32]) -> Device<MemoryTun> {
+        let cfg = DeviceConfig {
+            private_key,
+            fwmark: 0,
+            port: 0,
+            peers: Default::default(),
+            address: "10.99.0.1".parse().unwrap(),
+            network: "10.99.0.0/24".parse().unwrap(),
+            address6: None,
+            network6: None,
+            acl: Default::default(),
+            bypass: vec![],
+            route: Default::default(),
+            rekey: Default::default(),
+        };
+        Device::with_tun(MemoryTun::new(name), cfg).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_set() {
+        let private_key = LocalStaticSecret::random().private_key().to_bytes();
+        let device = device("uapi0", private_key).await;
+        let ctrl = device.control();
+        let mut events = device.event_bus.subscribe();
+
+        let peer = encode_to_hex(&LocalStaticSecret::random().public_key().to_bytes());
+        let lines = vec![
+            format!("public_key={peer}"),
+            "endpoint=127.0.0.1:51820".to_string(),
+            "persistent_keepalive_interval=25".to_string(),
+            "allowed_ip=fd00::2/128".to_string(),
+            "allowed_ip=10.99.0.2/32".to_string(),
+            "allowed_ip=192.168.1.0/24".to_string(),
+        ];
+        set(&ctrl, &lines).await.unwrap();
+        let cfg = ctrl.peers.read().unwrap().to_config().pop().unwrap();
+        assert_eq!(cfg.ip_addr, "10.99.0.2");
+
+        let out = get(&ctrl);
+        assert!(out.contains(&format!("private_key={}", encode_to_hex(&private_key))));
+        assert!(out.contains(&format!("listen_port={}", device.port)));
+        assert!(out.contains(&format!("public_key={peer}")));
+        assert!(out.contains("endpoint=127.0.0.1:51820"));
+        assert!(out.contains("persistent_keepalive_interval=25"));
+        assert!(out.contains("allowed_ip=10.99.0.2/32"));
+
+        let lines = vec![
+            "listen_port=0".to_string(),
+            format!("public_key={peer}"),
+            "replace_allowed_ips=true".to_string(),
+            "allowed_ip=10.99.0.3/32".to_string(),
+        ];
+        set(&ctrl, &lines).await.unwrap();
+        let out = get(&ctrl);
+        assert!(out.contains("allowed_ip=10.99.0.3/32"));
+        assert!(!out.contains("allowed_ip=10.99.0.2/32"));
+        assert_ne!(ctrl.listen_port(), device.port);
+        let mut changed = None;
+        while let Ok(e) = events.try_recv() {
+            if let DeviceEvent::ListenPortChanged(port) = e {
+                changed = Some(port);
+            }
+        }
+        assert_eq!(changed, Some(ctrl.listen_port()));
+
+        // 不允许清空 headlink 管理的peer
+        let err = set(&ctrl, &["replace_peers=true".to_string()]).await.unwrap_err();
+        assert_eq!(err.errno(), libc::EINVAL);
+        assert!(get(&ctrl).contains(&format!("public_key={peer}")));
+
+        set(&ctrl, &[format!("public_key={peer}"), "remove=true".to_string()]).await.unwrap();
+        assert!(!get(&ctrl).contains("public_key="));
+
+        let err = set(&ctrl, &["fwmark=1".to_string()]).await.unwrap_err();
+        assert_eq!(err.errno(), libc::EINVAL);
+    }
+
+    #[tokio::test]
+    async fn test_handle_conn() {
+        let private_key = LocalStaticSecret::random().private_key().to_bytes();
+        let device = device("uapi1", private_key).await;
+        let (client, server) = UnixStream::pair().unwrap();
+        let conn = tokio::spawn(handle_conn(device.control(), server));
+        let (reader, mut writer) = client.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        // 读取到空行为止的一次响应
+        let mut response = async || {
+            let mut out = vec![];
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if line.is_empty() {
+                    break;
+                }
+                out.push(line);
+            }
+            out
+        };
+
+        writer.write_all(b"get=1\n\n").await.unwrap();
+        let out = response().await;
+        assert_eq!(out.first().unwrap(), &format!("private_key={}", encode_to_hex(&private_key)));
+        assert_eq!(out.last().unwrap(), "errno=0");
+
+        // 同一连接继续执行, 错误只返回 errno
+        writer.write_all(b"set=1\nfwmark=1\n\n").await.unwrap();
+        assert_eq!(response().await, vec![format!("errno={}", libc::EINVAL)]);
+        writer.write_all(b"set=1\nlisten_port=0\n\n").await.unwrap();
+        assert_eq!(response().await, vec!["errno=0".to_string()]);
+
+        // 无法识别的操作断开连接
+        writer.write_all(b"foo=1\n\n").await.unwrap();
+        assert_eq!(response().await, vec![format!("errno={}", libc::EPROTO)]);
+        assert!(response().await.is_empty());
+        conn.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use ip_network::IpNetwork;
+use tokio::time::{sleep, timeout, Instant};
+use vlink_tun::device::config::RekeyConfig;
+use vlink_tun::device::peer::cidr::Cidr;
+use vlink_tun::{Device, DeviceConfig, LocalStaticSecret, MemoryTun, PeerConfig};
+
+pub const WAIT: Duration = Duration::from_secs(5);
+
+/// 一个测试节点, 通过内存tun 收发包
+pub struct Node {
+    pub device: Device<MemoryTun>,
+    pub tun: MemoryTun,
+    pub private_key: [u8; 32],
+    pub ip: Ipv4Addr,
+}
+
+impl Node {
+    pub async fn spawn(private_key: [u8; 32], ip: Ipv4Addr, peers: Vec<PeerConfig>) -> Self {
+        Self::with_config(device_config(private_key, ip, peers)).await
+    }
+
+    pub async fn with_config(cfg: DeviceConfig) -> Self {
+        let (private_key, ip) = (cfg.private_key, cfg.address);
+        let tun = MemoryTun::new(format!("mem{}", ip.octets()[3]).as_str());
+        let device = Device::with_tun(tun.clone(), cfg).await.unwrap();
+        Self {
+            device,
+            tun,
+            private_key,
+            ip,
+        }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        LocalStaticSecret::new(self.private_key).public_key().to_bytes()
+    }
+
+    pub fn endpoint(&self) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), self.device.port)
+    }
+
+    /// 作为其他节点的peer
+    pub fn peer_config(&self) -> PeerConfig {
+        PeerConfig {
+            public_key: self.public_key(),
+            allowed_ips: [Cidr::new(IpAddr::V4(self.ip), 32)].into_iter().collect(),
+            endpoint: Some(self.endpoint()),
+            is_online: true,
+            ip_addr: self.ip.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// 重复发送直到对方收到, 握手完成前的包会被丢弃
+    pub async fn send_until_received(&self, to: &Node, payload: &[u8]) -> Vec<u8> {
+        let packet = ipv4_packet(self.ip, to.ip, payload);
+        let deadline = Instant::now() + WAIT;
+        while Instant::now() < deadline {
+            self.tun.write_packet(&packet).await.unwrap();
+            if let Ok(Some(p)) = timeout(Duration::from_millis(200), to.tun.read_packet()).await {
+                return p;
+            }
+        }
+        panic!("{} -> {} 未收到数据", self.ip, to.ip);
+    }
+}
+
+/// 监听 localhost 随机udp 端口, 地址在 10.99.0.0/24
+pub fn device_config(private_key: [u8; 32], ip: Ipv4Addr, peers: Vec<PeerConfig>) -> DeviceConfig {
+    DeviceConfig {
+        private_key,
+        fwmark: 0,
+        port: 0,
+        peers: peers.into_iter().map(|p| (p.public_key, p)).collect(),
+        address: ip,
+        network: "10.99.0.0/24".parse::<IpNetwork>().unwrap(),
+        address6: None,
+        network6: None,
+        acl: Default::default(),
+        bypass: vec![],
+        route: Default::default(),
+        rekey: Default::default(),
+    }
+}
+
+/// n 个节点两两互为peer
+pub struct TestNet {
+    pub nodes: Vec<Node>,
+}
+
+impl TestNet {
+    pub async fn new(n: usize) -> Self {
+        Self::with_rekey(n, RekeyConfig::default()).await
+    }
+
+    /// 缩短重新握手的时间或包数
+    pub async fn with_rekey(n: usize, rekey: RekeyConfig) -> Self {
+        let mut nodes = vec![];
+        for i in 0..n {
+            let key = LocalStaticSecret::random().private_key().to_bytes();
+            let mut cfg = device_config(key, Ipv4Addr::new(10, 99, 0, i as u8 + 1), vec![]);
+            cfg.rekey = rekey.clone();
+            nodes.push(Node::with_config(cfg).await);
+        }
+        // 端口确定后再互相添加
+        let configs: HashMap<[u8; 32], PeerConfig> = nodes.iter()
+            .map(|n| (n.public_key(), n.peer_config()))
+            .collect();
+        for node in nodes.iter() {
+            for (key, cfg) in configs.iter() {
+                if *key != node.public_key() {
+                    node.device.insert_peer(cfg.clone());
+                }
+            }
+        }
+        Self { nodes }
+    }
+}
+
+/// 最小的ipv4 包, 设备只看目的地址
+pub fn ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr, payload: &[u8]) -> Vec<u8> {
+    let total = (20 + payload.len()) as u16;
+    let mut buf = vec![0x45, 0];
+    buf.extend_from_slice(&total.to_be_bytes());
+    buf.extend_from_slice(&[0, 0, 0, 0, 64, 253, 0, 0]);
+    buf.extend_from_slice(&src.octets());
+    buf.extend_from_slice(&dst.octets());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+pub fn payload(packet: &[u8]) -> &[u8] {
+    &packet[20..]
+}
+
+/// 等待条件成立
+pub async fn wait_for(mut f: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + WAIT;
+    while Instant::now() < deadline {
+        if f() {
+            return true;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
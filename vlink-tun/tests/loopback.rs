@@ -0,0 +1,131 @@
+//! 多个 Device 通过 localhost udp 互连, 使用内存tun, 不需要root
+
+mod common;
+
+use std::time::{Duration, Instant, SystemTime};
+
+use common::{ipv4_packet, payload, wait_for, Node, TestNet};
+use tokio::time::timeout;
+use vlink_tun::device::config::RekeyConfig;
+use vlink_tun::device::transport::udp::PROTO_NAME;
+
+#[tokio::test]
+async fn test_handshake_and_data() {
+    let net = TestNet::new(3).await;
+    for a in net.nodes.iter() {
+        for b in net.nodes.iter() {
+            if a.ip == b.ip {
+                continue;
+            }
+            let msg = format!("{} -> {}", a.ip, b.ip);
+            let p = a.send_until_received(b, msg.as_bytes()).await;
+            assert_eq!(payload(&p), msg.as_bytes());
+        }
+    }
+    let a = &net.nodes[0];
+    let peer = a.device.get_peer_by_key(&net.nodes[1].public_key()).unwrap();
+    let metrics = peer.metrics();
+    assert!(metrics.last_handshake_at > SystemTime::UNIX_EPOCH);
+    assert!(metrics.tx_bytes > 0 && metrics.rx_bytes > 0);
+}
+
+/// 一端重置peer 后重新握手, 另一端替换会话
+#[tokio::test]
+async fn test_rehandshake() {
+    let net = TestNet::new(2).await;
+    let (a, b) = (&net.nodes[0], &net.nodes[1]);
+    a.send_until_received(b, b"first").await;
+    let handshake_at = || a.device.get_peer_by_key(&b.public_key()).unwrap().metrics().last_handshake_at;
+    let before = handshake_at();
+
+    b.device.reset_peers(vec![a.peer_config()]);
+    let p = b.send_until_received(a, b"after reset").await;
+    assert_eq!(payload(&p), b"after reset");
+    assert!(wait_for(|| handshake_at() > before).await);
+    let p = a.send_until_received(b, b"new session").await;
+    assert_eq!(payload(&p), b"new session");
+}
+
+/// 节点换了端口重新上线, 对端从握手包学到新地址
+#[tokio::test]
+async fn test_roaming() {
+    let net = TestNet::new(2).await;
+    let mut nodes = net.nodes;
+    let b = nodes.pop().unwrap();
+    let a = nodes.pop().unwrap();
+    a.send_until_received(&b, b"before").await;
+
+    let (key, ip) = (a.private_key, a.ip);
+    let old = a.endpoint();
+    drop(a);
+    let a = Node::spawn(key, ip, vec![b.peer_config()]).await;
+    assert_ne!(a.endpoint(), old);
+
+    a.send_until_received(&b, b"roamed").await;
+    let p = b.send_until_received(&a, b"reply").await;
+    assert_eq!(payload(&p), b"reply");
+    let peer = b.device.get_peer_by_key(&a.public_key()).unwrap();
+    let dst = peer.endpoint.read().unwrap().as_ref().map(|e| e.dst());
+    assert_eq!(dst, Some(a.endpoint()));
+}
+
+/// 只有单向流量时, 接收端每 10s 回一个被动保活, 发送端一直能收到对端的数据, 直连路径不会被判定失效
+#[tokio::test]
+async fn test_one_way_keepalive() {
+    let net = TestNet::new(2).await;
+    let (a, b) = (&net.nodes[0], &net.nodes[1]);
+    a.send_until_received(b, b"start").await;
+    let peer = a.device.get_peer_by_key(&b.public_key()).unwrap();
+    let start = Instant::now();
+    let packet = ipv4_packet(a.ip, b.ip, b"one way");
+    while start.elapsed() < Duration::from_secs(12) {
+        a.tun.write_packet(&packet).await.unwrap();
+        let _ = timeout(Duration::from_millis(200), b.tun.read_packet()).await;
+    }
+    let last = peer.last_recv_at(PROTO_NAME).unwrap();
+    assert!(last > start + Duration::from_secs(5));
+}
+
+/// 持续双向收发, 每个包都要送达, 返回期间对端最后一次握手时间是否变化
+async fn ping_while_rekey(net: &TestNet, duration: Duration, interval: Duration) -> bool {
+    let (a, b) = (&net.nodes[0], &net.nodes[1]);
+    a.send_until_received(b, b"start").await;
+    let handshake_at = || a.device.get_peer_by_key(&b.public_key()).unwrap().metrics().last_handshake_at;
+    let first = handshake_at();
+    let start = Instant::now();
+    let mut i = 0;
+    while start.elapsed() < duration {
+        for (from, to) in [(a, b), (b, a)] {
+            let msg = format!("{} -> {} #{}", from.ip, to.ip, i);
+            from.tun.write_packet(&ipv4_packet(from.ip, to.ip, msg.as_bytes())).await.unwrap();
+            let p = timeout(Duration::from_secs(1), to.tun.read_packet()).await
+                .unwrap_or_else(|_| panic!("{} 丢失", msg))
+                .unwrap();
+            assert_eq!(payload(&p), msg.as_bytes());
+        }
+        i += 1;
+        tokio::time::sleep(interval).await;
+    }
+    handshake_at() > first
+}
+
+/// 会话超过 rekey 时间后重新握手, 期间收发不中断
+#[tokio::test]
+async fn test_rekey_after_time() {
+    let net = TestNet::with_rekey(2, RekeyConfig {
+        after_time: Some(Duration::from_secs(1)),
+        after_messages: None,
+    }).await;
+    // 会话建立后每 REKEY_TIMEOUT(5s) 检查一次
+    assert!(ping_while_rekey(&net, Duration::from_secs(8), Duration::from_millis(50)).await);
+}
+
+/// 发送的包数超过 rekey 包数后重新握手, 新会话重新计数
+#[tokio::test]
+async fn test_rekey_after_messages() {
+    let net = TestNet::with_rekey(2, RekeyConfig {
+        after_time: None,
+        after_messages: Some(20),
+    }).await;
+    assert!(ping_while_rekey(&net, Duration::from_secs(8), Duration::from_millis(50)).await);
+}
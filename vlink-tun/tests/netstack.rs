@@ -0,0 +1,79 @@
+//! Device 使用用户态协议栈作为tun, 通过 localhost udp 互连后走 tcp
+#![cfg(feature = "netstack")]
+
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use common::{device_config, WAIT};
+use tokio::time::timeout;
+use vlink_tun::device::peer::cidr::Cidr;
+use vlink_tun::{Device, LocalStaticSecret, NetstackTun, PeerConfig};
+
+struct Node {
+    device: Device<NetstackTun>,
+    tun: NetstackTun,
+    public_key: [u8; 32],
+    ip: Ipv4Addr,
+}
+
+impl Node {
+    async fn spawn(ip: Ipv4Addr) -> Self {
+        let secret = LocalStaticSecret::random();
+        let tun = NetstackTun::new(format!("ns{}", ip.octets()[3]).as_str(), 1420);
+        let cfg = device_config(secret.private_key().to_bytes(), ip, vec![]);
+        let device = Device::with_tun(tun.clone(), cfg).await.unwrap();
+        Self {
+            device,
+            tun,
+            public_key: secret.public_key().to_bytes(),
+            ip,
+        }
+    }
+
+    fn peer_config(&self) -> PeerConfig {
+        PeerConfig {
+            public_key: self.public_key,
+            allowed_ips: [Cidr::new(IpAddr::V4(self.ip), 32)].into_iter().collect(),
+            endpoint: Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), self.device.port)),
+            is_online: true,
+            ip_addr: self.ip.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_tcp_over_device() {
+    let a = Node::spawn(Ipv4Addr::new(10, 99, 0, 1)).await;
+    let b = Node::spawn(Ipv4Addr::new(10, 99, 0, 2)).await;
+    a.device.insert_peer(b.peer_config());
+    b.device.insert_peer(a.peer_config());
+
+    // 多个连接同时到达, 不能因为只有一个监听socket 被重置
+    let mut listener = b.tun.listen(80).unwrap();
+    let server = tokio::spawn(async move {
+        for _ in 0..3 {
+            let (stream, addr) = listener.accept().await.unwrap();
+            assert_eq!(addr.ip(), IpAddr::V4(Ipv4Addr::new(10, 99, 0, 1)));
+            tokio::spawn(async move {
+                let mut buf = [0u8; 64];
+                let n = stream.read(&mut buf).await.unwrap();
+                stream.write_all(&buf[..n]).await.unwrap();
+            });
+        }
+    });
+
+    let dst: SocketAddr = "10.99.0.2:80".parse().unwrap();
+    let (s1, s2, s3) = timeout(WAIT, async {
+        tokio::join!(a.tun.connect(dst), a.tun.connect(dst), a.tun.connect(dst))
+    }).await.unwrap();
+    for (i, stream) in [s1.unwrap(), s2.unwrap(), s3.unwrap()].into_iter().enumerate() {
+        let msg = format!("hello {}", i);
+        stream.write_all(msg.as_bytes()).await.unwrap();
+        let mut buf = [0u8; 64];
+        let n = timeout(WAIT, stream.read(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..n], msg.as_bytes());
+    }
+    timeout(WAIT, server).await.unwrap().unwrap();
+}
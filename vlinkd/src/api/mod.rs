@@ -7,6 +7,7 @@ use crate::network::ctrl::NetworkCtrl;
 mod router;
 mod state;
 mod network;
+mod error;
 
 const WEB_PORT: u16 = 5514;
 
@@ -1,5 +1,35 @@
+use axum::extract::State;
+use axum::Json;
+use tokio::sync::oneshot;
+use crate::api::error::{ApiError, ApiResult};
+use crate::api::state::AppState;
+use crate::network::ctrl::NetworkCtrlCmd;
+use crate::network::types::{AclRuleInfo, ExtraProtoInfo, NetworkInfo, PeerInfo};
 
+/// 向网络管理器请求当前状态
+async fn req_info(state: &AppState) -> Result<NetworkInfo, ApiError> {
+    let (tx, rx) = oneshot::channel();
+    state.ctrl.send(NetworkCtrlCmd::ReqInfo(tx)).await
+        .map_err(|_| ApiError::NetworkNotReady)?;
+    rx.await.map_err(|_| ApiError::NetworkNotReady)
+}
 
-///获取
-pub fn test() {
-}
\ No newline at end of file
+///获取节点信息
+pub async fn info(State(state): State<AppState>) -> ApiResult<NetworkInfo> {
+    Ok(Json(req_info(&state).await?))
+}
+
+///获取所有peer 状态
+pub async fn peers(State(state): State<AppState>) -> ApiResult<Vec<PeerInfo>> {
+    Ok(Json(req_info(&state).await?.peers))
+}
+
+///获取扩展协议状态
+pub async fn extra_protos(State(state): State<AppState>) -> ApiResult<Vec<ExtraProtoInfo>> {
+    Ok(Json(req_info(&state).await?.extra_protos))
+}
+
+///获取访问规则命中次数
+pub async fn acl(State(state): State<AppState>) -> ApiResult<Vec<AclRuleInfo>> {
+    Ok(Json(req_info(&state).await?.acl_rules))
+}
@@ -1,7 +1,14 @@
 use axum::Router;
+use axum::routing::get;
+use crate::api::network;
 use crate::api::state::AppState;
 
 pub fn api() -> Router<AppState> {
     Router::new()
-        .nest("/network", Router::new())
-}
\ No newline at end of file
+        .nest("/network", Router::new()
+            .route("/info", get(network::info))
+            .route("/peers", get(network::peers))
+            .route("/extra_protos", get(network::extra_protos))
+            .route("/acl", get(network::acl)),
+        )
+}
@@ -1,3 +1,4 @@
+use std::ops::Deref;
 use std::sync::Arc;
 use derive_new::new;
 use crate::network::ctrl::NetworkCtrl;
@@ -9,5 +10,13 @@ pub struct AppState {
 
 #[derive(new)]
 pub struct AppStateInner {
-    ctrl: NetworkCtrl,
-}
\ No newline at end of file
+    pub ctrl: NetworkCtrl,
+}
+
+impl Deref for AppState {
+    type Target = AppStateInner;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref()
+    }
+}
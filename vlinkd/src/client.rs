@@ -1,11 +1,13 @@
+use std::net::IpAddr;
 use std::ops::Deref;
-use std::sync::{Arc, mpsc};
+use std::sync::{Arc, Mutex, mpsc};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use anyhow::anyhow;
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
 use futures_util::{SinkExt, StreamExt, TryFutureExt};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use tap::TapFallible;
 use tokio::net::TcpStream;
 use tokio::select;
@@ -29,13 +31,22 @@ pub struct VlinkClient {
     server_addr: String,
     pub token: CancellationToken,
     timeout: Duration,
+    /// 用于注册节点的网络token
+    join_token: Option<String>,
+    hostname: Option<String>,
+    /// 节点等待审核
+    pending: Arc<AtomicBool>,
+    /// 服务器看到的本机地址,每次握手更新
+    observed_ip: Arc<Mutex<Option<IpAddr>>>,
 }
 
 impl VlinkClient {
     pub fn new(
         server_addr: String,
         secret: VlinkStaticSecret,
-        ctrl: NetworkCtrl) -> Self {
+        ctrl: NetworkCtrl,
+        join_token: Option<String>,
+        hostname: Option<String>) -> Self {
         let token = CancellationToken::new();
         Self {
             conn: Arc::new(RwLock::new(None)),
@@ -45,8 +56,15 @@ impl VlinkClient {
             server_addr,
             token,
             timeout: Duration::from_secs(30),
+            join_token,
+            hostname,
+            pending: Arc::new(AtomicBool::new(false)),
+            observed_ip: Default::default(),
         }
     }
+    pub fn server_addr(&self) -> &str {
+        self.server_addr.as_str()
+    }
     /// 挂起客户端
     /// 1. 连接握手
 
@@ -70,6 +88,10 @@ impl VlinkClient {
         let lock_conn_c = self.conn.clone();
         let secret = self.secret.clone();
         let timeout_c = self.timeout.clone();
+        let join_token = self.join_token.clone();
+        let hostname = self.hostname.clone();
+        let pending_c = self.pending.clone();
+        let observed_ip_c = self.observed_ip.clone();
         let reconnect = async move {
             let mut count = 0;
             let mut if_first = true;
@@ -109,12 +131,22 @@ impl VlinkClient {
                 let pc = HandshakeParam {
                     pub_key,
                     sign: secret.hello_sign(BASE64_STANDARD.decode(info.key)?.as_slice().try_into()?)?,
-                    token: None,
+                    token: join_token.clone(),
+                    hostname: hostname.clone(),
                 };
-                if let Err(e) = handshake(&conn, pc.clone()).await {
-                    error!("握手失败:{}", e);
-                    //退出
-                    break;
+                match handshake(&conn, pc.clone()).await {
+                    Ok((pending, observed_ip)) => {
+                        if pending {
+                            warn!("节点等待管理员审核");
+                        }
+                        pending_c.store(pending, Ordering::SeqCst);
+                        *observed_ip_c.lock().unwrap() = observed_ip;
+                    }
+                    Err(e) => {
+                        error!("握手失败:{}", e);
+                        //退出
+                        break;
+                    }
                 };
                 debug!("重连握手成功");
                 {
@@ -152,9 +184,24 @@ impl VlinkClient {
         Ok(())
     }
 
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::SeqCst)
+    }
+    /// 服务器看到的本机ip, 直连服务器时即公网ip
+    pub fn observed_ip(&self) -> Option<IpAddr> {
+        *self.observed_ip.lock().unwrap()
+    }
+    /// 审核中的请求不设超时,审核通过后服务端才响应
     pub async fn request(&self, data: ToServerData) -> anyhow::Result<ToClientData> {
         let conn = self.get_conn().await?;
-        let data = conn.request(data).await?.ok_or(anyhow!("请求失败,返回数据为空"));
+        let data = if self.is_pending() {
+            let data = conn.request_wait(data).await;
+            self.pending.store(false, Ordering::SeqCst);
+            data
+        } else {
+            conn.request(data).await
+        };
+        let data = data?.ok_or(anyhow!("请求失败,返回数据为空"));
         if let Ok(ToClientData::Error(e)) = &data {
             return Err(anyhow!("请求失败:{}",e.msg));
         }
@@ -210,13 +257,15 @@ pub struct HandshakeParam {
     pub pub_key: String,
     pub sign: String,
     pub token: Option<String>,
+    pub hostname: Option<String>,
 }
 
 async fn connect_server() {}
 
 /// 客户端握手
 /// token(用于加入网络) or encrypt_flag(校验私钥是否正确)
-async fn handshake(conn: &ClientConnect, param: HandshakeParam) -> anyhow::Result<()> {
+/// 返回是否等待审核和服务器看到的本机ip
+async fn handshake(conn: &ClientConnect, param: HandshakeParam) -> anyhow::Result<(bool, Option<IpAddr>)> {
     //私钥签名
 
     let resp = conn.request(ToServerData::Handshake(ReqHandshake {
@@ -224,16 +273,17 @@ async fn handshake(conn: &ClientConnect, param: HandshakeParam) -> anyhow::Resul
         pub_key: param.pub_key,
         token: param.token,
         sign: param.sign,
+        hostname: param.hostname,
     })).await?;
     match resp {
         Some(ToClientData::RespHandshake(e)) => {
             if !e.success {
                 return Err(anyhow!("握手失败:{}",e.msg.unwrap_or("".to_string())));
             }
+            Ok((e.pending, e.observed_ip.and_then(|ip| ip.parse().ok())))
         }
         _ => {
-            return Err(anyhow!("握手失败,数据包错误"));
+            Err(anyhow!("握手失败,数据包错误"))
         }
     }
-    Ok(())
 }
\ No newline at end of file
@@ -1,14 +1,15 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::net::SocketAddr;
 use base64::Engine;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::Error;
-use vlink_core::proto::pb::abi::{BcPeerEnter, PeerExtraTransport};
+use vlink_core::proto::pb::abi::{BcPeerEnter, PeerExtraTransport, RelayServer};
 use vlink_core::secret::VlinkStaticSecret;
 use vlink_tun::device::config::{ArgConfig, TransportConfig};
 use vlink_tun::{DeviceConfig, PeerConfig, PeerStaticSecret};
 use vlink_tun::device::peer::cidr::Cidr;
+use crate::network::dns::HostRecord;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct StorageConfig {
@@ -21,7 +22,8 @@ pub struct StorageConfig {
 pub struct PeersConfig {}
 
 
-#[derive(Debug, Clone)]
+/// 网络配置,每次从服务器获取后缓存到本地,服务器连不上时使用缓存启动
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VlinkNetworkConfig {
     pub tun_name: Option<String>,
     pub device_config: DeviceConfig,
@@ -32,6 +34,15 @@ pub struct VlinkNetworkConfig {
 
     // peer 扩展协议
     pub peer_extra_transports: Vec<PeerExtraTransport>,
+    // 中继服务器
+    #[serde(default)]
+    pub relay_servers: Vec<RelayServer>,
+    // 内置dns 的主机记录,包括本机
+    #[serde(default)]
+    pub hosts: Vec<HostRecord>,
+    // 服务器看到的节点地址, 公钥 -> 地址, 打洞时作为候选
+    #[serde(default)]
+    pub observed_candidates: HashMap<String, SocketAddr>,
     // pub test: RespConfig,
 }
 
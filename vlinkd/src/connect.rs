@@ -115,6 +115,21 @@ impl ClientConnect {
     }
 
 
+    /// 发送请求,一直等待到响应或连接断开
+    pub async fn request_wait(&self, data: ToServerData) -> anyhow::Result<Option<ToClientData>> {
+        if !self.is_connected.load(Ordering::SeqCst) {
+            return Err(anyhow::anyhow!("客户端已断开连接"));
+        }
+        let mut rx = self.stream.subscribe();
+        let id = self.send(None, data).await?;
+        loop {
+            let data = rx.recv().await?;
+            if data.id == id {
+                return Ok(data.to_client_data);
+            }
+        }
+    }
+
     pub async fn send(&self, id: Option<u64>, data: ToServerData) -> anyhow::Result<u64> {
         if !self.is_connected.load(Ordering::SeqCst) {
             return Err(anyhow::anyhow!("客户端已断开连接"));
@@ -1,16 +1,37 @@
 use std::collections::HashSet;
 use std::net::SocketAddr;
-use vlink_core::proto::pb::abi::BcPeerEnter;
+use anyhow::anyhow;
+use log::warn;
+use vlink_core::proto::pb::abi::{AclRule, BcPeerEnter};
+use vlink_tun::device::acl::{AclConfig, AclProto, AclRule as DeviceAclRule};
 use vlink_tun::device::peer::cidr::Cidr;
 use vlink_tun::PeerConfig;
+use crate::network::exit_node::{is_default_route, ExitNodeClient};
 
-pub fn bc_peer_enter2peer_config(p: &BcPeerEnter) -> anyhow::Result<PeerConfig> {
-    let pk = vlink_core::base64::decode_base64(p.pub_key.as_str())?;
+/// 默认路由只接受选定出口节点的
+pub fn bc_peer_enter2peer_config(p: &BcPeerEnter, exit: Option<&ExitNodeClient>) -> anyhow::Result<PeerConfig> {
+    let pk: [u8; 32] = vlink_core::base64::decode_base64(p.pub_key.as_str())?
+        .try_into()
+        .map_err(|_| anyhow!("pub_key 长度错误"))?;
     let mut allowed_ips = HashSet::new();
-    allowed_ips.insert(Cidr::new(p.ip.parse().unwrap(), 32));
+    allowed_ips.insert(Cidr::new(p.ip.parse().map_err(|e| anyhow!("ip错误 {}:{}", p.ip, e))?, 32));
+    if let Some(ipv6) = p.ipv6.as_ref() {
+        allowed_ips.insert(Cidr::new(ipv6.parse()?, 128));
+    }
+    // 节点后面的子网, 错误的路由跳过, 不影响节点本身
+    let use_exit = exit.map(|e| e.matches(p)).unwrap_or(false);
+    for r in p.routes.iter().filter(|r| use_exit || !is_default_route(r)) {
+        match r.parse() {
+            Ok(cidr) => {
+                allowed_ips.insert(cidr);
+            }
+            Err(e) => warn!("peer {} 子网路由错误 {}:{}", p.ip, r, e),
+        }
+    }
     Ok(PeerConfig {
-        public_key: pk.try_into().unwrap(),
+        public_key: pk,
         allowed_ips,
+        // 只使用明确配置的端点, 服务器看到的地址只作为打洞候选
         endpoint: match p.endpoint_addr.clone() {
             None => { None }
             Some(addr) => {
@@ -26,3 +47,31 @@ pub fn bc_peer_enter2peer_config(p: &BcPeerEnter) -> anyhow::Result<PeerConfig>
     })
 }
 
+/// 服务器看到的节点ip 加上节点的监听端口, 未经验证, 只用于打洞探测
+pub fn observed_candidate(p: &BcPeerEnter) -> Option<SocketAddr> {
+    let ip = p.observed_ip.as_ref()?.parse().ok()?;
+    (p.port > 0).then(|| SocketAddr::new(ip, p.port as u16))
+}
+
+/// 服务器下发的规则转换为设备规则, 没有命中时默认放行
+pub fn acl_rules2config(rules: &[AclRule]) -> anyhow::Result<AclConfig> {
+    let mut cfg = AclConfig::default();
+    for r in rules {
+        let parse = |v: &Vec<String>| v.iter()
+            .map(|c| c.parse::<Cidr>().map_err(|e| anyhow!("acl({}) cidr错误 {}:{}", r.id, c, e)))
+            .collect::<anyhow::Result<Vec<Cidr>>>();
+        cfg.rules.push(DeviceAclRule {
+            id: r.id,
+            src: parse(&r.src)?,
+            dst: parse(&r.dst)?,
+            proto: AclProto::parse(r.proto.as_str())
+                .ok_or(anyhow!("acl({}) 协议错误:{}", r.id, r.proto))?,
+            ports: match (r.port_start, r.port_end) {
+                (0, 0) => None,
+                (start, end) => Some((start as u16, end as u16)),
+            },
+            allow: r.allow,
+        });
+    }
+    Ok(cfg)
+}
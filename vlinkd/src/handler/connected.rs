@@ -14,7 +14,7 @@ use crate::network::{ExtraProto, ExtraProtoStatus};
 pub async fn handler_connected(client: Arc<VlinkClient>, device: Arc<Device>, args: &ArgConfig, es: RwMap<ExtraProto, ExtraProtoStatus>) -> anyhow::Result<()> {
     let (ip, port) = {
         let get_info = |dev: &Device| {
-            (dev.tun_addr.clone().to_string(), dev.port as u32)
+            (dev.tun_addr.clone().to_string(), dev.listen_port() as u32)
         };
         get_info(device.as_ref())
     };
@@ -35,6 +35,7 @@ pub async fn handler_connected(client: Arc<VlinkClient>, device: Arc<Device>, ar
         endpoint_addr: args.endpoint_addr.clone(),
         port,
         extra_endpoints,
+        advertise_routes: args.advertise_routes.clone(),
     })).await?;
     Ok(())
 }
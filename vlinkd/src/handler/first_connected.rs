@@ -1,16 +1,20 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 use std::sync::Arc;
 use anyhow::anyhow;
-use ip_network::IpNetwork;
-use log::info;
+use ip_network::{IpNetwork, Ipv6Network};
+use log::{info, warn};
 use vlink_core::proto::pb::abi::ReqConfig;
 use vlink_core::proto::pb::abi::to_client::ToClientData;
 use vlink_core::proto::pb::abi::to_server::ToServerData;
 use vlink_tun::device::config::{ArgConfig, TransportConfig};
-use vlink_tun::DeviceConfig;
+use vlink_core::base64::encode_base64;
+use vlink_tun::{DeviceConfig, LocalStaticSecret};
+use vlink_tun::router::RouteConfig;
 use crate::client::VlinkClient;
 use crate::config::VlinkNetworkConfig;
-use crate::handler::common::bc_peer_enter2peer_config;
+use crate::handler::common::{acl_rules2config, bc_peer_enter2peer_config, observed_candidate};
+use crate::network::dns::HostRecord;
+use crate::network::exit_node::ExitNodeClient;
 
 pub async fn request_for_config(client: Arc<VlinkClient>, private_key: [u8; 32], args: &ArgConfig) -> anyhow::Result<VlinkNetworkConfig> {
     let resp = client.request(ToServerData::ReqConfig(ReqConfig {})).await?;
@@ -23,6 +27,18 @@ pub async fn request_for_config(client: Arc<VlinkClient>, private_key: [u8; 32],
 
     let network = IpNetwork::new(IpAddr::V4(resp_config.network.into()), resp_config.mask as u8)?;
 
+    // 双栈, fd00::2/64
+    let (address6, network6) = match resp_config.ipv6_addr.as_ref() {
+        None => (None, None),
+        Some(s) => {
+            let (addr, prefix) = s.split_once('/')
+                .ok_or(anyhow!("ipv6地址格式错误:{}", s))?;
+            let addr: Ipv6Addr = addr.parse()?;
+            let network6 = Ipv6Network::new_truncate(addr, prefix.parse()?)?;
+            (Some(addr), Some(network6))
+        }
+    };
+
     let mut device_config = DeviceConfig {
         private_key,
         fwmark: 0,
@@ -36,10 +52,44 @@ pub async fn request_for_config(client: Arc<VlinkClient>, private_key: [u8; 32],
         peers: Default::default(),
         address: resp_config.address.into(),
         network,
+        address6,
+        network6,
+        acl: acl_rules2config(&resp_config.acl_rules)?,
+        bypass: vec![],
+        route: RouteConfig {
+            metric: args.route_metric,
+            table: args.route_table,
+        },
+        rekey: Default::default(),
     };
+    let exit = args.use_exit_node.clone()
+        .map(|peer| ExitNodeClient::new(peer, client.server_addr().to_string()));
     for p in resp_config.peers.iter() {
-        let c = bc_peer_enter2peer_config(p)?;
-        device_config = device_config.peer(c);
+        match bc_peer_enter2peer_config(p, exit.as_ref()) {
+            Ok(c) => device_config = device_config.peer(c),
+            Err(e) => warn!("peer {} 配置错误:{}", p.pub_key, e),
+        }
+    }
+    if let Some(exit) = exit {
+        let endpoints = device_config.peers.values()
+            .filter_map(|p| p.endpoint.map(|e| e.ip()))
+            .collect::<Vec<_>>();
+        device_config.bypass = exit.bypass(endpoints, &resp_config.relay_servers).await
+            .into_iter()
+            .collect();
+    }
+    // 在线节点和本机的主机名
+    let mut hosts: Vec<HostRecord> = resp_config.peers.iter()
+        .filter(|p| p.is_online)
+        .filter_map(HostRecord::from_peer)
+        .collect();
+    if let Some(hostname) = resp_config.hostname.clone() {
+        hosts.push(HostRecord {
+            pub_key: encode_base64(LocalStaticSecret::new(private_key).public_key().as_bytes()),
+            hostname,
+            ip: Some(device_config.address),
+            ipv6: device_config.address6,
+        });
     }
     let mut transports = vec![];
     for t in resp_config.extra_transports.iter() {
@@ -57,6 +107,11 @@ pub async fn request_for_config(client: Arc<VlinkClient>, private_key: [u8; 32],
         transports,
         stun_servers: vec![],
         peer_extra_transports: resp_config.peer_extra_transports.clone(),
+        relay_servers: resp_config.relay_servers.clone(),
+        hosts,
+        observed_candidates: resp_config.peers.iter()
+            .filter_map(|p| Some((p.pub_key.clone(), observed_candidate(p)?)))
+            .collect(),
     };
     Ok(cfg)
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
 pub mod first_connected;
-mod common;
+pub(crate) mod common;
 pub(crate) mod connected;
 mod peer_enter;
\ No newline at end of file
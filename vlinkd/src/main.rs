@@ -8,6 +8,7 @@ use vlink_tun::device::config::ArgConfig;
 use vlinkd::api::start_http_server;
 use vlinkd::client::VlinkClient;
 use vlinkd::network::{VlinkNetworkManager};
+use vlinkd::network::exit_node::{DEFAULT_ROUTE_V4, DEFAULT_ROUTE_V6};
 use vlinkd::network::ctrl::NetworkCtrl;
 use vlinkd::storage::Storage;
 
@@ -53,6 +54,34 @@ struct Args {
     /// http 控制监听地址
     #[arg(short, long)]
     listen_addr: Option<String>,
+    /// 宣告本机后面的子网,逗号分隔,如 192.168.1.0/24
+    /// 需要开启ip 转发,服务器审核后生效
+    #[arg(long, value_delimiter = ',')]
+    advertise_routes: Vec<String>,
+    /// 作为出口节点,宣告默认路由并开启转发和 MASQUERADE,仅支持linux
+    #[arg(long)]
+    exit_node: bool,
+    /// 通过指定节点(公钥或虚拟ip)访问互联网
+    #[arg(long)]
+    use_exit_node: Option<String>,
+    /// 系统路由优先级(metric)
+    #[arg(long)]
+    route_metric: Option<u32>,
+    /// 系统路由使用的路由表id,默认main
+    #[arg(long)]
+    route_table: Option<u32>,
+    /// 在tun 地址上开启dns,解析 <主机名>.<dns_domain>
+    #[arg(long)]
+    magic_dns: bool,
+    /// 内置dns 的域名,默认 vlink
+    #[arg(long)]
+    dns_domain: Option<String>,
+    /// 上游dns,如 223.5.5.5:53,默认取 /etc/resolv.conf
+    #[arg(long)]
+    dns_upstream: Option<String>,
+    /// 打洞使用的stun 服务器,逗号分隔,如 stun.l.google.com:19302
+    #[arg(long, value_delimiter = ',')]
+    stun_servers: Vec<String>,
 }
 
 #[tokio::main]
@@ -60,9 +89,10 @@ pub async fn main() -> anyhow::Result<()> {
     log4rs::init_file("log4rs.yaml", Default::default()).unwrap();
     let mut args = Args::parse();
     //取目录生成秘钥对
-    let state = Storage {
+    let storage = Storage {
         path: args.config_dir.take(),
-    }.load_config().await?;
+    };
+    let state = storage.load_config().await?;
 
     info!("state:{:?}",state);
     let server_addr = args.server.as_str();
@@ -70,20 +100,44 @@ pub async fn main() -> anyhow::Result<()> {
     // 用于客户端去控制网络
     let (ctrl, rx) = NetworkCtrl::new();
     let secret = state.secret.clone();
-    let client = VlinkClient::new(server_addr.to_string(), secret.clone(), ctrl.clone());
+    let client = VlinkClient::new(server_addr.to_string(), secret.clone(), ctrl.clone(),
+                                  args.token.clone(), args.hostname.clone());
     client.spawn().await?;
     //启动http 控制,ctrl
     start_http_server(args.listen_addr.clone(), ctrl.clone()).await?;
 
-    let network = VlinkNetworkManager::new(client, rx, secret.clone());
-    network.start(ArgConfig {
+    let mut advertise_routes = args.advertise_routes;
+    if args.exit_node {
+        advertise_routes.push(DEFAULT_ROUTE_V4.to_string());
+        advertise_routes.push(DEFAULT_ROUTE_V6.to_string());
+    }
+    let network = VlinkNetworkManager::new(client, rx, secret.clone(), storage);
+    let arg_config = ArgConfig {
         endpoint_addr: args.endpoint_addr,
         port: args.port,
-    }).await?;
+        advertise_routes,
+        exit_node: args.exit_node,
+        use_exit_node: args.use_exit_node,
+        route_metric: args.route_metric,
+        route_table: args.route_table,
+        magic_dns: args.magic_dns,
+        dns_domain: args.dns_domain,
+        dns_upstream: args.dns_upstream,
+        stun_servers: args.stun_servers,
+    };
+    let result = tokio::select! {
+        r = network.start(arg_config) => r,
+        _ = tokio::signal::ctrl_c() => {
+            info!("收到退出信号");
+            Ok(())
+        }
+    };
+    // 出错退出时也要删除添加的路由和 nat 规则
+    network.shutdown().await;
     //http ctrl server
 
     error!("客户端关闭");
-    Ok(())
+    result
 }
 
 #[cfg(test)]
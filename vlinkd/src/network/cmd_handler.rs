@@ -1,27 +1,74 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
-use log::warn;
+use log::{info, warn};
 use tokio::sync::RwLock;
 use vlink_core::base64::decode_base64;
 use vlink_core::proto::pb::abi::to_client::ToClientData;
 use vlink_tun::Device;
+use vlink_tun::noise::crypto::PublicKey;
+use crate::handler::common::{acl_rules2config, bc_peer_enter2peer_config, observed_candidate};
+use crate::network::dns::{HostRecord, MagicDns};
+use crate::network::exit_node::ExitNodeClient;
+use crate::network::VlinkNetworkManager;
+use crate::transport::proto::dynamic_ip;
 
-pub async fn handle_to_client_data(data: ToClientData, device: Arc<Device>)->anyhow::Result<()> {
+pub async fn handle_to_client_data(manager: &VlinkNetworkManager, data: ToClientData, device: Arc<Device>,
+                                   exit: Option<&ExitNodeClient>, dns: Option<&MagicDns>) -> anyhow::Result<()> {
     match data {
         ToClientData::PeerEnter(e) => {
-            //标记节点在线
-            let peer = device.get_peer_by_key(&decode_base64(e.pub_key.as_str())?.try_into().unwrap());
+            //标记节点在线, 单个节点的数据错误不影响后续指令
+            let cfg = match bc_peer_enter2peer_config(&e, exit) {
+                Ok(cfg) => cfg,
+                Err(err) => {
+                    warn!("peer {} 配置错误:{}", e.pub_key, err);
+                    return Ok(());
+                }
+            };
+            let peer = device.get_peer_by_key(&cfg.public_key);
+            if let Some(dns) = dns {
+                match HostRecord::from_peer(&e).filter(|_| e.is_online) {
+                    Some(record) => dns.upsert(record).await,
+                    None => dns.remove(e.pub_key.as_str()).await,
+                }
+            }
+            // 出口节点的端点先加入旁路, 再添加默认路由
+            if let (Some(exit), Some(addr)) = (exit, cfg.endpoint) {
+                if exit.matches(&e) && !device.bypass().contains(&addr.ip()) {
+                    let mut bypass = device.bypass();
+                    bypass.insert(addr.ip());
+                    device.set_bypass(bypass);
+                }
+            }
+            // 先记录打洞候选, 新节点的选择器启动后就会打洞
+            manager.set_punch_hint(&cfg.public_key, observed_candidate(&e)).await;
             match peer {
                 None => {
-                    warn!("peer not found");
+                    //新加入的节点
+                    info!("peer added:{}", e.ip);
+                    let key = cfg.public_key;
+                    device.insert_peer(cfg);
+                    manager.start_selector(&device, &key, vec![]).await;
                 }
-                Some(e) => {
-                    e.set_online(true);
+                Some(p) => {
+                    p.set_online(e.is_online);
+                    // 配置了端点且还没有端点时使用
+                    if let Some(addr) = cfg.endpoint.filter(|_| p.endpoint.read().unwrap().is_none()) {
+                        device.update_peer_endpoint(&cfg.public_key, addr);
+                    }
+                    // 子网路由可能有变化
+                    if device.update_peer_allowed_ips(&cfg.public_key, cfg.allowed_ips) {
+                        info!("peer {} routes updated:{:?}", e.ip, e.routes);
+                    }
                 }
             }
         }
         ToClientData::PeerLeave(e) => {
             //标记节点离线
-            let peer = device.get_peer_by_key(&decode_base64(e.pub_key.as_str())?.try_into().unwrap());
+            if let Some(dns) = dns {
+                dns.remove(e.pub_key.as_str()).await;
+            }
+            let peer = decode_key(e.pub_key.as_str())
+                .and_then(|key| device.get_peer_by_key(&key));
             match peer {
                 None => {
                     warn!("peer not found");
@@ -31,7 +78,61 @@ pub async fn handle_to_client_data(data: ToClientData, device: Arc<Device>)->any
                 }
             }
         }
+        ToClientData::PeerRemoved(e) => {
+            //节点被禁用,删除节点和会话
+            let Some(key) = decode_key(e.pub_key.as_str()) else {
+                warn!("pub_key 错误:{}", e.pub_key);
+                return Ok(());
+            };
+            if let Some(dns) = dns {
+                dns.remove(e.pub_key.as_str()).await;
+            }
+            manager.remove_peer_transports(&PublicKey::from(key)).await;
+            match device.remove_peer(&key) {
+                None => {
+                    warn!("peer not found");
+                }
+                Some(p) => {
+                    info!("peer removed:{}", p.ip_addr());
+                }
+            }
+        }
+        ToClientData::UpdateExtraEndpoint(e) => {
+            // 对端公网ip 变化,直接替换udp 端点
+            if e.proto.as_str() == dynamic_ip::PROTO_NAME {
+                let Some(key) = decode_key(e.pub_key.as_str()) else {
+                    warn!("UpdateExtraEndpoint pub_key 错误:{}", e.pub_key);
+                    return Ok(());
+                };
+                let addr: SocketAddr = match e.endpoint.parse() {
+                    Ok(addr) => addr,
+                    Err(_) => {
+                        warn!("peer {} endpoint 格式错误:{}", e.pub_key, e.endpoint);
+                        return Ok(());
+                    }
+                };
+                // 出口节点的新地址要先加入旁路
+                if let (Some(exit), Some(p)) = (exit, device.get_peer_by_key(&key)) {
+                    if exit.peer == e.pub_key || exit.peer == p.ip_addr() {
+                        let mut bypass = device.bypass();
+                        bypass.insert(addr.ip());
+                        device.set_bypass(bypass);
+                    }
+                }
+                if device.update_peer_endpoint(&key, addr) {
+                    info!("peer {} endpoint updated:{}", e.pub_key, addr);
+                }
+            }
+        }
+        ToClientData::AclUpdate(e) => {
+            info!("acl updated, rules:{}", e.rules.len());
+            device.set_acl(acl_rules2config(&e.rules)?);
+        }
         _ => {}
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+fn decode_key(pub_key: &str) -> Option<[u8; 32]> {
+    decode_base64(pub_key).ok()?.try_into().ok()
+}
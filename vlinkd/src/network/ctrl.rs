@@ -1,13 +1,14 @@
 use std::ops::Deref;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::sync::mpsc::Receiver;
 use vlink_core::proto::pb::abi::BcPeerEnter;
 use vlink_core::proto::pb::abi::to_client::ToClientData;
+use crate::network::types::NetworkInfo;
 
 #[derive(Debug)]
 pub enum NetworkCtrlCmd {
     /// 请求查看网络信息
-    ReqInfo,
+    ReqInfo(oneshot::Sender<NetworkInfo>),
     ChangeIp,
     // PeerEnter(BcPeerEnter),
     FirstConnected,
@@ -4,12 +4,14 @@ use log::{info, warn};
 use vlink_core::base64::encode_base64;
 use vlink_core::proto::pb::abi::{DevHandshakeComplete, ExtraEndpoint};
 use vlink_core::proto::pb::abi::to_server::ToServerData;
-use vlink_tun::Device;
+use vlink_tun::{Device, OutboundSender};
+use vlink_tun::device::config::ArgConfig;
 use vlink_tun::device::event::DeviceEvent;
+use crate::handler::connected::handler_connected;
 use crate::network::{ExtraProto, ExtraProtoStatus, VlinkNetworkManager, VlinkNetworkManagerInner};
 
 /// 通过设备产生的事件，去处理网络
-pub async fn handle_device_event(net: VlinkNetworkManager, dev: Arc<Device>, event: DeviceEvent) -> anyhow::Result<()> {
+pub async fn handle_device_event(net: VlinkNetworkManager, dev: Arc<Device>, args: &ArgConfig, event: DeviceEvent) -> anyhow::Result<()> {
     let cc = net.client.clone();
     let peers =dev.peers.clone();
     //处理设备事件
@@ -46,12 +48,27 @@ pub async fn handle_device_event(net: VlinkNetworkManager, dev: Arc<Device>, eve
         }
         DeviceEvent::TransportFailed(data) => {
             warn!("transport failed:{:?}", data);
+            // 对端连进来的连接断开, 清除仍在使用该连接的端点, 避免一直发往已关闭的连接
+            for peer in peers.read().unwrap().all() {
+                let mut endpoint = peer.endpoint.write().unwrap();
+                let current = endpoint.as_ref()
+                    .map(|e| e.protocol() == data.proto && e.dst().to_string() == data.endpoint)
+                    .unwrap_or(false);
+                if current {
+                    *endpoint = None;
+                }
+            }
         }
         DeviceEvent::PeerEndpointFailed(p) => {
             if let Some(peer) = peers.read().unwrap().get_by_key(p.as_bytes()) {
                 peer.clear_endpoint();
             }
         }
+        DeviceEvent::ListenPortChanged(port) => {
+            // 如 wg set listen-port, 重新进入网络让其他节点使用新端口
+            info!("监听端口改为 {}, 重新上报", port);
+            handler_connected(cc, dev, args, net.extra_status.clone()).await?;
+        }
         //协议失败
         _ => {}
     }
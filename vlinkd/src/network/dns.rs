@@ -0,0 +1,327 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+use vlink_core::proto::pb::abi::BcPeerEnter;
+use vlink_core::rw_map::RwMap;
+
+pub const DEFAULT_DNS_DOMAIN: &str = "vlink";
+pub const DEFAULT_UPSTREAM: &str = "8.8.8.8:53";
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_SERVFAIL: u8 = 2;
+const TTL: u32 = 60;
+
+/// 主机名 -> 虚拟ip
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostRecord {
+    pub pub_key: String,
+    pub hostname: String,
+    pub ip: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+impl HostRecord {
+    /// 没有主机名的节点不解析
+    pub fn from_peer(p: &BcPeerEnter) -> Option<Self> {
+        let hostname = p.hostname.clone().filter(|h| !h.is_empty())?;
+        Some(Self {
+            pub_key: p.pub_key.clone(),
+            hostname: hostname.to_ascii_lowercase(),
+            ip: p.ip.parse().ok(),
+            ipv6: p.ipv6.as_ref().and_then(|ip| ip.parse().ok()),
+        })
+    }
+}
+
+/// 内置dns, 监听在tun 地址上
+/// 回答 <hostname>.<domain>, 其他域名转发到上游
+#[derive(Clone)]
+pub struct MagicDns {
+    inner: Arc<MagicDnsInner>,
+}
+
+pub struct MagicDnsInner {
+    domain: String,
+    upstream: SocketAddr,
+    /// pub_key -> 记录,节点离开时按公钥删除
+    hosts: RwMap<String, HostRecord>,
+}
+
+impl Deref for MagicDns {
+    type Target = MagicDnsInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl MagicDns {
+    pub fn new(domain: &str, upstream: SocketAddr) -> Self {
+        Self {
+            inner: Arc::new(MagicDnsInner {
+                domain: domain.trim_matches('.').to_ascii_lowercase(),
+                upstream,
+                hosts: RwMap::new(),
+            }),
+        }
+    }
+
+    pub async fn reset(&self, hosts: Vec<HostRecord>) {
+        *self.hosts.write_lock().await = hosts.into_iter()
+            .map(|h| (h.pub_key.clone(), h))
+            .collect();
+    }
+
+    pub async fn upsert(&self, record: HostRecord) {
+        debug!("dns 记录:{}.{} -> {:?}", record.hostname, self.domain, record.ip);
+        self.hosts.insert(record.pub_key.clone(), record).await;
+    }
+
+    pub async fn remove(&self, pub_key: &str) {
+        self.hosts.remove(&pub_key.to_string()).await;
+    }
+
+    /// 绑定端口后在后台处理请求
+    pub async fn start(&self, listen: SocketAddr) -> anyhow::Result<()> {
+        let socket = Arc::new(UdpSocket::bind(listen).await?);
+        info!("dns 监听:{} 域名:{} 上游:{}", listen, self.domain, self.upstream);
+        let dns = self.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1500];
+            loop {
+                let (n, from) = match socket.recv_from(&mut buf).await {
+                    Ok(r) => r,
+                    Err(e) => {
+                        warn!("dns 接收失败:{}", e);
+                        continue;
+                    }
+                };
+                let query = buf[..n].to_vec();
+                let dns = dns.clone();
+                let socket = socket.clone();
+                // 转发上游可能较慢,每个请求单独处理
+                tokio::spawn(async move {
+                    if let Some(resp) = dns.handle(&query).await {
+                        if let Err(e) = socket.send_to(&resp, from).await {
+                            debug!("dns 响应失败 {}:{}", from, e);
+                        }
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    async fn handle(&self, query: &[u8]) -> Option<Vec<u8>> {
+        let question = Question::parse(query)?;
+        let Some(label) = self.local_label(question.name.as_str()) else {
+            return match forward(self.upstream, query).await {
+                Ok(resp) => Some(resp),
+                Err(e) => {
+                    debug!("dns 转发失败 {}:{}", question.name, e);
+                    Some(build_response(query, &question, &[], RCODE_SERVFAIL))
+                }
+            };
+        };
+        // 域名本身没有记录
+        if label.is_empty() {
+            return Some(build_response(query, &question, &[], 0));
+        }
+        let record = self.hosts.read_lock().await
+            .values()
+            .find(|h| h.hostname == label)
+            .cloned();
+        let Some(record) = record else {
+            return Some(build_response(query, &question, &[], RCODE_NXDOMAIN));
+        };
+        let answers: Vec<IpAddr> = match (question.qtype, question.qclass) {
+            (TYPE_A, CLASS_IN) => record.ip.map(IpAddr::V4).into_iter().collect(),
+            (TYPE_AAAA, CLASS_IN) => record.ipv6.map(IpAddr::V6).into_iter().collect(),
+            _ => vec![],
+        };
+        Some(build_response(query, &question, &answers, 0))
+    }
+
+    /// 属于虚拟网络域名时返回主机名部分
+    fn local_label(&self, name: &str) -> Option<String> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        if name == self.domain {
+            return Some(String::new());
+        }
+        name.strip_suffix(self.domain.as_str())
+            .and_then(|s| s.strip_suffix('.'))
+            .map(|s| s.to_string())
+    }
+}
+
+/// 系统当前的dns, 排除自身地址避免循环
+/// 127.0.0.53 等本机解析服务可以作为上游
+pub fn system_upstream(exclude: IpAddr) -> Option<SocketAddr> {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("nameserver"))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .find(|ip| *ip != exclude)
+        .map(|ip| SocketAddr::new(ip, 53))
+}
+
+/// socket 连接到上游, 只接收上游的响应, 并且事务id 要与查询一致
+async fn forward(upstream: SocketAddr, query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let bind: SocketAddr = if upstream.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse()?;
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(upstream).await?;
+    socket.send(query).await?;
+    let mut buf = vec![0u8; 4096];
+    timeout(Duration::from_secs(3), async {
+        loop {
+            let n = socket.recv(&mut buf).await?;
+            if n >= 12 && buf[..2] == query[..2] {
+                buf.truncate(n);
+                return anyhow::Ok(buf);
+            }
+            debug!("丢弃事务id 不匹配的dns 响应");
+        }
+    }).await?
+}
+
+#[derive(Debug, PartialEq)]
+struct Question {
+    name: String,
+    qtype: u16,
+    qclass: u16,
+    /// 问题部分结束位置
+    end: usize,
+}
+
+impl Question {
+    /// 只处理单个问题的标准查询
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 12 || buf[2] & 0x80 != 0 || u16::from_be_bytes([buf[4], buf[5]]) != 1 {
+            return None;
+        }
+        let mut labels = vec![];
+        let mut pos = 12;
+        loop {
+            let len = *buf.get(pos)? as usize;
+            pos += 1;
+            if len == 0 {
+                break;
+            }
+            // 查询中不应出现压缩指针
+            if len > 63 {
+                return None;
+            }
+            labels.push(String::from_utf8_lossy(buf.get(pos..pos + len)?).to_string());
+            pos += len;
+        }
+        let tail = buf.get(pos..pos + 4)?;
+        Some(Self {
+            name: labels.join("."),
+            qtype: u16::from_be_bytes([tail[0], tail[1]]),
+            qclass: u16::from_be_bytes([tail[2], tail[3]]),
+            end: pos + 4,
+        })
+    }
+}
+
+fn build_response(query: &[u8], question: &Question, answers: &[IpAddr], rcode: u8) -> Vec<u8> {
+    let mut resp = Vec::with_capacity(question.end + answers.len() * 28);
+    resp.extend_from_slice(&query[..2]);
+    // QR, AA, 保留 opcode 和 RD, RA
+    resp.push(0x84 | (query[2] & 0x79));
+    resp.push(0x80 | (rcode & 0x0F));
+    resp.extend_from_slice(&1u16.to_be_bytes());
+    resp.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    resp.extend_from_slice(&[0, 0, 0, 0]);
+    resp.extend_from_slice(&query[12..question.end]);
+    for ip in answers {
+        // 指向问题中的域名
+        resp.extend_from_slice(&[0xC0, 0x0C]);
+        let (rtype, data) = match ip {
+            IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+        };
+        resp.extend_from_slice(&rtype.to_be_bytes());
+        resp.extend_from_slice(&CLASS_IN.to_be_bytes());
+        resp.extend_from_slice(&TTL.to_be_bytes());
+        resp.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        resp.extend_from_slice(&data);
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut buf = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+        buf.push(0);
+        buf.extend_from_slice(&qtype.to_be_bytes());
+        buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_magic_dns() {
+        let dns = MagicDns::new("vlink", DEFAULT_UPSTREAM.parse().unwrap());
+        dns.upsert(HostRecord {
+            pub_key: "a".to_string(),
+            hostname: "db1".to_string(),
+            ip: Some(Ipv4Addr::new(10, 0, 0, 2)),
+            ipv6: None,
+        }).await;
+
+        let q = query("DB1.vlink", TYPE_A);
+        let resp = dns.handle(&q).await.unwrap();
+        assert_eq!(&resp[..2], &[0x12, 0x34]);
+        assert_eq!(resp[3] & 0x0F, 0);
+        assert_eq!(u16::from_be_bytes([resp[6], resp[7]]), 1);
+        assert_eq!(&resp[resp.len() - 4..], &[10, 0, 0, 2]);
+
+        // 有主机名没有ipv6
+        let resp = dns.handle(&query("db1.vlink", TYPE_AAAA)).await.unwrap();
+        assert_eq!(resp[3] & 0x0F, 0);
+        assert_eq!(u16::from_be_bytes([resp[6], resp[7]]), 0);
+
+        let resp = dns.handle(&query("web.vlink", TYPE_A)).await.unwrap();
+        assert_eq!(resp[3] & 0x0F, RCODE_NXDOMAIN);
+
+        dns.remove("a").await;
+        let resp = dns.handle(&q).await.unwrap();
+        assert_eq!(resp[3] & 0x0F, RCODE_NXDOMAIN);
+    }
+
+    #[tokio::test]
+    async fn test_forward_txid() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (n, from) = upstream.recv_from(&mut buf).await.unwrap();
+            // 先回一个事务id 错误的响应
+            let mut wrong = buf[..n].to_vec();
+            wrong[0] ^= 0xFF;
+            upstream.send_to(&wrong, from).await.unwrap();
+            let mut resp = buf[..n].to_vec();
+            resp[2] |= 0x80;
+            upstream.send_to(&resp, from).await.unwrap();
+        });
+        let q = query("example.com", TYPE_A);
+        let resp = forward(addr, &q).await.unwrap();
+        assert_eq!(&resp[..2], &q[..2]);
+        assert_eq!(resp[2] & 0x80, 0x80);
+    }
+}
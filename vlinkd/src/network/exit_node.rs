@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::process::Command;
+use anyhow::anyhow;
+use log::warn;
+use tokio::net::lookup_host;
+use vlink_core::proto::pb::abi::{BcPeerEnter, RelayServer};
+use vlink_tun::Device;
+
+pub const DEFAULT_ROUTE_V4: &str = "0.0.0.0/0";
+pub const DEFAULT_ROUTE_V6: &str = "::/0";
+
+pub fn is_default_route(route: &str) -> bool {
+    route == DEFAULT_ROUTE_V4 || route == DEFAULT_ROUTE_V6
+}
+
+/// 通过出口节点上网的客户端
+/// 默认路由只接受选定节点的,其他节点宣告的默认路由忽略
+#[derive(Clone, Debug)]
+pub struct ExitNodeClient {
+    /// 出口节点的公钥或虚拟ip
+    pub peer: String,
+    server_addr: String,
+}
+
+impl ExitNodeClient {
+    pub fn new(peer: String, server_addr: String) -> Self {
+        Self { peer, server_addr }
+    }
+
+    pub fn matches(&self, p: &BcPeerEnter) -> bool {
+        self.peer == p.pub_key || self.peer == p.ip
+    }
+
+    /// 服务器,中继和对端的传输层地址不能走隧道
+    pub async fn bypass(&self, endpoints: impl IntoIterator<Item=IpAddr>, relays: &[RelayServer]) -> HashSet<IpAddr> {
+        let mut ips = HashSet::new();
+        let mut hosts = vec![self.server_addr.clone()];
+        hosts.extend(relays.iter().filter_map(|r| url_host_port(r.url.as_str())));
+        for host in hosts {
+            match lookup_host(host.as_str()).await {
+                Ok(addrs) => ips.extend(addrs.map(|a| a.ip())),
+                Err(e) => warn!("解析{}失败:{}", host, e),
+            }
+        }
+        ips.extend(endpoints);
+        ips.retain(|ip| !ip.is_unspecified() && !ip.is_loopback());
+        ips
+    }
+
+    /// 按当前对端端点刷新旁路
+    pub async fn refresh(&self, device: &Device, relays: &[RelayServer]) {
+        let endpoints: Vec<IpAddr> = device.peers.read().unwrap().all()
+            .iter()
+            .filter_map(|p| p.endpoint.read().unwrap().as_ref().map(|e| e.dst().ip()))
+            .collect();
+        let ips = self.bypass(endpoints, relays).await;
+        device.set_bypass(ips);
+    }
+}
+
+fn url_host_port(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("https", url));
+    let host = rest.split('/').next()?;
+    if host.is_empty() {
+        return None;
+    }
+    if host.rsplit_once(':').map(|(_, p)| p.parse::<u16>().is_ok()).unwrap_or(false) {
+        return Some(host.to_string());
+    }
+    let port = if scheme == "http" || scheme == "ws" { 80 } else { 443 };
+    Some(format!("{}:{}", host, port))
+}
+
+/// 作为出口节点, 开启转发并对虚拟网段做源地址转换, drop 时恢复
+pub struct ExitNodeNat {
+    rules: Vec<Vec<String>>,
+    forward: Vec<(String, String)>,
+}
+
+impl ExitNodeNat {
+    #[cfg(target_os = "linux")]
+    pub fn enable(tun_name: &str, networks: Vec<String>) -> anyhow::Result<Self> {
+        let mut nat = Self { rules: vec![], forward: vec![] };
+        for key in ["net.ipv4.ip_forward", "net.ipv6.conf.all.forwarding"] {
+            let old = run("sysctl", &["-n", key])?;
+            run("sysctl", &["-w", &format!("{}=1", key)])?;
+            nat.forward.push((key.to_string(), old.trim().to_string()));
+        }
+        for network in networks {
+            let cmd = if network.contains(':') { "ip6tables" } else { "iptables" };
+            let rule: Vec<String> = ["-t", "nat", "POSTROUTING", "-s", network.as_str(), "!", "-o", tun_name, "-j", "MASQUERADE"]
+                .iter().map(|s| s.to_string()).collect();
+            let mut args = vec![cmd.to_string()];
+            args.extend(rule);
+            // 上次异常退出残留的规则直接沿用, 避免重复添加, 退出时一并删除
+            if nat.exec_rule(&args, "-C").is_ok() {
+                log::info!("出口节点 {} 的 MASQUERADE 已存在", network);
+            } else {
+                nat.exec_rule(&args, "-A")?;
+                log::info!("出口节点 {} 开启 MASQUERADE", network);
+            }
+            nat.rules.push(args);
+        }
+        Ok(nat)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn enable(_tun_name: &str, _networks: Vec<String>) -> anyhow::Result<Self> {
+        Err(anyhow!("出口节点只支持linux"))
+    }
+
+    /// args: [cmd, -t, nat, CHAIN, ...]
+    fn exec_rule(&self, args: &[String], op: &str) -> anyhow::Result<()> {
+        let mut cmd_args: Vec<&str> = args[1..].iter().map(|s| s.as_str()).collect();
+        cmd_args.insert(2, op);
+        run(args[0].as_str(), &cmd_args).map(|_| ())
+    }
+}
+
+impl Drop for ExitNodeNat {
+    fn drop(&mut self) {
+        for rule in std::mem::take(&mut self.rules) {
+            if let Err(e) = self.exec_rule(&rule, "-D") {
+                warn!("删除 MASQUERADE 失败:{}", e);
+            }
+        }
+        for (key, old) in self.forward.drain(..) {
+            if let Err(e) = run("sysctl", &["-w", &format!("{}={}", key, old)]) {
+                warn!("恢复{}失败:{}", key, e);
+            }
+        }
+    }
+}
+
+fn run(cmd: &str, args: &[&str]) -> anyhow::Result<String> {
+    let out = Command::new(cmd).args(args).output()?;
+    if !out.status.success() {
+        return Err(anyhow!("{} {}: {}", cmd, args.join(" "), String::from_utf8_lossy(&out.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_host_port() {
+        assert_eq!(url_host_port("https://derp.example.com/derp"), Some("derp.example.com:443".to_string()));
+        assert_eq!(url_host_port("http://10.0.0.1:8080"), Some("10.0.0.1:8080".to_string()));
+        assert_eq!(url_host_port("relay.example.com"), Some("relay.example.com:443".to_string()));
+    }
+}
@@ -7,9 +7,10 @@ use vlink_tun::device::event::DevicePublisher;
 use vlink_tun::InboundResult;
 use crate::client::VlinkClient;
 use crate::network::ExtraProto;
-use crate::transport::proto::dynamic_ip::DipParam;
+use crate::transport::proto::dynamic_ip::{DipParam, DynamicIpTransport};
 use crate::transport::proto::nat_tcp::{NatTcpTransport, NatTcpTransportParam};
 use crate::transport::proto::nat_udp::{NatUdpTransport, NatUdpTransportParam};
+use crate::transport::proto::websocket::{WebsocketTransport, WebsocketTransportParam};
 
 pub(crate) async fn start_extra_transport(cc: Arc<VlinkClient>,
                                sender: Sender<InboundResult>,
@@ -27,9 +28,16 @@ pub(crate) async fn start_extra_transport(cc: Arc<VlinkClient>,
             let mut ts = NatTcpTransport::new(cc, sender, param, event_pub).await?;
             ts.start().await?;
         }
+        ExtraProto::Ws => {
+            let param: WebsocketTransportParam = serde_json::from_str(&cfg.params)?;
+            let _ts = WebsocketTransport::spawn(sender, param, event_pub).await?;
+            // 服务随 _ts drop 停止
+            std::future::pending::<()>().await;
+        }
         ExtraProto::Dip => {
             let param: DipParam = serde_json::from_str(&cfg.params)?;
-
+            let mut ts = DynamicIpTransport::new(cc, param, event_pub);
+            ts.start().await?;
         }
 
         _ => {}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -12,7 +13,8 @@ use tokio::sync::{Mutex, RwLock};
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::time::timeout;
 
-use vlink_core::base64::decode_base64;
+use vlink_core::base64::{decode_base64, encode_base64};
+use vlink_core::proto::pb::abi::PeerExtraTransport;
 use vlink_core::rw_map::RwMap;
 use vlink_core::secret::VlinkStaticSecret;
 use vlink_tun::{InboundResult, Tun};
@@ -20,6 +22,7 @@ use vlink_tun::device::config::{ArgConfig, TransportConfig};
 use vlink_tun::device::Device;
 use vlink_tun::device::event::DevicePublisher;
 use vlink_tun::noise::crypto::PublicKey;
+use vlink_tun::router::RouteConfig;
 
 use crate::client::VlinkClient;
 use crate::config::VlinkNetworkConfig;
@@ -27,15 +30,22 @@ use crate::handler;
 use crate::handler::first_connected::request_for_config;
 use crate::network::cmd_handler::handle_to_client_data;
 use crate::network::ctrl::NetworkCtrlCmd;
+use crate::network::dns::{MagicDns, system_upstream, DEFAULT_DNS_DOMAIN, DEFAULT_UPSTREAM};
+use crate::network::exit_node::{ExitNodeClient, ExitNodeNat};
 use crate::network::extra_transport::start_extra_transport;
+use crate::network::types::{AclRuleInfo, ExtraProtoInfo, NetworkInfo, PeerInfo};
+use crate::storage::Storage;
 use crate::transport::ext_transport_selector::ExtTransportSelector;
-use crate::transport::proto::relay_transport::RelayTransport;
+use crate::transport::proto::relay_transport::{RelayTransport, RELAY_PROTOCOL};
+use crate::transport::punch::PunchTransport;
 
 pub mod ctrl;
 pub mod types;
 mod device_handler;
 mod cmd_handler;
 pub mod extra_transport;
+pub mod exit_node;
+pub mod dns;
 
 pub enum NetworkStatus {
     Running,
@@ -53,6 +63,7 @@ pub enum ExtraProto {
     NatUdp,
     NatTcp,
     Dip,
+    Ws,
 }
 
 #[derive(Clone)]
@@ -74,13 +85,19 @@ pub struct VlinkNetworkManagerInner {
     extra_status: RwMap<ExtraProto, ExtraProtoStatus>,
     /// 中继传输层
     relay_transport: RwLock<Option<Arc<RelayTransport>>>,
+    /// udp 打洞
+    punch_transport: RwLock<Option<PunchTransport>>,
+    /// 本地存储,缓存网络配置
+    storage: Storage,
+    /// 作为出口节点时的转发规则
+    exit_nat: Mutex<Option<ExitNodeNat>>,
     // status: RwLock<NetworkStatus>,
 }
 
 /// change_ip
 /// add_peer
 impl VlinkNetworkManager {
-    pub fn new(client: VlinkClient, rx: Receiver<NetworkCtrlCmd>, secret: VlinkStaticSecret) -> Self {
+    pub fn new(client: VlinkClient, rx: Receiver<NetworkCtrlCmd>, secret: VlinkStaticSecret, storage: Storage) -> Self {
         Self {
             inner: Arc::new(VlinkNetworkManagerInner {
                 client: Arc::new(client),
@@ -90,6 +107,9 @@ impl VlinkNetworkManager {
                 extra_selector: Default::default(),
                 extra_status: RwMap::new(),
                 relay_transport: Default::default(),
+                punch_transport: Default::default(),
+                storage,
+                exit_nat: Default::default(),
             }),
         }
     }
@@ -106,7 +126,7 @@ impl VlinkNetworkManager {
         let rxc = self.rx.clone();
         let client_c = self.client.clone();
         let secret_c = self.secret.clone();
-        let config = match timeout(Duration::from_secs(2), async move {
+        let (config, mut from_cache) = match timeout(Duration::from_secs(2), async move {
             loop {
                 if let Some(NetworkCtrlCmd::FirstConnected) = rxc.lock().await.recv().await {
                     info!("首次连接");
@@ -116,18 +136,81 @@ impl VlinkNetworkManager {
         }).await {
             Ok(_) => {
                 //向服务器请求配置并保存
-                Some(request_for_config(client_c, *secret_c.private_key.as_bytes(), &args).await?)
+                let cfg = request_for_config(client_c, *secret_c.private_key.as_bytes(), &args).await?;
+                if let Err(e) = self.storage.save_network_config(&cfg).await {
+                    error!("缓存网络配置失败:{:?}", e);
+                }
+                (cfg, false)
             }
             Err(_) => {
                 //文件中读取上一次缓存,
-                todo!("服务器连接失败,尝试重缓存文件读取配置");
+                warn!("服务器连接失败,尝试从缓存文件读取配置");
+                let mut cfg = self.storage.load_network_config().await?
+                    .ok_or(anyhow!("服务器连接失败,且无本地缓存配置"))?;
+                cfg.device_config.private_key = *secret_c.private_key.as_bytes();
+                cfg.device_config.route = RouteConfig {
+                    metric: args.route_metric,
+                    table: args.route_table,
+                };
+                cfg.arg_config = args.clone();
+                (cfg, true)
             }
         };
-        let device = if let Some(cfg) = config {
-            self.start_device(cfg).await?
+        let network = config.device_config.network.to_string();
+        let network6 = config.device_config.network6.map(|n| n.to_string());
+        let relay_servers = config.relay_servers.clone();
+        let hosts = config.hosts.clone();
+        let device = self.start_device(config).await?;
+        let dns = if args.magic_dns {
+            let upstream = match args.dns_upstream.as_ref() {
+                Some(s) => s.parse()?,
+                None => match system_upstream(IpAddr::V4(device.tun_addr)) {
+                    Some(upstream) => upstream,
+                    None => {
+                        warn!("未找到系统 dns, 上游使用 {}, 可通过 dns_upstream 指定", DEFAULT_UPSTREAM);
+                        DEFAULT_UPSTREAM.parse()?
+                    }
+                },
+            };
+            let dns = MagicDns::new(args.dns_domain.as_deref().unwrap_or(DEFAULT_DNS_DOMAIN), upstream);
+            dns.reset(hosts).await;
+            match dns.start(SocketAddr::new(IpAddr::V4(device.tun_addr), 53)).await {
+                Ok(_) => Some(dns),
+                Err(e) => {
+                    error!("dns 启动失败:{}", e);
+                    None
+                }
+            }
         } else {
-            todo!("配置文件读取失败,退出");
+            None
         };
+        if args.exit_node {
+            let mut networks = vec![network.clone()];
+            networks.extend(network6.clone());
+            match ExitNodeNat::enable(device.tun.name(), networks) {
+                Ok(nat) => {
+                    self.exit_nat.lock().await.replace(nat);
+                }
+                Err(e) => error!("开启出口节点失败:{}", e),
+            }
+        }
+        let exit = args.use_exit_node.clone()
+            .map(|peer| ExitNodeClient::new(peer, self.client.server_addr().to_string()));
+        if let Some(exit) = exit.clone() {
+            // 端点会随握手变化,定时刷新旁路
+            // 只持有弱引用, 不阻止设备释放时清理路由
+            let dev = Arc::downgrade(&device);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(30));
+                loop {
+                    interval.tick().await;
+                    let Some(dev) = dev.upgrade() else {
+                        break;
+                    };
+                    exit.refresh(&dev, &relay_servers).await;
+                }
+            });
+        }
         /*
          //todo 检查配置网段冲突
         */
@@ -138,11 +221,17 @@ impl VlinkNetworkManager {
         while let Some(cmd) = self.rx.lock().await.recv().await {
             info!("接受指令:{:?}",cmd);
             match cmd {
+                NetworkCtrlCmd::ReqInfo(tx) => {
+                    let info = self.network_info(&device_c, network.clone(), network6.clone()).await;
+                    let _ = tx.send(info);
+                }
                 NetworkCtrlCmd::ChangeIp => {
                     // device.change_ip();
                 }
                 NetworkCtrlCmd::ToClientData(data) => {
-                    handle_to_client_data(data, device_c.clone()).await?;
+                    if let Err(e) = handle_to_client_data(self, data, device_c.clone(), exit.as_ref(), dns.as_ref()).await {
+                        error!("处理服务器消息失败:{:?}", e);
+                    }
                 }
                 NetworkCtrlCmd::Connected => {
                     let esc = self.extra_status.clone();
@@ -151,9 +240,24 @@ impl VlinkNetworkManager {
                 }
                 //NetworkCtrlCmd::peer
                 NetworkCtrlCmd::FirstConnected => {
-                    // 请求配置并保存
-                    // request_for_config(&self.client).await?;
-                    //new_device()
+                    // 使用缓存启动的,连上服务器后请求配置并保存
+                    // 失败时继续使用缓存配置运行, 下次连上再试
+                    if from_cache {
+                        let cfg = match request_for_config(client_c.clone(), *self.secret.private_key.as_bytes(), &args).await {
+                            Ok(cfg) => cfg,
+                            Err(e) => {
+                                error!("请求网络配置失败:{:?}", e);
+                                continue;
+                            }
+                        };
+                        if let Err(e) = self.storage.save_network_config(&cfg).await {
+                            error!("缓存网络配置失败:{:?}", e);
+                        }
+                        match self.reconcile_config(device_c.clone(), cfg, dns.as_ref()).await {
+                            Ok(_) => from_cache = false,
+                            Err(e) => error!("校正缓存配置失败:{:?}", e),
+                        }
+                    }
                 }
                 _ => {}
             }
@@ -162,6 +266,161 @@ impl VlinkNetworkManager {
         Ok(())
     }
 
+    /// 退出前删除添加的路由,旁路和转发规则
+    pub async fn shutdown(&self) {
+        if let Some(device) = self.device.read().await.clone() {
+            device.clear_routes();
+        }
+        self.exit_nat.lock().await.take();
+    }
+
+    /// 当前网络信息
+    async fn network_info(&self, device: &Device, network: String, network6: Option<String>) -> NetworkInfo {
+        let peers = device.peers.read().unwrap().all()
+            .iter()
+            .map(|p| PeerInfo::from(p.as_ref()))
+            .collect();
+        let extra_protos = self.extra_status.read_lock().await
+            .iter()
+            .map(|(proto, s)| ExtraProtoInfo {
+                proto: proto.as_ref().to_string(),
+                endpoint: s.endpoint.clone(),
+                running: s.running,
+                error: s.error.clone(),
+            })
+            .collect();
+        let handshake = device.handshake_metrics();
+        NetworkInfo {
+            pub_key: self.secret.base64_pub(),
+            address: device.tun_addr.to_string(),
+            address6: device.tun_addr6.map(|a| a.to_string()),
+            network,
+            network6,
+            port: device.listen_port(),
+            peers,
+            extra_protos,
+            cookie_replies: handshake.cookie_replies,
+            throttled_handshakes: handshake.throttled,
+            acl_rules: device.acl_metrics()
+                .into_iter()
+                .map(|m| AclRuleInfo { id: m.id, hits: m.hits })
+                .collect(),
+        }
+    }
+
+    /// 以服务器最新配置校正缓存启动的设备
+    /// 已存在的peer 更新在线状态,路由,端点和保活,新的peer 插入并启动扩展协议选择,配置中不存在的peer 删除
+    async fn reconcile_config(&self, device: Arc<Device>, config: VlinkNetworkConfig, dns: Option<&MagicDns>) -> anyhow::Result<()> {
+        let dc = &config.device_config;
+        if dc.address != device.tun_addr || dc.address6 != device.tun_addr6 || dc.port != device.port {
+            warn!("网络地址或端口变更,需要重启后生效");
+        }
+        let relay = self.relay_transport.read().await.clone()
+            .ok_or(anyhow!("中继传输层未启动"))?;
+        relay.set_servers(config.relay_servers.clone()).await;
+        if !dc.bypass.is_empty() {
+            let mut bypass = device.bypass();
+            bypass.extend(dc.bypass.iter().cloned());
+            device.set_bypass(bypass);
+        }
+        if dc.acl != device.acl_config() {
+            device.set_acl(dc.acl.clone());
+        }
+        if let Some(dns) = dns {
+            dns.reset(config.hosts.clone()).await;
+        }
+
+        let mut extra = HashMap::new();
+        for i in config.peer_extra_transports.into_iter() {
+            let key = decode_base64(i.target_pub_key.as_str())?;
+            let pub_key: [u8; 32] = key.try_into().map_err(|_| anyhow::anyhow!("error"))?;
+            extra.entry(PublicKey::from(pub_key))
+                .or_insert(vec![])
+                .push(i);
+        }
+
+        let keys: Vec<[u8; 32]> = dc.peers.keys().cloned().collect();
+        for key in keys.iter() {
+            let addr = config.observed_candidates.get(&encode_base64(key)).copied();
+            self.set_punch_hint(key, addr).await;
+        }
+        for (key, cfg) in config.device_config.peers.into_iter() {
+            match device.get_peer_by_key(&key) {
+                Some(p) => {
+                    p.set_online(cfg.is_online);
+                    device.update_peer_allowed_ips(&key, cfg.allowed_ips);
+                    if p.keepalive_interval() != cfg.persistent_keepalive {
+                        device.update_peer_keepalive(&key, cfg.persistent_keepalive);
+                    }
+                    // 中继中的节点交给路径选择升级, 不直接覆盖
+                    if let Some(addr) = cfg.endpoint {
+                        let current = p.endpoint.read().unwrap().as_ref()
+                            .map(|e| (e.protocol(), e.dst()));
+                        let replace = match current {
+                            None => true,
+                            Some((proto, _)) if proto == RELAY_PROTOCOL => false,
+                            Some((_, dst)) => dst != addr,
+                        };
+                        if replace {
+                            device.update_peer_endpoint(&key, addr);
+                        }
+                    }
+                }
+                None => {
+                    device.insert_peer(cfg);
+                    let ps = extra.remove(&PublicKey::from(key)).unwrap_or_default();
+                    self.start_selector(&device, &key, ps).await;
+                }
+            }
+        }
+        // 服务器上已不存在的节点
+        let gone: Vec<PublicKey> = device.peers.read().unwrap().all()
+            .iter()
+            .map(|p| p.pub_key)
+            .filter(|k| !keys.contains(k.as_bytes()))
+            .collect();
+        for key in gone {
+            self.remove_peer_transports(&key).await;
+            if let Some(p) = device.remove_peer(key.as_bytes()) {
+                info!("peer removed:{}", p.ip_addr());
+            }
+        }
+        info!("缓存配置校正完成");
+        Ok(())
+    }
+
+    /// 新加入的节点启动扩展协议选择, 没有直连时中继并打洞
+    async fn start_selector(&self, device: &Device, key: &[u8; 32], transports: Vec<PeerExtraTransport>) {
+        let relay = self.relay_transport.read().await.clone();
+        let punch = self.punch_transport.read().await.clone();
+        let (Some(relay), Some(punch)) = (relay, punch) else {
+            warn!("传输层未启动");
+            return;
+        };
+        let Some(p) = device.get_peer_by_key(key) else {
+            return;
+        };
+        self.extra_selector.write_lock().await
+            .entry(p.pub_key)
+            .or_insert_with(|| ExtTransportSelector::new(p, device.inbound_tx(), transports, relay, punch));
+    }
+
+    /// 删除节点的扩展协议选择和中继状态
+    async fn remove_peer_transports(&self, key: &PublicKey) {
+        self.extra_selector.write_lock().await.remove(key);
+        if let Some(relay) = self.relay_transport.read().await.as_ref() {
+            relay.remove_target(key.as_bytes()).await;
+        }
+        self.set_punch_hint(key.as_bytes(), None).await;
+    }
+
+    /// 服务器看到的节点地址, 交给打洞作为候选
+    async fn set_punch_hint(&self, key: &[u8; 32], addr: Option<SocketAddr>) {
+        if let Some(punch) = self.punch_transport.read().await.as_ref() {
+            punch.set_hint(*key, addr).await;
+        }
+    }
+
     // async fn get_device(&self) -> anyhow::Result<dyn AsRef<Device>> {
     //     self.device.read().await.ok_or(anyhow::anyhow!("device is none"))
     // }
@@ -169,6 +428,10 @@ impl VlinkNetworkManager {
         //启动peer 协议协商
         let trans_cfg = config.transports.clone();
         let extra_transports = config.peer_extra_transports.clone();
+        let relay_servers = config.relay_servers.clone();
+        let stun_servers = config.arg_config.stun_servers.clone();
+        let args = config.arg_config.clone();
+        let observed_candidates = config.observed_candidates.clone();
         let device = Arc::new(Device::new(config.tun_name, config.device_config).await?);
 
         let peers = device.peers.clone();
@@ -177,6 +440,16 @@ impl VlinkNetworkManager {
         let mut event_rx = device.event_bus.subscribe();
 
         self.device.write().await.replace(device.clone());
+        // wg 命令可以通过 uapi 查看和修改设备
+        #[cfg(unix)]
+        {
+            let ctrl = device.control();
+            tokio::spawn(async move {
+                if let Err(e) = vlink_tun::uapi::bind_and_handle(ctrl).await {
+                    warn!("uapi 启动失败:{}", e);
+                }
+            });
+        }
         //监听设备事件
 
         // 连接扩展端点
@@ -200,9 +473,18 @@ impl VlinkNetworkManager {
         let relay = RelayTransport::spawn(self.client.clone(),
                                           inbound_tx.clone(),
                                           peers.clone(),
-                                          event_bus.clone());
+                                          event_bus.clone(),
+                                          relay_servers);
         let relay = Arc::new(relay);
         self.relay_transport.write().await.replace(relay.clone());
+        // 打洞成功后替换中继
+        let punch = PunchTransport::spawn(self.client.clone(), device.clone(), stun_servers);
+        for (k, addr) in observed_candidates {
+            if let Some(key) = decode_base64(k.as_str()).ok().and_then(|k| <[u8; 32]>::try_from(k).ok()) {
+                punch.set_hint(key, Some(addr)).await;
+            }
+        }
+        self.punch_transport.write().await.replace(punch.clone());
 
 
         for (k, ps) in map {
@@ -217,7 +499,7 @@ impl VlinkNetworkManager {
                 {
                     let mut wr = self.extra_selector.write_lock().await;
                     let entry = wr.entry(k.clone());
-                    let selector = entry.or_insert(ExtTransportSelector::new(p, inbound_tx_c, ps, relay.clone()));
+                    let selector = entry.or_insert(ExtTransportSelector::new(p, inbound_tx_c, ps, relay.clone(), punch.clone()));
                     //selector.insert(ps);
                 }
                 // self.device
@@ -230,7 +512,7 @@ impl VlinkNetworkManager {
         let dev_c = device.clone();
         tokio::spawn(async move {
             while let Ok(e) = event_rx.recv().await {
-                if let Err(err) = device_handler::handle_device_event(self_c.clone(), dev_c.clone(), e).await {
+                if let Err(err) = device_handler::handle_device_event(self_c.clone(), dev_c.clone(), &args, e).await {
                     error!("handle device event error:{:?}", err);
                 }
             }
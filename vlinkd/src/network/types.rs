@@ -1,2 +1,85 @@
-#[derive(Debug)]
-pub struct NetworkInfo {}
\ No newline at end of file
+use std::time::UNIX_EPOCH;
+use serde::Serialize;
+use vlink_core::base64::encode_base64;
+use vlink_tun::device::peer::Peer;
+
+/// 网络信息
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkInfo {
+    /// 本机公钥
+    pub pub_key: String,
+    pub address: String,
+    pub address6: Option<String>,
+    pub network: String,
+    pub network6: Option<String>,
+    /// udp 监听端口
+    pub port: u16,
+    pub peers: Vec<PeerInfo>,
+    pub extra_protos: Vec<ExtraProtoInfo>,
+    /// 负载高时回复cookie 的握手数
+    pub cookie_replies: u64,
+    /// 负载高时被限速丢弃的握手数
+    pub throttled_handshakes: u64,
+    /// 访问规则命中次数, 按规则顺序
+    pub acl_rules: Vec<AclRuleInfo>,
+}
+
+/// 节点状态
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerInfo {
+    pub pub_key: String,
+    pub ip: String,
+    pub online: bool,
+    /// 当前端点协议
+    pub protocol: Option<String>,
+    pub endpoint: Option<String>,
+    /// 最后握手时间,unix 秒,未握手为None
+    pub last_handshake_at: Option<u64>,
+    pub tx_messages: u64,
+    pub rx_messages: u64,
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+}
+
+/// 访问规则命中
+#[derive(Debug, Clone, Serialize)]
+pub struct AclRuleInfo {
+    pub id: i64,
+    pub hits: u64,
+}
+
+/// 扩展协议状态
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtraProtoInfo {
+    pub proto: String,
+    pub endpoint: Option<String>,
+    pub running: bool,
+    pub error: Option<String>,
+}
+
+impl From<&Peer> for PeerInfo {
+    fn from(peer: &Peer) -> Self {
+        let (protocol, endpoint) = match peer.endpoint.read().unwrap().as_ref() {
+            None => (None, None),
+            Some(e) => (Some(e.protocol().to_string()), Some(e.to_string())),
+        };
+        let metrics = peer.metrics();
+        let last_handshake_at = metrics.last_handshake_at
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs())
+            .filter(|s| *s > 0);
+        Self {
+            pub_key: encode_base64(peer.pub_key.as_bytes()),
+            ip: peer.ip_addr().to_string(),
+            online: peer.is_online(),
+            protocol,
+            endpoint,
+            last_handshake_at,
+            tx_messages: metrics.tx_messages,
+            rx_messages: metrics.rx_messages,
+            tx_bytes: metrics.tx_bytes,
+            rx_bytes: metrics.rx_bytes,
+        }
+    }
+}
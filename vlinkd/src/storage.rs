@@ -1,4 +1,5 @@
 use std::io;
+use std::path::PathBuf;
 use anyhow::anyhow;
 use directories::ProjectDirs;
 use log::info;
@@ -6,15 +7,20 @@ use tokio::fs;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use vlink_core::secret::VlinkStaticSecret;
-use crate::config::{ StorageConfig};
+use crate::config::{StorageConfig, VlinkNetworkConfig};
 
+/// 网络配置缓存文件
+const NETWORK_CONFIG_FILE: &str = "network.json";
+
+#[derive(Clone)]
 pub struct Storage {
     pub path: Option<String>,
 }
 
 impl Storage {
-    pub async fn load_config(&self) -> anyhow::Result<StorageConfig> {
-        let cp =match &self.path {
+    /// 配置目录
+    pub fn config_dir(&self) -> anyhow::Result<PathBuf> {
+        Ok(match &self.path {
             None => {
                 let proj_dirs = ProjectDirs::from("cn", "hperfect", "vlink")
                     .ok_or(anyhow!("配置目录打开错误"))?;
@@ -23,7 +29,11 @@ impl Storage {
             Some(s) => {
                 s.into()
             }
-        };
+        })
+    }
+
+    pub async fn load_config(&self) -> anyhow::Result<StorageConfig> {
+        let cp = self.config_dir()?;
         //读取配置文件
         let key = cp.join("config.json");
         let file = File::open(key.as_path()).await;
@@ -51,5 +61,26 @@ impl Storage {
         };
         // ProjectDirs::from
     }
-}
 
+    /// 缓存网络配置,私钥不写入缓存
+    pub async fn save_network_config(&self, config: &VlinkNetworkConfig) -> anyhow::Result<()> {
+        let cp = self.config_dir()?;
+        fs::create_dir_all(cp.as_path()).await?;
+        let txt = serde_json::to_string(config)?;
+        fs::write(cp.join(NETWORK_CONFIG_FILE), txt.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// 读取缓存的网络配置,不存在返回None
+    pub async fn load_network_config(&self) -> anyhow::Result<Option<VlinkNetworkConfig>> {
+        let path = self.config_dir()?.join(NETWORK_CONFIG_FILE);
+        let txt = match fs::read_to_string(path.as_path()).await {
+            Ok(txt) => txt,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let cfg: VlinkNetworkConfig = serde_json::from_str(txt.as_str())?;
+        info!("读取缓存网络配置:{:?}", path.as_os_str());
+        Ok(Some(cfg))
+    }
+}
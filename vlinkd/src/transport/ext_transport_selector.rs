@@ -1,89 +1,420 @@
 use std::sync::Arc;
-use std::time::Duration;
-use log::{debug, error, info};
+use std::time::{Duration, Instant};
+use log::{debug, error, info, warn};
 use tokio::sync::mpsc;
-use tokio::time;
+use tokio::{select, time};
+use tokio_util::sync::CancellationToken;
 use vlink_core::proto::pb::abi::PeerExtraTransport;
 use vlink_tun::device::peer::Peer;
-use vlink_tun::InboundResult;
-use crate::transport::proto::nat_tcp::{NatTcpTransportClient, NatTcpTransportParam};
+use vlink_tun::{InboundResult, OutboundSender};
+use crate::transport::proto::nat_tcp::NatTcpTransportClient;
 use crate::transport::proto::nat_udp::NatUdpTransportClient;
-use crate::transport::proto::relay_transport::RelayTransport;
+use crate::transport::proto::relay_transport::{RelayTransport, RELAY_PROTOCOL};
+use crate::transport::proto::websocket::WebsocketTransportClient;
+use crate::transport::punch::PunchTransport;
 
 /// 协议选择器间隔
 const SELECTOR_INTERVAL: u64 = 10;
+/// 升级失败或回退后的等待时间, 连续失败时翻倍
+const UPGRADE_BACKOFF: Duration = Duration::from_secs(60);
+const UPGRADE_BACKOFF_MAX: Duration = Duration::from_secs(600);
+/// 同一轮中两个候选之间的间隔, 留给打洞完成
+const UPGRADE_STEP: Duration = Duration::from_secs(15);
+/// 直连发送数据后这么久没有收到任何数据, 认为不可用
+const PATH_TIMEOUT: Duration = Duration::from_secs(30);
+/// 直连稳定这么久后清除失败次数
+const PATH_STABLE: Duration = Duration::from_secs(300);
 
 /// 扩展传输层选择器
 /// 选择扩展协议，更新peer的endpoint
-
-
+/// 使用中继时在后台尝试打洞和扩展协议,成功后切换为直连
+/// 直连收不到数据时回退中继, 按失败次数退避, 避免来回切换
 pub struct ExtTransportSelector {
     peer: Arc<Peer>,
     transports: Vec<PeerExtraTransport>,
+    /// 选择器删除时停止检测
+    token: CancellationToken,
 }
 
 /// 对目标节点选择扩展协议
 impl ExtTransportSelector {
     pub fn new(peer: Arc<Peer>, inbound_tx: mpsc::Sender<InboundResult>,
-               transports: Vec<PeerExtraTransport>, relay: Arc<RelayTransport>) -> Self {
-        let peer_c = Arc::clone(&peer);
-        let transports_c = transports.clone();
-        let inbound_tx_c = inbound_tx.clone();
-        let relay_c = relay.clone();
-
+               transports: Vec<PeerExtraTransport>, relay: Arc<RelayTransport>, punch: PunchTransport) -> Self {
+        // 配置的固定端点, 回退中继后也要作为升级候选
+        let fixed = peer.endpoint.read().unwrap().as_ref()
+            .filter(|e| e.protocol() != RELAY_PROTOCOL)
+            .map(|e| e.box_clone());
+        let mut manager = PathManager {
+            peer: peer.clone(),
+            inbound_tx,
+            transports: transports.clone(),
+            relay,
+            punch,
+            fixed,
+            extra: None,
+            direct: None,
+            upgrade: UpgradeState::new(Instant::now()),
+        };
+        let token = CancellationToken::new();
+        let token_c = token.clone();
         tokio::spawn(async move {
             // 启动循环检测peer 的 endpoint
             let mut interval = time::interval(Duration::from_secs(SELECTOR_INTERVAL));
             loop {
-                let inbound_tx_c = inbound_tx_c.clone();
-                peer_c.await_online().await;
-                if peer_c.endpoint.read().unwrap().is_none() {
-                    //启动对应的传输层连接器
-                    if transports_c.len() > 0 {
-                        if let Some(e) = transports_c.get(0) {
-                            let proto = e.proto.clone();
-                            match proto.as_str() {
-                                "NatUdp" => {
-                                    let c = NatUdpTransportClient::new(peer_c.clone(), inbound_tx_c, e.endpoint.clone()).await.unwrap();
-                                    let e = c.endpoint();
-                                    info!("start endpoint success {e}");
-                                    peer_c.update_endpoint(e);
-                                }
-                                "NatTcp" => {
-                                    match NatTcpTransportClient::spawn(peer_c.clone(), inbound_tx_c, e.endpoint.clone()).await {
-                                        Ok(c) => {
-                                            let e = c.endpoint();
-                                            info!("start endpoint success {e}");
-                                            peer_c.update_endpoint(e);
-                                        }
-                                        Err(e) => {
-                                            error!("start endpoint failed {e}");
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    error!("not support proto {proto}");
-                                }
-                            }
-                        }
-                    } else {
-                        //启动中继
-                        debug!("require_reply for {:?}", peer_c);
-                        let _ = relay_c.require_reply(peer_c.pub_key.as_bytes()).await;
-                    }
-                    //NatUdpTransportClient
+                select! {
+                    _ = token_c.cancelled() => break,
+                    _ = async {
+                        manager.peer.await_online().await;
+                        manager.tick().await;
+                        interval.tick().await;
+                    } => {}
                 }
-
-                interval.tick().await;
             }
-            //peer_c.update_endpoint().await;
+            debug!("{:?} 停止扩展协议选择", manager.peer);
         });
 
         Self {
             peer,
             transports,
+            token,
         }
     }
 
     pub fn insert(&mut self, ps: Vec<PeerExtraTransport>) {}
-}
\ No newline at end of file
+}
+
+impl Drop for ExtTransportSelector {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+/// 当前直连路径
+struct DirectPath {
+    endpoint: Box<dyn OutboundSender>,
+    since: Instant,
+}
+
+struct PathManager {
+    peer: Arc<Peer>,
+    inbound_tx: mpsc::Sender<InboundResult>,
+    transports: Vec<PeerExtraTransport>,
+    relay: Arc<RelayTransport>,
+    punch: PunchTransport,
+    /// 配置的固定端点
+    fixed: Option<Box<dyn OutboundSender>>,
+    /// 当前扩展协议的客户端, 每次连接都新建, 替换或不再使用时删除以关闭连接
+    extra: Option<ExtraClient>,
+    /// 中继数据到达时端点会漫游回中继, 保留直连以便恢复
+    direct: Option<DirectPath>,
+    upgrade: UpgradeState,
+}
+
+/// 扩展协议客户端, 删除时关闭连接和接收任务
+enum ExtraClient {
+    NatUdp(NatUdpTransportClient),
+    NatTcp(NatTcpTransportClient),
+    Ws(WebsocketTransportClient),
+}
+
+impl ExtraClient {
+    fn endpoint(&self) -> Box<dyn OutboundSender> {
+        match self {
+            ExtraClient::NatUdp(c) => c.endpoint(),
+            ExtraClient::NatTcp(c) => c.endpoint(),
+            ExtraClient::Ws(c) => c.endpoint(),
+        }
+    }
+
+    fn is(&self, e: &dyn OutboundSender) -> bool {
+        let endpoint = self.endpoint();
+        endpoint.protocol() == e.protocol() && endpoint.dst() == e.dst()
+    }
+}
+
+/// 升级候选, 每次尝试一个
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Candidate {
+    Punch,
+    Fixed,
+    Extra(usize),
+}
+
+/// 升级的退避和候选轮换
+#[derive(Debug)]
+struct UpgradeState {
+    /// 下一次尝试升级的时间
+    next_upgrade: Instant,
+    /// 连续失败次数
+    failures: u32,
+    /// 下一个尝试的候选序号
+    candidate: usize,
+}
+
+impl UpgradeState {
+    fn new(now: Instant) -> Self {
+        Self {
+            next_upgrade: now,
+            failures: 0,
+            candidate: 0,
+        }
+    }
+
+    fn due(&self, now: Instant) -> bool {
+        now >= self.next_upgrade
+    }
+
+    /// 取出本次尝试的候选序号, 一轮结束时退避, 否则隔一小段时间尝试下一个
+    fn next(&mut self, total: usize, now: Instant) -> usize {
+        let index = self.candidate % total;
+        self.candidate = (index + 1) % total;
+        self.next_upgrade = if self.candidate == 0 {
+            now + self.backoff()
+        } else {
+            now + UPGRADE_STEP
+        };
+        index
+    }
+
+    /// 直连失败, 从第一个候选重新开始并加倍退避
+    fn failed(&mut self, now: Instant) {
+        self.failures = self.failures.saturating_add(1);
+        self.candidate = 0;
+        self.next_upgrade = now + self.backoff();
+    }
+
+    /// 直连稳定后清除失败次数
+    fn stable(&mut self, since: Instant, now: Instant) {
+        if self.failures > 0 && now.duration_since(since) > PATH_STABLE {
+            self.failures = 0;
+        }
+    }
+
+    fn backoff(&self) -> Duration {
+        UPGRADE_BACKOFF.saturating_mul(1 << self.failures.min(4))
+            .min(UPGRADE_BACKOFF_MAX)
+    }
+}
+
+/// 切换后发送过数据, 且超过 PATH_TIMEOUT 没有通过该协议收到数据时不可用
+/// 对端收到数据后会立即回复或被动保活, 空闲时不判断
+fn path_alive(since: Instant, last_sent: Instant, last_recv: Option<Instant>, now: Instant) -> bool {
+    let last_recv = last_recv.map(|t| t.max(since)).unwrap_or(since);
+    last_sent <= last_recv || now.duration_since(last_recv) < PATH_TIMEOUT
+}
+
+impl PathManager {
+    async fn tick(&mut self) {
+        let endpoint = self.peer.endpoint.read().unwrap().as_ref().map(|e| e.box_clone());
+        // 扩展协议既不是当前端点也不是保留的直连, 如打洞成功或连接断开
+        let unused = self.extra.as_ref().is_some_and(|c| {
+            !endpoint.as_ref().is_some_and(|e| c.is(&**e))
+                && !self.direct.as_ref().is_some_and(|d| c.is(&*d.endpoint))
+        });
+        if unused {
+            debug!("{:?} 关闭不再使用的扩展协议连接", self.peer);
+            self.extra = None;
+        }
+        match endpoint {
+            None => {
+                self.direct = None;
+                self.connect().await;
+            }
+            Some(e) if e.protocol() == RELAY_PROTOCOL => {
+                if let Some(direct) = self.direct.take() {
+                    if self.alive(&direct) {
+                        // 对端还在通过中继发送, 直连仍然可用
+                        debug!("{:?} 恢复直连 {}", self.peer, direct.endpoint);
+                        self.peer.update_endpoint(direct.endpoint.box_clone());
+                        self.direct = Some(direct);
+                        return;
+                    }
+                    self.upgrade.failed(Instant::now());
+                    self.extra = None;
+                }
+                if self.upgrade.due(Instant::now()) {
+                    self.upgrade().await;
+                }
+            }
+            Some(e) => {
+                let since = match self.direct.as_ref() {
+                    Some(d) if d.endpoint.dst() == e.dst() && d.endpoint.protocol() == e.protocol() => d.since,
+                    _ => Instant::now(),
+                };
+                let direct = DirectPath { endpoint: e, since };
+                if !self.alive(&direct) {
+                    warn!("{:?} 直连 {} 超时,回退中继", self.peer, direct.endpoint);
+                    self.direct = None;
+                    self.extra = None;
+                    self.upgrade.failed(Instant::now());
+                    self.require_relay().await;
+                    return;
+                }
+                self.upgrade.stable(direct.since, Instant::now());
+                self.direct = Some(direct);
+            }
+        }
+    }
+
+    /// 没有端点时, 优先使用扩展协议, 失败时打洞并使用中继
+    async fn connect(&mut self) {
+        if let Some(e) = self.transports.first().cloned() {
+            match self.connect_extra(&e).await {
+                Ok(client) => {
+                    let endpoint = self.use_extra(client);
+                    info!("start endpoint success {endpoint}");
+                    self.peer.update_endpoint(endpoint);
+                    return;
+                }
+                Err(err) => error!("start endpoint {} failed {err}", e.proto),
+            }
+        }
+        //先打洞,成功后替换中继
+        if let Err(e) = self.punch.punch(self.peer.pub_key.as_bytes()).await {
+            debug!("punch {:?} error:{}", self.peer, e);
+        }
+        self.require_relay().await;
+        self.upgrade.next_upgrade = Instant::now() + self.upgrade.backoff();
+    }
+
+    async fn require_relay(&self) {
+        //启动中继
+        debug!("require_reply for {:?}", self.peer);
+        if let Err(e) = self.relay.require_reply(self.peer.pub_key.as_bytes()).await {
+            warn!("require_reply for {:?} error:{}", self.peer, e);
+        }
+    }
+
+    /// 打洞, 固定端点, 扩展协议
+    fn candidates(&self) -> Vec<Candidate> {
+        let mut candidates = vec![Candidate::Punch];
+        if self.fixed.is_some() {
+            candidates.push(Candidate::Fixed);
+        }
+        candidates.extend((0..self.transports.len()).map(Candidate::Extra));
+        candidates
+    }
+
+    /// 每次尝试一个候选, 一轮都不成功时退避
+    async fn upgrade(&mut self) {
+        let candidates = self.candidates();
+        let index = self.upgrade.next(candidates.len(), Instant::now());
+        let endpoint = match candidates[index] {
+            Candidate::Punch => {
+                // 成功时打洞直接替换端点
+                if let Err(e) = self.punch.punch(self.peer.pub_key.as_bytes()).await {
+                    debug!("punch {:?} error:{}", self.peer, e);
+                }
+                return;
+            }
+            Candidate::Fixed => match self.fixed.as_ref() {
+                Some(e) => {
+                    let e = e.box_clone();
+                    self.extra = None;
+                    e
+                }
+                None => return,
+            },
+            Candidate::Extra(i) => {
+                let e = self.transports[i].clone();
+                match self.connect_extra(&e).await {
+                    Ok(client) => self.use_extra(client),
+                    Err(err) => {
+                        debug!("{:?} 升级 {} 失败:{err}", self.peer, e.proto);
+                        return;
+                    }
+                }
+            }
+        };
+        info!("{:?} 尝试升级 {endpoint}", self.peer);
+        self.direct = Some(DirectPath {
+            endpoint: endpoint.box_clone(),
+            since: Instant::now(),
+        });
+        self.peer.update_endpoint(endpoint);
+    }
+
+    async fn connect_extra(&self, e: &PeerExtraTransport) -> anyhow::Result<ExtraClient> {
+        let peer = self.peer.clone();
+        let inbound_tx = self.inbound_tx.clone();
+        let endpoint = e.endpoint.clone();
+        match e.proto.as_str() {
+            // Dip 端点也是对端 wireguard 的udp 地址
+            "NatUdp" | "Dip" => Ok(ExtraClient::NatUdp(NatUdpTransportClient::new(peer, inbound_tx, endpoint).await?)),
+            "NatTcp" => Ok(ExtraClient::NatTcp(NatTcpTransportClient::spawn(peer, inbound_tx, endpoint).await?)),
+            "Ws" => Ok(ExtraClient::Ws(WebsocketTransportClient::spawn(peer, inbound_tx, endpoint).await?)),
+            proto => Err(anyhow::anyhow!("not support proto {proto}")),
+        }
+    }
+
+    /// 替换当前客户端, 旧的连接随之关闭
+    fn use_extra(&mut self, client: ExtraClient) -> Box<dyn OutboundSender> {
+        let endpoint = client.endpoint();
+        self.extra = Some(client);
+        endpoint
+    }
+
+    fn alive(&self, direct: &DirectPath) -> bool {
+        let last_recv = self.peer.last_recv_at(direct.endpoint.protocol());
+        path_alive(direct.since, self.peer.last_sent_at(), last_recv, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_backoff() {
+        let now = Instant::now();
+        let mut state = UpgradeState::new(now);
+        assert!(state.due(now));
+        // 打洞, 固定端点, 一个扩展协议
+        assert_eq!(state.next(3, now), 0);
+        assert_eq!(state.next_upgrade, now + UPGRADE_STEP);
+        assert!(!state.due(now));
+        assert_eq!(state.next(3, now), 1);
+        // 一轮结束后退避
+        assert_eq!(state.next(3, now), 2);
+        assert_eq!(state.next_upgrade, now + UPGRADE_BACKOFF);
+        assert_eq!(state.next(3, now), 0);
+
+        // 连续失败时翻倍, 不超过上限
+        state.next(3, now);
+        state.failed(now);
+        assert_eq!(state.candidate, 0);
+        assert_eq!(state.next_upgrade, now + UPGRADE_BACKOFF * 2);
+        state.failed(now);
+        assert_eq!(state.backoff(), UPGRADE_BACKOFF * 4);
+        for _ in 0..10 {
+            state.failed(now);
+        }
+        assert_eq!(state.backoff(), UPGRADE_BACKOFF_MAX);
+
+        // 稳定前不清除
+        state.stable(now, now + PATH_STABLE);
+        assert!(state.failures > 0);
+        state.stable(now, now + PATH_STABLE + Duration::from_secs(1));
+        assert_eq!(state.failures, 0);
+        assert_eq!(state.backoff(), UPGRADE_BACKOFF);
+    }
+
+    #[test]
+    fn test_path_alive() {
+        let since = Instant::now();
+        let later = |secs| since + Duration::from_secs(secs);
+        // 切换后没有发送, 空闲不判断
+        assert!(path_alive(since, since, None, later(100)));
+        // 发送后还在等待回复
+        assert!(path_alive(since, later(1), None, later(PATH_TIMEOUT.as_secs() - 1)));
+        assert!(!path_alive(since, later(1), None, later(PATH_TIMEOUT.as_secs() + 1)));
+        // 收到回复
+        assert!(path_alive(since, later(1), Some(later(2)), later(100)));
+        // 切换前通过同一协议收到的数据不算
+        let before = since - Duration::from_secs(1);
+        assert!(!path_alive(since, later(1), Some(before), later(PATH_TIMEOUT.as_secs() + 1)));
+        // 最后收到后又发送, 超时前仍然可用
+        assert!(path_alive(since, later(20), Some(later(10)), later(PATH_TIMEOUT.as_secs() + 9)));
+        assert!(!path_alive(since, later(20), Some(later(10)), later(PATH_TIMEOUT.as_secs() + 11)));
+    }
+}
@@ -4,3 +4,4 @@ pub mod forward;
 pub mod ext_transport_selector;
 pub mod sender;
 pub mod proto;
+pub mod punch;
@@ -230,7 +230,25 @@ async fn recv_from_udp(tx: Sender<SocketAddrV4>, socket: UdpSocket, upnp_service
     Ok(())
 }
 
-fn bind_request() -> Vec<u8> {
+/// binding 响应中的映射地址, 优先 XorMappedAddress
+pub(crate) fn mapped_addr(data: &[u8]) -> Option<SocketAddrV4> {
+    let msg = stun_format::Msg::from(data);
+    let mut mapped = None;
+    for attr in msg.attrs_iter() {
+        match attr {
+            Attr::XorMappedAddress(stun_format::SocketAddr::V4(ip, port)) => {
+                return Some(SocketAddrV4::new(Ipv4Addr::from(ip), port));
+            }
+            Attr::MappedAddress(stun_format::SocketAddr::V4(ip, port)) => {
+                mapped = Some(SocketAddrV4::new(Ipv4Addr::from(ip), port));
+            }
+            _ => {}
+        }
+    }
+    mapped
+}
+
+pub(crate) fn bind_request() -> Vec<u8> {
     let mut buf = [0u8; 28];
     let mut msg = stun_format::MsgBuilder::from(buf.as_mut_slice());
     msg.typ(stun_format::MsgType::BindingRequest);
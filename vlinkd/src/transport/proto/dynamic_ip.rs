@@ -1,14 +1,124 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::anyhow;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::timeout;
+use vlink_tun::device::event::{DeviceEvent, DevicePublisher, ExtraEndpoint};
+use crate::client::VlinkClient;
+use crate::transport::nat2pub::nat_service::{bind_request, mapped_addr};
 
 /// 仿ddns 动态公网ip
 /// 连接服务器成功后上报注册公网ip
-///
+/// 路由器把 pub_port 转发到本机wireguard 端口,对端直接用udp 连接 公网ip:pub_port
+/// 公网ip 变化后重新上报,服务器通知其他节点替换端点
 pub const PROTO_NAME: &str = "Dip";
+const DEFAULT_INTERVAL: u64 = 60;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DipParam {
     /// 外部端口
     pub_port: u16,
+    /// 获取公网ip 的stun 服务器,都失败时使用服务器看到的地址
+    #[serde(default)]
+    stun_servers: Vec<String>,
+    /// 检测间隔(秒),默认60
+    #[serde(default)]
+    interval: Option<u64>,
 }
 
-pub struct DynamicIpTransport {}
\ No newline at end of file
+pub struct DynamicIpTransport {
+    cc: Arc<VlinkClient>,
+    param: DipParam,
+    event_pub: DevicePublisher,
+    /// 已上报的公网ip
+    current: Option<IpAddr>,
+}
+
+impl DynamicIpTransport {
+    pub fn new(cc: Arc<VlinkClient>, param: DipParam, event_pub: DevicePublisher) -> Self {
+        Self {
+            cc,
+            param,
+            event_pub,
+            current: None,
+        }
+    }
+
+    pub async fn start(&mut self) -> anyhow::Result<()> {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.param.interval.unwrap_or(DEFAULT_INTERVAL)));
+        loop {
+            interval.tick().await;
+            let Some(ip) = self.public_ip().await else {
+                warn!("获取公网ip 失败");
+                continue;
+            };
+            if let Some(endpoint) = changed_endpoint(&mut self.current, ip, self.param.pub_port) {
+                //发送更新端点事件
+                let _ = self.event_pub.send(DeviceEvent::ExtraEndpointSuccess(endpoint));
+            }
+        }
+    }
+
+    async fn public_ip(&self) -> Option<IpAddr> {
+        for server in self.param.stun_servers.iter() {
+            match stun_public_ip(server.as_str()).await {
+                Ok(ip) => return Some(ip),
+                Err(e) => debug!("stun {} 失败:{}", server, e),
+            }
+        }
+        self.cc.observed_ip()
+    }
+}
+
+/// ip 与已上报的不同时返回需要上报的端点
+fn changed_endpoint(current: &mut Option<IpAddr>, ip: IpAddr, pub_port: u16) -> Option<ExtraEndpoint> {
+    if *current == Some(ip) {
+        return None;
+    }
+    info!("公网ip 变化:{:?} -> {}", current, ip);
+    *current = Some(ip);
+    Some(ExtraEndpoint {
+        proto: PROTO_NAME.to_string(),
+        endpoint: SocketAddr::new(ip, pub_port).to_string(),
+    })
+}
+
+/// 发送 binding request, 取响应中的映射地址
+async fn stun_public_ip(server: &str) -> anyhow::Result<IpAddr> {
+    let server = lookup_host(server).await?
+        .find(|a| a.is_ipv4())
+        .ok_or(anyhow!("stun 服务器地址错误:{}", server))?;
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(server).await?;
+    socket.send(bind_request().as_slice()).await?;
+    let mut buf = [0u8; 1024];
+    let n = timeout(Duration::from_secs(3), socket.recv(&mut buf)).await??;
+    mapped_addr(&buf[..n])
+        .map(|addr| IpAddr::V4(*addr.ip()))
+        .ok_or(anyhow!("stun 响应没有映射地址"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_changed_endpoint() {
+        let mut current = None;
+        let a = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+        let b = IpAddr::V4(Ipv4Addr::new(5, 6, 7, 8));
+        let e = changed_endpoint(&mut current, a, 51820).unwrap();
+        assert_eq!(e.proto, PROTO_NAME);
+        assert_eq!(e.endpoint, "1.2.3.4:51820");
+        // 没变化不重复上报
+        assert!(changed_endpoint(&mut current, a, 51820).is_none());
+        assert_eq!(changed_endpoint(&mut current, b, 51820).unwrap().endpoint, "5.6.7.8:51820");
+        assert_eq!(changed_endpoint(&mut current, a, 51820).unwrap().endpoint, "1.2.3.4:51820");
+        assert_eq!(current, Some(a));
+    }
+}
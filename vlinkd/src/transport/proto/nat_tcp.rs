@@ -11,6 +11,7 @@ use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::TcpSocket;
 use tokio::sync::{mpsc, Mutex};
 use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
 use vlink_tun::device::event::{DeviceEvent, DevicePublisher, ExtraEndpoint};
 use vlink_tun::device::peer::Peer;
 use vlink_tun::{BoxCloneOutboundSender, InboundResult, OutboundSender};
@@ -77,8 +78,16 @@ pub struct NatTcpListener {
 }
 
 
+/// 删除时关闭连接
 pub struct NatTcpTransportClient {
     pub sender: TcpOutboundSender<OwnedWriteHalf>,
+    token: CancellationToken,
+}
+
+impl Drop for NatTcpTransportClient {
+    fn drop(&mut self) {
+        self.token.cancel()
+    }
 }
 
 pub struct TcpOutboundSender<T: AsyncWriteExt> {
@@ -127,8 +136,8 @@ impl<T: AsyncWriteExt + Send + Sync + Unpin + 'static> OutboundSender for TcpOut
         self.dst
     }
 
-    fn protocol(&self) -> String {
-        PROTO_NAME.to_string()
+    fn protocol(&self) -> &'static str {
+        PROTO_NAME
     }
 }
 
@@ -147,20 +156,33 @@ impl NatTcpTransportClient {
             writer: awh,
         };
         let sender_c = sender.clone();
+        let token = CancellationToken::new();
+        let token_c = token.clone();
         // let tx = Arc::new(tx);
         tokio::spawn(async move {
             let mut buf = vec![0u8; 2048];
-            while let Ok(n) = rh.read(&mut buf).await {
-                if n <= 0 {
+            loop {
+                let n = tokio::select! {
+                    _ = token_c.cancelled() => {
+                        // 被替换, 不修改端点
+                        let _ = sender_c.writer.lock().await.shutdown().await;
+                        return;
+                    }
+                    rs = rh.read(&mut buf) => match rs {
+                        Ok(n) if n > 0 => n,
+                        _ => break,
+                    },
+                };
+                if inbound_tx.send((buf[..n].to_vec(), Box::new(sender_c.clone()))).await.is_err() {
                     break;
                 }
-                inbound_tx.send((buf[..n].to_vec(), Box::new(sender_c.clone()))).await.unwrap();
             }
             //断开
             *peer.endpoint.write().unwrap() = None;
         });
         Ok(Self {
             sender,
+            token,
         })
     }
 
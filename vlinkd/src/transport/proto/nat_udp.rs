@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
 
 use vlink_tun::{InboundResult, OutboundSender};
 use vlink_tun::device::event::{DeviceEvent, DevicePublisher, ExtraEndpoint};
@@ -96,10 +97,17 @@ impl NatUdpTransport {
     }
 }
 
+/// 删除时停止接收
 pub struct NatUdpTransportClient {
     dst: SocketAddr,
     socket: Arc<UdpSocket>,
+    token: CancellationToken,
+}
 
+impl Drop for NatUdpTransportClient {
+    fn drop(&mut self) {
+        self.token.cancel()
+    }
 }
 
 /// 客户端
@@ -113,10 +121,16 @@ impl NatUdpTransportClient {
         let dst: SocketAddr = endpoint.parse()?;
         // 接受数据
         let socket_c = socket.clone();
+        let token = CancellationToken::new();
+        let token_c = token.clone();
         tokio::spawn(async move {
             let mut buf = [0u8; 2048];
             loop {
-                match socket_c.recv_from(&mut buf).await {
+                let rs = tokio::select! {
+                    _ = token_c.cancelled() => break,
+                    rs = socket_c.recv_from(&mut buf) => rs,
+                };
+                match rs {
                     Ok((n, addr)) => {
                         debug!("recv from {},data:{n},dst:{dst}", addr);
                         let data = buf[..n].to_vec();
@@ -138,6 +152,7 @@ impl NatUdpTransportClient {
         Ok(Self {
             dst,
             socket,
+            token,
         })
     }
     pub fn endpoint(&self) -> Box<dyn OutboundSender> {
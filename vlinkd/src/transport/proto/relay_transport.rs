@@ -1,48 +1,70 @@
 use std::collections::hash_map::Entry;
+use std::collections::HashSet;
 use std::fmt::{Debug, Display, Formatter};
 use std::io::ErrorKind;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use anyhow::anyhow;
 use async_trait::async_trait;
+use futures_util::future::join_all;
 use futures_util::StreamExt;
-use log::{debug, error};
+use log::{debug, error, info, warn};
 use tokio::select;
 use tokio::sync::mpsc;
+use tokio::time::timeout;
 use tokio_util::sync::CancellationToken;
 use tailscale_derp::derp_client::DerpClient;
 use tailscale_derp::{DerpRequest, DerpResponse};
 use vlink_core::base64::{decode_base64, decode_base64_key, encode_base64};
-use vlink_core::proto::pb::abi::{peer_forward, PeerForward, RequireReply};
+use vlink_core::proto::pb::abi::{peer_forward, PeerForward, RelayServer, RequireReply};
 use vlink_core::proto::pb::abi::to_client::ToClientData;
 use vlink_core::rw_map::RwMap;
+use vlink_core::secret::VlinkStaticSecret;
 use vlink_tun::{BoxCloneOutboundSender, InboundResult, OutboundSender, PeerList};
 use vlink_tun::device::event;
 use crate::client::VlinkClient;
 
+/// 中继服务器探测间隔
+const PROBE_INTERVAL: Duration = Duration::from_secs(60);
+/// 探测握手超时
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+/// 中继连接无数据超时,服务端每60s 发送一次保活
+const RELAY_IDLE_TIMEOUT: Duration = Duration::from_secs(150);
+/// 中继 endpoint 协议名
+pub(crate) const RELAY_PROTOCOL: &str = "Reply";
+
 pub struct DerpTask {
     token: CancellationToken,
     client: DerpClient,
+    /// 通过该服务器中继的节点
+    targets: HashSet<[u8; 32]>,
 }
 
 /// 中继传输层
 /// 协商最快的中继服务器
 /// 启动中继服务，替换掉两个peer的endpoint
 ///
-/// 握手过程,
+/// 握手过程,发起方按延迟排序自己可达的服务器,连接最快的一个,通过RequireReply 发给目标
+/// 目标按顺序选第一个自己也能连上的,与发起方不同时回复自己的选择
 #[derive(Clone)]
 pub struct RelayTransport {
-    ///中继服务器列表
+    ///已连接的中继服务器
     derp_client_map: RwMap<String, DerpTask>,
     client: Arc<VlinkClient>,
     inbound_sender: mpsc::Sender<InboundResult>,
     peers: Arc<RwLock<PeerList>>,
+    ///中继服务器列表
+    servers: Arc<tokio::sync::RwLock<Vec<RelayServer>>>,
+    /// 握手延迟,None 为不可达
+    latency: RwMap<String, Option<Duration>>,
 }
 
 
 impl RelayTransport {
     // 启动中继传输层
     pub fn spawn(cc: Arc<VlinkClient>, sender: mpsc::Sender<InboundResult>,
-                 peers: Arc<RwLock<PeerList>>, bus: event::DevicePublisher, ) -> Self {
+                 peers: Arc<RwLock<PeerList>>, bus: event::DevicePublisher, servers: Vec<RelayServer>) -> Self {
         let mut tx = cc.subscribe();
         let client_map = RwMap::new();
         let client = cc.clone();
@@ -53,6 +75,8 @@ impl RelayTransport {
             client,
             inbound_sender: sender_c,
             peers,
+            servers: Arc::new(tokio::sync::RwLock::new(servers)),
+            latency: RwMap::new(),
         };
         let tt = t.clone();
         tokio::spawn(async move {
@@ -61,19 +85,79 @@ impl RelayTransport {
                 if let Some(ToClientData::RequireReply(data)) = data.to_client_data {
                     //来源key
                     let src_key = decode_base64_key(data.src.as_str());
-                    if let Err(e) = tt.connect_derp_server(data.server.clone(), src_key).await {
+                    if let Err(e) = tt.accept_require_reply(data, src_key).await {
                         error!("connect derp server error:{:?}", e);
                     }
                 }
             }
         });
+
+        // 定时探测中继服务器延迟
+        let tt = t.clone();
+        let token = cc.token.child_token();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PROBE_INTERVAL);
+            loop {
+                select! {
+                    _ = interval.tick() => {
+                        tt.probe_servers().await;
+                    }
+                    _ = token.cancelled() => {
+                        break;
+                    }
+                }
+            }
+        });
         t
     }
 
-    ///选择最近的中继服务器,要求目标连接该服务器
+    /// 更新中继服务器列表,并重新探测
+    pub async fn set_servers(&self, servers: Vec<RelayServer>) {
+        *self.servers.write().await = servers;
+        self.probe_servers().await;
+    }
 
+    /// 探测所有中继服务器的握手延迟
+    pub async fn probe_servers(&self) {
+        let servers = self.servers.read().await.clone();
+        let results = join_all(servers.into_iter().map(|s| async move {
+            let rtt = probe(s.url.as_str()).await;
+            (s.url, rtt)
+        })).await;
+        let mut latency = self.latency.write_lock().await;
+        latency.clear();
+        for (url, rtt) in results {
+            debug!("relay server {url} rtt:{:?}", rtt);
+            latency.insert(url, rtt);
+        }
+    }
+
+    /// 可达的中继服务器,按延迟排序,延迟相同按优先级
+    pub async fn ranked_servers(&self) -> Vec<String> {
+        let servers = self.servers.read().await.clone();
+        let probed = {
+            let latency = self.latency.read_lock().await;
+            servers.iter().all(|s| latency.contains_key(&s.url))
+        };
+        if !probed {
+            self.probe_servers().await;
+        }
+        let latency = self.latency.read_lock().await;
+        let mut ranked: Vec<(Duration, i32, String)> = servers.into_iter()
+            .filter_map(|s| {
+                latency.get(&s.url).cloned().flatten()
+                    .map(|rtt| (rtt, s.priority, s.url))
+            })
+            .collect();
+        ranked.sort();
+        ranked.into_iter().map(|(_, _, url)| url).collect()
+    }
+
+    ///选择最近的中继服务器,要求目标连接该服务器
     pub async fn require_reply(&self, target_pub: &[u8; 32]) -> anyhow::Result<()> {
-        let server = "wss://oaodev.local.hperfect.cn:11443/derp".to_string();
+        let servers = self.ranked_servers().await;
+        let server = servers.first().cloned()
+            .ok_or(anyhow!("没有可用的中继服务器"))?;
         if let Err(e) = self.connect_derp_server(server.clone(), target_pub.clone()).await {
             error!("connect derp server error:{:?}", e);
         }
@@ -83,39 +167,87 @@ impl RelayTransport {
         let _ = self.client.forward_to(target_pub_key, peer_forward::Data::RequireReply(RequireReply {
             src,
             proto: "".to_string(),
-            server: server.clone(),
+            server,
+            servers,
         })).await;
         Ok(())
     }
 
+    /// 收到中继请求,按发起方的顺序选第一个能连上的服务器
+    /// 与发起方选择不同时,回复自己的选择
+    async fn accept_require_reply(&self, data: RequireReply, src_key: [u8; 32]) -> anyhow::Result<()> {
+        let mut candidates = data.servers.clone();
+        if candidates.is_empty() {
+            candidates.push(data.server.clone());
+        }
+        for server in candidates {
+            if let Err(e) = self.connect_derp_server(server.clone(), src_key).await {
+                warn!("relay server {server} 不可达:{:?}", e);
+                continue;
+            }
+            if server != data.server {
+                info!("relay server 改用 {server}");
+                let _ = self.client.forward_to(data.src.clone(), peer_forward::Data::RequireReply(RequireReply {
+                    src: self.client.secret.base64_pub(),
+                    proto: "".to_string(),
+                    server: server.clone(),
+                    servers: vec![server],
+                })).await;
+            }
+            return Ok(());
+        }
+        Err(anyhow!("没有双方都可达的中继服务器"))
+    }
+
     ///连接中继服务器
     /// pub_key 本机公钥
     pub async fn connect_derp_server(&self, server: String, target: [u8; 32]) -> anyhow::Result<mpsc::Sender<DerpRequest>> {
         let key = self.client.secret.private_key.as_bytes().clone();
         let tx = match self.derp_client_map.write_lock().await.entry(server.clone()) {
-            Entry::Occupied(e) => {
+            Entry::Occupied(mut e) => {
                 debug!("server {server} is connected");
+                e.get_mut().targets.insert(target);
                 Ok(e.get().client.sender.clone())
             }
             Entry::Vacant(vacant) => {
                 //来源key
                 //检测是否连接
-                return match DerpClient::new(key, server.as_str()).await {
+                match DerpClient::new(key, server.as_str()).await {
                     Ok(mut derp_cli) => {
                         let tx = derp_cli.sender.clone();
                         match derp_cli.async_connect().await {
-                            Ok((mut stream)) => {
+                            Ok(mut stream) => {
                                 let token = self.client.token.child_token();
                                 //连接成功
+                                let mut targets = HashSet::new();
+                                targets.insert(target);
                                 vacant.insert(DerpTask {
                                     token: token.clone(),
                                     client: derp_cli,
+                                    targets,
                                 });
                                 //启动数据交换,derp 服务器->tun
                                 let inbound = self.inbound_sender.clone();
                                 let txc = tx.clone();
-                                let c = async move {
-                                    while let Some(Ok(resp)) = stream.next().await {
+                                let self_c = self.clone();
+                                let server_c = server.clone();
+                                tokio::spawn(async move {
+                                    loop {
+                                        let resp = select! {
+                                            r = timeout(RELAY_IDLE_TIMEOUT, stream.next()) => r,
+                                            // 主动断开, 服务器仍然可达
+                                            _ = token.cancelled() => {
+                                                return;
+                                            }
+                                        };
+                                        let resp = match resp {
+                                            Ok(Some(Ok(resp))) => resp,
+                                            Ok(_) => break,
+                                            Err(_) => {
+                                                warn!("relay server {server_c} 无响应");
+                                                break;
+                                            }
+                                        };
                                         match resp {
                                             DerpResponse::FrameRecvPacket((src, data)) => {
                                                 // debug!("recv data from derp:{:?}", encode_base64(&data));
@@ -130,22 +262,8 @@ impl RelayTransport {
                                             _ => {}
                                         }
                                     };
-                                    // 连接断开
-                                    //todo 发送传输层中断请求
-                                    //peer.clear_endpoint();
-                                };
-
-                                tokio::spawn(async move {
-                                    loop {
-                                        select! {
-                                                _ = c => {
-                                                    break;
-                                                }
-                                                _ = token.cancelled() => {
-                                                    break;
-                                                }
-                                            }
-                                    }
+                                    // 连接断开,重新选择中继服务器
+                                    self_c.on_server_lost(server_c.as_str()).await;
                                 });
                                 Ok(txc)
                             }
@@ -159,7 +277,7 @@ impl RelayTransport {
                         error!("create derp client error:{:?}", e);
                         Err(anyhow::anyhow!("create derp client error:{:?}", e))
                     }
-                };
+                }
             }
         };
 
@@ -176,6 +294,52 @@ impl RelayTransport {
         }
         return tx;
     }
+
+    /// 节点被删除, 不再为它中继, 没有其他节点使用的服务器断开连接
+    pub async fn remove_target(&self, target: &[u8; 32]) {
+        let mut map = self.derp_client_map.write_lock().await;
+        map.retain(|server, task| {
+            if !task.targets.remove(target) || !task.targets.is_empty() {
+                return true;
+            }
+            debug!("relay server {server} 没有中继节点,断开");
+            task.token.cancel();
+            false
+        });
+    }
+
+    /// 中继服务器断开,标记不可达,清除经过该服务器的endpoint
+    /// 由扩展协议选择器重新发起中继请求
+    async fn on_server_lost(&self, server: &str) {
+        self.latency.insert(server.to_string(), None).await;
+        let task = match self.derp_client_map.remove(&server.to_string()).await {
+            None => return,
+            Some(t) => t,
+        };
+        task.token.cancel();
+        let peers = self.peers.read().unwrap();
+        for target in task.targets {
+            if let Some(peer) = peers.get_by_key(&target) {
+                let is_relay = peer.endpoint.read().unwrap().as_ref()
+                    .map(|e| e.protocol() == RELAY_PROTOCOL)
+                    .unwrap_or(false);
+                if is_relay {
+                    peer.clear_endpoint();
+                }
+            }
+        }
+    }
+}
+
+/// 测量与中继服务器的握手延迟,使用临时key 避免挤掉正在使用的连接
+async fn probe(url: &str) -> Option<Duration> {
+    let key = *VlinkStaticSecret::generate().private_key.as_bytes();
+    let start = Instant::now();
+    let mut cli = DerpClient::new(key, url).await.ok()?;
+    match timeout(PROBE_TIMEOUT, cli.async_connect()).await {
+        Ok(Ok(_)) => Some(start.elapsed()),
+        _ => None,
+    }
 }
 
 
@@ -216,7 +380,7 @@ impl OutboundSender for TailscleDerpOutboundSender {
         SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))
     }
 
-    fn protocol(&self) -> String {
-        "Reply".to_string()
+    fn protocol(&self) -> &'static str {
+        RELAY_PROTOCOL
     }
 }
\ No newline at end of file
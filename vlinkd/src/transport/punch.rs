@@ -0,0 +1,449 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{debug, info, warn};
+use rand_core::{OsRng, RngCore};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::{timeout, Instant};
+use vlink_core::base64::{decode_base64, encode_base64};
+use vlink_core::proto::pb::abi::{peer_forward, Punch};
+use vlink_core::proto::pb::abi::to_client::ToClientData;
+use vlink_core::rw_map::RwMap;
+use vlink_tun::Device;
+use vlink_tun::noise::crypto::{hash, mac};
+use x25519_dalek::PublicKey;
+use crate::client::VlinkClient;
+use crate::transport::nat2pub::nat_service::{bind_request, mapped_addr};
+
+/// 探测包: magic(4) + 类型(1) + session(8) + 发送方公钥(32) + mac(16)
+/// mac 的key 由双方静态密钥协商得到, 伪造的 pong 不能改变端点
+const PROBE_MAGIC: &[u8; 4] = b"VLPH";
+const PROBE_BODY_LEN: usize = 45;
+const PROBE_LEN: usize = PROBE_BODY_LEN + 16;
+const PROBE_LABEL: &[u8] = b"vlink punch probe";
+const PROBE_PING: u8 = 0;
+const PROBE_PONG: u8 = 1;
+/// 探测包发送间隔
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+/// 单次打洞时长,超时后仍然使用中继
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+const STUN_TIMEOUT: Duration = Duration::from_secs(2);
+
+struct PunchSession {
+    id: u64,
+    /// 已找到可用地址,停止探测,但继续回应对方
+    done: bool,
+}
+
+/// udp 打洞
+/// 双方通过stun 获取wireguard 端口的映射地址,加上服务器看到的ip 和本机局域网ip 作为候选,经服务器交换
+/// 然后从设备udp 端口向对方的候选地址发送探测包,第一个收到回应的地址作为端点,替换中继
+#[derive(Clone)]
+pub struct PunchTransport {
+    inner: Arc<PunchTransportInner>,
+}
+
+pub struct PunchTransportInner {
+    client: Arc<VlinkClient>,
+    device: Arc<Device>,
+    stun_servers: Vec<String>,
+    /// 本机公钥,探测包携带
+    pub_key: [u8; 32],
+    /// 对端公钥 -> 进行中的打洞
+    sessions: RwMap<[u8; 32], PunchSession>,
+    /// 服务器看到的对端地址, 不直接作为端点, 探测时加入候选
+    hints: RwMap<[u8; 32], SocketAddr>,
+}
+
+impl Deref for PunchTransport {
+    type Target = PunchTransportInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl PunchTransport {
+    pub fn spawn(cc: Arc<VlinkClient>, device: Arc<Device>, stun_servers: Vec<String>) -> Self {
+        let t = Self {
+            inner: Arc::new(PunchTransportInner {
+                client: cc.clone(),
+                device: device.clone(),
+                stun_servers,
+                pub_key: PublicKey::from(&cc.secret.private_key).to_bytes(),
+                sessions: RwMap::new(),
+                hints: RwMap::new(),
+            }),
+        };
+        // 服务器转发的打洞请求
+        let mut rx = cc.subscribe();
+        let tt = t.clone();
+        tokio::spawn(async move {
+            while let Ok(data) = rx.recv().await {
+                if let Some(ToClientData::Punch(data)) = data.to_client_data {
+                    if let Err(e) = tt.accept(data).await {
+                        warn!("处理打洞请求失败:{}", e);
+                    }
+                }
+            }
+        });
+        // 设备udp 端口收到的探测包
+        let mut udp_rx = device.subscribe_udp();
+        let tt = t.clone();
+        let token = cc.token.child_token();
+        tokio::spawn(async move {
+            loop {
+                let (src, data) = tokio::select! {
+                    r = udp_rx.recv() => match r {
+                        Ok(r) => r,
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(_) => break,
+                    },
+                    _ = token.cancelled() => break,
+                };
+                if let Some(probe) = Probe::parse(&data) {
+                    tt.on_probe(src, probe, &data).await;
+                }
+            }
+        });
+        t
+    }
+
+    /// 向目标发起打洞,已在进行中时忽略
+    pub async fn punch(&self, target: &[u8; 32]) -> anyhow::Result<()> {
+        let id = OsRng.next_u64();
+        if !begin_session(&mut *self.sessions.write_lock().await, *target, id) {
+            return Ok(());
+        }
+        self.expire(*target, id);
+        let candidates = self.candidates().await;
+        debug!("punch {} candidates:{:?}", encode_base64(target), candidates);
+        self.client.forward_to(encode_base64(target), peer_forward::Data::Punch(Punch {
+            src: self.client.secret.base64_pub(),
+            session: id,
+            candidates,
+            reply: false,
+        })).await?;
+        Ok(())
+    }
+
+    /// 收到请求时回复自己的候选地址并开始探测, 收到回复时开始探测
+    async fn accept(&self, data: Punch) -> anyhow::Result<()> {
+        let src: [u8; 32] = decode_base64(data.src.as_str())?.try_into()
+            .map_err(|_| anyhow::anyhow!("pub_key 长度错误"))?;
+        if self.device.get_peer_by_key(&src).is_none() {
+            return Ok(());
+        }
+        if data.reply {
+            let matched = self.sessions.read_lock().await
+                .get(&src)
+                .map(|s| s.id == data.session && !s.done)
+                .unwrap_or(false);
+            if matched {
+                self.probe(src, data.session, data.candidates);
+            }
+            return Ok(());
+        }
+        if !accept_session(&mut *self.sessions.write_lock().await, src, data.session) {
+            return Ok(());
+        }
+        self.expire(src, data.session);
+        let candidates = self.candidates().await;
+        self.client.forward_to(data.src.clone(), peer_forward::Data::Punch(Punch {
+            src: self.client.secret.base64_pub(),
+            session: data.session,
+            candidates,
+            reply: true,
+        })).await?;
+        self.probe(src, data.session, data.candidates);
+        Ok(())
+    }
+
+    /// 更新服务器看到的对端地址, None 时删除
+    pub async fn set_hint(&self, target: [u8; 32], addr: Option<SocketAddr>) {
+        match addr {
+            Some(addr) => {
+                self.hints.insert(target, addr).await;
+            }
+            None => {
+                self.hints.remove(&target).await;
+            }
+        }
+    }
+
+    /// 超时后删除, 仍然是同一次打洞时才删除
+    fn expire(&self, target: [u8; 32], id: u64) {
+        let t = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(PUNCH_TIMEOUT).await;
+            if expire_session(&mut *t.sessions.write_lock().await, target, id) == Some(false) {
+                info!("打洞超时:{}", encode_base64(&target));
+            }
+        });
+    }
+
+    /// 定时向所有候选地址发送 ping, 直到收到 pong 或超时
+    fn probe(&self, target: [u8; 32], id: u64, candidates: Vec<String>) {
+        let mut addrs: Vec<SocketAddr> = candidates.iter().filter_map(|c| c.parse().ok()).collect();
+        let ping = Probe { kind: PROBE_PING, session: id, pub_key: self.pub_key }.to_bytes(&self.probe_key(&target));
+        let t = self.clone();
+        tokio::spawn(async move {
+            if let Some(hint) = t.hints.read_lock().await.get(&target) {
+                if !addrs.contains(hint) {
+                    addrs.push(*hint);
+                }
+            }
+            if addrs.is_empty() {
+                return;
+            }
+            let deadline = Instant::now() + PUNCH_TIMEOUT;
+            let mut interval = tokio::time::interval(PROBE_INTERVAL);
+            while Instant::now() < deadline {
+                interval.tick().await;
+                let active = t.sessions.read_lock().await
+                    .get(&target)
+                    .map(|s| s.id == id && !s.done)
+                    .unwrap_or(false);
+                if !active {
+                    break;
+                }
+                for addr in addrs.iter() {
+                    if let Err(e) = t.device.send_udp(*addr, &ping).await {
+                        debug!("send probe to {} error:{}", addr, e);
+                    }
+                }
+            }
+        });
+    }
+
+    /// 双方静态密钥协商出的探测包 mac key
+    fn probe_key(&self, peer: &[u8; 32]) -> [u8; 32] {
+        let shared = self.client.secret.private_key.diffie_hellman(&PublicKey::from(*peer));
+        hash(PROBE_LABEL, shared.as_bytes())
+    }
+
+    async fn on_probe(&self, src: SocketAddr, probe: Probe, data: &[u8]) {
+        let active = self.sessions.read_lock().await
+            .get(&probe.pub_key)
+            .map(|s| s.id == probe.session)
+            .unwrap_or(false);
+        if !active {
+            return;
+        }
+        let key = self.probe_key(&probe.pub_key);
+        if !Probe::verify(data, &key) {
+            debug!("探测包 mac 错误:{}", src);
+            return;
+        }
+        match probe.kind {
+            PROBE_PING => {
+                let pong = Probe { kind: PROBE_PONG, session: probe.session, pub_key: self.pub_key };
+                let _ = self.device.send_udp(src, &pong.to_bytes(&key)).await;
+            }
+            PROBE_PONG => {
+                if !finish_session(&mut *self.sessions.write_lock().await, probe.pub_key, probe.session) {
+                    return;
+                }
+                if self.device.update_peer_endpoint(&probe.pub_key, src) {
+                    info!("打洞成功 {} -> {}", encode_base64(&probe.pub_key), src);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// stun 映射地址, 服务器看到的ip 和本机出口ip, 端口都是wireguard 端口
+    async fn candidates(&self) -> Vec<String> {
+        let port = self.device.listen_port();
+        let mut addrs = vec![];
+        for server in self.stun_servers.iter() {
+            match self.stun(server.as_str()).await {
+                Ok(addr) => {
+                    addrs.push(addr);
+                    break;
+                }
+                Err(e) => debug!("stun {} 失败:{}", server, e),
+            }
+        }
+        if let Some(ip) = self.client.observed_ip() {
+            addrs.push(SocketAddr::new(ip, port));
+        }
+        if let Some(ip) = local_ip(self.client.server_addr()).await {
+            addrs.push(SocketAddr::new(ip, port));
+        }
+        let mut seen = HashSet::new();
+        addrs.into_iter()
+            .filter(|a| seen.insert(*a))
+            .map(|a| a.to_string())
+            .collect()
+    }
+
+    /// 从设备udp 端口请求stun, 得到wireguard 端口的nat 映射
+    async fn stun(&self, server: &str) -> anyhow::Result<SocketAddr> {
+        let server = lookup_host(server).await?
+            .find(|a| a.is_ipv4())
+            .ok_or(anyhow::anyhow!("stun 服务器地址错误:{}", server))?;
+        let mut rx = self.device.subscribe_udp();
+        self.device.send_udp(server, bind_request().as_slice()).await?;
+        timeout(STUN_TIMEOUT, async {
+            loop {
+                let (src, data) = rx.recv().await?;
+                if src != server {
+                    continue;
+                }
+                if let Some(addr) = mapped_addr(&data) {
+                    return Ok(SocketAddr::V4(addr));
+                }
+            }
+        }).await?
+    }
+}
+
+/// 发起打洞, 已有进行中的打洞时返回 false
+fn begin_session(sessions: &mut HashMap<[u8; 32], PunchSession>, target: [u8; 32], id: u64) -> bool {
+    if sessions.get(&target).map(|s| !s.done).unwrap_or(false) {
+        return false;
+    }
+    sessions.insert(target, PunchSession { id, done: false });
+    true
+}
+
+/// 收到对方发起的打洞, 双方同时发起时都保留 id 较大的一次
+fn accept_session(sessions: &mut HashMap<[u8; 32], PunchSession>, src: [u8; 32], id: u64) -> bool {
+    if sessions.get(&src).map(|s| !s.done && s.id > id).unwrap_or(false) {
+        return false;
+    }
+    sessions.insert(src, PunchSession { id, done: false });
+    true
+}
+
+/// 收到同一次打洞的第一个 pong 时返回 true
+fn finish_session(sessions: &mut HashMap<[u8; 32], PunchSession>, target: [u8; 32], id: u64) -> bool {
+    match sessions.get_mut(&target) {
+        Some(s) if s.id == id && !s.done => {
+            s.done = true;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// 仍然是同一次打洞时删除, 返回删除的打洞是否已成功
+fn expire_session(sessions: &mut HashMap<[u8; 32], PunchSession>, target: [u8; 32], id: u64) -> Option<bool> {
+    if sessions.get(&target)?.id != id {
+        return None;
+    }
+    sessions.remove(&target).map(|s| s.done)
+}
+
+/// 连接服务器使用的本机ip, 同一局域网的节点可以直连
+async fn local_ip(server_addr: &str) -> Option<std::net::IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.ok()?;
+    socket.connect(server_addr).await.ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    if ip.is_unspecified() || ip.is_loopback() {
+        return None;
+    }
+    Some(ip)
+}
+
+#[derive(Debug, PartialEq)]
+struct Probe {
+    kind: u8,
+    session: u64,
+    pub_key: [u8; 32],
+}
+
+impl Probe {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() != PROBE_LEN || &data[..4] != PROBE_MAGIC {
+            return None;
+        }
+        Some(Self {
+            kind: data[4],
+            session: u64::from_be_bytes(data[5..13].try_into().ok()?),
+            pub_key: data[13..45].try_into().ok()?,
+        })
+    }
+
+    fn to_bytes(&self, key: &[u8; 32]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(PROBE_LEN);
+        buf.extend_from_slice(PROBE_MAGIC);
+        buf.push(self.kind);
+        buf.extend_from_slice(&self.session.to_be_bytes());
+        buf.extend_from_slice(&self.pub_key);
+        let tag = mac(key, &buf);
+        buf.extend_from_slice(&tag);
+        buf
+    }
+
+    /// 比较不提前返回
+    fn verify(data: &[u8], key: &[u8; 32]) -> bool {
+        if data.len() != PROBE_LEN {
+            return false;
+        }
+        let tag = mac(key, &data[..PROBE_BODY_LEN]);
+        tag.iter().zip(&data[PROBE_BODY_LEN..]).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe() {
+        let probe = Probe { kind: PROBE_PONG, session: 42, pub_key: [7u8; 32] };
+        let key = [1u8; 32];
+        let mut data = probe.to_bytes(&key);
+        assert_eq!(data.len(), PROBE_LEN);
+        assert_eq!(Probe::parse(&data), Some(probe));
+        assert!(Probe::verify(&data, &key));
+        // 不知道协商密钥无法伪造
+        assert!(!Probe::verify(&data, &[2u8; 32]));
+        data[5] ^= 1;
+        assert!(!Probe::verify(&data, &key));
+        // wireguard 数据不会被当成探测包
+        assert_eq!(Probe::parse(&[4, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_session_race() {
+        let mut sessions = HashMap::new();
+        let peer = [3u8; 32];
+        assert!(begin_session(&mut sessions, peer, 10));
+        // 进行中不重复发起
+        assert!(!begin_session(&mut sessions, peer, 11));
+        // 同时发起, id 小的一方放弃
+        assert!(!accept_session(&mut sessions, peer, 5));
+        assert_eq!(sessions[&peer].id, 10);
+        assert!(accept_session(&mut sessions, peer, 20));
+        assert_eq!(sessions[&peer].id, 20);
+        // 旧的 pong 不结束新的打洞
+        assert!(!finish_session(&mut sessions, peer, 10));
+        assert!(finish_session(&mut sessions, peer, 20));
+        assert!(!finish_session(&mut sessions, peer, 20));
+        // 成功后可以重新发起
+        assert!(begin_session(&mut sessions, peer, 30));
+    }
+
+    #[test]
+    fn test_session_expire() {
+        let mut sessions = HashMap::new();
+        let peer = [3u8; 32];
+        assert!(begin_session(&mut sessions, peer, 1));
+        assert!(accept_session(&mut sessions, peer, 2));
+        // 被替换的打洞超时不删除新的
+        assert_eq!(expire_session(&mut sessions, peer, 1), None);
+        assert!(sessions.contains_key(&peer));
+        assert_eq!(expire_session(&mut sessions, peer, 2), Some(false));
+        assert!(sessions.is_empty());
+
+        assert!(begin_session(&mut sessions, peer, 3));
+        assert!(finish_session(&mut sessions, peer, 3));
+        assert_eq!(expire_session(&mut sessions, peer, 3), Some(true));
+        assert_eq!(expire_session(&mut sessions, peer, 3), None);
+    }
+}
@@ -43,8 +43,8 @@ impl OutboundSender for Ipv4UdpOutboundSender {
         self.dst
     }
 
-    fn protocol(&self) -> String {
-        PROTO_NAME.to_string()
+    fn protocol(&self) -> &'static str {
+        PROTO_NAME
     }
 }
 